@@ -0,0 +1,100 @@
+// ビットスライスの AES と RustCrypto の aes / aes-gcm (AES-NI などを使う) の処理速度の比較
+// cargo bench --bench aes
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+use aes_gcm::aead::{Aead, Payload};
+use tls_from_scratch::tls::crypto::{Aes, Gcm, AES_BLOCK_SIZE};
+
+// TLS のレコードの最大長
+const RECORD_SIZE: usize = 16384;
+const DURATION: Duration = Duration::from_secs(1);
+
+// DURATION の間 f を繰り返し、MB/s を返す
+fn throughput(bytes: usize, mut f: impl FnMut()) -> f64 {
+    let start = Instant::now();
+    let mut count = 0;
+    while start.elapsed() < DURATION {
+        f();
+        count += 1;
+    }
+    (bytes * count) as f64 / start.elapsed().as_secs_f64() / 1_000_000.0
+}
+
+fn report(name: &str, ours: f64, theirs: f64) {
+    println!(
+        "{:<20} tls-from-scratch {:>9.2} MB/s  RustCrypto {:>9.2} MB/s  ({:.1}x)",
+        name,
+        ours,
+        theirs,
+        theirs / ours
+    );
+}
+
+fn bench_block(key: &[u8]) {
+    let blocks = RECORD_SIZE / AES_BLOCK_SIZE;
+
+    let aes = Aes::new(key);
+    let mut data = vec![[0u8; AES_BLOCK_SIZE]; blocks];
+    let ours = throughput(RECORD_SIZE, || aes.encrypt_blocks(black_box(&mut data)));
+
+    let mut data = vec![GenericArray::default(); blocks];
+    let theirs = match key.len() {
+        16 => {
+            let aes = aes::Aes128::new_from_slice(key).unwrap();
+            throughput(RECORD_SIZE, || aes.encrypt_blocks(black_box(&mut data)))
+        }
+        24 => {
+            let aes = aes::Aes192::new_from_slice(key).unwrap();
+            throughput(RECORD_SIZE, || aes.encrypt_blocks(black_box(&mut data)))
+        }
+        _ => {
+            let aes = aes::Aes256::new_from_slice(key).unwrap();
+            throughput(RECORD_SIZE, || aes.encrypt_blocks(black_box(&mut data)))
+        }
+    };
+
+    report(&format!("AES-{}", key.len() * 8), ours, theirs);
+}
+
+fn bench_gcm(key: &[u8]) {
+    let nonce = [0u8; 12];
+    let aad = [0u8; 13];
+    let plaintext = vec![0u8; RECORD_SIZE];
+
+    let gcm = Gcm::new(key);
+    let ours = throughput(RECORD_SIZE, || {
+        black_box(gcm.seal(&nonce, &aad, black_box(&plaintext)));
+    });
+
+    let payload = || Payload {
+        msg: black_box(&plaintext),
+        aad: &aad,
+    };
+    let theirs = match key.len() {
+        16 => {
+            let gcm = aes_gcm::Aes128Gcm::new_from_slice(key).unwrap();
+            throughput(RECORD_SIZE, || {
+                black_box(gcm.encrypt(&nonce.into(), payload()).unwrap());
+            })
+        }
+        _ => {
+            let gcm = aes_gcm::Aes256Gcm::new_from_slice(key).unwrap();
+            throughput(RECORD_SIZE, || {
+                black_box(gcm.encrypt(&nonce.into(), payload()).unwrap());
+            })
+        }
+    };
+
+    report(&format!("AES-{}-GCM", key.len() * 8), ours, theirs);
+}
+
+fn main() {
+    for key_length in [16, 24, 32] {
+        bench_block(&vec![0x2b; key_length]);
+    }
+    for key_length in [16, 32] {
+        bench_gcm(&vec![0x2b; key_length]);
+    }
+}
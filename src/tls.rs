@@ -1,10 +1,33 @@
+mod certificate;
+mod client;
+mod connection;
+pub mod crypto;
 mod de;
 mod handshake;
 mod hello_messaage;
-
+mod key_schedule;
+mod record;
+mod server;
+mod server_session;
+mod session;
+#[cfg(test)]
+mod test_util;
+mod verifier;
+
+pub use certificate::*;
+pub use client::*;
+pub use connection::*;
 use de::*;
 pub use handshake::*;
 pub use hello_messaage::*;
+pub use key_schedule::*;
+pub use record::*;
+pub use server::*;
+pub use server_session::*;
+pub use session::*;
+pub use verifier::*;
+
+use crypto::{AeadAlgorithm, DigestAlgorithm};
 
 use serde::Serialize;
 use serde_repr::{Deserialize_repr, Serialize_repr};
@@ -46,21 +69,7 @@ impl TLSRecord {
 
         let (input, fragment) = take(input, length)?;
         let fragment = Buffer::new(fragment, length as usize);
-        let fragment = match content_type {
-            ContentType::Handshake => {
-                let (_, handshake) = Handshake::deserialize(fragment)?;
-                Fragment::Handshake(handshake)
-            }
-            ContentType::ChangeCipherSpec => {
-                let (_, spec) = ChangeCipherSpec::deserialize(fragment)?;
-                Fragment::ChangeCipherSpec(spec)
-            }
-            ContentType::Alert => {
-                let (_, alert) = Alert::deserialize(fragment)?;
-                Fragment::Alert(alert)
-            }
-            _ => unimplemented!(),
-        };
+        let (_, fragment) = Fragment::deserialize(&content_type, fragment)?;
 
         Ok((
             input,
@@ -79,6 +88,31 @@ pub enum Fragment {
     Handshake(Handshake),
     ChangeCipherSpec(ChangeCipherSpec),
     Alert(Alert),
+    ApplicationData(Vec<u8>),
+}
+
+impl Fragment {
+    pub fn deserialize<'a>(content_type: &ContentType, input: Buffer<'a>) -> IResult<'a, Self> {
+        match content_type {
+            ContentType::Handshake => {
+                let (input, handshake) = Handshake::deserialize(input, ProtocolVersion::TLSv1_2)?;
+                Ok((input, Fragment::Handshake(handshake)))
+            }
+            ContentType::ChangeCipherSpec => {
+                let (input, spec) = ChangeCipherSpec::deserialize(input)?;
+                Ok((input, Fragment::ChangeCipherSpec(spec)))
+            }
+            ContentType::Alert => {
+                let (input, alert) = Alert::deserialize(input)?;
+                Ok((input, Fragment::Alert(alert)))
+            }
+            ContentType::ApplicationData => {
+                let length = input.length();
+                let (input, data) = take(input, length)?;
+                Ok((input, Fragment::ApplicationData(data.to_vec())))
+            }
+        }
+    }
 }
 
 impl_enum_try_from! {
@@ -95,21 +129,80 @@ impl_enum_try_from! {
 impl ChangeCipherSpec {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, spec) = be_u8(input)?;
-        Ok((input, ChangeCipherSpec::try_from(spec).unwrap()))
+        match ChangeCipherSpec::try_from(spec) {
+            Ok(spec) => Ok((input, spec)),
+            Err(_) => invalid_value(input),
+        }
     }
 }
 
 #[derive(Debug)]
 pub enum Error {
     InvalidValue,
+    // こちらから送るアラート
+    Alert(AlertDescription),
+    // 相手から受け取ったアラート
+    ReceivedAlert(AlertDescription),
 }
 
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::InvalidValue => write!(f, "invalid value"),
+            Error::Alert(description) => write!(f, "{:?}", description),
+            Error::ReceivedAlert(description) => write!(f, "received alert: {:?}", description),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
 #[derive(Serialize, Debug)]
 pub struct Vector<T, D> {
     pub length: T,
     pub data: Vec<D>,
 }
 
+impl<D: Serialize> Vector<u8, D> {
+    pub fn new(data: Vec<D>) -> Self {
+        let length = ser::bytes_size(&data).unwrap() as u8;
+        Vector { length, data }
+    }
+}
+
+impl<D: Serialize> Vector<u16, D> {
+    pub fn new(data: Vec<D>) -> Self {
+        let length = ser::bytes_size(&data).unwrap() as u16;
+        Vector { length, data }
+    }
+
+    // 要素のデコーダーが None を返したもの (未知の値など) は読み飛ばす
+    pub fn deserialize_with<'a, F>(input: Buffer<'a>, f: F) -> IResult<'a, Self>
+    where
+        F: Fn(Buffer<'a>) -> IResult<'a, Option<D>>,
+    {
+        let (input, length) = be_u16(input)?;
+        let (input, data) = take(input, length)?;
+        let mut data = Buffer::new(data, length);
+
+        let mut items = vec![];
+        while data.length() > 0 {
+            let (rest, item) = f(data)?;
+            data = rest;
+            items.extend(item);
+        }
+
+        Ok((input, Vector::<u16, D>::new(items)))
+    }
+}
+
+impl<D: Serialize> Vector<u24, D> {
+    pub fn new(data: Vec<D>) -> Self {
+        let length = u24::from(ser::bytes_size(&data).unwrap());
+        Vector { length, data }
+    }
+}
+
 pub type Opaque<S> = Vector<S, u8>;
 
 impl Opaque<u8> {
@@ -126,6 +219,20 @@ impl Opaque<u8> {
     }
 }
 
+impl Opaque<u16> {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, length) = be_u16(input)?;
+        let (input, data) = take(input, length)?;
+        Ok((
+            input,
+            Vector {
+                length,
+                data: data.to_vec(),
+            },
+        ))
+    }
+}
+
 impl Opaque<u24> {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, length) = u24::deserialize(input)?;
@@ -143,7 +250,7 @@ impl Opaque<u24> {
 impl_enum_try_from! {
     #[allow(dead_code)]
     #[repr(u16)]
-    #[derive(Serialize_repr, Deserialize_repr, Debug)]
+    #[derive(Serialize_repr, Deserialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum ProtocolVersion {
         SSLv3 = 0x0300,
         TLSv1 = 0x0301,
@@ -159,15 +266,17 @@ impl_enum_try_from! {
 impl ProtocolVersion {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, version) = be_u16(input)?;
-        let version = ProtocolVersion::try_from(version).unwrap();
-        Ok((input, version))
+        match ProtocolVersion::try_from(version) {
+            Ok(version) => Ok((input, version)),
+            Err(_) => invalid_value(input),
+        }
     }
 }
 
 impl_enum_try_from! {
     #[allow(dead_code)]
     #[repr(u8)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum ContentType {
         ChangeCipherSpec = 20,
         Alert = 21,
@@ -182,15 +291,17 @@ impl_enum_try_from! {
 impl ContentType {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, content_type) = be_u8(input)?;
-        let content_type = ContentType::try_from(content_type).unwrap();
-        Ok((input, content_type))
+        match ContentType::try_from(content_type) {
+            Ok(content_type) => Ok((input, content_type)),
+            Err(_) => invalid_value(input),
+        }
     }
 }
 
 impl_enum_try_from! {
     #[allow(non_camel_case_types)]
     #[repr(u16)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum CipherSuite {
         TLS_NULL_WITH_NULL_NULL = 0x0000,
         TLS_RSA_WITH_NULL_MD5 = 0x0001,
@@ -230,11 +341,28 @@ impl_enum_try_from! {
         TLS_DH_anon_WITH_AES_128_CBC_SHA256 = 0x006C,
         TLS_DH_anon_WITH_AES_256_CBC_SHA256 = 0x006D,
         TLS_RSA_WITH_AES_128_GCM_SHA256 = 0x009C,
+        TLS_RSA_WITH_AES_256_GCM_SHA384 = 0x009D,
+        TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256 = 0xC02B,
+        TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384 = 0xC02C,
+        TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256 = 0xC02F,
+        TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384 = 0xC030,
+        TLS_RSA_WITH_AES_128_CCM = 0xC09C,
+        TLS_RSA_WITH_AES_256_CCM = 0xC09D,
+        TLS_RSA_WITH_AES_128_CCM_8 = 0xC0A0,
+        TLS_RSA_WITH_AES_256_CCM_8 = 0xC0A1,
+        TLS_ECDHE_ECDSA_WITH_AES_128_CCM = 0xC0AC,
+        TLS_ECDHE_ECDSA_WITH_AES_256_CCM = 0xC0AD,
+        TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8 = 0xC0AE,
+        TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8 = 0xC0AF,
         TLS_AES_128_GCM_SHA256 = 0x1301,
         TLS_AES_256_GCM_SHA384 = 0x1302,
         TLS_CHACHA20_POLY1305_SHA256 = 0x1303,
         TLS_AES_128_CCM_SHA256 = 0x1304,
         TLS_AES_128_CCM_8_SHA256 = 0x1305,
+        // RFC 5746 3.3. 空の renegotiation_info 拡張の代わり (実際のスイートではない)
+        TLS_EMPTY_RENEGOTIATION_INFO_SCSV = 0x00FF,
+        // RFC 7507. フォールバックでの再接続であることを示す (実際のスイートではない)
+        TLS_FALLBACK_SCSV = 0x5600,
     },
     u16,
     Error,
@@ -244,8 +372,100 @@ impl_enum_try_from! {
 impl CipherSuite {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, suite) = be_u16(input)?;
-        let suite = CipherSuite::try_from(suite).unwrap();
-        Ok((input, suite))
+        match CipherSuite::try_from(suite) {
+            Ok(suite) => Ok((input, suite)),
+            Err(_) => invalid_value(input),
+        }
+    }
+
+    pub fn is_tls13(&self) -> bool {
+        (*self as u16) >> 8 == 0x13
+    }
+
+    // TLS 1.2 で鍵交換が ECDHE のスイート
+    pub fn is_ecdhe_key_exchange(&self) -> bool {
+        matches!(
+            self,
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+                | CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+                | CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8
+        )
+    }
+
+    // TLS 1.2 でサーバー証明書の鍵が ECDSA でなければならないスイート
+    pub fn is_ecdsa_authentication(&self) -> bool {
+        matches!(
+            self,
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8
+                | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8
+        )
+    }
+
+    // 鍵交換が静的 RSA のスイート
+    pub fn is_rsa_key_exchange(&self) -> bool {
+        matches!(
+            self,
+            CipherSuite::TLS_RSA_WITH_NULL_MD5
+                | CipherSuite::TLS_RSA_WITH_NULL_SHA
+                | CipherSuite::TLS_RSA_WITH_RC4_128_MD5
+                | CipherSuite::TLS_RSA_WITH_RC4_128_SHA
+                | CipherSuite::TLS_RSA_WITH_3DES_EDE_CBC_SHA
+                | CipherSuite::TLS_RSA_WITH_AES_128_CBC_SHA
+                | CipherSuite::TLS_RSA_WITH_AES_256_CBC_SHA
+                | CipherSuite::TLS_RSA_WITH_NULL_SHA256
+                | CipherSuite::TLS_RSA_WITH_AES_128_CBC_SHA256
+                | CipherSuite::TLS_RSA_WITH_AES_256_CBC_SHA256
+                | CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256
+                | CipherSuite::TLS_RSA_WITH_AES_256_GCM_SHA384
+                | CipherSuite::TLS_RSA_WITH_AES_128_CCM
+                | CipherSuite::TLS_RSA_WITH_AES_256_CCM
+                | CipherSuite::TLS_RSA_WITH_AES_128_CCM_8
+                | CipherSuite::TLS_RSA_WITH_AES_256_CCM_8
+        )
+    }
+
+    // TLS 1.2 では PRF、TLS 1.3 では HKDF と Transcript-Hash に使うハッシュ
+    pub fn hash_algorithm(&self) -> DigestAlgorithm {
+        match self {
+            CipherSuite::TLS_RSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_AES_256_GCM_SHA384 => DigestAlgorithm::Sha384,
+            _ => DigestAlgorithm::Sha256,
+        }
+    }
+
+    pub fn aead_algorithm(&self) -> Option<AeadAlgorithm> {
+        match self {
+            CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256
+            | CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256
+            | CipherSuite::TLS_AES_128_GCM_SHA256 => Some(AeadAlgorithm::Aes128Gcm),
+            CipherSuite::TLS_RSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384
+            | CipherSuite::TLS_AES_256_GCM_SHA384 => Some(AeadAlgorithm::Aes256Gcm),
+            CipherSuite::TLS_RSA_WITH_AES_128_CCM
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM
+            | CipherSuite::TLS_AES_128_CCM_SHA256 => Some(AeadAlgorithm::Aes128Ccm),
+            CipherSuite::TLS_RSA_WITH_AES_128_CCM_8
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_CCM_8
+            | CipherSuite::TLS_AES_128_CCM_8_SHA256 => Some(AeadAlgorithm::Aes128Ccm8),
+            CipherSuite::TLS_RSA_WITH_AES_256_CCM
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM => Some(AeadAlgorithm::Aes256Ccm),
+            CipherSuite::TLS_RSA_WITH_AES_256_CCM_8
+            | CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_CCM_8 => Some(AeadAlgorithm::Aes256Ccm8),
+            _ => None,
+        }
     }
 }
 
@@ -320,7 +540,7 @@ impl nom::ToUsize for u24 {
     }
 }
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone, Copy)]
 pub struct Alert {
     pub level: AlertLevel,
     pub description: AlertDescription,
@@ -329,17 +549,35 @@ pub struct Alert {
 impl Alert {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, level) = be_u8(input)?;
-        let level = AlertLevel::try_from(level).unwrap();
+        let Ok(level) = AlertLevel::try_from(level) else {
+            return invalid_value(input);
+        };
         let (input, description) = be_u8(input)?;
-        let description = AlertDescription::try_from(description).unwrap();
+        let Ok(description) = AlertDescription::try_from(description) else {
+            return invalid_value(input);
+        };
         Ok((input, Alert { level, description }))
     }
+
+    // 受信したレコードの中身。未知のレベルは illegal_parameter、それ以外の不正な形式は decode_error
+    pub fn from_bytes(data: &[u8]) -> anyhow::Result<Self> {
+        let [level, _] = data else {
+            anyhow::bail!(Error::Alert(AlertDescription::DecodeError))
+        };
+        if AlertLevel::try_from(*level).is_err() {
+            anyhow::bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        let Ok((_, alert)) = Alert::deserialize(Buffer::new(data, data.len())) else {
+            anyhow::bail!(Error::Alert(AlertDescription::DecodeError))
+        };
+        Ok(alert)
+    }
 }
 
 impl_enum_try_from! {
     #[allow(dead_code)]
     #[repr(u8)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum AlertLevel {
         Warning = 1,
         Fatal = 2,
@@ -352,7 +590,7 @@ impl_enum_try_from! {
 impl_enum_try_from! {
     #[allow(dead_code)]
     #[repr(u8)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum AlertDescription {
         CloseNotify = 0,
         UnexpectedMessage = 10,
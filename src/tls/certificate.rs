@@ -0,0 +1,392 @@
+use super::crypto::SigningKey;
+use super::{CipherSuite, ClientCertificateType, ProtocolVersion, SignatureScheme};
+
+use anyhow::{bail, Result};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::sync::Arc;
+use x509_parser::parse_x509_certificate;
+use x509_parser::pem::Pem;
+
+// 証明書チェーン (DER, 先頭がエンドエンティティ) と秘密鍵
+#[derive(Debug)]
+pub struct CertifiedKey {
+    pub certificates: Vec<Vec<u8>>,
+    pub key: SigningKey,
+}
+
+impl CertifiedKey {
+    // 秘密鍵がエンドエンティティ証明書の公開鍵と対応しているか確かめる
+    pub fn new(certificates: Vec<Vec<u8>>, key: SigningKey) -> Result<Self> {
+        let Some(certificate) = certificates.first() else {
+            bail!("no certificate found")
+        };
+        let (_, certificate) = parse_x509_certificate(certificate)?;
+        if !key.matches_public_key(certificate.public_key().raw) {
+            bail!("private key does not match the certificate");
+        }
+        Ok(CertifiedKey { certificates, key })
+    }
+
+    // certificates は CERTIFICATE を並べた PEM (先頭がエンドエンティティ)
+    // 鍵は PKCS#8, PKCS#1, SEC1 のいずれか
+    pub fn from_pem(certificates: &[u8], key: &[u8]) -> Result<Self> {
+        let mut chain = vec![];
+        for pem in Pem::iter_from_buffer(certificates) {
+            let pem = pem?;
+            if pem.label == "CERTIFICATE" {
+                chain.push(pem.contents);
+            }
+        }
+        CertifiedKey::new(chain, SigningKey::from_pem(key)?)
+    }
+
+    // certificates は DER の証明書 (先頭がエンドエンティティ)
+    pub fn from_der(certificates: Vec<Vec<u8>>, key: &[u8]) -> Result<Self> {
+        CertifiedKey::new(certificates, SigningKey::from_der(key)?)
+    }
+
+    // TLS 1.2 ではスイートが鍵交換と認証の方式を決めるので、鍵の種類と合うものしか使えない
+    pub fn supports_cipher_suite(&self, cipher_suite: CipherSuite) -> bool {
+        if cipher_suite.is_tls13() {
+            return true;
+        }
+        match self.key {
+            SigningKey::Rsa(_) => {
+                cipher_suite.is_rsa_key_exchange()
+                    || cipher_suite.is_ecdhe_key_exchange()
+                        && !cipher_suite.is_ecdsa_authentication()
+            }
+            SigningKey::Secp256r1(_) | SigningKey::Secp384r1(_) => {
+                cipher_suite.is_ecdsa_authentication()
+            }
+        }
+    }
+
+    // TLS 1.2 の CertificateRequest.certificate_types と照合する
+    pub fn certificate_type(&self) -> ClientCertificateType {
+        match self.key {
+            SigningKey::Rsa(_) => ClientCertificateType::rsa_sign,
+            SigningKey::Secp256r1(_) | SigningKey::Secp384r1(_) => {
+                ClientCertificateType::ecdsa_sign
+            }
+        }
+    }
+
+    // 鍵の優先順で、相手が対応している署名方式を選ぶ
+    // TLS 1.3 の CertificateVerify では PKCS#1 v1.5 は使えない
+    pub fn choose_scheme(
+        &self,
+        version: ProtocolVersion,
+        peer_schemes: &[SignatureScheme],
+    ) -> Option<SignatureScheme> {
+        self.key.schemes().into_iter().find(|scheme| {
+            peer_schemes.contains(scheme)
+                && (version != ProtocolVersion::TLSv1_3 || !is_pkcs1_scheme(*scheme))
+        })
+    }
+}
+
+pub fn is_pkcs1_scheme(scheme: SignatureScheme) -> bool {
+    matches!(
+        scheme,
+        SignatureScheme::rsa_pkcs1_sha256
+            | SignatureScheme::rsa_pkcs1_sha384
+            | SignatureScheme::rsa_pkcs1_sha512
+    )
+}
+
+// サーバーの CertificateRequest から取り出した条件
+#[derive(Debug)]
+pub struct ClientCertificateRequest {
+    pub version: ProtocolVersion,
+    // TLS 1.3 では空
+    pub certificate_types: Vec<ClientCertificateType>,
+    pub signature_schemes: Vec<SignatureScheme>,
+    // 受け入れる CA の DN (DER)。空ならサーバーは指定していない
+    pub certificate_authorities: Vec<Vec<u8>>,
+}
+
+// クライアント証明書を選ぶ
+// None を返すと空の Certificate を送り、認証するかはサーバーに任せる
+pub trait ResolvesClientCert: Debug + Send + Sync {
+    fn resolve(&self, request: &ClientCertificateRequest) -> Option<Arc<CertifiedKey>>;
+}
+
+// 常に同じ証明書を使う
+#[derive(Debug)]
+pub struct StaticClientCert {
+    certified_key: Arc<CertifiedKey>,
+}
+
+impl StaticClientCert {
+    pub fn new(certified_key: CertifiedKey) -> Self {
+        StaticClientCert {
+            certified_key: Arc::new(certified_key),
+        }
+    }
+}
+
+impl ResolvesClientCert for StaticClientCert {
+    fn resolve(&self, request: &ClientCertificateRequest) -> Option<Arc<CertifiedKey>> {
+        if request.version != ProtocolVersion::TLSv1_3
+            && !request
+                .certificate_types
+                .contains(&self.certified_key.certificate_type())
+        {
+            return None;
+        }
+        self.certified_key
+            .choose_scheme(request.version, &request.signature_schemes)
+            .map(|_| self.certified_key.clone())
+    }
+}
+
+// サーバー証明書の選択に使う ClientHello の内容
+#[derive(Debug)]
+pub struct ClientHelloInfo {
+    pub version: ProtocolVersion,
+    // server_name の host_name。送られていなければ None
+    pub server_name: Option<String>,
+    // application_layer_protocol_negotiation で提示されたプロトコル (優先度の高い順)
+    pub alpn_protocols: Vec<Vec<u8>>,
+    pub signature_schemes: Vec<SignatureScheme>,
+}
+
+// サーバー証明書を選ぶ
+// None を返すと server_name を認識できなかったとして unrecognized_name でハンドシェイクを中止する
+// 返した証明書の鍵で使える署名方式が無ければ handshake_failure になる
+pub trait ResolvesServerCert: Debug + Send + Sync {
+    fn resolve(&self, client_hello: &ClientHelloInfo) -> Option<Arc<CertifiedKey>>;
+}
+
+// 常に同じ証明書を使う
+#[derive(Debug)]
+pub struct StaticServerCert {
+    certified_key: Arc<CertifiedKey>,
+}
+
+impl StaticServerCert {
+    pub fn new(certified_key: CertifiedKey) -> Self {
+        StaticServerCert {
+            certified_key: Arc::new(certified_key),
+        }
+    }
+}
+
+impl ResolvesServerCert for StaticServerCert {
+    fn resolve(&self, _client_hello: &ClientHelloInfo) -> Option<Arc<CertifiedKey>> {
+        Some(self.certified_key.clone())
+    }
+}
+
+// server_name のホスト名で証明書を選ぶ
+// 完全一致、"*.example.test" のようなワイルドカード (左端の1ラベルのみ)、デフォルトの順に探す
+#[derive(Debug, Default)]
+pub struct SniServerCert {
+    certified_keys: HashMap<String, Arc<CertifiedKey>>,
+    default: Option<Arc<CertifiedKey>>,
+}
+
+impl SniServerCert {
+    pub fn new() -> Self {
+        SniServerCert::default()
+    }
+
+    pub fn add(&mut self, host_name: &str, certified_key: CertifiedKey) -> Result<()> {
+        let host_name = normalize_host_name(host_name);
+        let name = host_name.strip_prefix("*.").unwrap_or(&host_name);
+        if name.is_empty()
+            || name
+                .split('.')
+                .any(|label| label.is_empty() || label == "*")
+        {
+            bail!("invalid host name: {}", host_name);
+        }
+        self.certified_keys
+            .insert(host_name, Arc::new(certified_key));
+        Ok(())
+    }
+
+    // server_name が無い、または一致するものが無いときに使う
+    pub fn set_default(&mut self, certified_key: CertifiedKey) {
+        self.default = Some(Arc::new(certified_key));
+    }
+
+    fn lookup(&self, server_name: Option<&str>) -> Option<&Arc<CertifiedKey>> {
+        let Some(server_name) = server_name else {
+            return self.default.as_ref();
+        };
+        let server_name = normalize_host_name(server_name);
+        self.certified_keys
+            .get(&server_name)
+            .or_else(|| {
+                let (_, parent) = server_name.split_once('.')?;
+                self.certified_keys.get(&format!("*.{}", parent))
+            })
+            .or(self.default.as_ref())
+    }
+}
+
+impl ResolvesServerCert for SniServerCert {
+    fn resolve(&self, client_hello: &ClientHelloInfo) -> Option<Arc<CertifiedKey>> {
+        self.lookup(client_hello.server_name.as_deref()).cloned()
+    }
+}
+
+// ホスト名は大文字小文字を区別せず、末尾のドットは無視する
+pub fn normalize_host_name(host_name: &str) -> String {
+    host_name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::test_util::load;
+
+    fn pem_certificates(pem: &[u8]) -> Vec<Vec<u8>> {
+        Pem::iter_from_buffer(pem)
+            .map(|pem| pem.unwrap().contents)
+            .collect()
+    }
+
+    fn assert_mismatch(result: Result<CertifiedKey>) {
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "private key does not match the certificate"
+        );
+    }
+
+    #[test]
+    fn certified_key_from_pem() {
+        let chain = [load("verifier/server.pem"), load("verifier/inter.pem")].concat();
+        for key in ["verifier/leaf.key", "verifier/leaf_pkcs8.key"] {
+            let certified_key = CertifiedKey::from_pem(&chain, &load(key)).unwrap();
+            // 順序を保ち、先頭がエンドエンティティになる
+            assert_eq!(certified_key.certificates, pem_certificates(&chain));
+            assert_eq!(
+                certified_key.certificate_type(),
+                ClientCertificateType::ecdsa_sign
+            );
+        }
+
+        let certificate = load("rsa/server.pem");
+        for key in ["rsa/key2048.pem", "rsa/key2048_pkcs1.pem"] {
+            let certified_key = CertifiedKey::from_pem(&certificate, &load(key)).unwrap();
+            assert_eq!(certified_key.certificates.len(), 1);
+            assert_eq!(
+                certified_key.certificate_type(),
+                ClientCertificateType::rsa_sign
+            );
+        }
+
+        // CERTIFICATE 以外のブロックは無視する
+        let mixed = [load("rsa/pub2048.pem"), chain.clone()].concat();
+        let certified_key = CertifiedKey::from_pem(&mixed, &load("verifier/leaf.key")).unwrap();
+        assert_eq!(certified_key.certificates, pem_certificates(&chain));
+    }
+
+    #[test]
+    fn certified_key_from_der() {
+        let chain =
+            pem_certificates(&[load("verifier/server.pem"), load("verifier/inter.pem")].concat());
+        for key in ["verifier/leaf.der", "verifier/leaf_pkcs8.der"] {
+            let certified_key = CertifiedKey::from_der(chain.clone(), &load(key)).unwrap();
+            assert_eq!(certified_key.certificates, chain);
+        }
+        for key in ["rsa/key2048.der", "rsa/key2048_pkcs1.der"] {
+            assert!(CertifiedKey::from_der(vec![load("rsa/server.der")], &load(key)).is_ok());
+        }
+    }
+
+    #[test]
+    fn certified_key_mismatch() {
+        // 鍵はエンドエンティティ証明書と照合する
+        let chain = [load("verifier/server.pem"), load("verifier/inter.pem")].concat();
+        assert_mismatch(CertifiedKey::from_pem(&chain, &load("rsa/key2048.pem")));
+        let chain = [load("verifier/inter.pem"), load("verifier/server.pem")].concat();
+        assert_mismatch(CertifiedKey::from_pem(&chain, &load("verifier/leaf.key")));
+        assert_mismatch(CertifiedKey::from_der(
+            vec![load("rsa/server.der")],
+            &load("verifier/leaf.der"),
+        ));
+        assert_mismatch(CertifiedKey::from_pem(
+            &load("rsa/server.pem"),
+            &load("rsa/key1025.pem"),
+        ));
+
+        assert_eq!(
+            CertifiedKey::from_pem(b"", &load("verifier/leaf.key"))
+                .unwrap_err()
+                .to_string(),
+            "no certificate found"
+        );
+    }
+
+    // 証明書の代わりに名前を入れておき、どれが選ばれたかを見る
+    fn named_key(name: &str) -> CertifiedKey {
+        CertifiedKey {
+            certificates: vec![name.as_bytes().to_vec()],
+            key: SigningKey::from_pem(&load("verifier/leaf.key")).unwrap(),
+        }
+    }
+
+    fn lookup<'a>(sni: &'a SniServerCert, server_name: Option<&str>) -> Option<&'a [u8]> {
+        sni.lookup(server_name)
+            .map(|certified_key| certified_key.certificates[0].as_slice())
+    }
+
+    #[test]
+    fn sni_lookup() {
+        let mut sni = SniServerCert::new();
+        sni.add("example.test", named_key("exact")).unwrap();
+        sni.add("*.example.test", named_key("wildcard")).unwrap();
+        sni.add("Www.Example.Test.", named_key("www")).unwrap();
+
+        assert_eq!(lookup(&sni, Some("example.test")), Some(&b"exact"[..]));
+        assert_eq!(lookup(&sni, Some("EXAMPLE.test.")), Some(&b"exact"[..]));
+        assert_eq!(lookup(&sni, Some("a.example.test")), Some(&b"wildcard"[..]));
+        assert_eq!(
+            lookup(&sni, Some("A.Example.Test.")),
+            Some(&b"wildcard"[..])
+        );
+        // 完全一致がワイルドカードより優先される
+        assert_eq!(lookup(&sni, Some("www.example.test")), Some(&b"www"[..]));
+        assert_eq!(lookup(&sni, Some("other.test")), None);
+        assert_eq!(lookup(&sni, None), None);
+    }
+
+    #[test]
+    fn sni_wildcard_does_not_match_parent() {
+        let mut sni = SniServerCert::new();
+        sni.add("*.example.test", named_key("wildcard")).unwrap();
+
+        // ワイルドカードは左端の1ラベルにだけ一致する
+        assert_eq!(lookup(&sni, Some("example.test")), None);
+        assert_eq!(lookup(&sni, Some("a.b.example.test")), None);
+    }
+
+    #[test]
+    fn sni_default() {
+        let mut sni = SniServerCert::new();
+        sni.add("example.test", named_key("exact")).unwrap();
+        sni.set_default(named_key("default"));
+
+        assert_eq!(lookup(&sni, Some("example.test")), Some(&b"exact"[..]));
+        assert_eq!(lookup(&sni, Some("other.test")), Some(&b"default"[..]));
+        assert_eq!(lookup(&sni, Some("a.example.test")), Some(&b"default"[..]));
+        assert_eq!(lookup(&sni, None), Some(&b"default"[..]));
+    }
+
+    #[test]
+    fn sni_invalid_host_name() {
+        let mut sni = SniServerCert::new();
+        for host_name in ["", ".", "*.", "*", "a..test", "*.*.test", "a.*.test"] {
+            assert!(
+                sni.add(host_name, named_key("invalid")).is_err(),
+                "{}",
+                host_name
+            );
+        }
+    }
+}
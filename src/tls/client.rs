@@ -0,0 +1,2113 @@
+use super::crypto::{verify_signature, EphemeralSecret, RsaPublicKey};
+use super::{
+    certificate_verify_message, constant_time_eq, finished_verify_data, master_secret, psk_binder,
+    resumption_psk, traffic_protection, verify_data, AlertDescription, ApplicationMessage,
+    Certificate, CertificateRequest, CertificateVerify, CertifiedKey, CipherSuite, CipherSuites,
+    ClientCertificateRequest, ClientHello, ClientKeyExchange, ClientSessionStore,
+    CompressionMethod, CompressionMethods, ConnectionCore, Error, Extension, ExtensionData,
+    ExtensionType, Extensions, Finished, Handshake, HandshakeBody, HandshakeType, KeyBlock,
+    KeySchedule, KeyShareEntries, KeyShareEntry, Message, NamedGroup, NamedGroupList,
+    NewSessionTicket, OfferedPsks, Opaque, PreMasterSecret, ProtocolVersion, ProtocolVersionList,
+    PskBinderEntries, PskBinderEntry, PskIdentities, PskIdentity, PskKeyExchangeMode,
+    PskKeyExchangeModeList, Random, RecordProtection, ResolvesClientCert, RootCertStore,
+    ServerHello, ServerKeyExchange, ServerName, ServerNameList, SignatureScheme,
+    SignatureSchemeList, Tls12Session, Tls13Session, Transcript,
+};
+
+use anyhow::{bail, Result};
+use rand::RngCore;
+use ser::NetworkEndian;
+use std::io::{Read, Write};
+use std::net::IpAddr;
+use std::sync::Arc;
+use x509_parser::parse_x509_certificate;
+
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    // 優先度の高い順
+    pub versions: Vec<ProtocolVersion>,
+    pub cipher_suites: Vec<CipherSuite>,
+    // 先頭のグループで key_share を送る
+    pub groups: Vec<NamedGroup>,
+    pub signature_schemes: Vec<SignatureScheme>,
+    // サーバー証明書の検証に使う CA。空ならどの証明書も受け付けない
+    pub roots: RootCertStore,
+    // None ならセッション再開をしない
+    pub session_store: Option<Arc<ClientSessionStore>>,
+    // バージョンを下げて再接続するときに TLS_FALLBACK_SCSV を送る
+    pub send_fallback_scsv: bool,
+    // TLS 1.3 のハンドシェイクを TLS 1.2 のセッション再開に見せる (RFC 8446 D.4)
+    pub middlebox_compatibility: bool,
+    // サーバーから CertificateRequest が来たときに送る証明書を選ぶ
+    // TLS 1.3 では Some なら post_handshake_auth を送る
+    pub client_cert_resolver: Option<Arc<dyn ResolvesClientCert>>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> Self {
+        ClientConfig {
+            versions: vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+            cipher_suites: vec![
+                CipherSuite::TLS_AES_128_GCM_SHA256,
+                CipherSuite::TLS_AES_256_GCM_SHA384,
+                CipherSuite::TLS_AES_128_CCM_SHA256,
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                // 前方秘匿性が無いので最後に提示する
+                CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256,
+                CipherSuite::TLS_RSA_WITH_AES_256_GCM_SHA384,
+                CipherSuite::TLS_RSA_WITH_AES_128_CCM,
+                CipherSuite::TLS_RSA_WITH_AES_256_CCM,
+            ],
+            groups: vec![
+                NamedGroup::x25519,
+                NamedGroup::secp256r1,
+                NamedGroup::secp384r1,
+            ],
+            signature_schemes: vec![
+                SignatureScheme::ecdsa_secp256r1_sha256,
+                SignatureScheme::ecdsa_secp384r1_sha384,
+                SignatureScheme::rsa_pss_rsae_sha256,
+                SignatureScheme::rsa_pss_rsae_sha384,
+                SignatureScheme::rsa_pss_rsae_sha512,
+                SignatureScheme::rsa_pkcs1_sha256,
+                SignatureScheme::rsa_pkcs1_sha384,
+                SignatureScheme::rsa_pkcs1_sha512,
+            ],
+            roots: RootCertStore::new(),
+            session_store: Some(Arc::new(ClientSessionStore::new())),
+            send_fallback_scsv: false,
+            middlebox_compatibility: true,
+            client_cert_resolver: None,
+        }
+    }
+}
+
+impl ClientConfig {
+    // ハンドシェイクに失敗したサーバーへ、最も優先度の高いバージョンを外して再接続するための設定
+    // サーバーがより高いバージョンに対応していれば inappropriate_fallback で拒否される (RFC 7507)
+    pub fn fallback(&self) -> Option<ClientConfig> {
+        if self.versions.len() < 2 {
+            return None;
+        }
+        let mut config = self.clone();
+        config.versions.remove(0);
+        config.send_fallback_scsv = true;
+        Some(config)
+    }
+
+    fn offers(&self, version: ProtocolVersion) -> bool {
+        self.versions.contains(&version)
+    }
+
+    // 設定されていても実装の無いスイートは送らない
+    fn offered_cipher_suites(&self) -> Vec<CipherSuite> {
+        self.cipher_suites
+            .iter()
+            .filter(|suite| suite.aead_algorithm().is_some())
+            .filter(|suite| {
+                if suite.is_tls13() {
+                    self.offers(ProtocolVersion::TLSv1_3)
+                } else {
+                    self.offers(ProtocolVersion::TLSv1_2)
+                        && (suite.is_rsa_key_exchange()
+                            || suite.is_ecdhe_key_exchange() && !self.groups.is_empty())
+                }
+            })
+            .copied()
+            .collect()
+    }
+}
+
+// HelloRetryRequest 後の ClientHello2 は random を変えずに作り直す
+struct HelloState {
+    random: Random,
+    // TLS 1.2 で再開するセッションの ID か、ミドルボックス互換モードでは 32 bytes のランダムな値
+    session_id: Vec<u8>,
+    key_share: Option<EphemeralSecret>,
+    cookie: Option<Vec<u8>>,
+    // pre_shared_key で送るチケット
+    session: Option<Tls13Session>,
+    // early_data 拡張を付けて 0-RTT を送るか
+    early_data: bool,
+    // session_id で再開を提示する TLS 1.2 のセッション
+    tls12_session: Option<Tls12Session>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EarlyDataStatus {
+    // クライアントに 0-RTT に使えるチケットが無かった
+    NotSent,
+    Accepted,
+    // 拒否されたので、クライアントはハンドシェイク後に送り直した
+    Rejected,
+}
+
+pub struct ClientConnection<S> {
+    config: Arc<ClientConfig>,
+    core: ConnectionCore<S>,
+    peer_certificates: Vec<Vec<u8>>,
+    server_name: String,
+    resumed: bool,
+    resumption_master_secret: Vec<u8>,
+    early_data_status: EarlyDataStatus,
+    change_cipher_spec_sent: bool,
+    // クライアントの Finished までのハンドシェイク (TLS 1.3 の post-handshake 認証で使う)
+    transcript: Transcript,
+}
+
+impl<S: Read + Write> ClientConnection<S> {
+    // ハンドシェイクが完了した状態で返す
+    pub fn new(config: Arc<ClientConfig>, server_name: &str, stream: S) -> Result<Self> {
+        Self::connect(config, server_name, stream, &[])
+    }
+
+    // early_data を 0-RTT で送りながらハンドシェイクする
+    // 0-RTT のデータは攻撃者にリプレイされうるので、何度処理されても問題の無い冪等なリクエストだけを渡すこと
+    // 0-RTT で送れなかった分や拒否された分はハンドシェイク後に通常のデータとして送る
+    pub fn new_with_replayable_early_data(
+        config: Arc<ClientConfig>,
+        server_name: &str,
+        stream: S,
+        early_data: &[u8],
+    ) -> Result<Self> {
+        Self::connect(config, server_name, stream, early_data)
+    }
+
+    fn connect(
+        config: Arc<ClientConfig>,
+        server_name: &str,
+        stream: S,
+        early_data: &[u8],
+    ) -> Result<Self> {
+        let mut connection = ClientConnection {
+            config,
+            core: ConnectionCore::new(stream),
+            peer_certificates: vec![],
+            server_name: server_name.to_string(),
+            resumed: false,
+            resumption_master_secret: vec![],
+            early_data_status: EarlyDataStatus::NotSent,
+            change_cipher_spec_sent: false,
+            transcript: Transcript::new(),
+        };
+
+        if let Err(e) = connection.handshake(server_name, early_data) {
+            connection.core.abort(&e);
+            return Err(e);
+        }
+
+        Ok(connection)
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.core.version
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.core.cipher_suite
+    }
+
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    // PSK によるセッション再開でハンドシェイクしたか
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    pub fn early_data_status(&self) -> EarlyDataStatus {
+        self.early_data_status
+    }
+
+    // TLS 1.3 では鍵ごとのレコード数の上限に達する前に自動で KeyUpdate する
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.core.write(data)
+    }
+
+    // RFC 8446 4.6.3. 送信鍵を更新する。request_peer なら相手にも受信鍵の更新を要求する
+    pub fn update_keys(&mut self, request_peer: bool) -> Result<()> {
+        self.core.update_keys(request_peer)
+    }
+
+    // close_notify を受け取ったら None
+    pub fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        let result = self.read_application_data();
+        if let Err(e) = &result {
+            self.core.abort(e);
+        }
+        result
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.core.close()
+    }
+
+    fn read_application_data(&mut self) -> Result<Option<Vec<u8>>> {
+        loop {
+            match self.core.read_application_message()? {
+                ApplicationMessage::Data(data) => return Ok(Some(data)),
+                ApplicationMessage::CloseNotify => return Ok(None),
+                ApplicationMessage::Handshake(data) => {
+                    self.process_post_handshake_message(&data)?
+                }
+            }
+        }
+    }
+
+    fn process_post_handshake_message(&mut self, data: &[u8]) -> Result<()> {
+        match HandshakeType::try_from(data[0]) {
+            Ok(HandshakeType::NewSessionTicket) => {
+                let Ok(Handshake {
+                    body: HandshakeBody::NewSessionTicket(ticket),
+                    ..
+                }) = Handshake::from_bytes(data, self.core.version)
+                else {
+                    bail!(Error::Alert(AlertDescription::DecodeError))
+                };
+                self.store_ticket(ticket);
+                Ok(())
+            }
+            Ok(HandshakeType::CertificateRequest) => {
+                let Ok(Handshake {
+                    body: HandshakeBody::CertificateRequest(certificate_request),
+                    ..
+                }) = Handshake::from_bytes(data, self.core.version)
+                else {
+                    bail!(Error::Alert(AlertDescription::DecodeError))
+                };
+                self.process_certificate_request(data, certificate_request)
+            }
+            _ => bail!(Error::Alert(AlertDescription::UnexpectedMessage)),
+        }
+    }
+
+    // RFC 8446 4.6.2. post_handshake_auth を送っていなければ CertificateRequest は受け付けない
+    // 応答は application traffic secret で暗号化し、Transcript はクライアントの Finished までのものに続ける
+    fn process_certificate_request(
+        &mut self,
+        data: &[u8],
+        certificate_request: CertificateRequest,
+    ) -> Result<()> {
+        if self.config.client_cert_resolver.is_none() {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage));
+        }
+        // 4.3.2. ハンドシェイク後の要求は、応答と対応付けるための空でない context を持つ
+        let context = certificate_request
+            .certificate_request_context
+            .as_ref()
+            .map(|context| context.data.clone())
+            .unwrap_or_default();
+        if context.is_empty() {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let client_certificate = self.resolve_client_certificate(&certificate_request)?;
+
+        let mut transcript = self.transcript.clone();
+        transcript.update(data);
+        self.send_client_certificate_tls13(&mut transcript, &context, client_certificate)?;
+
+        let verify_data = finished_verify_data(
+            hash,
+            &self.core.write_traffic_secret,
+            &transcript.hash(hash),
+        );
+        self.core.send_handshake(
+            &mut transcript,
+            HandshakeBody::Finished(Finished { verify_data }),
+        )
+    }
+
+    // TLS 1.3 の CertificateRequest への応答のうち Finished より前の部分
+    // 証明書が無ければ空の Certificate だけを送る
+    fn send_client_certificate_tls13(
+        &mut self,
+        transcript: &mut Transcript,
+        context: &[u8],
+        client_certificate: Option<(Arc<CertifiedKey>, SignatureScheme)>,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let certificates = client_certificate
+            .as_ref()
+            .map(|(certified_key, _)| certified_key.certificates.clone())
+            .unwrap_or_default();
+        self.core.send_handshake(
+            transcript,
+            HandshakeBody::Certificate(Certificate::new(self.core.version, context, &certificates)),
+        )?;
+
+        if let Some((certified_key, scheme)) = client_certificate {
+            let message = certificate_verify_message(
+                b"TLS 1.3, client CertificateVerify",
+                &transcript.hash(hash),
+            );
+            let signature = certified_key.key.sign(scheme, &message)?;
+            self.core.send_handshake(
+                transcript,
+                HandshakeBody::CertificateVerify(CertificateVerify {
+                    algorithm: scheme,
+                    signature: Opaque::<u16>::new(signature),
+                }),
+            )?;
+        }
+        Ok(())
+    }
+
+    // 送る証明書と CertificateVerify の署名方式。None なら空の Certificate を送る
+    fn resolve_client_certificate(
+        &self,
+        certificate_request: &CertificateRequest,
+    ) -> Result<Option<(Arc<CertifiedKey>, SignatureScheme)>> {
+        let request = if self.core.version == ProtocolVersion::TLSv1_3 {
+            let Some(extensions) = &certificate_request.extensions else {
+                bail!(Error::Alert(AlertDescription::DecodeError))
+            };
+            let Some(ExtensionData::SignatureAlgorithms(signature_schemes)) =
+                extensions.get(ExtensionType::SignatureAlgorithms)
+            else {
+                bail!(Error::Alert(AlertDescription::MissingExtension))
+            };
+            let certificate_authorities =
+                match extensions.get(ExtensionType::CertificateAuthorities) {
+                    Some(ExtensionData::CertificateAuthorities(names)) => {
+                        names.data.iter().map(|name| name.data.clone()).collect()
+                    }
+                    _ => vec![],
+                };
+            ClientCertificateRequest {
+                version: self.core.version,
+                certificate_types: vec![],
+                signature_schemes: signature_schemes.data.clone(),
+                certificate_authorities,
+            }
+        } else {
+            let (Some(certificate_types), Some(signature_schemes), Some(certificate_authorities)) = (
+                &certificate_request.certificate_types,
+                &certificate_request.supported_signature_algorithms,
+                &certificate_request.certificate_authorities,
+            ) else {
+                bail!(Error::Alert(AlertDescription::DecodeError))
+            };
+            ClientCertificateRequest {
+                version: self.core.version,
+                certificate_types: certificate_types.data.clone(),
+                signature_schemes: signature_schemes.data.clone(),
+                certificate_authorities: certificate_authorities
+                    .data
+                    .iter()
+                    .map(|name| name.data.clone())
+                    .collect(),
+            }
+        };
+
+        let Some(resolver) = &self.config.client_cert_resolver else {
+            return Ok(None);
+        };
+        let Some(certified_key) = resolver.resolve(&request) else {
+            return Ok(None);
+        };
+        if certified_key.certificates.is_empty() {
+            return Ok(None);
+        }
+        let Some(scheme) =
+            certified_key.choose_scheme(self.core.version, &request.signature_schemes)
+        else {
+            bail!(Error::Alert(AlertDescription::HandshakeFailure))
+        };
+        Ok(Some((certified_key, scheme)))
+    }
+
+    fn store_ticket(&self, ticket: NewSessionTicket) {
+        let Some(session_store) = &self.config.session_store else {
+            return;
+        };
+        let NewSessionTicket {
+            ticket_lifetime,
+            ticket_age_add: Some(ticket_age_add),
+            ticket_nonce: Some(ticket_nonce),
+            ticket,
+            extensions: Some(extensions),
+        } = ticket
+        else {
+            return;
+        };
+        let max_early_data_size = match extensions.get(ExtensionType::EarlyData) {
+            Some(ExtensionData::MaxEarlyDataSize(size)) => *size,
+            _ => 0,
+        };
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let psk = resumption_psk(hash, &self.resumption_master_secret, &ticket_nonce.data);
+        let session = Tls13Session::new(
+            self.core.cipher_suite,
+            ticket.data,
+            psk,
+            ticket_age_add,
+            ticket_lifetime,
+            max_early_data_size,
+            self.peer_certificates.clone(),
+        );
+        if let Some(session) = session {
+            session_store.insert(&self.server_name, session);
+        }
+    }
+
+    // pre_shared_key を含む場合は binder を埋めてから送る
+    fn send_client_hello(
+        &mut self,
+        transcript: &mut Transcript,
+        server_name: &str,
+        hello: &HelloState,
+    ) -> Result<()> {
+        let client_hello = self.client_hello(server_name, hello);
+        let binders_size = match client_hello.extensions.get(ExtensionType::PreSharedKey) {
+            Some(ExtensionData::PreSharedKeyClientHello(offered_psks)) => {
+                offered_psks.binders_size()
+            }
+            _ => 0,
+        };
+        let mut data =
+            Handshake::new(HandshakeBody::ClientHello(client_hello)).to_bytes::<NetworkEndian>();
+
+        if let Some(session) = &hello.session {
+            // binders を除いた部分までの Transcript-Hash を使う
+            let hash = session.cipher_suite.hash_algorithm();
+            let mut partial_transcript = transcript.clone();
+            partial_transcript.update(&data[..data.len() - binders_size]);
+            let binder = psk_binder(hash, &session.psk, &partial_transcript.hash(hash));
+
+            // 送る PSK は1つだけなので末尾がそのまま binder になる
+            let offset = data.len() - binder.len();
+            data[offset..].copy_from_slice(&binder);
+        }
+
+        transcript.update(&data);
+        self.core.record_layer.write_handshake(&data)
+    }
+
+    // 0-RTT: ClientHello に続けて client_early_traffic_secret で送る
+    // max_early_data_size を超える分は送らず、送った長さを返す
+    fn send_early_data(
+        &mut self,
+        transcript: &Transcript,
+        session: &Tls13Session,
+        early_data: &[u8],
+    ) -> Result<usize> {
+        let hash = session.cipher_suite.hash_algorithm();
+        let aead = session.cipher_suite.aead_algorithm().unwrap();
+        let key_schedule = KeySchedule::new(hash, Some(&session.psk));
+        let client_early_traffic_secret =
+            key_schedule.derive_secret("c e traffic", &transcript.hash(hash));
+        self.core
+            .record_layer
+            .set_write_protection(traffic_protection(hash, aead, &client_early_traffic_secret));
+
+        let size = early_data.len().min(session.max_early_data_size as usize);
+        self.core
+            .record_layer
+            .write_application_data(&early_data[..size])?;
+        Ok(size)
+    }
+
+    // ミドルボックス互換モードでは 2回目のフライトの前にダミーの ChangeCipherSpec を1回だけ送る
+    fn send_compatibility_change_cipher_spec(&mut self) -> Result<()> {
+        if !self.config.middlebox_compatibility || self.change_cipher_spec_sent {
+            return Ok(());
+        }
+        self.change_cipher_spec_sent = true;
+        self.core.record_layer.write_change_cipher_spec()
+    }
+
+    fn read_server_hello(&mut self) -> Result<(Vec<u8>, ServerHello)> {
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::ServerHello(server_hello) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        Ok((data, server_hello))
+    }
+
+    fn handshake(&mut self, server_name: &str, early_data: &[u8]) -> Result<()> {
+        let config = self.config.clone();
+        let mut transcript = Transcript::new();
+
+        let key_share = match (
+            config.offers(ProtocolVersion::TLSv1_3),
+            config.groups.first(),
+        ) {
+            (true, Some(group)) => Some(EphemeralSecret::generate(*group)?),
+            _ => None,
+        };
+        let session = match (&key_share, &config.session_store) {
+            (Some(_), Some(session_store)) => session_store.take(server_name).filter(|session| {
+                config
+                    .offered_cipher_suites()
+                    .contains(&session.cipher_suite)
+            }),
+            _ => None,
+        };
+        let send_early_data = !early_data.is_empty()
+            && session
+                .as_ref()
+                .is_some_and(|session| session.max_early_data_size > 0);
+        let tls12_session = match &config.session_store {
+            Some(session_store) if config.offers(ProtocolVersion::TLSv1_2) => {
+                session_store.tls12_session(server_name).filter(|session| {
+                    config
+                        .offered_cipher_suites()
+                        .contains(&session.cipher_suite)
+                })
+            }
+            _ => None,
+        };
+        // RFC 8446 4.1.2. TLS 1.3 も提示する場合でも、TLS 1.2 のセッション ID があればそれを送る
+        // チケットを送る場合はランダムな値にし、サーバーが同じ値を返せば再開 (RFC 5077 3.4)
+        let session_id = if let Some(session) = &tls12_session {
+            if session.ticket.is_empty() {
+                session.session_id.clone()
+            } else {
+                rand::random::<[u8; 32]>().to_vec()
+            }
+        } else if config.middlebox_compatibility && key_share.is_some() {
+            rand::random::<[u8; 32]>().to_vec()
+        } else {
+            vec![]
+        };
+        let mut hello = HelloState {
+            random: Random::generate(),
+            session_id,
+            key_share,
+            cookie: None,
+            session,
+            early_data: send_early_data,
+            tls12_session,
+        };
+        self.send_client_hello(&mut transcript, server_name, &hello)?;
+
+        let mut early_data_size = 0;
+        if let (true, Some(session)) = (hello.early_data, &hello.session) {
+            // 0-RTT を送る場合は ClientHello の直後
+            self.send_compatibility_change_cipher_spec()?;
+            early_data_size = self.send_early_data(&transcript, session, early_data)?;
+        }
+
+        let (mut data, mut server_hello) = self.read_server_hello()?;
+        let mut retry_cipher_suite = None;
+        if server_hello.random.is_hello_retry_request() {
+            self.process_hello_retry_request(&mut transcript, &mut hello, &server_hello)?;
+            transcript.update(&data);
+            // ClientHello2 には early_data を付けられないので 0-RTT は拒否扱い
+            if hello.early_data {
+                hello.early_data = false;
+                self.core.record_layer.clear_write_protection();
+                self.early_data_status = EarlyDataStatus::Rejected;
+            }
+            retry_cipher_suite = Some(server_hello.cipher_suite);
+            // HelloRetryRequest は TLS 1.3 でしか送られない
+            self.core.version = ProtocolVersion::TLSv1_3;
+
+            self.send_compatibility_change_cipher_spec()?;
+            self.send_client_hello(&mut transcript, server_name, &hello)?;
+
+            (data, server_hello) = self.read_server_hello()?;
+            // HelloRetryRequest は1回まで
+            if server_hello.random.is_hello_retry_request() {
+                bail!(Error::Alert(AlertDescription::UnexpectedMessage));
+            }
+        }
+        transcript.update(&data);
+
+        // TLS 1.3 は supported_versions でのみネゴシエーションされる
+        let version = match server_hello
+            .extensions
+            .get(ExtensionType::SupportedVersions)
+        {
+            Some(ExtensionData::SelectedVersion(ProtocolVersion::TLSv1_3))
+                if hello.key_share.is_some() =>
+            {
+                ProtocolVersion::TLSv1_3
+            }
+            Some(_) => bail!(Error::Alert(AlertDescription::IllegalParameter)),
+            // RFC 8446 4.2.10. 0-RTT を送った後に TLS 1.2 以下が選ばれたら失敗させる
+            None if hello.early_data => {
+                self.core.record_layer.clear_write_protection();
+                bail!(Error::Alert(AlertDescription::ProtocolVersion))
+            }
+            None if server_hello.protocol_version == ProtocolVersion::TLSv1_2
+                && config.offers(ProtocolVersion::TLSv1_2)
+                && retry_cipher_suite.is_none() =>
+            {
+                ProtocolVersion::TLSv1_2
+            }
+            None => bail!(Error::Alert(AlertDescription::ProtocolVersion)),
+        };
+
+        if version == ProtocolVersion::TLSv1_3 && server_hello.session_id.data != hello.session_id {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+
+        // TLS 1.3 を提示したのに TLS 1.2 以下になった場合、サーバーが downgrade を示していたら攻撃とみなす
+        if version != ProtocolVersion::TLSv1_3
+            && config.offers(ProtocolVersion::TLSv1_3)
+            && server_hello.random.is_downgrade()
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+
+        let cipher_suite = server_hello.cipher_suite;
+        if !config.offered_cipher_suites().contains(&cipher_suite)
+            || cipher_suite.is_tls13() != (version == ProtocolVersion::TLSv1_3)
+            || retry_cipher_suite.is_some_and(|suite| suite != cipher_suite)
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+
+        // サーバーが PSK を受け入れたら再開
+        let session = match server_hello.extensions.get(ExtensionType::PreSharedKey) {
+            None => None,
+            Some(_) if hello.session.is_none() => {
+                bail!(Error::Alert(AlertDescription::UnsupportedExtension))
+            }
+            Some(ExtensionData::PreSharedKeyServerHello(0))
+                if hello.session.as_ref().is_some_and(|session| {
+                    session.cipher_suite.hash_algorithm() == cipher_suite.hash_algorithm()
+                }) =>
+            {
+                hello.session.take()
+            }
+            Some(_) => bail!(Error::Alert(AlertDescription::IllegalParameter)),
+        };
+
+        // TLS 1.2 でサーバーが同じセッション ID を返したら短縮ハンドシェイク
+        let tls12_session = hello.tls12_session.filter(|_| {
+            version == ProtocolVersion::TLSv1_2 && server_hello.session_id.data == hello.session_id
+        });
+        if server_hello
+            .extensions
+            .contains(ExtensionType::SessionTicket)
+            && (version != ProtocolVersion::TLSv1_2 || config.session_store.is_none())
+        {
+            bail!(Error::Alert(AlertDescription::UnsupportedExtension));
+        }
+        if tls12_session
+            .as_ref()
+            .is_some_and(|session| session.cipher_suite != cipher_suite)
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+
+        self.core.version = version;
+        self.core.cipher_suite = cipher_suite;
+
+        match (version, hello.key_share, tls12_session) {
+            (ProtocolVersion::TLSv1_3, Some(key_share), _) => self.handshake_tls13(
+                transcript,
+                key_share,
+                server_hello,
+                session,
+                hello.early_data,
+            )?,
+            (_, _, Some(session)) => {
+                self.resume_tls12(transcript, hello.random, server_hello, session)?
+            }
+            _ => self.handshake_tls12(transcript, hello.random, server_hello)?,
+        }
+
+        // 0-RTT で届かなかった分は通常のデータとして送る
+        let sent = match self.early_data_status {
+            EarlyDataStatus::Accepted => early_data_size,
+            _ => 0,
+        };
+        if sent < early_data.len() {
+            self.write(&early_data[sent..])?;
+        }
+
+        Ok(())
+    }
+
+    // RFC 8446 4.1.4. 指定されたグループで key_share を作り直し、cookie があれば返す
+    fn process_hello_retry_request(
+        &self,
+        transcript: &mut Transcript,
+        hello: &mut HelloState,
+        retry_request: &ServerHello,
+    ) -> Result<()> {
+        let config = &self.config;
+
+        let Some(key_share) = &hello.key_share else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let Some(ExtensionData::SelectedVersion(ProtocolVersion::TLSv1_3)) = retry_request
+            .extensions
+            .get(ExtensionType::SupportedVersions)
+        else {
+            bail!(Error::Alert(AlertDescription::IllegalParameter))
+        };
+        if retry_request.session_id.data != hello.session_id {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        let cipher_suite = retry_request.cipher_suite;
+        if !config.offered_cipher_suites().contains(&cipher_suite) || !cipher_suite.is_tls13() {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+
+        let group = match retry_request.extensions.get(ExtensionType::KeyShare) {
+            Some(ExtensionData::KeyShareHelloRetryRequest(group)) => Some(*group),
+            None => None,
+            Some(_) => bail!(Error::Alert(AlertDescription::DecodeError)),
+        };
+        let cookie = match retry_request.extensions.get(ExtensionType::Cookie) {
+            Some(ExtensionData::Cookie(cookie)) => Some(cookie.data.clone()),
+            _ => None,
+        };
+
+        // ClientHello に変化を起こさない HelloRetryRequest は不正
+        if group.is_none() && cookie.is_none() {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        if let Some(group) = group {
+            if group == key_share.group()
+                || !config.groups.contains(&group)
+                || !EphemeralSecret::is_supported(group)
+            {
+                bail!(Error::Alert(AlertDescription::IllegalParameter));
+            }
+            hello.key_share = Some(EphemeralSecret::generate(group)?);
+        }
+        hello.cookie = cookie;
+        // ハッシュが異なる PSK は ClientHello2 では送らない
+        if hello.session.as_ref().is_some_and(|session| {
+            session.cipher_suite.hash_algorithm() != cipher_suite.hash_algorithm()
+        }) {
+            hello.session = None;
+        }
+
+        transcript.replace_with_message_hash(cipher_suite.hash_algorithm());
+        Ok(())
+    }
+
+    fn client_hello(&self, server_name: &str, hello: &HelloState) -> ClientHello {
+        let config = &self.config;
+
+        let mut extensions = vec![];
+        // IP アドレスは SNI に入れられない
+        if server_name.parse::<IpAddr>().is_err() {
+            extensions.push(Extension::new(ExtensionData::ServerName(
+                ServerNameList::new(vec![ServerName::new(server_name)]),
+            )));
+        }
+        extensions.push(Extension::new(ExtensionData::SupportedGroups(
+            NamedGroupList::new(config.groups.clone()),
+        )));
+        extensions.push(Extension::new(ExtensionData::SignatureAlgorithms(
+            SignatureSchemeList::new(config.signature_schemes.clone()),
+        )));
+        if config.session_store.is_some() && config.offers(ProtocolVersion::TLSv1_2) {
+            let ticket = hello
+                .tls12_session
+                .as_ref()
+                .map(|session| session.ticket.clone())
+                .unwrap_or_default();
+            extensions.push(Extension::new(ExtensionData::SessionTicket(ticket)));
+        }
+        if let Some(key_share) = &hello.key_share {
+            extensions.push(Extension::new(ExtensionData::SupportedVersions(
+                ProtocolVersionList::new(config.versions.clone()),
+            )));
+            if let Some(cookie) = &hello.cookie {
+                extensions.push(Extension::new(ExtensionData::Cookie(Opaque::<u16>::new(
+                    cookie.clone(),
+                ))));
+            }
+            extensions.push(Extension::new(ExtensionData::KeyShareClientHello(
+                KeyShareEntries::new(vec![KeyShareEntry {
+                    group: key_share.group(),
+                    key_exchange: Opaque::<u16>::new(key_share.public_key()),
+                }]),
+            )));
+        }
+        if hello.early_data {
+            extensions.push(Extension::new(ExtensionData::EarlyData(())));
+        }
+        if hello.key_share.is_some() && config.client_cert_resolver.is_some() {
+            extensions.push(Extension::new(ExtensionData::PostHandshakeAuth(())));
+        }
+        // チケットを受け取るためには、再開しない場合も psk_key_exchange_modes を送る (RFC 8446 4.2.9)
+        if hello.key_share.is_some() && config.session_store.is_some() {
+            extensions.push(Extension::new(ExtensionData::PskKeyExchangeModes(
+                PskKeyExchangeModeList::new(vec![PskKeyExchangeMode::psk_dhe_ke]),
+            )));
+        }
+        if let Some(session) = &hello.session {
+            // binder は送信前に計算するので、ここでは同じ長さの 0 で埋めておく
+            let binder_length = session.cipher_suite.hash_algorithm().output_length();
+            extensions.push(Extension::new(ExtensionData::PreSharedKeyClientHello(
+                OfferedPsks {
+                    identities: PskIdentities::new(vec![PskIdentity {
+                        identity: Opaque::<u16>::new(session.ticket.clone()),
+                        obfuscated_ticket_age: session.obfuscated_ticket_age(),
+                    }]),
+                    binders: PskBinderEntries::new(vec![PskBinderEntry::new(vec![
+                        0;
+                        binder_length
+                    ])]),
+                },
+            )));
+        }
+
+        let mut cipher_suites = config.offered_cipher_suites();
+        if config.send_fallback_scsv {
+            cipher_suites.push(CipherSuite::TLS_FALLBACK_SCSV);
+        }
+
+        ClientHello {
+            protocol_version: ProtocolVersion::TLSv1_2,
+            random: hello.random,
+            session_id: Opaque::<u8>::new(hello.session_id.clone()),
+            chipher_suites: CipherSuites::new(cipher_suites),
+            compression_methods: CompressionMethods::new(vec![CompressionMethod::Null]),
+            extensions: Extensions::new(extensions),
+        }
+    }
+
+    fn verify_server_signature(
+        &self,
+        scheme: SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        let Some(certificate) = self.peer_certificates.first() else {
+            bail!(Error::Alert(AlertDescription::CertificateRequired))
+        };
+        let Ok((_, certificate)) = parse_x509_certificate(certificate) else {
+            bail!(Error::Alert(AlertDescription::BadCertificate))
+        };
+        verify_signature(scheme, certificate.public_key().raw, message, signature)
+    }
+
+    //=========================================================================
+    // TLS 1.3
+    //=========================================================================
+
+    fn handshake_tls13(
+        &mut self,
+        mut transcript: Transcript,
+        key_share: EphemeralSecret,
+        server_hello: ServerHello,
+        session: Option<Tls13Session>,
+        early_data_sent: bool,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let aead = self.core.cipher_suite.aead_algorithm().unwrap();
+
+        let Some(ExtensionData::KeyShareServerHello(entry)) =
+            server_hello.extensions.get(ExtensionType::KeyShare)
+        else {
+            bail!(Error::Alert(AlertDescription::MissingExtension))
+        };
+        if entry.group != key_share.group() {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        let shared_secret = key_share.agree(&entry.key_exchange.data)?;
+
+        let psk = session.as_ref().map(|session| session.psk.as_slice());
+        let mut key_schedule = KeySchedule::new(hash, psk);
+        key_schedule.advance(Some(&shared_secret));
+        let client_handshake_traffic_secret =
+            key_schedule.derive_secret("c hs traffic", &transcript.hash(hash));
+        let server_handshake_traffic_secret =
+            key_schedule.derive_secret("s hs traffic", &transcript.hash(hash));
+        self.core
+            .record_layer
+            .set_read_protection(traffic_protection(
+                hash,
+                aead,
+                &server_handshake_traffic_secret,
+            ))?;
+        // 0-RTT を送った場合、受け入れられたら EndOfEarlyData まで client_early_traffic_secret のまま
+        if !early_data_sent {
+            self.core
+                .record_layer
+                .set_write_protection(traffic_protection(
+                    hash,
+                    aead,
+                    &client_handshake_traffic_secret,
+                ));
+        }
+
+        // EncryptedExtensions
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::EncryptedExtensions(extensions) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        transcript.update(&data);
+
+        // early data を受け入れるのは最初の PSK をチケットと同じスイートで選んだ場合のみ
+        let early_data_accepted = match extensions.get(ExtensionType::EarlyData) {
+            None => false,
+            Some(_) if !early_data_sent => {
+                bail!(Error::Alert(AlertDescription::UnsupportedExtension))
+            }
+            Some(_)
+                if session
+                    .as_ref()
+                    .is_some_and(|session| session.cipher_suite == self.core.cipher_suite) =>
+            {
+                true
+            }
+            Some(_) => bail!(Error::Alert(AlertDescription::IllegalParameter)),
+        };
+        if early_data_sent {
+            if early_data_accepted {
+                self.early_data_status = EarlyDataStatus::Accepted;
+            } else {
+                self.early_data_status = EarlyDataStatus::Rejected;
+                self.core
+                    .record_layer
+                    .set_write_protection(traffic_protection(
+                        hash,
+                        aead,
+                        &client_handshake_traffic_secret,
+                    ));
+            }
+        }
+
+        // 再開時は CertificateRequest, Certificate, CertificateVerify が送られない
+        let mut client_certificate = None;
+        if let Some(session) = session {
+            self.peer_certificates = session.peer_certificates;
+            self.resumed = true;
+        } else {
+            // CertificateRequest (クライアント認証を求める場合のみ)
+            let (mut data, mut handshake) = self.core.read_handshake()?;
+            if let HandshakeBody::CertificateRequest(certificate_request) = handshake.body {
+                // 4.3.2. ハンドシェイク中の要求の context は空
+                if certificate_request
+                    .certificate_request_context
+                    .as_ref()
+                    .is_some_and(|context| !context.data.is_empty())
+                {
+                    bail!(Error::Alert(AlertDescription::IllegalParameter));
+                }
+                transcript.update(&data);
+                client_certificate = Some(self.resolve_client_certificate(&certificate_request)?);
+                (data, handshake) = self.core.read_handshake()?;
+            }
+            self.read_server_certificate_tls13(&mut transcript, &data, handshake)?;
+        }
+
+        // Finished
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::Finished(finished) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let expected = finished_verify_data(
+            hash,
+            &server_handshake_traffic_secret,
+            &transcript.hash(hash),
+        );
+        if !constant_time_eq(&finished.verify_data, &expected) {
+            bail!(Error::Alert(AlertDescription::DecryptError));
+        }
+        transcript.update(&data);
+
+        key_schedule.advance(None);
+        let client_application_traffic_secret =
+            key_schedule.derive_secret("c ap traffic", &transcript.hash(hash));
+        let server_application_traffic_secret =
+            key_schedule.derive_secret("s ap traffic", &transcript.hash(hash));
+
+        self.send_compatibility_change_cipher_spec()?;
+        if early_data_accepted {
+            self.core
+                .send_handshake(&mut transcript, HandshakeBody::EndOfEarlyData(()))?;
+            self.core
+                .record_layer
+                .set_write_protection(traffic_protection(
+                    hash,
+                    aead,
+                    &client_handshake_traffic_secret,
+                ));
+        }
+
+        if let Some(client_certificate) = client_certificate {
+            self.send_client_certificate_tls13(&mut transcript, &[], client_certificate)?;
+        }
+
+        let verify_data = finished_verify_data(
+            hash,
+            &client_handshake_traffic_secret,
+            &transcript.hash(hash),
+        );
+        self.core.send_handshake(
+            &mut transcript,
+            HandshakeBody::Finished(Finished { verify_data }),
+        )?;
+        self.resumption_master_secret =
+            key_schedule.derive_secret("res master", &transcript.hash(hash));
+
+        self.core
+            .record_layer
+            .set_read_protection(traffic_protection(
+                hash,
+                aead,
+                &server_application_traffic_secret,
+            ))?;
+        self.core
+            .record_layer
+            .set_write_protection(traffic_protection(
+                hash,
+                aead,
+                &client_application_traffic_secret,
+            ));
+        self.core.write_traffic_secret = client_application_traffic_secret;
+        self.core.read_traffic_secret = server_application_traffic_secret;
+        self.transcript = transcript;
+
+        Ok(())
+    }
+
+    // Certificate (読み込み済み) と CertificateVerify
+    fn read_server_certificate_tls13(
+        &mut self,
+        transcript: &mut Transcript,
+        data: &[u8],
+        handshake: Handshake,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+
+        let HandshakeBody::Certificate(certificate) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        // RFC 8446 4.4.2. サーバー認証の Certificate では certificate_request_context は空
+        if certificate
+            .certificate_request_context
+            .as_ref()
+            .is_some_and(|context| !context.data.is_empty())
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        transcript.update(data);
+        self.peer_certificates = certificate
+            .certificates()
+            .into_iter()
+            .map(|certificate| certificate.to_vec())
+            .collect();
+        if self.peer_certificates.is_empty() {
+            bail!(Error::Alert(AlertDescription::DecodeError));
+        }
+        // 署名を検証する前にチェーンとホスト名を確かめる
+        self.config
+            .roots
+            .verify_server_chain(&self.peer_certificates, &self.server_name)?;
+
+        // CertificateVerify
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::CertificateVerify(certificate_verify) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let scheme = certificate_verify.algorithm;
+        if !self.config.signature_schemes.contains(&scheme) || !is_tls13_signature_scheme(scheme) {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        let message = certificate_verify_message(
+            b"TLS 1.3, server CertificateVerify",
+            &transcript.hash(hash),
+        );
+        self.verify_server_signature(scheme, &message, &certificate_verify.signature.data)?;
+        transcript.update(&data);
+
+        Ok(())
+    }
+
+    //=========================================================================
+    // TLS 1.2
+    //=========================================================================
+
+    fn handshake_tls12(
+        &mut self,
+        mut transcript: Transcript,
+        client_random: Random,
+        server_hello: ServerHello,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let aead = self.core.cipher_suite.aead_algorithm().unwrap();
+
+        // Certificate
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::Certificate(certificate) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        transcript.update(&data);
+        self.peer_certificates = certificate
+            .certificates()
+            .into_iter()
+            .map(|certificate| certificate.to_vec())
+            .collect();
+        self.config
+            .roots
+            .verify_server_chain(&self.peer_certificates, &self.server_name)?;
+
+        // ServerKeyExchange (ECDHE のみ)
+        let (mut data, mut handshake) = self.core.read_handshake()?;
+        let mut ecdhe = None;
+        if self.core.cipher_suite.is_ecdhe_key_exchange() {
+            let HandshakeBody::ServerKeyExchange(server_key_exchange) = handshake.body else {
+                bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+            };
+            self.verify_server_key_exchange(
+                &client_random,
+                &server_hello.random,
+                &server_key_exchange,
+            )?;
+            ecdhe = Some(server_key_exchange.params);
+            transcript.update(&data);
+            (data, handshake) = self.core.read_handshake()?;
+        }
+
+        // CertificateRequest (クライアント認証を求める場合のみ)
+        let mut client_certificate = None;
+        if let HandshakeBody::CertificateRequest(certificate_request) = handshake.body {
+            transcript.update(&data);
+            client_certificate = Some(self.resolve_client_certificate(&certificate_request)?);
+            (data, handshake) = self.core.read_handshake()?;
+        }
+
+        // ServerHelloDone
+        let HandshakeBody::ServerHelloDone(_) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        transcript.update(&data);
+
+        // Certificate
+        // 要求されたら、送る証明書が無くても空の Certificate を送る
+        if let Some(client_certificate) = &client_certificate {
+            let certificates = client_certificate
+                .as_ref()
+                .map(|(certified_key, _)| certified_key.certificates.clone())
+                .unwrap_or_default();
+            self.core.send_handshake(
+                &mut transcript,
+                HandshakeBody::Certificate(Certificate::new(self.core.version, &[], &certificates)),
+            )?;
+        }
+
+        // ClientKeyExchange
+        let pre_master_secret = match ecdhe {
+            // 5.10. ECDH の共有鍵 (x 座標) がそのまま pre_master_secret になる
+            Some(params) => {
+                let key_share = EphemeralSecret::generate(params.named_curve)?;
+                let public_key = key_share.public_key();
+                let shared_secret = key_share.agree(&params.public.data)?;
+                self.core.send_handshake(
+                    &mut transcript,
+                    HandshakeBody::ClientKeyExchange(ClientKeyExchange::new_ecdh(public_key)),
+                )?;
+                shared_secret
+            }
+            None => {
+                let Some(certificate) = self.peer_certificates.first() else {
+                    bail!(Error::Alert(AlertDescription::DecodeError))
+                };
+                let Ok((_, certificate)) = parse_x509_certificate(certificate) else {
+                    bail!(Error::Alert(AlertDescription::BadCertificate))
+                };
+                let Ok(public_key) =
+                    RsaPublicKey::from_public_key_der(certificate.public_key().raw)
+                else {
+                    bail!(Error::Alert(AlertDescription::UnsupportedCertificate))
+                };
+
+                let mut random = vec![0; 46];
+                rand::thread_rng().fill_bytes(&mut random);
+                let pre_master_secret = PreMasterSecret {
+                    protocol_version: ProtocolVersion::TLSv1_2,
+                    random,
+                };
+                let pre_master_secret = pre_master_secret.to_bytes::<NetworkEndian>();
+                let encrypted_pre_master_secret =
+                    public_key.encrypt_pkcs1v15(&pre_master_secret)?;
+                self.core.send_handshake(
+                    &mut transcript,
+                    HandshakeBody::ClientKeyExchange(ClientKeyExchange::new_rsa(
+                        encrypted_pre_master_secret,
+                    )),
+                )?;
+                pre_master_secret
+            }
+        };
+
+        // CertificateVerify
+        // TLS 1.2 ではここまでのハンドシェイクメッセージそのものに署名する
+        if let Some(Some((certified_key, scheme))) = client_certificate {
+            let signature = certified_key.key.sign(scheme, transcript.messages())?;
+            self.core.send_handshake(
+                &mut transcript,
+                HandshakeBody::CertificateVerify(CertificateVerify {
+                    algorithm: scheme,
+                    signature: Opaque::<u16>::new(signature),
+                }),
+            )?;
+        }
+
+        let master_secret = master_secret(
+            hash,
+            &pre_master_secret,
+            &client_random,
+            &server_hello.random,
+        );
+        let key_block = KeyBlock::new(
+            hash,
+            aead,
+            &master_secret,
+            &client_random,
+            &server_hello.random,
+        );
+
+        self.send_finished_tls12(&mut transcript, &master_secret, &key_block)?;
+        let ticket = self.read_new_session_ticket_tls12(&mut transcript, &server_hello)?;
+        self.read_finished_tls12(&mut transcript, &master_secret, &key_block)?;
+
+        self.store_tls12_session(&server_hello.session_id.data, ticket, master_secret);
+        Ok(())
+    }
+
+    // RFC 8422 5.4. 曲線と署名方式はこちらが提示したもので、署名方式はスイートの認証方式に合っていること
+    fn verify_server_key_exchange(
+        &self,
+        client_random: &Random,
+        server_random: &Random,
+        server_key_exchange: &ServerKeyExchange,
+    ) -> Result<()> {
+        let params = &server_key_exchange.params;
+        if !self.config.groups.contains(&params.named_curve)
+            || !EphemeralSecret::is_supported(params.named_curve)
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        let scheme = server_key_exchange.algorithm;
+        if !self.config.signature_schemes.contains(&scheme)
+            || is_ecdsa_scheme(scheme) != self.core.cipher_suite.is_ecdsa_authentication()
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+
+        let mut message = client_random.to_bytes();
+        message.extend_from_slice(&server_random.to_bytes());
+        message.extend_from_slice(&params.to_bytes());
+        self.verify_server_signature(scheme, &message, &server_key_exchange.signature.data)
+    }
+
+    // 短縮ハンドシェイク (RFC 5246 7.3)
+    // ServerHello の後、サーバーの ChangeCipherSpec と Finished が先に来る
+    fn resume_tls12(
+        &mut self,
+        mut transcript: Transcript,
+        client_random: Random,
+        server_hello: ServerHello,
+        session: Tls12Session,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let aead = self.core.cipher_suite.aead_algorithm().unwrap();
+
+        let key_block = KeyBlock::new(
+            hash,
+            aead,
+            &session.master_secret,
+            &client_random,
+            &server_hello.random,
+        );
+        self.peer_certificates = session.peer_certificates;
+        self.resumed = true;
+
+        // チケットで再開した場合、サーバーは新しいチケットを送ってくることがある
+        let ticket = self.read_new_session_ticket_tls12(&mut transcript, &server_hello)?;
+        self.read_finished_tls12(&mut transcript, &session.master_secret, &key_block)?;
+        self.send_finished_tls12(&mut transcript, &session.master_secret, &key_block)?;
+
+        if ticket.is_some() {
+            self.store_tls12_session(&session.session_id, ticket, session.master_secret);
+        }
+        Ok(())
+    }
+
+    // ServerHello に session_ticket が付いていれば ChangeCipherSpec の前に NewSessionTicket が来る
+    fn read_new_session_ticket_tls12(
+        &mut self,
+        transcript: &mut Transcript,
+        server_hello: &ServerHello,
+    ) -> Result<Option<NewSessionTicket>> {
+        if !server_hello
+            .extensions
+            .contains(ExtensionType::SessionTicket)
+        {
+            return Ok(None);
+        }
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::NewSessionTicket(ticket) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        transcript.update(&data);
+        Ok(Some(ticket))
+    }
+
+    fn send_finished_tls12(
+        &mut self,
+        transcript: &mut Transcript,
+        master_secret: &[u8],
+        key_block: &KeyBlock,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let aead = self.core.cipher_suite.aead_algorithm().unwrap();
+
+        self.core.record_layer.write_change_cipher_spec()?;
+        self.core
+            .record_layer
+            .set_write_protection(RecordProtection::new(
+                aead,
+                &key_block.client_write_key,
+                &key_block.client_write_iv,
+            ));
+
+        let verify_data = verify_data(
+            hash,
+            master_secret,
+            b"client finished",
+            &transcript.hash(hash),
+        );
+        self.core.send_handshake(
+            transcript,
+            HandshakeBody::Finished(Finished { verify_data }),
+        )
+    }
+
+    fn read_finished_tls12(
+        &mut self,
+        transcript: &mut Transcript,
+        master_secret: &[u8],
+        key_block: &KeyBlock,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let aead = self.core.cipher_suite.aead_algorithm().unwrap();
+
+        // ChangeCipherSpec の後は暗号化された Finished が来る
+        match self.core.record_layer.read_message()? {
+            Message::ChangeCipherSpec => {}
+            Message::Alert(alert) => bail!(Error::ReceivedAlert(alert.description)),
+            _ => bail!(Error::Alert(AlertDescription::UnexpectedMessage)),
+        }
+        self.core
+            .record_layer
+            .set_read_protection(RecordProtection::new(
+                aead,
+                &key_block.server_write_key,
+                &key_block.server_write_iv,
+            ))?;
+
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::Finished(finished) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let expected = verify_data(
+            hash,
+            master_secret,
+            b"server finished",
+            &transcript.hash(hash),
+        );
+        if !constant_time_eq(&finished.verify_data, &expected) {
+            bail!(Error::Alert(AlertDescription::DecryptError));
+        }
+        transcript.update(&data);
+        Ok(())
+    }
+
+    // サーバーがセッション ID もチケットも返さなければキャッシュしない
+    // 空のチケットはチケットを発行しないという意味 (RFC 5077 3.3)
+    fn store_tls12_session(
+        &self,
+        session_id: &[u8],
+        ticket: Option<NewSessionTicket>,
+        master_secret: Vec<u8>,
+    ) {
+        let Some(session_store) = &self.config.session_store else {
+            return;
+        };
+        let (ticket, ticket_lifetime_hint) = match ticket {
+            Some(ticket) => (ticket.ticket.data, ticket.ticket_lifetime),
+            None => (vec![], 0),
+        };
+        if session_id.is_empty() && ticket.is_empty() {
+            session_store.remove_tls12(&self.server_name);
+            return;
+        }
+        session_store.insert_tls12(
+            &self.server_name,
+            Tls12Session::new(
+                session_id.to_vec(),
+                ticket,
+                ticket_lifetime_hint,
+                self.core.cipher_suite,
+                master_secret,
+                self.peer_certificates.clone(),
+            ),
+        );
+    }
+}
+
+// TLS 1.3 の CertificateVerify では PKCS#1 v1.5 と SHA-1 は使えない
+fn is_tls13_signature_scheme(scheme: SignatureScheme) -> bool {
+    !matches!(
+        scheme,
+        SignatureScheme::rsa_pkcs1_sha1
+            | SignatureScheme::ecdsa_sha1
+            | SignatureScheme::rsa_pkcs1_sha256
+            | SignatureScheme::rsa_pkcs1_sha384
+            | SignatureScheme::rsa_pkcs1_sha512
+    )
+}
+
+fn is_ecdsa_scheme(scheme: SignatureScheme) -> bool {
+    matches!(
+        scheme,
+        SignatureScheme::ecdsa_sha1
+            | SignatureScheme::ecdsa_secp256r1_sha256
+            | SignatureScheme::ecdsa_secp384r1_sha384
+            | SignatureScheme::ecdsa_secp521r1_sha512
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::test_util::{certified_key, load, roots, run, GracefulStream};
+    use crate::tls::{
+        read_record, ClientAuthMode, RecordLayer, ServerConfig, ServerConnection, StaticClientCert,
+        TLSCiphertext,
+    };
+    use std::net::TcpStream;
+
+    // サーバー側で分かったこと
+    #[derive(Debug)]
+    struct Accepted {
+        peer_certificates: usize,
+        resumed: bool,
+        early_data_status: EarlyDataStatus,
+        early_data: Vec<u8>,
+        // 0-RTT 以外で受け取ったデータ
+        received: Vec<u8>,
+    }
+
+    // 先に読んで書き換えたレコードを、残りのデータより前に読ませる
+    struct Tampered {
+        head: std::io::Cursor<Vec<u8>>,
+        stream: TcpStream,
+    }
+
+    impl Read for Tampered {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            match self.head.read(buf)? {
+                0 => self.stream.read(buf),
+                n => Ok(n),
+            }
+        }
+    }
+
+    impl Write for Tampered {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.stream.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            self.stream.flush()
+        }
+    }
+
+    // サーバーは受け取ったデータをそのまま返す
+    // tamper はクライアントの最初のレコード (ClientHello) の中身をサーバーが読む前に書き換える
+    fn connect_with(
+        client_config: ClientConfig,
+        server_config: ServerConfig,
+        early_data: &[u8],
+        tamper: fn(&mut Vec<u8>),
+    ) -> (Result<ClientConnection<GracefulStream>>, Result<Accepted>) {
+        let early_data = early_data.to_vec();
+        run(
+            move |stream| {
+                let mut client = ClientConnection::new_with_replayable_early_data(
+                    Arc::new(client_config),
+                    "example.test",
+                    stream,
+                    &early_data,
+                )?;
+                client.write(b"ping")?;
+                // 0-RTT で受け付けられなかったデータも返ってくる
+                let mut echoed = vec![];
+                while !echoed.ends_with(b"ping") {
+                    let Some(data) = client.read()? else {
+                        bail!("closed before echo")
+                    };
+                    echoed.extend(data);
+                }
+                client.close()?;
+                Ok(client)
+            },
+            move |mut stream| {
+                let mut record = read_record(&mut stream)?;
+                tamper(&mut record.fragment);
+                let record = TLSCiphertext::new(record.content_type, record.fragment);
+                let stream = Tampered {
+                    head: std::io::Cursor::new(record.to_bytes()),
+                    stream,
+                };
+
+                let mut connection = ServerConnection::new(Arc::new(server_config), stream)?;
+                let early_data = connection.take_early_data();
+                let mut received = vec![];
+                while let Some(data) = connection.read()? {
+                    connection.write(&data)?;
+                    received.extend(data);
+                }
+                Ok(Accepted {
+                    peer_certificates: connection.peer_certificates().len(),
+                    resumed: connection.is_resumed(),
+                    early_data_status: connection.early_data_status(),
+                    early_data,
+                    received,
+                })
+            },
+        )
+    }
+
+    fn connect(
+        client_config: ClientConfig,
+        server_config: ServerConfig,
+    ) -> (Result<ClientConnection<GracefulStream>>, Result<Accepted>) {
+        connect_with(client_config, server_config, &[], |_| {})
+    }
+
+    // 中止した側が送ったアラート
+    fn sent_alert<T>(result: &Result<T>) -> Option<AlertDescription> {
+        match result.as_ref().err()?.downcast_ref::<Error>()? {
+            Error::Alert(description) => Some(*description),
+            _ => None,
+        }
+    }
+
+    fn received_alert<T>(result: &Result<T>) -> Option<AlertDescription> {
+        match result.as_ref().err()?.downcast_ref::<Error>()? {
+            Error::ReceivedAlert(description) => Some(*description),
+            _ => None,
+        }
+    }
+
+    fn configs(version: ProtocolVersion) -> (ClientConfig, ServerConfig) {
+        let client_config = ClientConfig {
+            versions: vec![version],
+            roots: roots(),
+            ..ClientConfig::default()
+        };
+        (
+            client_config,
+            ServerConfig::new(certified_key("server.pem")),
+        )
+    }
+
+    fn mtls_configs(version: ProtocolVersion) -> (ClientConfig, ServerConfig) {
+        let (mut client_config, mut server_config) = configs(version);
+        client_config.client_cert_resolver =
+            Some(Arc::new(StaticClientCert::new(certified_key("client.pem"))));
+        server_config.client_auth = ClientAuthMode::Required;
+        server_config.client_roots = roots();
+        (client_config, server_config)
+    }
+
+    // ServerConnection の代わりに script がレコード層で直接やり取りする
+    fn connect_to_script<T: Send + 'static>(
+        client_config: ClientConfig,
+        script: impl FnOnce(&mut RecordLayer<TcpStream>) -> Result<T> + Send + 'static,
+    ) -> (Result<ClientConnection<GracefulStream>>, Result<T>) {
+        run(
+            move |stream| ClientConnection::new(Arc::new(client_config), "example.test", stream),
+            move |stream| script(&mut RecordLayer::new(stream)),
+        )
+    }
+
+    // ミドルボックス互換モードのダミーの ChangeCipherSpec は読み飛ばす
+    fn read_client_hello(record_layer: &mut RecordLayer<TcpStream>) -> Result<ClientHello> {
+        loop {
+            match record_layer.read_message()? {
+                Message::ChangeCipherSpec => continue,
+                Message::Handshake(data) => {
+                    let HandshakeBody::ClientHello(client_hello) =
+                        Handshake::from_bytes(&data, ProtocolVersion::TLSv1_2)?.body
+                    else {
+                        bail!("expected ClientHello")
+                    };
+                    return Ok(client_hello);
+                }
+                _ => bail!("expected ClientHello"),
+            }
+        }
+    }
+
+    fn read_alert(record_layer: &mut RecordLayer<TcpStream>) -> Result<AlertDescription> {
+        loop {
+            match record_layer.read_message()? {
+                Message::ChangeCipherSpec => continue,
+                Message::Alert(alert) => return Ok(alert.description),
+                _ => bail!("expected alert"),
+            }
+        }
+    }
+
+    fn send_hello_retry_request(
+        record_layer: &mut RecordLayer<TcpStream>,
+        client_hello: &ClientHello,
+        group: Option<NamedGroup>,
+        cookie: Option<&[u8]>,
+    ) -> Result<()> {
+        let mut extensions = vec![Extension::new(ExtensionData::SelectedVersion(
+            ProtocolVersion::TLSv1_3,
+        ))];
+        if let Some(group) = group {
+            extensions.push(Extension::new(ExtensionData::KeyShareHelloRetryRequest(
+                group,
+            )));
+        }
+        if let Some(cookie) = cookie {
+            extensions.push(Extension::new(ExtensionData::Cookie(Opaque::<u16>::new(
+                cookie.to_vec(),
+            ))));
+        }
+        let retry_request = ServerHello {
+            protocol_version: ProtocolVersion::TLSv1_2,
+            random: Random::hello_retry_request(),
+            session_id: Opaque::<u8>::new(client_hello.session_id.data.clone()),
+            cipher_suite: CipherSuite::TLS_AES_128_GCM_SHA256,
+            compression_method: CompressionMethod::Null,
+            extensions: Extensions::new(extensions),
+        };
+        record_layer.write_handshake(
+            &Handshake::new(HandshakeBody::ServerHello(retry_request)).to_bytes::<NetworkEndian>(),
+        )
+    }
+
+    fn key_share_groups(client_hello: &ClientHello) -> Vec<NamedGroup> {
+        match client_hello.extensions.get(ExtensionType::KeyShare) {
+            Some(ExtensionData::KeyShareClientHello(entries)) => {
+                entries.data.iter().map(|entry| entry.group).collect()
+            }
+            _ => vec![],
+        }
+    }
+
+    #[test]
+    fn tls13_mutual_authentication() {
+        let (client_config, server_config) = mtls_configs(ProtocolVersion::TLSv1_3);
+        let (client, server) = connect(client_config, server_config);
+        let client = client.unwrap();
+        assert_eq!(client.protocol_version(), ProtocolVersion::TLSv1_3);
+        assert_eq!(client.peer_certificates().len(), 2);
+        assert_eq!(server.unwrap().peer_certificates, 2);
+    }
+
+    #[test]
+    fn tls12_ecdhe() {
+        for group in [
+            NamedGroup::x25519,
+            NamedGroup::secp256r1,
+            NamedGroup::secp384r1,
+        ] {
+            let (mut client_config, server_config) = mtls_configs(ProtocolVersion::TLSv1_2);
+            client_config.cipher_suites = vec![
+                CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256,
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+            ];
+            // ECDSA の証明書の曲線 (P-256) も含めなければならない (RFC 8422 5.1)
+            client_config.groups = vec![group, NamedGroup::secp256r1];
+            let (client, server) = connect(client_config, server_config);
+            let client = client.unwrap();
+            assert_eq!(
+                client.cipher_suite(),
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384
+            );
+            assert_eq!(server.unwrap().peer_certificates, 2);
+        }
+    }
+
+    // 静的 RSA の鍵交換。testdata/rsa/server.pem は key2048.pem の自己署名証明書
+    #[test]
+    fn tls12_rsa_key_exchange() {
+        let certificate = load("rsa/server.pem");
+        let mut roots = RootCertStore::new();
+        roots.add_pem(&certificate).unwrap();
+        for cipher_suite in [
+            CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256,
+            CipherSuite::TLS_RSA_WITH_AES_256_GCM_SHA384,
+            CipherSuite::TLS_RSA_WITH_AES_128_CCM,
+            CipherSuite::TLS_RSA_WITH_AES_256_CCM,
+        ] {
+            let client_config = ClientConfig {
+                versions: vec![ProtocolVersion::TLSv1_2],
+                cipher_suites: vec![cipher_suite],
+                roots: roots.clone(),
+                ..ClientConfig::default()
+            };
+            let mut server_config =
+                ServerConfig::from_pem(&certificate, &load("rsa/key2048.pem")).unwrap();
+            server_config.cipher_suites = vec![cipher_suite];
+            let (client, server) = connect(client_config, server_config);
+            let client = client.unwrap();
+            assert_eq!(client.protocol_version(), ProtocolVersion::TLSv1_2);
+            assert_eq!(client.cipher_suite(), cipher_suite);
+            assert_eq!(client.peer_certificates().len(), 1);
+            assert_eq!(server.unwrap().received, b"ping");
+        }
+    }
+
+    #[test]
+    fn tls13_client_without_certificate() {
+        let (mut client_config, server_config) = mtls_configs(ProtocolVersion::TLSv1_3);
+        client_config.client_cert_resolver = None;
+        let (_, server) = connect(client_config, server_config);
+        let error = server.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::Alert(AlertDescription::CertificateRequired))
+        ));
+    }
+
+    #[test]
+    fn untrusted_server() {
+        let (mut client_config, server_config) = mtls_configs(ProtocolVersion::TLSv1_3);
+        client_config.roots = RootCertStore::new();
+        let (client, server) = connect(client_config, server_config);
+        let Err(error) = client else {
+            panic!("connected without trusted roots")
+        };
+        assert!(matches!(
+            error.downcast_ref::<Error>(),
+            Some(Error::Alert(AlertDescription::UnknownCa))
+        ));
+        assert!(matches!(
+            server.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::ReceivedAlert(AlertDescription::UnknownCa))
+        ));
+    }
+
+    #[test]
+    fn tls13_resumption() {
+        let (client_config, server_config) = configs(ProtocolVersion::TLSv1_3);
+        let (client, server) = connect(client_config.clone(), server_config.clone());
+        assert!(!client.unwrap().is_resumed());
+        assert!(!server.unwrap().resumed);
+
+        // 再開するたびに新しいチケットを受け取るので、続けて再開できる
+        for _ in 0..2 {
+            let (client, server) = connect(client_config.clone(), server_config.clone());
+            let client = client.unwrap();
+            assert!(client.is_resumed());
+            assert_eq!(client.peer_certificates().len(), 2);
+            let server = server.unwrap();
+            assert!(server.resumed);
+            assert_eq!(server.early_data_status, EarlyDataStatus::NotSent);
+            assert!(server.early_data.is_empty());
+            assert_eq!(server.received, b"ping");
+        }
+        let stats = server_config.resumption_metrics.snapshot();
+        assert_eq!(stats.full_handshakes, 1);
+        assert_eq!(stats.psk_hits, 2);
+    }
+
+    #[test]
+    fn tls13_resumption_with_modified_binder() {
+        let (client_config, server_config) = configs(ProtocolVersion::TLSv1_3);
+        let (client, _) = connect(client_config.clone(), server_config.clone());
+        client.unwrap();
+
+        // pre_shared_key は最後の拡張なので、ClientHello の最後の byte は binder
+        let (client, server) = connect_with(client_config, server_config, &[], |fragment| {
+            *fragment.last_mut().unwrap() ^= 1
+        });
+        assert_eq!(sent_alert(&server), Some(AlertDescription::DecryptError));
+        assert_eq!(
+            received_alert(&client),
+            Some(AlertDescription::DecryptError)
+        );
+    }
+
+    // 0-RTT を受け付けるサーバーから最初のチケットを受け取っておく
+    fn early_data_configs(max_early_data_size: u32) -> (ClientConfig, ServerConfig) {
+        let (client_config, mut server_config) = configs(ProtocolVersion::TLSv1_3);
+        server_config.max_early_data_size = max_early_data_size;
+        let (client, _) = connect(client_config.clone(), server_config.clone());
+        client.unwrap();
+        (client_config, server_config)
+    }
+
+    #[test]
+    fn early_data_accepted() {
+        let (client_config, server_config) = early_data_configs(1024);
+        let (client, server) = connect_with(client_config, server_config, b"early", |_| {});
+        let client = client.unwrap();
+        assert!(client.is_resumed());
+        assert_eq!(client.early_data_status(), EarlyDataStatus::Accepted);
+        let server = server.unwrap();
+        assert_eq!(server.early_data_status, EarlyDataStatus::Accepted);
+        assert_eq!(server.early_data, b"early");
+        assert_eq!(server.received, b"ping");
+    }
+
+    #[test]
+    fn early_data_rejected() {
+        let (client_config, mut server_config) = early_data_configs(1024);
+        server_config.max_early_data_size = 0;
+        let (client, server) = connect_with(client_config, server_config, b"early", |_| {});
+        // 拒否されたデータはハンドシェイク後に送り直す
+        let client = client.unwrap();
+        assert!(client.is_resumed());
+        assert_eq!(client.early_data_status(), EarlyDataStatus::Rejected);
+        let server = server.unwrap();
+        assert_eq!(server.early_data_status, EarlyDataStatus::Rejected);
+        assert!(server.early_data.is_empty());
+        assert_eq!(server.received, b"earlyping");
+    }
+
+    #[test]
+    fn early_data_limited_by_ticket() {
+        // チケットの max_early_data_size を超える分は 0-RTT では送らない
+        let (client_config, server_config) = early_data_configs(8);
+        let (client, server) =
+            connect_with(client_config, server_config, b"0123456789abcdef", |_| {});
+        assert_eq!(
+            client.unwrap().early_data_status(),
+            EarlyDataStatus::Accepted
+        );
+        let server = server.unwrap();
+        assert_eq!(server.early_data, b"01234567");
+        assert_eq!(server.received, b"89abcdefping");
+    }
+
+    #[test]
+    fn early_data_over_max_early_data_size() {
+        // チケットを発行した後に上限を下げたので、上限を超える 0-RTT が届く
+        let (client_config, mut server_config) = early_data_configs(1024);
+        server_config.max_early_data_size = 4;
+        let (client, server) =
+            connect_with(client_config, server_config, b"0123456789abcdef", |_| {});
+        assert_eq!(
+            sent_alert(&server),
+            Some(AlertDescription::UnexpectedMessage)
+        );
+        assert_eq!(
+            received_alert(&client),
+            Some(AlertDescription::UnexpectedMessage)
+        );
+    }
+
+    #[test]
+    fn hello_retry_request_for_key_share() {
+        // クライアントは x25519 の key_share だけを送るので、secp256r1 を要求される
+        let (client_config, mut server_config) = configs(ProtocolVersion::TLSv1_3);
+        server_config.groups = vec![NamedGroup::secp256r1];
+        let (client, server) = connect(client_config, server_config);
+        assert_eq!(client.unwrap().protocol_version(), ProtocolVersion::TLSv1_3);
+        assert_eq!(server.unwrap().received, b"ping");
+    }
+
+    #[test]
+    fn hello_retry_request_with_cookie() {
+        let (client_config, _) = configs(ProtocolVersion::TLSv1_3);
+        let (_, server) = connect_to_script(client_config, |record_layer| {
+            let client_hello = read_client_hello(record_layer)?;
+            assert_eq!(key_share_groups(&client_hello), [NamedGroup::x25519]);
+            assert!(!client_hello.extensions.contains(ExtensionType::Cookie));
+            send_hello_retry_request(
+                record_layer,
+                &client_hello,
+                Some(NamedGroup::secp256r1),
+                Some(b"cookie"),
+            )?;
+            read_client_hello(record_layer)
+        });
+        let client_hello = server.unwrap();
+        assert_eq!(key_share_groups(&client_hello), [NamedGroup::secp256r1]);
+        assert!(matches!(
+            client_hello.extensions.get(ExtensionType::Cookie),
+            Some(ExtensionData::Cookie(cookie)) if cookie.data == b"cookie"
+        ));
+    }
+
+    #[test]
+    fn second_hello_retry_request() {
+        let (client_config, _) = configs(ProtocolVersion::TLSv1_3);
+        let (client, server) = connect_to_script(client_config, |record_layer| {
+            let client_hello = read_client_hello(record_layer)?;
+            send_hello_retry_request(record_layer, &client_hello, None, Some(b"first"))?;
+            let client_hello = read_client_hello(record_layer)?;
+            send_hello_retry_request(record_layer, &client_hello, None, Some(b"second"))?;
+            read_alert(record_layer)
+        });
+        assert_eq!(
+            sent_alert(&client),
+            Some(AlertDescription::UnexpectedMessage)
+        );
+        assert_eq!(server.unwrap(), AlertDescription::UnexpectedMessage);
+    }
+
+    #[test]
+    fn hello_retry_request_for_offered_group() {
+        let (client_config, _) = configs(ProtocolVersion::TLSv1_3);
+        let (client, server) = connect_to_script(client_config, |record_layer| {
+            let client_hello = read_client_hello(record_layer)?;
+            send_hello_retry_request(record_layer, &client_hello, Some(NamedGroup::x25519), None)?;
+            read_alert(record_layer)
+        });
+        assert_eq!(
+            sent_alert(&client),
+            Some(AlertDescription::IllegalParameter)
+        );
+        assert_eq!(server.unwrap(), AlertDescription::IllegalParameter);
+    }
+
+    // 攻撃者が supported_versions を取り除いて TLS 1.2 にさせる
+    fn remove_supported_versions(fragment: &mut Vec<u8>) {
+        let handshake = Handshake::from_bytes(fragment, ProtocolVersion::TLSv1_2).unwrap();
+        let HandshakeBody::ClientHello(mut client_hello) = handshake.body else {
+            panic!("expected ClientHello")
+        };
+        let extensions = std::mem::take(&mut client_hello.extensions.data)
+            .into_iter()
+            .filter(|extension| extension.extension_type != ExtensionType::SupportedVersions)
+            .collect();
+        client_hello.extensions = Extensions::new(extensions);
+        *fragment =
+            Handshake::new(HandshakeBody::ClientHello(client_hello)).to_bytes::<NetworkEndian>();
+    }
+
+    #[test]
+    fn downgrade_sentinel() {
+        let (mut client_config, server_config) = configs(ProtocolVersion::TLSv1_3);
+        client_config.versions = vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2];
+        let (client, server) = connect_with(
+            client_config.clone(),
+            server_config.clone(),
+            &[],
+            remove_supported_versions,
+        );
+        assert_eq!(
+            sent_alert(&client),
+            Some(AlertDescription::IllegalParameter)
+        );
+        assert_eq!(
+            received_alert(&server),
+            Some(AlertDescription::IllegalParameter)
+        );
+
+        // TLS 1.3 を提示していなければ、サーバーの random に印があっても続ける
+        client_config.versions = vec![ProtocolVersion::TLSv1_2];
+        let (client, _) = connect(client_config, server_config);
+        assert_eq!(client.unwrap().protocol_version(), ProtocolVersion::TLSv1_2);
+    }
+
+    #[test]
+    fn inappropriate_fallback() {
+        let (mut client_config, mut server_config) = configs(ProtocolVersion::TLSv1_3);
+        client_config.versions = vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2];
+        let fallback = client_config.fallback().unwrap();
+        assert_eq!(fallback.versions, [ProtocolVersion::TLSv1_2]);
+        let (client, server) = connect(fallback.clone(), server_config.clone());
+        assert_eq!(
+            sent_alert(&server),
+            Some(AlertDescription::InappropriateFallback)
+        );
+        assert_eq!(
+            received_alert(&client),
+            Some(AlertDescription::InappropriateFallback)
+        );
+
+        // TLS 1.2 までのサーバーへのフォールバックは受け付けられる
+        server_config.versions = vec![ProtocolVersion::TLSv1_2];
+        let (client, _) = connect(fallback, server_config);
+        assert_eq!(client.unwrap().protocol_version(), ProtocolVersion::TLSv1_2);
+    }
+
+    #[test]
+    fn middlebox_compatibility() {
+        // サーバーは 32 bytes の session_id を受け取ったときだけダミーの ChangeCipherSpec を返す
+        for middlebox_compatibility in [true, false] {
+            let (mut client_config, server_config) = configs(ProtocolVersion::TLSv1_3);
+            client_config.middlebox_compatibility = middlebox_compatibility;
+            let (client, server) = connect(client_config, server_config);
+            let client = client.unwrap();
+            assert_eq!(client.change_cipher_spec_sent, middlebox_compatibility);
+            assert_eq!(server.unwrap().received, b"ping");
+        }
+    }
+
+    #[test]
+    fn tls12_session_id_resumption() {
+        let (client_config, mut server_config) = mtls_configs(ProtocolVersion::TLSv1_2);
+        server_config.ticket_keys = None;
+        let (client, server) = connect(client_config.clone(), server_config.clone());
+        assert!(!client.unwrap().is_resumed());
+        assert!(!server.unwrap().resumed);
+        let session = client_config
+            .session_store
+            .as_ref()
+            .unwrap()
+            .tls12_session("example.test")
+            .unwrap();
+        assert_eq!(session.session_id.len(), 32);
+        assert!(session.ticket.is_empty());
+
+        // 短縮ハンドシェイクでも前回の証明書を引き継ぐ
+        let (client, server) = connect(client_config, server_config.clone());
+        let client = client.unwrap();
+        assert!(client.is_resumed());
+        assert_eq!(client.peer_certificates().len(), 2);
+        let server = server.unwrap();
+        assert!(server.resumed);
+        assert_eq!(server.peer_certificates, 2);
+        assert_eq!(server.received, b"ping");
+
+        let stats = server_config.resumption_metrics.snapshot();
+        assert_eq!(stats.full_handshakes, 1);
+        assert_eq!(stats.session_id_hits, 1);
+        assert_eq!(stats.ticket_hits, 0);
+    }
+
+    #[test]
+    fn tls12_ticket_resumption() {
+        let (client_config, server_config) = configs(ProtocolVersion::TLSv1_2);
+        let session_store = client_config.session_store.clone().unwrap();
+        let (client, _) = connect(client_config.clone(), server_config.clone());
+        assert!(!client.unwrap().is_resumed());
+        // NewSessionTicket を受け取っている
+        let ticket = session_store.tls12_session("example.test").unwrap().ticket;
+        assert!(!ticket.is_empty());
+
+        let (client, server) = connect(client_config.clone(), server_config.clone());
+        assert!(client.unwrap().is_resumed());
+        assert!(server.unwrap().resumed);
+        // 有効なチケットで再開したので新しいチケットは送られない
+        let session = session_store.tls12_session("example.test").unwrap();
+        assert_eq!(session.ticket, ticket);
+
+        // 1つ前の鍵のチケットで再開すると、再開時の NewSessionTicket で発行し直される
+        server_config.ticket_keys.as_ref().unwrap().rotate();
+        let (client, server) = connect(client_config, server_config.clone());
+        assert!(client.unwrap().is_resumed());
+        assert!(server.unwrap().resumed);
+        let session = session_store.tls12_session("example.test").unwrap();
+        assert!(!session.ticket.is_empty());
+        assert_ne!(session.ticket, ticket);
+
+        let stats = server_config.resumption_metrics.snapshot();
+        assert_eq!(stats.full_handshakes, 1);
+        assert_eq!(stats.ticket_hits, 2);
+        assert_eq!(stats.session_id_hits, 0);
+    }
+}
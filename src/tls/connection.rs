@@ -0,0 +1,292 @@
+use super::{
+    next_traffic_secret, traffic_protection, AlertDescription, AlertLevel, CipherSuite, Error,
+    Handshake, HandshakeBody, HandshakeType, KeyUpdate, KeyUpdateRequest, Message, ProtocolVersion,
+    RecordLayer, Transcript, MAX_PLAINTEXT_LENGTH,
+};
+
+use anyhow::{bail, Result};
+use ser::NetworkEndian;
+use std::io::{Read, Write};
+
+// ハンドシェイク後に受け取ったもの
+pub enum ApplicationMessage {
+    Data(Vec<u8>),
+    CloseNotify,
+    // KeyUpdate 以外の TLS 1.3 の post-handshake メッセージ。扱いはクライアントとサーバーで異なる
+    Handshake(Vec<u8>),
+}
+
+// クライアントとサーバーで共通の、レコード層の上の状態と処理
+pub struct ConnectionCore<S> {
+    pub record_layer: RecordLayer<S>,
+    pub version: ProtocolVersion,
+    pub cipher_suite: CipherSuite,
+    // KeyUpdate で更新していく application traffic secret
+    pub write_traffic_secret: Vec<u8>,
+    pub read_traffic_secret: Vec<u8>,
+}
+
+impl<S: Read + Write> ConnectionCore<S> {
+    pub fn new(stream: S) -> Self {
+        ConnectionCore {
+            record_layer: RecordLayer::new(stream),
+            version: ProtocolVersion::TLSv1_2,
+            cipher_suite: CipherSuite::TLS_NULL_WITH_NULL_NULL,
+            write_traffic_secret: vec![],
+            read_traffic_secret: vec![],
+        }
+    }
+
+    // TLS 1.3 では鍵ごとのレコード数の上限に達する前に自動で KeyUpdate する
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        for fragment in data.chunks(MAX_PLAINTEXT_LENGTH) {
+            if self.version == ProtocolVersion::TLSv1_3
+                && self.record_layer.is_write_key_exhausted()
+            {
+                self.update_keys(false)?;
+            }
+            self.record_layer.write_application_data(fragment)?;
+        }
+        Ok(())
+    }
+
+    // RFC 8446 4.6.3. 送信鍵を更新する。request_peer なら相手にも受信鍵の更新を要求する
+    pub fn update_keys(&mut self, request_peer: bool) -> Result<()> {
+        if self.version != ProtocolVersion::TLSv1_3 {
+            bail!("KeyUpdate is only available in TLS 1.3");
+        }
+        let request_update = if request_peer {
+            KeyUpdateRequest::update_requested
+        } else {
+            KeyUpdateRequest::update_not_requested
+        };
+        self.send_key_update(request_update)
+    }
+
+    fn send_key_update(&mut self, request_update: KeyUpdateRequest) -> Result<()> {
+        let hash = self.cipher_suite.hash_algorithm();
+        let aead = self.cipher_suite.aead_algorithm().unwrap();
+
+        let data = Handshake::new(HandshakeBody::KeyUpdate(KeyUpdate { request_update }))
+            .to_bytes::<NetworkEndian>();
+        self.record_layer.write_handshake(&data)?;
+
+        self.write_traffic_secret = next_traffic_secret(hash, &self.write_traffic_secret);
+        self.record_layer.set_write_protection(traffic_protection(
+            hash,
+            aead,
+            &self.write_traffic_secret,
+        ));
+        Ok(())
+    }
+
+    // KeyUpdate はレコードの境界に揃っていなければならない (set_read_protection で確認)
+    fn process_key_update(&mut self, key_update: KeyUpdate) -> Result<()> {
+        let hash = self.cipher_suite.hash_algorithm();
+        let aead = self.cipher_suite.aead_algorithm().unwrap();
+
+        self.read_traffic_secret = next_traffic_secret(hash, &self.read_traffic_secret);
+        self.record_layer.set_read_protection(traffic_protection(
+            hash,
+            aead,
+            &self.read_traffic_secret,
+        ))?;
+
+        if key_update.request_update == KeyUpdateRequest::update_requested {
+            self.send_key_update(KeyUpdateRequest::update_not_requested)?;
+        }
+        Ok(())
+    }
+
+    // 再ネゴシエーションには対応しないので、TLS 1.2 のハンドシェイクは受け付けない
+    pub fn read_application_message(&mut self) -> Result<ApplicationMessage> {
+        loop {
+            match self.record_layer.read_message()? {
+                Message::ApplicationData(data) => return Ok(ApplicationMessage::Data(data)),
+                Message::Alert(alert) if alert.description == AlertDescription::CloseNotify => {
+                    return Ok(ApplicationMessage::CloseNotify)
+                }
+                Message::Alert(alert)
+                    if self.version == ProtocolVersion::TLSv1_2
+                        && alert.level == AlertLevel::Warning =>
+                {
+                    continue
+                }
+                Message::Alert(alert) => bail!(Error::ReceivedAlert(alert.description)),
+                Message::Handshake(data)
+                    if self.version == ProtocolVersion::TLSv1_3
+                        && matches!(
+                            HandshakeType::try_from(data[0]),
+                            Ok(HandshakeType::KeyUpdate)
+                        ) =>
+                {
+                    let Handshake {
+                        body: HandshakeBody::KeyUpdate(key_update),
+                        ..
+                    } = Handshake::from_bytes(&data, self.version)?
+                    else {
+                        bail!(Error::Alert(AlertDescription::DecodeError))
+                    };
+                    self.process_key_update(key_update)?
+                }
+                Message::Handshake(data) if self.version == ProtocolVersion::TLSv1_3 => {
+                    return Ok(ApplicationMessage::Handshake(data))
+                }
+                _ => bail!(Error::Alert(AlertDescription::UnexpectedMessage)),
+            }
+        }
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.record_layer
+            .write_alert(AlertLevel::Warning, AlertDescription::CloseNotify)
+    }
+
+    // こちらで検出したエラーなら致命的なアラートを送る
+    pub fn abort(&mut self, error: &anyhow::Error) {
+        if let Some(Error::Alert(description)) = error.downcast_ref::<Error>() {
+            let _ = self
+                .record_layer
+                .write_alert(AlertLevel::Fatal, *description);
+        }
+    }
+
+    pub fn send_handshake(
+        &mut self,
+        transcript: &mut Transcript,
+        body: HandshakeBody,
+    ) -> Result<()> {
+        let data = Handshake::new(body).to_bytes::<NetworkEndian>();
+        transcript.update(&data);
+        self.record_layer.write_handshake(&data)
+    }
+
+    // Transcript への追加は呼び出し側で行う (CertificateVerify や Finished は追加前のハッシュを使うため)
+    pub fn read_handshake(&mut self) -> Result<(Vec<u8>, Handshake)> {
+        loop {
+            match self.record_layer.read_message()? {
+                Message::Handshake(data) => {
+                    let handshake = Handshake::from_bytes(&data, self.version)?;
+                    return Ok((data, handshake));
+                }
+                // TLS 1.3 ではミドルボックス互換のための ChangeCipherSpec が来ることがある
+                Message::ChangeCipherSpec if self.version == ProtocolVersion::TLSv1_3 => continue,
+                Message::Alert(alert) => bail!(Error::ReceivedAlert(alert.description)),
+                _ => bail!(Error::Alert(AlertDescription::UnexpectedMessage)),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixStream;
+
+    const CIPHER_SUITE: CipherSuite = CipherSuite::TLS_AES_128_GCM_SHA256;
+
+    // ハンドシェイクを済ませた状態の TLS 1.3 の接続。skip は送信鍵で送ったことにするレコード数
+    fn tls13_core(
+        stream: UnixStream,
+        write_secret: &[u8],
+        read_secret: &[u8],
+        skip: (u64, u64),
+    ) -> ConnectionCore<UnixStream> {
+        let hash = CIPHER_SUITE.hash_algorithm();
+        let aead = CIPHER_SUITE.aead_algorithm().unwrap();
+        let mut core = ConnectionCore::new(stream);
+        core.version = ProtocolVersion::TLSv1_3;
+        core.cipher_suite = CIPHER_SUITE;
+        core.write_traffic_secret = write_secret.to_vec();
+        core.read_traffic_secret = read_secret.to_vec();
+
+        let mut write_protection = traffic_protection(hash, aead, write_secret);
+        write_protection.skip_records(skip.0);
+        core.record_layer.set_write_protection(write_protection);
+        let mut read_protection = traffic_protection(hash, aead, read_secret);
+        read_protection.skip_records(skip.1);
+        core.record_layer
+            .set_read_protection(read_protection)
+            .unwrap();
+        core
+    }
+
+    fn pair(skip: u64) -> (ConnectionCore<UnixStream>, ConnectionCore<UnixStream>) {
+        let (a, b) = UnixStream::pair().unwrap();
+        let (a_secret, b_secret) = ([1; 32], [2; 32]);
+        (
+            tls13_core(a, &a_secret, &b_secret, (skip, 0)),
+            tls13_core(b, &b_secret, &a_secret, (0, skip)),
+        )
+    }
+
+    fn read_data<S: Read + Write>(core: &mut ConnectionCore<S>) -> Vec<u8> {
+        match core.read_application_message().unwrap() {
+            ApplicationMessage::Data(data) => data,
+            _ => panic!("expected application data"),
+        }
+    }
+
+    #[test]
+    fn key_update() {
+        let (mut a, mut b) = pair(0);
+        let (a_secret, b_secret) = (
+            a.write_traffic_secret.clone(),
+            b.write_traffic_secret.clone(),
+        );
+
+        // a の送信鍵だけ更新する
+        a.update_keys(false).unwrap();
+        a.write(b"after update").unwrap();
+        assert_eq!(read_data(&mut b), b"after update");
+        assert_ne!(a.write_traffic_secret, a_secret);
+        assert_eq!(b.read_traffic_secret, a.write_traffic_secret);
+
+        // b は a にも更新を要求し、a は次に読んだときに応える
+        b.update_keys(true).unwrap();
+        b.write(b"requested").unwrap();
+        assert_eq!(read_data(&mut a), b"requested");
+        a.write(b"answered").unwrap();
+        assert_eq!(read_data(&mut b), b"answered");
+
+        assert_ne!(b.write_traffic_secret, b_secret);
+        assert_eq!(a.read_traffic_secret, b.write_traffic_secret);
+        assert_eq!(b.read_traffic_secret, a.write_traffic_secret);
+        assert_eq!(
+            a.write_traffic_secret,
+            next_traffic_secret(
+                CIPHER_SUITE.hash_algorithm(),
+                &next_traffic_secret(CIPHER_SUITE.hash_algorithm(), &a_secret)
+            )
+        );
+    }
+
+    #[test]
+    fn automatic_key_update() {
+        let limit = CIPHER_SUITE.aead_algorithm().unwrap().record_limit();
+        let (mut a, mut b) = pair(limit - 1);
+        let secret = a.write_traffic_secret.clone();
+
+        // 上限の直前のレコードはそのまま送り、次のレコードの前に鍵を更新する
+        a.write(b"last").unwrap();
+        assert_eq!(a.write_traffic_secret, secret);
+        assert!(a.record_layer.is_write_key_exhausted());
+        a.write(b"first").unwrap();
+        assert_ne!(a.write_traffic_secret, secret);
+        assert!(!a.record_layer.is_write_key_exhausted());
+
+        assert_eq!(read_data(&mut b), b"last");
+        assert_eq!(read_data(&mut b), b"first");
+        assert_eq!(b.read_traffic_secret, a.write_traffic_secret);
+
+        b.write(b"reply").unwrap();
+        assert_eq!(read_data(&mut a), b"reply");
+    }
+
+    #[test]
+    fn tls12_key_update() {
+        let (a, _) = UnixStream::pair().unwrap();
+        let mut core = ConnectionCore::new(a);
+        assert!(core.update_keys(false).is_err());
+    }
+}
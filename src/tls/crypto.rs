@@ -0,0 +1,25 @@
+mod aead;
+mod aes;
+mod bigint;
+mod ccm;
+mod gcm;
+mod hash;
+mod hkdf;
+mod hmac;
+mod kx;
+mod prf;
+mod rsa;
+mod signature;
+
+pub use aead::*;
+pub use aes::*;
+pub use bigint::*;
+pub use ccm::*;
+pub use gcm::*;
+pub use hash::*;
+pub use hkdf::*;
+pub use hmac::*;
+pub use kx::*;
+pub use prf::*;
+pub use rsa::*;
+pub use signature::*;
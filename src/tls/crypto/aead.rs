@@ -0,0 +1,97 @@
+use super::ccm::Ccm;
+use super::gcm::Gcm;
+
+use anyhow::Result;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AeadAlgorithm {
+    Aes128Gcm,
+    Aes256Gcm,
+    Aes128Ccm,
+    Aes128Ccm8,
+    Aes256Ccm,
+    Aes256Ccm8,
+}
+
+impl AeadAlgorithm {
+    pub fn key_length(&self) -> usize {
+        match self {
+            AeadAlgorithm::Aes128Gcm | AeadAlgorithm::Aes128Ccm | AeadAlgorithm::Aes128Ccm8 => 16,
+            AeadAlgorithm::Aes256Gcm | AeadAlgorithm::Aes256Ccm | AeadAlgorithm::Aes256Ccm8 => 32,
+        }
+    }
+
+    pub fn nonce_length(&self) -> usize {
+        12
+    }
+
+    pub fn tag_length(&self) -> usize {
+        match self {
+            AeadAlgorithm::Aes128Ccm8 | AeadAlgorithm::Aes256Ccm8 => 8,
+            _ => 16,
+        }
+    }
+
+    // 1つの鍵で暗号化してよいレコード数 (RFC 8446 5.5, RFC 9147 4.5.3 の値より少し小さくしている)
+    pub fn record_limit(&self) -> u64 {
+        match self {
+            AeadAlgorithm::Aes128Gcm | AeadAlgorithm::Aes256Gcm => 1 << 24,
+            _ => 1 << 23,
+        }
+    }
+}
+
+pub enum AeadCipher {
+    Gcm(Box<Gcm>),
+    Ccm(Box<Ccm>),
+}
+
+impl AeadCipher {
+    pub fn new(algorithm: AeadAlgorithm, key: &[u8]) -> Self {
+        assert_eq!(key.len(), algorithm.key_length());
+        match algorithm {
+            AeadAlgorithm::Aes128Gcm | AeadAlgorithm::Aes256Gcm => {
+                AeadCipher::Gcm(Box::new(Gcm::new(key)))
+            }
+            _ => AeadCipher::Ccm(Box::new(Ccm::new(key, algorithm.tag_length()))),
+        }
+    }
+
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        match self {
+            AeadCipher::Gcm(c) => c.seal(nonce, aad, plaintext),
+            AeadCipher::Ccm(c) => c.seal(nonce, aad, plaintext),
+        }
+    }
+
+    pub fn open(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            AeadCipher::Gcm(c) => c.open(nonce, aad, ciphertext),
+            AeadCipher::Ccm(c) => c.open(nonce, aad, ciphertext),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seal_and_open() {
+        for algorithm in [
+            AeadAlgorithm::Aes128Gcm,
+            AeadAlgorithm::Aes256Gcm,
+            AeadAlgorithm::Aes128Ccm,
+            AeadAlgorithm::Aes128Ccm8,
+            AeadAlgorithm::Aes256Ccm,
+            AeadAlgorithm::Aes256Ccm8,
+        ] {
+            let cipher = AeadCipher::new(algorithm, &vec![0x2b; algorithm.key_length()]);
+            let nonce = vec![0x5c; algorithm.nonce_length()];
+            let sealed = cipher.seal(&nonce, b"aad", b"plaintext");
+            assert_eq!(sealed.len(), 9 + algorithm.tag_length());
+            assert_eq!(cipher.open(&nonce, b"aad", &sealed).unwrap(), b"plaintext");
+            assert!(cipher.open(&nonce, b"other", &sealed).is_err());
+        }
+    }
+}
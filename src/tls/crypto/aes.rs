@@ -0,0 +1,381 @@
+// FIPS 197 AES
+// 秘密の値で表を引くとキャッシュのタイミングで鍵が漏れるので、S-box も含めてビットスライスで計算する
+//
+// 4 ブロック (64 bytes) をまとめて、各バイトの j ビット目を q[j] の 1 ビットとして持つ
+// ブロック b の i バイト目 (i = 4 * 列 + 行) は q[j] の 16 * b + i ビット目
+pub const AES_BLOCK_SIZE: usize = 16;
+
+// 同時に処理するブロック数
+const PARALLEL_BLOCKS: usize = 4;
+
+type State = [u64; 8];
+
+// FIPS 197 5.2. 鍵拡張の Rcon[i] の先頭のバイト
+const RCON: [u8; 10] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1b, 0x36];
+
+#[derive(Clone)]
+pub struct Aes {
+    // 全ブロック分に並べた各ラウンドの鍵
+    round_keys: Vec<State>,
+}
+
+impl Aes {
+    // 鍵の長さで AES-128, AES-192, AES-256 を選ぶ
+    pub fn new(key: &[u8]) -> Self {
+        assert!(matches!(key.len(), 16 | 24 | 32));
+        let round_keys = expand_key(key)
+            .iter()
+            .map(|round_key| bitslice(&[*round_key; PARALLEL_BLOCKS]))
+            .collect();
+        Aes { round_keys }
+    }
+
+    pub fn rounds(&self) -> usize {
+        self.round_keys.len() - 1
+    }
+
+    pub fn encrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        self.encrypt_blocks(std::slice::from_mut(block));
+    }
+
+    pub fn decrypt_block(&self, block: &mut [u8; AES_BLOCK_SIZE]) {
+        self.decrypt_blocks(std::slice::from_mut(block));
+    }
+
+    // FIPS 197 5.1. Cipher
+    pub fn encrypt_blocks(&self, blocks: &mut [[u8; AES_BLOCK_SIZE]]) {
+        let rounds = self.rounds();
+        for chunk in blocks.chunks_mut(PARALLEL_BLOCKS) {
+            let mut q = bitslice(chunk);
+            add_round_key(&mut q, &self.round_keys[0]);
+            for round_key in &self.round_keys[1..rounds] {
+                sub_bytes(&mut q);
+                shift_rows(&mut q);
+                mix_columns(&mut q);
+                add_round_key(&mut q, round_key);
+            }
+            sub_bytes(&mut q);
+            shift_rows(&mut q);
+            add_round_key(&mut q, &self.round_keys[rounds]);
+            unbitslice(&q, chunk);
+        }
+    }
+
+    // FIPS 197 5.3. InvCipher
+    pub fn decrypt_blocks(&self, blocks: &mut [[u8; AES_BLOCK_SIZE]]) {
+        let rounds = self.rounds();
+        for chunk in blocks.chunks_mut(PARALLEL_BLOCKS) {
+            let mut q = bitslice(chunk);
+            add_round_key(&mut q, &self.round_keys[rounds]);
+            for round_key in self.round_keys[1..rounds].iter().rev() {
+                inv_shift_rows(&mut q);
+                inv_sub_bytes(&mut q);
+                add_round_key(&mut q, round_key);
+                inv_mix_columns(&mut q);
+            }
+            inv_shift_rows(&mut q);
+            inv_sub_bytes(&mut q);
+            add_round_key(&mut q, &self.round_keys[0]);
+            unbitslice(&q, chunk);
+        }
+    }
+}
+
+// FIPS 197 5.2. KeyExpansion
+fn expand_key(key: &[u8]) -> Vec<[u8; AES_BLOCK_SIZE]> {
+    let nk = key.len() / 4;
+    let rounds = nk + 6;
+    let mut words: Vec<[u8; 4]> = key
+        .chunks_exact(4)
+        .map(|word| word.try_into().unwrap())
+        .collect();
+    for i in nk..4 * (rounds + 1) {
+        let mut temp = words[i - 1];
+        if i % nk == 0 {
+            temp.rotate_left(1);
+            temp = sub_word(temp);
+            temp[0] ^= RCON[i / nk - 1];
+        } else if nk > 6 && i % nk == 4 {
+            temp = sub_word(temp);
+        }
+        let word = words[i - nk];
+        words.push(std::array::from_fn(|j| word[j] ^ temp[j]));
+    }
+    words
+        .chunks_exact(4)
+        .map(|round_key| round_key.concat().try_into().unwrap())
+        .collect()
+}
+
+// 鍵も秘密なので、鍵拡張の S-box もビットスライスで計算する
+fn sub_word(word: [u8; 4]) -> [u8; 4] {
+    let mut block = [0u8; AES_BLOCK_SIZE];
+    block[..4].copy_from_slice(&word);
+    let mut q = bitslice(&[block]);
+    sub_bytes(&mut q);
+    let mut blocks = [[0u8; AES_BLOCK_SIZE]];
+    unbitslice(&q, &mut blocks);
+    blocks[0][..4].try_into().unwrap()
+}
+
+fn bitslice(blocks: &[[u8; AES_BLOCK_SIZE]]) -> State {
+    let mut q = [0u64; 8];
+    for (b, block) in blocks.iter().enumerate() {
+        for (i, byte) in block.iter().enumerate() {
+            for (j, plane) in q.iter_mut().enumerate() {
+                *plane |= (((byte >> j) & 1) as u64) << (AES_BLOCK_SIZE * b + i);
+            }
+        }
+    }
+    q
+}
+
+fn unbitslice(q: &State, blocks: &mut [[u8; AES_BLOCK_SIZE]]) {
+    for (b, block) in blocks.iter_mut().enumerate() {
+        for (i, byte) in block.iter_mut().enumerate() {
+            *byte = q.iter().enumerate().fold(0, |acc, (j, plane)| {
+                acc | ((((plane >> (AES_BLOCK_SIZE * b + i)) & 1) as u8) << j)
+            });
+        }
+    }
+}
+
+fn add_round_key(q: &mut State, round_key: &State) {
+    for (plane, key) in q.iter_mut().zip(round_key) {
+        *plane ^= key;
+    }
+}
+
+//==================================================================================================
+// SubBytes
+//==================================================================================================
+
+// S-box は GF(2^8) の逆元にアフィン変換をかけたもの (FIPS 197 5.1.1)
+fn sub_bytes(q: &mut State) {
+    let x = gf_inverse(q);
+    for i in 0..8 {
+        // 0x63 のビットが立っているところは反転する
+        let constant = if (0x63 >> i) & 1 == 1 { !0 } else { 0 };
+        q[i] = x[i] ^ x[(i + 4) % 8] ^ x[(i + 5) % 8] ^ x[(i + 6) % 8] ^ x[(i + 7) % 8] ^ constant;
+    }
+}
+
+// アフィン変換を戻してから逆元を取る (FIPS 197 5.3.2)
+fn inv_sub_bytes(q: &mut State) {
+    let mut x = [0u64; 8];
+    for i in 0..8 {
+        let constant = if (0x05 >> i) & 1 == 1 { !0 } else { 0 };
+        x[i] = q[(i + 2) % 8] ^ q[(i + 5) % 8] ^ q[(i + 7) % 8] ^ constant;
+    }
+    *q = gf_inverse(&x);
+}
+
+// x^254 = x^-1 (0 は 0 になる)
+fn gf_inverse(x: &State) -> State {
+    let x2 = gf_square(x);
+    let x3 = gf_mul(&x2, x);
+    let x6 = gf_square(&x3);
+    let x12 = gf_square(&x6);
+    let x15 = gf_mul(&x12, &x3);
+    let x30 = gf_square(&x15);
+    let x60 = gf_square(&x30);
+    let x120 = gf_square(&x60);
+    let x240 = gf_square(&x120);
+    let x252 = gf_mul(&x240, &x12);
+    gf_mul(&x252, &x2)
+}
+
+fn gf_mul(a: &State, b: &State) -> State {
+    let mut product = [0u64; 15];
+    for (i, a) in a.iter().enumerate() {
+        for (j, b) in b.iter().enumerate() {
+            product[i + j] ^= a & b;
+        }
+    }
+    gf_reduce(product)
+}
+
+// 2乗は係数を1つおきに並べるだけ
+fn gf_square(a: &State) -> State {
+    let mut product = [0u64; 15];
+    for (i, a) in a.iter().enumerate() {
+        product[2 * i] = *a;
+    }
+    gf_reduce(product)
+}
+
+// x^8 = x^4 + x^3 + x + 1 で次数を下げる
+fn gf_reduce(mut product: [u64; 15]) -> State {
+    for k in (8..15).rev() {
+        let t = product[k];
+        product[k - 8] ^= t;
+        product[k - 7] ^= t;
+        product[k - 5] ^= t;
+        product[k - 4] ^= t;
+    }
+    product[..8].try_into().unwrap()
+}
+
+//==================================================================================================
+// ShiftRows, MixColumns
+//==================================================================================================
+
+const ROW_MASK: u64 = 0x1111_1111_1111_1111;
+
+// 各ブロック (16 bits) の中で右に rotate する
+fn rotate_blocks(x: u64, shift: u32) -> u64 {
+    let low = 0x0001_0001_0001_0001 * (0xffff >> shift);
+    ((x >> shift) & low) | ((x << (AES_BLOCK_SIZE as u32 - shift)) & !low)
+}
+
+// r 行目は r 列左にずらす。列が 4 bits 間隔なので、ブロックの中で 4r bits 右に rotate する
+fn shift_rows(q: &mut State) {
+    for plane in q.iter_mut() {
+        let mut x = *plane & ROW_MASK;
+        for row in 1..4 {
+            x |= rotate_blocks(*plane & (ROW_MASK << row), 4 * row);
+        }
+        *plane = x;
+    }
+}
+
+fn inv_shift_rows(q: &mut State) {
+    for plane in q.iter_mut() {
+        let mut x = *plane & ROW_MASK;
+        for row in 1..4 {
+            x |= rotate_blocks(*plane & (ROW_MASK << row), 16 - 4 * row);
+        }
+        *plane = x;
+    }
+}
+
+// 列の中で n 行下のバイトを持ってくる
+fn rotate_rows(x: u64, n: u32) -> u64 {
+    let low = 0x1111_1111_1111_1111 * (0xf >> n);
+    ((x >> n) & low) | ((x << (4 - n)) & !low)
+}
+
+fn xtime(q: &State) -> State {
+    [
+        q[7],
+        q[0] ^ q[7],
+        q[1],
+        q[2] ^ q[7],
+        q[3] ^ q[7],
+        q[4],
+        q[5],
+        q[6],
+    ]
+}
+
+// 2a_r + 3a_{r+1} + a_{r+2} + a_{r+3} = 2(a_r + a_{r+1}) + a_{r+1} + a_{r+2} + a_{r+3}
+fn mix_columns(q: &mut State) {
+    let t = q.map(|x| x ^ rotate_rows(x, 1));
+    let t = xtime(&t);
+    for (plane, t) in q.iter_mut().zip(t) {
+        *plane = t ^ rotate_rows(*plane, 1) ^ rotate_rows(*plane, 2) ^ rotate_rows(*plane, 3);
+    }
+}
+
+// InvMixColumns の行列は MixColumns の行列と {05, 00, 04, 00} の積
+fn inv_mix_columns(q: &mut State) {
+    let t = q.map(|x| x ^ rotate_rows(x, 2));
+    let t = xtime(&xtime(&t));
+    for (plane, t) in q.iter_mut().zip(t) {
+        *plane ^= t;
+    }
+    mix_columns(q);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::{generic_array::GenericArray, BlockEncrypt, KeyInit};
+    use hex_literal::hex;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    fn check(key: &[u8], plaintext: [u8; 16], ciphertext: [u8; 16]) {
+        let aes = Aes::new(key);
+        let mut block = plaintext;
+        aes.encrypt_block(&mut block);
+        assert_eq!(block, ciphertext);
+        aes.decrypt_block(&mut block);
+        assert_eq!(block, plaintext);
+    }
+
+    // FIPS 197 Appendix B と C.1 - C.3
+    #[test]
+    fn fips197() {
+        check(
+            &hex!("2b7e151628aed2a6abf7158809cf4f3c"),
+            hex!("3243f6a8885a308d313198a2e0370734"),
+            hex!("3925841d02dc09fbdc118597196a0b32"),
+        );
+
+        let plaintext = hex!("00112233445566778899aabbccddeeff");
+        check(
+            &hex!("000102030405060708090a0b0c0d0e0f"),
+            plaintext,
+            hex!("69c4e0d86a7b0430d8cdb78070b4c55a"),
+        );
+        check(
+            &hex!("000102030405060708090a0b0c0d0e0f1011121314151617"),
+            plaintext,
+            hex!("dda97ca4864cdfe06eaf70a0ec0d7191"),
+        );
+        check(
+            &hex!("000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"),
+            plaintext,
+            hex!("8ea2b7ca516745bfeafc49904b496089"),
+        );
+    }
+
+    #[test]
+    fn rounds() {
+        assert_eq!(Aes::new(&[0; 16]).rounds(), 10);
+        assert_eq!(Aes::new(&[0; 24]).rounds(), 12);
+        assert_eq!(Aes::new(&[0; 32]).rounds(), 14);
+    }
+
+    // PARALLEL_BLOCKS で割り切れない個数のブロックも RustCrypto の aes と一致すること
+    #[test]
+    fn compare_with_aes_crate() {
+        let mut rng = StdRng::seed_from_u64(197);
+        for key_length in [16, 24, 32] {
+            for count in [1, 3, 4, 5, 9] {
+                let mut key = vec![0u8; key_length];
+                rng.fill_bytes(&mut key);
+                let mut blocks = vec![[0u8; AES_BLOCK_SIZE]; count];
+                blocks.iter_mut().for_each(|block| rng.fill_bytes(block));
+                let plaintext = blocks.clone();
+
+                let mut expected: Vec<_> = blocks
+                    .iter()
+                    .map(|block| GenericArray::clone_from_slice(block))
+                    .collect();
+                match key_length {
+                    16 => aes::Aes128::new_from_slice(&key)
+                        .unwrap()
+                        .encrypt_blocks(&mut expected),
+                    24 => aes::Aes192::new_from_slice(&key)
+                        .unwrap()
+                        .encrypt_blocks(&mut expected),
+                    _ => aes::Aes256::new_from_slice(&key)
+                        .unwrap()
+                        .encrypt_blocks(&mut expected),
+                }
+
+                let aes = Aes::new(&key);
+                aes.encrypt_blocks(&mut blocks);
+                assert!(blocks
+                    .iter()
+                    .zip(&expected)
+                    .all(|(ours, theirs)| ours[..] == theirs[..]));
+
+                aes.decrypt_blocks(&mut blocks);
+                assert_eq!(blocks, plaintext);
+            }
+        }
+    }
+}
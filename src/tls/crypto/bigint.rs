@@ -0,0 +1,486 @@
+use anyhow::{bail, Result};
+
+// RSA 用の多倍長の符号なし整数
+// 秘密の値を扱う演算は、値によって分岐やメモリアクセスが変わらないようにマスクで計算する
+// 桁数 (limb の数) は鍵の長さから決まるので秘密ではないものとして扱う
+#[derive(Clone)]
+pub struct BigUint {
+    // 下位の limb から並べる
+    limbs: Vec<u64>,
+}
+
+impl BigUint {
+    pub fn from_bytes_be(bytes: &[u8]) -> Self {
+        let mut limbs = vec![0u64; bytes.len().div_ceil(8).max(1)];
+        for (i, byte) in bytes.iter().rev().enumerate() {
+            limbs[i / 8] |= (*byte as u64) << (8 * (i % 8));
+        }
+        BigUint { limbs }
+    }
+
+    pub fn from_u64(value: u64) -> Self {
+        BigUint { limbs: vec![value] }
+    }
+
+    // length bytes に収まらない上位の桁は捨てる
+    pub fn to_bytes_be(&self, length: usize) -> Vec<u8> {
+        (0..length)
+            .rev()
+            .map(|i| {
+                self.limbs
+                    .get(i / 8)
+                    .map_or(0, |limb| (limb >> (8 * (i % 8))) as u8)
+            })
+            .collect()
+    }
+
+    // 公開された値にだけ使う
+    pub fn bits(&self) -> usize {
+        self.limbs
+            .iter()
+            .rposition(|limb| *limb != 0)
+            .map_or(0, |i| 64 * i + 64 - self.limbs[i].leading_zeros() as usize)
+    }
+
+    pub fn is_odd(&self) -> bool {
+        self.limbs[0] & 1 == 1
+    }
+
+    // 値は変えずに limb の数を揃える。length より上の桁は 0 でなければならない
+    fn resize(&self, length: usize) -> Vec<u64> {
+        let mut limbs = self.limbs.clone();
+        debug_assert!(limbs.iter().skip(length).all(|limb| *limb == 0));
+        limbs.resize(length, 0);
+        limbs
+    }
+
+    pub fn ct_eq(&self, other: &BigUint) -> bool {
+        let length = self.limbs.len().max(other.limbs.len());
+        let diff = self
+            .resize(length)
+            .iter()
+            .zip(other.resize(length))
+            .fold(0, |acc, (a, b)| acc | (a ^ b));
+        diff == 0
+    }
+
+    // self < other (公開された値にだけ使う)
+    pub fn lt(&self, other: &BigUint) -> bool {
+        let length = self.limbs.len().max(other.limbs.len());
+        let mut a = self.resize(length);
+        sub_limbs(&mut a, &other.resize(length)) == 1
+    }
+
+    pub fn add(&self, other: &BigUint) -> BigUint {
+        let length = self.limbs.len().max(other.limbs.len()) + 1;
+        let mut limbs = self.resize(length);
+        add_limbs(&mut limbs, &other.resize(length));
+        BigUint { limbs }
+    }
+
+    // self >= other でなければならない
+    pub fn sub(&self, other: &BigUint) -> BigUint {
+        let length = self.limbs.len().max(other.limbs.len());
+        let mut limbs = self.resize(length);
+        sub_limbs(&mut limbs, &other.resize(length));
+        BigUint { limbs }
+    }
+
+    pub fn mul(&self, other: &BigUint) -> BigUint {
+        let mut limbs = vec![0u64; self.limbs.len() + other.limbs.len()];
+        for (i, a) in self.limbs.iter().enumerate() {
+            let mut carry = 0u64;
+            for (j, b) in other.limbs.iter().enumerate() {
+                let t = *a as u128 * *b as u128 + limbs[i + j] as u128 + carry as u128;
+                limbs[i + j] = t as u64;
+                carry = (t >> 64) as u64;
+            }
+            limbs[i + other.limbs.len()] = carry;
+        }
+        BigUint { limbs }
+    }
+}
+
+// a += b。桁上がりを返す
+fn add_limbs(a: &mut [u64], b: &[u64]) -> u64 {
+    let mut carry = 0u64;
+    for (a, b) in a.iter_mut().zip(b) {
+        let t = *a as u128 + *b as u128 + carry as u128;
+        *a = t as u64;
+        carry = (t >> 64) as u64;
+    }
+    carry
+}
+
+// a -= b。桁借りを返す
+fn sub_limbs(a: &mut [u64], b: &[u64]) -> u64 {
+    let mut borrow = 0u64;
+    for (a, b) in a.iter_mut().zip(b) {
+        let t = (*a as u128).wrapping_sub(*b as u128 + borrow as u128);
+        *a = t as u64;
+        borrow = ((t >> 64) as u64) & 1;
+    }
+    borrow
+}
+
+// condition が 1 なら b、0 なら a
+fn select(a: &mut [u64], b: &[u64], condition: u64) {
+    let mask = 0u64.wrapping_sub(condition);
+    for (a, b) in a.iter_mut().zip(b) {
+        *a ^= (*a ^ b) & mask;
+    }
+}
+
+// a == b なら全ビット 1、それ以外は 0
+fn eq_mask(a: u64, b: u64) -> u64 {
+    let x = a ^ b;
+    ((x | x.wrapping_neg()) >> 63).wrapping_sub(1)
+}
+
+//==================================================================================================
+// 剰余演算
+//==================================================================================================
+
+// 奇数の法 m。掛け算とべき乗はモンゴメリ乗算で行う (R = 2^(64 * limbs))
+#[derive(Clone)]
+pub struct Modulus {
+    m: Vec<u64>,
+    // -m^-1 mod 2^64
+    m_inv: u64,
+    // R^2 mod m
+    r2: Vec<u64>,
+}
+
+impl Modulus {
+    pub fn new(m: &BigUint) -> Result<Self> {
+        if !m.is_odd() || m.bits() < 2 {
+            bail!("modulus must be odd and greater than 1");
+        }
+        let m = m.resize(m.bits().div_ceil(64));
+
+        // ニュートン法で m[0] の 2^64 を法とした逆元を求める
+        let mut inv = 1u64;
+        for _ in 0..6 {
+            inv = inv.wrapping_mul(2u64.wrapping_sub(m[0].wrapping_mul(inv)));
+        }
+
+        let mut modulus = Modulus {
+            m_inv: inv.wrapping_neg(),
+            r2: vec![],
+            m,
+        };
+        // 1 を 2 倍することを繰り返して R^2 mod m を作る
+        let mut r2 = vec![0u64; modulus.m.len()];
+        r2[0] = 1;
+        for _ in 0..128 * modulus.m.len() {
+            let carry = double(&mut r2);
+            modulus.reduce_once(&mut r2, carry);
+        }
+        modulus.r2 = r2;
+        Ok(modulus)
+    }
+
+    // carry * 2^(64 * limbs) + a < 2m のとき、m 未満にする
+    fn reduce_once(&self, a: &mut [u64], carry: u64) {
+        let mut t = a.to_vec();
+        let borrow = sub_limbs(&mut t, &self.m);
+        select(a, &t, carry | (borrow ^ 1));
+    }
+
+    // x mod m。上位のビットから 1 ビットずつ足し込む
+    pub fn reduce(&self, x: &BigUint) -> BigUint {
+        let mut r = vec![0u64; self.m.len()];
+        for limb in x.limbs.iter().rev() {
+            for i in (0..64).rev() {
+                let carry = double(&mut r);
+                r[0] |= (limb >> i) & 1;
+                self.reduce_once(&mut r, carry);
+            }
+        }
+        BigUint { limbs: r }
+    }
+
+    // CIOS 法のモンゴメリ乗算 a * b * R^-1 mod m (a, b < m)
+    fn mont_mul(&self, a: &[u64], b: &[u64]) -> Vec<u64> {
+        let n = self.m.len();
+        let mut t = vec![0u64; n + 2];
+        for b in b {
+            let mut carry = 0u64;
+            for (t, a) in t.iter_mut().zip(a) {
+                let s = *a as u128 * *b as u128 + *t as u128 + carry as u128;
+                *t = s as u64;
+                carry = (s >> 64) as u64;
+            }
+            let s = t[n] as u128 + carry as u128;
+            t[n] = s as u64;
+            t[n + 1] = (s >> 64) as u64;
+
+            let u = t[0].wrapping_mul(self.m_inv);
+            let s = t[0] as u128 + u as u128 * self.m[0] as u128;
+            let mut carry = (s >> 64) as u64;
+            for j in 1..n {
+                let s = u as u128 * self.m[j] as u128 + t[j] as u128 + carry as u128;
+                t[j - 1] = s as u64;
+                carry = (s >> 64) as u64;
+            }
+            let s = t[n] as u128 + carry as u128;
+            t[n - 1] = s as u64;
+            t[n] = t[n + 1] + (s >> 64) as u64;
+        }
+        let carry = t[n];
+        t.truncate(n);
+        self.reduce_once(&mut t, carry);
+        t
+    }
+
+    fn to_montgomery(&self, a: &BigUint) -> Vec<u64> {
+        self.mont_mul(&self.reduce(a).limbs, &self.r2)
+    }
+
+    // モンゴメリ表現から戻す (a * R^-1 mod m)
+    fn redc(&self, a: &[u64]) -> BigUint {
+        let mut one = vec![0u64; self.m.len()];
+        one[0] = 1;
+        BigUint {
+            limbs: self.mont_mul(a, &one),
+        }
+    }
+
+    // a * b mod m
+    pub fn mul(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        let a = self.to_montgomery(a);
+        let b = self.reduce(b);
+        BigUint {
+            limbs: self.mont_mul(&a, &b.limbs),
+        }
+    }
+
+    // a - b mod m (a, b < m)
+    pub fn sub(&self, a: &BigUint, b: &BigUint) -> BigUint {
+        let mut r = a.resize(self.m.len());
+        let borrow = sub_limbs(&mut r, &b.resize(self.m.len()));
+        let mut t = r.clone();
+        add_limbs(&mut t, &self.m);
+        select(&mut r, &t, borrow);
+        BigUint { limbs: r }
+    }
+
+    // base^exponent mod m
+    // 4 bits ずつの固定ウィンドウで、表は全要素を読んでマスクで選ぶ
+    pub fn pow(&self, base: &BigUint, exponent: &BigUint) -> BigUint {
+        const WINDOW: usize = 4;
+
+        let mut one = vec![0u64; self.m.len()];
+        one[0] = 1;
+        let mut table = vec![self.mont_mul(&one, &self.r2)];
+        let base = self.to_montgomery(base);
+        for i in 1..1 << WINDOW {
+            table.push(self.mont_mul(&table[i - 1], &base));
+        }
+
+        let mut result = table[0].clone();
+        for limb in exponent.limbs.iter().rev() {
+            for shift in (0..64 / WINDOW).rev() {
+                for _ in 0..WINDOW {
+                    result = self.mont_mul(&result, &result);
+                }
+                let index = (limb >> (shift * WINDOW)) & ((1 << WINDOW) - 1);
+                let mut factor = vec![0u64; self.m.len()];
+                for (i, entry) in table.iter().enumerate() {
+                    let mask = eq_mask(i as u64, index);
+                    for (f, e) in factor.iter_mut().zip(entry) {
+                        *f |= e & mask;
+                    }
+                }
+                result = self.mont_mul(&result, &factor);
+            }
+        }
+        self.redc(&result)
+    }
+}
+
+// a *= 2。桁上がりを返す
+fn double(a: &mut [u64]) -> u64 {
+    let mut carry = 0u64;
+    for limb in a.iter_mut() {
+        let next = *limb >> 63;
+        *limb = (*limb << 1) | carry;
+        carry = next;
+    }
+    carry
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::{Rng, SeedableRng};
+
+    // 奇数桁の 16 進数も受け付ける
+    fn int(hex: &str) -> BigUint {
+        let hex = format!("{}{}", "0".repeat(hex.len() % 2), hex);
+        let bytes: Vec<u8> = (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect();
+        BigUint::from_bytes_be(&bytes)
+    }
+
+    fn assert_int_eq(a: &BigUint, b: &BigUint) {
+        assert!(a.ct_eq(b), "{:x?} != {:x?}", a.limbs, b.limbs);
+    }
+
+    #[test]
+    fn bytes() {
+        let x = BigUint::from_bytes_be(&[0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(x.limbs, vec![0x0203040506070809, 0x01]);
+        assert_eq!(x.bits(), 65);
+        assert_eq!(x.to_bytes_be(4), vec![6, 7, 8, 9]);
+        assert_eq!(x.to_bytes_be(12), vec![0, 0, 0, 1, 2, 3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(BigUint::from_bytes_be(&[]).bits(), 0);
+        assert!(BigUint::from_u64(0).ct_eq(&BigUint::from_bytes_be(&[0; 20])));
+    }
+
+    #[test]
+    fn arithmetic() {
+        let max = BigUint::from_u64(u64::MAX);
+        let one = BigUint::from_u64(1);
+        let sum = max.add(&one);
+        assert_eq!(sum.limbs, vec![0, 1]);
+        assert_int_eq(&sum.sub(&one), &max);
+        assert_int_eq(&max.mul(&max), &int("fffffffffffffffe0000000000000001"));
+        assert!(max.lt(&sum));
+        assert!(!sum.lt(&max));
+        assert!(!max.lt(&max));
+    }
+
+    #[test]
+    fn invalid_modulus() {
+        assert!(Modulus::new(&BigUint::from_u64(0)).is_err());
+        assert!(Modulus::new(&BigUint::from_u64(1)).is_err());
+        assert!(Modulus::new(&BigUint::from_u64(1 << 40)).is_err());
+        assert!(Modulus::new(&BigUint::from_u64(3)).is_ok());
+    }
+
+    // 1 limb の法は u128 で計算した値と比べる
+    #[test]
+    fn single_limb() {
+        fn pow(mut base: u128, mut exponent: u64, m: u128) -> u128 {
+            let mut result = 1 % m;
+            base %= m;
+            while exponent > 0 {
+                if exponent & 1 == 1 {
+                    result = result * base % m;
+                }
+                base = base * base % m;
+                exponent >>= 1;
+            }
+            result
+        }
+
+        let mut rng = StdRng::seed_from_u64(8017);
+        for m in [3, 0x8000_0000_0000_0001, u64::MAX, rng.gen::<u64>() | 1] {
+            let modulus = Modulus::new(&BigUint::from_u64(m)).unwrap();
+            for _ in 0..20 {
+                let (a, b, e) = (rng.gen::<u64>(), rng.gen::<u64>() % m, rng.gen::<u64>());
+                let a_m = a % m;
+                let product = a as u128 * b as u128 % m as u128;
+                let (a, b) = (BigUint::from_u64(a), BigUint::from_u64(b));
+
+                assert_eq!(modulus.reduce(&a).limbs, vec![a_m]);
+                assert_eq!(modulus.mul(&a, &b).limbs, vec![product as u64]);
+                assert_eq!(
+                    modulus.pow(&a, &BigUint::from_u64(e)).limbs,
+                    vec![pow(a_m as u128, e, m as u128) as u64]
+                );
+                assert_eq!(
+                    modulus.sub(&modulus.reduce(&a), &b).limbs,
+                    vec![((a_m as u128 + m as u128 - b.limbs[0] as u128) % m as u128) as u64]
+                );
+            }
+            assert_eq!(
+                modulus
+                    .pow(&BigUint::from_u64(2), &BigUint::from_u64(0))
+                    .limbs,
+                vec![1 % m]
+            );
+        }
+    }
+
+    // 複数 limb の法。値は Python の int で計算したもの
+    #[test]
+    fn multi_limb() {
+        let cases = [
+            // 2^521 - 1。最上位の limb が 9 bits だけ
+            (
+                format!("1{}", "f".repeat(130)),
+                "f6bcf4e0dff7e035f25d1613d3e16f472b5498646fd835b692e9a54f2d42aaae406160ef0ecc227b4c819ce9ce7c2756adb6d73a96e8c853cde770da2a20d0348241d41845d9d0964decabc1af39755e1a85d691a881d0705d5451de96a731d4980f8a469a4ecf4b255bdd9667c5df739d6f2179433ba79d938d61340e7febf756725de9",
+                "11af196618867c04607c5eca3934d2c77806b233e2678b899e55115ed1a55a27ba9ea2a91572d41b850f551c6a1fbe3fa10d869008c1801f92ba14f1a64450756a9",
+                "7f4a4d0649642b117d848ae1560b26b1dbca12d782057a0a55a91c20a6cbb48a",
+                "92b649cc8668e5da4cb9236615be1b80dddab9bc8b389dc6b13e3dd329ef2fbaf711d6355c63021e64dcad1d8748c5fce4e0871c01bd7454ec7b94fc5f70b3bc82",
+                "95b82edea42680c870d5b81cc862bf71f302014b07eb3624ef4c71556aa0b55f38b51af911df566a607a7e70d8ac6bf6d9cf2619b9c7978b5c6ace07d714785081",
+                "e5ca9512a16892118a04018bbb67f78d594f9017122ef4de449a34f0b5b2c7a555da2ab5e6dce766d89cfb4be18937f891079a0257487feedfe4b21826bca56ed",
+                "177c4b36afe01259444f336c28270ef095d6f967e64bfe52ccbed27e60f998d3f4d27aba40535c06613e75b56e54ce202d4081e1b75a5725bc0da45e1fb2bac65d8",
+                "883b4c9501feda6bbb0cc93d7d8f10f6a29069819b401ad33412d819f06672c0b2d8545bfaca3f99ec18a4a91ab31dfd2bf7e1e48a5a8da43f25ba1e04d4539a27",
+            ),
+            // 2^192 - 2^64 - 1。全 limb がほぼ 1
+            (
+                "fffffffffffffffffffffffffffffffeffffffffffffffff".to_string(),
+                "9701f04b4216be16e2e4e04b5d087243f0a3768500173c1feeabe432018e666f5344f1541d0448bd1f021644ac940a0",
+                "260a58a7d42b21cb608b2b97b19f20efc0a39a64e263267e",
+                "acfcfd3ed0b340e79c7a2aa397105eb918aa82419a5436a5510bfe651f68048b",
+                "76892b4c8a0ad96cabdcf386fbc3ab531a6a77d14eec2043",
+                "95eb38389f4452402381cf2627d8c5b2a8379c596ae5d4a5",
+                "d8efd60f446b9fd46135bddc5d4f4d9cb6bcc737d0059744",
+                "507ed2a4b5dfb7a14b51c7ef4a248a6359c6dd6c6c88f9c5",
+                "af812d5b4a20485eb4ae3810b5db759ba63922939377063a",
+            ),
+            // 2^128 + 1。最上位の limb が 1
+            (
+                format!("1{}1", "0".repeat(31)),
+                "d147dcfe7efeea0da4e06053b91c074123903f5a62222e0db76a0d755fe985b7acaf",
+                "688c204343342b2a465d39872e2a5ff0",
+                "1a466be45bca0a2d3e453cc95ab0fa44961540122dbeee6547d344918b0d1bb4",
+                "625be32344001289ad21a6cd7e775a67",
+                "808e68561472f5171300f377359b23c",
+                "7f9a8ea879ed986e808d10ba92b22c01",
+                "f9cfc2e000cbe75f66c46d46504cfa78",
+                "6303d1fff3418a0993b92b9afb30589",
+            ),
+        ];
+
+        for (m, a, b, e, a_mod_m, product, power, difference, negated) in cases {
+            let modulus = Modulus::new(&int(&m)).unwrap();
+            let (a, b, e) = (int(a), int(b), int(e));
+            let a_mod_m_value = modulus.reduce(&a);
+            assert_int_eq(&a_mod_m_value, &int(a_mod_m));
+            assert_int_eq(&modulus.mul(&a, &b), &int(product));
+            assert_int_eq(&modulus.pow(&a, &e), &int(power));
+            assert_int_eq(&modulus.sub(&a_mod_m_value, &b), &int(difference));
+            assert_int_eq(&modulus.sub(&b, &a_mod_m_value), &int(negated));
+        }
+    }
+
+    // CRT で分けて計算した結果が法 n で直接計算した結果と一致すること
+    // p = 2^521 - 1, q = 2^192 - 2^64 - 1 はどちらも素数
+    #[test]
+    fn crt() {
+        let p = int(&format!("1{}", "f".repeat(130)));
+        let q = int("fffffffffffffffffffffffffffffffeffffffffffffffff");
+        let n = p.mul(&q);
+        let (p_modulus, q_modulus) = (Modulus::new(&p).unwrap(), Modulus::new(&q).unwrap());
+        let n_modulus = Modulus::new(&n).unwrap();
+
+        // q^-1 mod p = q^(p - 2) mod p
+        let q_inv = p_modulus.pow(&q, &p.sub(&BigUint::from_u64(2)));
+        assert_int_eq(&p_modulus.mul(&q, &q_inv), &BigUint::from_u64(1));
+
+        let c = n_modulus.reduce(&int(&"5a".repeat(100)));
+        let d = int(&"c3".repeat(60));
+        let m1 = p_modulus.pow(&c, &d);
+        let m2 = q_modulus.pow(&c, &d);
+        let h = p_modulus.mul(&q_inv, &p_modulus.sub(&m1, &p_modulus.reduce(&m2)));
+        let m = m2.add(&q.mul(&h));
+        assert_int_eq(&m, &n_modulus.pow(&c, &d));
+    }
+}
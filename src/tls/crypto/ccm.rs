@@ -0,0 +1,262 @@
+use super::aes::Aes;
+
+use anyhow::{bail, Result};
+
+// RFC 3610 Counter with CBC-MAC (CCM), NIST SP 800-38C
+pub struct Ccm {
+    cipher: Aes,
+    tag_length: usize,
+}
+
+impl Ccm {
+    pub fn new(key: &[u8], tag_length: usize) -> Self {
+        assert!(matches!(tag_length, 4 | 6 | 8 | 10 | 12 | 14 | 16));
+        Ccm {
+            cipher: Aes::new(key),
+            tag_length,
+        }
+    }
+
+    fn encrypt_block(&self, block: &mut [u8; 16]) {
+        self.cipher.encrypt_block(block);
+    }
+
+    // A_i = Flags | Nonce N | Counter i
+    fn counter_block(&self, nonce: &[u8], counter: usize) -> [u8; 16] {
+        let l = 15 - nonce.len();
+        let mut block = [0u8; 16];
+        block[0] = (l - 1) as u8;
+        block[1..1 + nonce.len()].copy_from_slice(nonce);
+        let counter = (counter as u64).to_be_bytes();
+        block[16 - l..].copy_from_slice(&counter[8 - l..]);
+        block
+    }
+
+    fn cbc_mac(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> [u8; 16] {
+        let l = 15 - nonce.len();
+
+        // B_0 = Flags | Nonce N | l(m)
+        let mut b0 = [0u8; 16];
+        let adata = if aad.is_empty() { 0 } else { 0x40 };
+        b0[0] = adata | ((((self.tag_length - 2) / 2) as u8) << 3) | (l - 1) as u8;
+        b0[1..1 + nonce.len()].copy_from_slice(nonce);
+        let length = (plaintext.len() as u64).to_be_bytes();
+        b0[16 - l..].copy_from_slice(&length[8 - l..]);
+
+        let mut blocks = vec![];
+        if !aad.is_empty() {
+            if aad.len() < 0xff00 {
+                blocks.extend_from_slice(&(aad.len() as u16).to_be_bytes());
+            } else {
+                blocks.extend_from_slice(&[0xff, 0xfe]);
+                blocks.extend_from_slice(&(aad.len() as u32).to_be_bytes());
+            }
+            blocks.extend_from_slice(aad);
+            blocks.resize(blocks.len().div_ceil(16) * 16, 0);
+        }
+        blocks.extend_from_slice(plaintext);
+        blocks.resize(blocks.len().div_ceil(16) * 16, 0);
+
+        let mut x = b0;
+        self.encrypt_block(&mut x);
+        for block in blocks.chunks(16) {
+            x.iter_mut().zip(block).for_each(|(x, b)| *x ^= b);
+            self.encrypt_block(&mut x);
+        }
+        x
+    }
+
+    fn ctr(&self, nonce: &[u8], data: &mut [u8]) {
+        for (i, chunk) in data.chunks_mut(16).enumerate() {
+            let mut s = self.counter_block(nonce, i + 1);
+            self.encrypt_block(&mut s);
+            chunk.iter_mut().zip(s.iter()).for_each(|(d, s)| *d ^= s);
+        }
+    }
+
+    fn tag(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let t = self.cbc_mac(nonce, aad, plaintext);
+        let mut s0 = self.counter_block(nonce, 0);
+        self.encrypt_block(&mut s0);
+        t.iter()
+            .zip(s0.iter())
+            .take(self.tag_length)
+            .map(|(t, s)| t ^ s)
+            .collect()
+    }
+
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let tag = self.tag(nonce, aad, plaintext);
+        let mut ciphertext = plaintext.to_vec();
+        self.ctr(nonce, &mut ciphertext);
+        ciphertext.extend(tag);
+        ciphertext
+    }
+
+    pub fn open(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if ciphertext.len() < self.tag_length {
+            bail!("bad record mac");
+        }
+
+        let (ciphertext, received_tag) = ciphertext.split_at(ciphertext.len() - self.tag_length);
+        let mut plaintext = ciphertext.to_vec();
+        self.ctr(nonce, &mut plaintext);
+
+        // タグの比較は定数時間で行う
+        let tag = self.tag(nonce, aad, &plaintext);
+        let diff = tag
+            .iter()
+            .zip(received_tag)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            bail!("bad record mac");
+        }
+
+        Ok(plaintext)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    fn check(
+        key: &[u8],
+        tag_length: usize,
+        nonce: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+        expected: &[u8],
+    ) {
+        let ccm = Ccm::new(key, tag_length);
+        let sealed = ccm.seal(nonce, aad, plaintext);
+        assert_eq!(sealed, expected);
+        assert_eq!(ccm.open(nonce, aad, &sealed).unwrap(), plaintext);
+    }
+
+    // RFC 3610 8. Packet Vector #1, #2 (M = 8), #7 (M = 10)
+    #[test]
+    fn rfc3610() {
+        let key = hex!("c0c1c2c3c4c5c6c7c8c9cacbcccdcecf");
+        let aad = hex!("0001020304050607");
+        let plaintext: Vec<u8> = (0x08..0x20).collect();
+        check(
+            &key,
+            8,
+            &hex!("00000003020100a0a1a2a3a4a5"),
+            &aad,
+            &plaintext[..23],
+            &hex!("588c979a61c663d2f066d0c2c0f989806d5f6b61dac38417e8d12cfdf926e0"),
+        );
+        check(
+            &key,
+            8,
+            &hex!("00000004030201a0a1a2a3a4a5"),
+            &aad,
+            &plaintext,
+            &hex!("72c91a36e135f8cf291ca894085c87e3cc15c439c9e43a3ba091d56e10400916"),
+        );
+        check(
+            &key,
+            10,
+            &hex!("00000009080706a0a1a2a3a4a5"),
+            &aad,
+            &plaintext[..23],
+            &hex!("0135d1b2c95f41d5d1d4fec185d166b8094e999dfed96c048c56602c97acbb7490"),
+        );
+    }
+
+    // NIST SP 800-38C Appendix C Example 1 - 4
+    // Example 4 は関連データが 2^16 bytes あるので、長さを 0xff 0xfe と 4 bytes で表す
+    #[test]
+    fn sp800_38c() {
+        let key = hex!("404142434445464748494a4b4c4d4e4f");
+        let aad: Vec<u8> = (0..=255).cycle().take(65536).collect();
+        let plaintext: Vec<u8> = (0x20..0x40).collect();
+        check(
+            &key,
+            4,
+            &hex!("10111213141516"),
+            &aad[..8],
+            &plaintext[..4],
+            &hex!("7162015b4dac255d"),
+        );
+        check(
+            &key,
+            6,
+            &hex!("1011121314151617"),
+            &aad[..16],
+            &plaintext[..16],
+            &hex!("d2a1f0e051ea5f62081a7792073d593d1fc64fbfaccd"),
+        );
+        check(
+            &key,
+            8,
+            &hex!("101112131415161718191a1b"),
+            &aad[..20],
+            &plaintext[..24],
+            &hex!("e3b201a9f5b71a7a9b1ceaeccd97e70b6176aad9a4428aa5484392fbc1b09951"),
+        );
+        check(
+            &key,
+            14,
+            &hex!("101112131415161718191a1b1c"),
+            &aad,
+            &plaintext,
+            &hex!(
+                "69915dad1e84c6376a68c2967e4dab615ae0fd1faec44cc484828529463ccf72"
+                "b4ac6bec93e8598e7f0dadbcea5b"
+            ),
+        );
+    }
+
+    // TLS の AES_256_CCM と AES_256_CCM_8 (nonce 12 bytes)。値は Python の cryptography で作ったもの
+    // CCM_8 はタグが短いだけで、暗号文は同じになる
+    #[test]
+    fn tls_ccm_and_ccm_8() {
+        let key: Vec<u8> = (0x20..0x40).collect();
+        let nonce: Vec<u8> = (0x50..0x5c).collect();
+        let aad = hex!("170303001d");
+        let plaintext = b"tls-from-scratch ccm";
+        let ciphertext = hex!("7d0039cbafd5f6023c0bbfe7ffa48f7eca25b65d");
+        check(
+            &key,
+            16,
+            &nonce,
+            &aad,
+            plaintext,
+            &[&ciphertext[..], &hex!("a64ff68b90046e0fff4e7722e8694952")].concat(),
+        );
+        check(
+            &key,
+            8,
+            &nonce,
+            &aad,
+            plaintext,
+            &[&ciphertext[..], &hex!("f1b7297cb9e8ccae")].concat(),
+        );
+    }
+
+    #[test]
+    fn open_rejects_modified_input() {
+        let key = hex!("c0c1c2c3c4c5c6c7c8c9cacbcccdcecf");
+        let nonce = hex!("00000003020100a0a1a2a3a4a5");
+        let aad = hex!("0001020304050607");
+        for tag_length in [8, 16] {
+            let ccm = Ccm::new(&key, tag_length);
+            let sealed = ccm.seal(&nonce, &aad, b"plaintext");
+            for i in [0, 8, 9, sealed.len() - 1] {
+                let mut modified = sealed.clone();
+                modified[i] ^= 0x80;
+                assert!(ccm.open(&nonce, &aad, &modified).is_err());
+            }
+            assert!(ccm.open(&nonce, &aad[1..], &sealed).is_err());
+            assert!(ccm.open(&nonce, &aad, &sealed[..tag_length - 1]).is_err());
+            // 空の平文はタグだけになる
+            let sealed = ccm.seal(&nonce, &aad, &[]);
+            assert_eq!(sealed.len(), tag_length);
+            assert!(ccm.open(&nonce, &aad, &sealed).unwrap().is_empty());
+        }
+    }
+}
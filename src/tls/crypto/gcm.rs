@@ -0,0 +1,300 @@
+use super::aes::{Aes, AES_BLOCK_SIZE};
+
+use anyhow::{bail, Result};
+
+const TAG_LENGTH: usize = 16;
+
+// NIST SP 800-38D Galois/Counter Mode
+pub struct Gcm {
+    cipher: Aes,
+    // ハッシュ鍵 H = CIPH_K(0^128)
+    h: [u8; AES_BLOCK_SIZE],
+}
+
+impl Gcm {
+    pub fn new(key: &[u8]) -> Self {
+        let cipher = Aes::new(key);
+        let mut h = [0u8; AES_BLOCK_SIZE];
+        cipher.encrypt_block(&mut h);
+        Gcm { cipher, h }
+    }
+
+    // TLS の nonce は 96 bits なので J_0 = IV || 0^31 || 1 だけに対応する
+    fn counter_block(&self, nonce: &[u8], counter: u32) -> [u8; AES_BLOCK_SIZE] {
+        assert_eq!(nonce.len(), 12);
+        let mut block = [0u8; AES_BLOCK_SIZE];
+        block[..12].copy_from_slice(nonce);
+        block[12..].copy_from_slice(&counter.to_be_bytes());
+        block
+    }
+
+    // 6.5 GCTR。J_0 + 1 から始める
+    fn ctr(&self, nonce: &[u8], data: &mut [u8]) {
+        let mut counter = 2u32;
+        for chunk in data.chunks_mut(AES_BLOCK_SIZE * 4) {
+            let mut blocks: Vec<[u8; AES_BLOCK_SIZE]> = (0..chunk.len().div_ceil(AES_BLOCK_SIZE))
+                .map(|_| {
+                    let block = self.counter_block(nonce, counter);
+                    counter = counter.wrapping_add(1);
+                    block
+                })
+                .collect();
+            self.cipher.encrypt_blocks(&mut blocks);
+            chunk
+                .iter_mut()
+                .zip(blocks.iter().flatten())
+                .for_each(|(d, s)| *d ^= s);
+        }
+    }
+
+    fn tag(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> [u8; TAG_LENGTH] {
+        let mut ghash = Ghash::new(&self.h);
+        ghash.update(aad);
+        ghash.update(ciphertext);
+        let mut lengths = [0u8; AES_BLOCK_SIZE];
+        lengths[..8].copy_from_slice(&(aad.len() as u64 * 8).to_be_bytes());
+        lengths[8..].copy_from_slice(&(ciphertext.len() as u64 * 8).to_be_bytes());
+        ghash.update(&lengths);
+        let s = ghash.finalize();
+
+        let mut tag = self.counter_block(nonce, 1);
+        self.cipher.encrypt_block(&mut tag);
+        tag.iter_mut().zip(s).for_each(|(t, s)| *t ^= s);
+        tag
+    }
+
+    pub fn seal(&self, nonce: &[u8], aad: &[u8], plaintext: &[u8]) -> Vec<u8> {
+        let mut ciphertext = plaintext.to_vec();
+        self.ctr(nonce, &mut ciphertext);
+        let tag = self.tag(nonce, aad, &ciphertext);
+        ciphertext.extend(tag);
+        ciphertext
+    }
+
+    pub fn open(&self, nonce: &[u8], aad: &[u8], ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if ciphertext.len() < TAG_LENGTH {
+            bail!("bad record mac");
+        }
+
+        let (ciphertext, received_tag) = ciphertext.split_at(ciphertext.len() - TAG_LENGTH);
+
+        // タグの比較は定数時間で行う
+        let tag = self.tag(nonce, aad, ciphertext);
+        let diff = tag
+            .iter()
+            .zip(received_tag)
+            .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+        if diff != 0 {
+            bail!("bad record mac");
+        }
+
+        let mut plaintext = ciphertext.to_vec();
+        self.ctr(nonce, &mut plaintext);
+        Ok(plaintext)
+    }
+}
+
+// 6.4 GHASH
+// update に渡したデータはそれぞれ 16 bytes 境界まで 0 で埋める
+pub struct Ghash {
+    h: u128,
+    y: u128,
+}
+
+impl Ghash {
+    pub fn new(h: &[u8; AES_BLOCK_SIZE]) -> Self {
+        Ghash {
+            h: u128::from_be_bytes(*h),
+            y: 0,
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for chunk in data.chunks(AES_BLOCK_SIZE) {
+            let mut block = [0u8; AES_BLOCK_SIZE];
+            block[..chunk.len()].copy_from_slice(chunk);
+            self.y = gf128_mul(self.y ^ u128::from_be_bytes(block), self.h);
+        }
+    }
+
+    pub fn finalize(self) -> [u8; AES_BLOCK_SIZE] {
+        self.y.to_be_bytes()
+    }
+}
+
+// 6.3 の乗算。GCM はビットの並びが逆で、最上位ビットが x^0 の係数
+// 分岐や表を使わずにマスクで計算する
+fn gf128_mul(x: u128, y: u128) -> u128 {
+    const R: u128 = 0xe1 << 120;
+    let mut z = 0u128;
+    let mut v = y;
+    for i in (0..128).rev() {
+        z ^= v & 0u128.wrapping_sub((x >> i) & 1);
+        v = (v >> 1) ^ (R & 0u128.wrapping_sub(v & 1));
+    }
+    z
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes_gcm::aead::{Aead, KeyInit, Payload};
+    use hex_literal::hex;
+    use rand::rngs::StdRng;
+    use rand::{RngCore, SeedableRng};
+
+    const K: [u8; 16] = hex!("feffe9928665731c6d6a8f9467308308");
+    const IV: [u8; 12] = hex!("cafebabefacedbaddecaf888");
+    const P: [u8; 64] = hex!(
+        "d9313225f88406e5a55909c5aff5269a86a7a9531534f7da2e4c303d8a318a72"
+        "1c3c0c95956809532fcf0e2449a6b525b16aedf5aa0de657ba637b391aafd255"
+    );
+    const A: [u8; 20] = hex!("feedfacedeadbeeffeedfacedeadbeefabaddad2");
+
+    fn check(
+        key: &[u8],
+        nonce: &[u8],
+        aad: &[u8],
+        plaintext: &[u8],
+        ciphertext: &[u8],
+        tag: &[u8],
+    ) {
+        let gcm = Gcm::new(key);
+        let sealed = gcm.seal(nonce, aad, plaintext);
+        assert_eq!(sealed[..plaintext.len()], *ciphertext);
+        assert_eq!(sealed[plaintext.len()..], *tag);
+        assert_eq!(gcm.open(nonce, aad, &sealed).unwrap(), plaintext);
+    }
+
+    // The Galois/Counter Mode of Operation (GCM) の Test Case 1 - 4
+    #[test]
+    fn aes128_test_cases() {
+        check(
+            &[0; 16],
+            &[0; 12],
+            &[],
+            &[],
+            &[],
+            &hex!("58e2fccefa7e3061367f1d57a4e7455a"),
+        );
+        check(
+            &[0; 16],
+            &[0; 12],
+            &[],
+            &[0; 16],
+            &hex!("0388dace60b6a392f328c2b971b2fe78"),
+            &hex!("ab6e47d42cec13bdf53a67b21257bddf"),
+        );
+        let c = hex!(
+            "42831ec2217774244b7221b784d0d49ce3aa212f2c02a4e035c17e2329aca12e"
+            "21d514b25466931c7d8f6a5aac84aa051ba30b396a0aac973d58e091473f5985"
+        );
+        check(
+            &K,
+            &IV,
+            &[],
+            &P,
+            &c,
+            &hex!("4d5c2af327cd64a62cf35abd2ba6fab4"),
+        );
+        check(
+            &K,
+            &IV,
+            &A,
+            &P[..60],
+            &c[..60],
+            &hex!("5bc94fbc3221a5db94fae95ae7121a47"),
+        );
+    }
+
+    // 同 Test Case 13 - 16
+    #[test]
+    fn aes256_test_cases() {
+        check(
+            &[0; 32],
+            &[0; 12],
+            &[],
+            &[],
+            &[],
+            &hex!("530f8afbc74536b9a963b4f1c4cb738b"),
+        );
+        check(
+            &[0; 32],
+            &[0; 12],
+            &[],
+            &[0; 16],
+            &hex!("cea7403d4d606b6e074ec5d3baf39d18"),
+            &hex!("d0d1c8a799996bf0265b98b5d48ab919"),
+        );
+        let key = [K, K].concat();
+        let c = hex!(
+            "522dc1f099567d07f47f37a32a84427d643a8cdcbfe5c0c97598a2bd2555d1aa"
+            "8cb08e48590dbb3da7b08b1056828838c5f61e6393ba7a0abcc9f662898015ad"
+        );
+        check(
+            &key,
+            &IV,
+            &[],
+            &P,
+            &c,
+            &hex!("b094dac5d93471bdec1a502270e3cc6c"),
+        );
+        check(
+            &key,
+            &IV,
+            &A,
+            &P[..60],
+            &c[..60],
+            &hex!("76fc6ece0f4e1768cddf8853bb2d551b"),
+        );
+    }
+
+    #[test]
+    fn open_rejects_modified_input() {
+        let gcm = Gcm::new(&K);
+        let sealed = gcm.seal(&IV, &A, &P[..60]);
+        for i in [0, 59, 60, sealed.len() - 1] {
+            let mut modified = sealed.clone();
+            modified[i] ^= 1;
+            assert!(gcm.open(&IV, &A, &modified).is_err());
+        }
+        assert!(gcm.open(&IV, &A[1..], &sealed).is_err());
+        assert!(gcm.open(&IV, &A, &sealed[..TAG_LENGTH - 1]).is_err());
+    }
+
+    // 長さがブロックの境界に揃わない入力も RustCrypto の aes-gcm と一致すること
+    #[test]
+    fn compare_with_aes_gcm_crate() {
+        let mut rng = StdRng::seed_from_u64(38);
+        for key_length in [16, 32] {
+            for (aad_length, length) in [(0, 1), (13, 15), (13, 17), (5, 64), (13, 100), (0, 257)] {
+                let mut key = vec![0u8; key_length];
+                let mut nonce = [0u8; 12];
+                let mut aad = vec![0u8; aad_length];
+                let mut plaintext = vec![0u8; length];
+                rng.fill_bytes(&mut key);
+                rng.fill_bytes(&mut nonce);
+                rng.fill_bytes(&mut aad);
+                rng.fill_bytes(&mut plaintext);
+
+                let payload = Payload {
+                    msg: &plaintext,
+                    aad: &aad,
+                };
+                let expected = match key_length {
+                    16 => aes_gcm::Aes128Gcm::new_from_slice(&key)
+                        .unwrap()
+                        .encrypt(&nonce.into(), payload),
+                    _ => aes_gcm::Aes256Gcm::new_from_slice(&key)
+                        .unwrap()
+                        .encrypt(&nonce.into(), payload),
+                }
+                .unwrap();
+
+                let gcm = Gcm::new(&key);
+                assert_eq!(gcm.seal(&nonce, &aad, &plaintext), expected);
+                assert_eq!(gcm.open(&nonce, &aad, &expected).unwrap(), plaintext);
+            }
+        }
+    }
+}
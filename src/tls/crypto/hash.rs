@@ -0,0 +1,137 @@
+mod sha1;
+mod sha256;
+mod sha512;
+
+pub use sha1::*;
+pub use sha256::*;
+pub use sha512::*;
+
+use crate::tls::{Error, HashAlgorithm};
+
+// FIPS 180-4 のハッシュ関数。HMAC などハッシュ関数を型で受け取る処理に使う
+// Clone で途中の状態を残せるので、Transcript の途中のハッシュも計算できる
+pub trait HashFunction: Clone {
+    const BLOCK_SIZE: usize;
+    const OUTPUT_SIZE: usize;
+
+    fn new() -> Self;
+    fn update(&mut self, data: &[u8]);
+    fn finalize(self) -> Vec<u8>;
+
+    fn digest(data: &[u8]) -> Vec<u8> {
+        let mut hash = Self::new();
+        hash.update(data);
+        hash.finalize()
+    }
+}
+
+// 実装しているハッシュ関数。HashAlgorithm の MD5, SHA224, None は扱わない
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DigestAlgorithm {
+    Sha1,
+    Sha256,
+    Sha384,
+    Sha512,
+}
+
+impl DigestAlgorithm {
+    pub fn output_length(&self) -> usize {
+        match self {
+            DigestAlgorithm::Sha1 => Sha1::OUTPUT_SIZE,
+            DigestAlgorithm::Sha256 => Sha256::OUTPUT_SIZE,
+            DigestAlgorithm::Sha384 => Sha384::OUTPUT_SIZE,
+            DigestAlgorithm::Sha512 => Sha512::OUTPUT_SIZE,
+        }
+    }
+}
+
+impl TryFrom<HashAlgorithm> for DigestAlgorithm {
+    type Error = Error;
+
+    fn try_from(algorithm: HashAlgorithm) -> Result<Self, Self::Error> {
+        match algorithm {
+            HashAlgorithm::SHA1 => Ok(DigestAlgorithm::Sha1),
+            HashAlgorithm::SHA256 => Ok(DigestAlgorithm::Sha256),
+            HashAlgorithm::SHA384 => Ok(DigestAlgorithm::Sha384),
+            HashAlgorithm::SHA512 => Ok(DigestAlgorithm::Sha512),
+            _ => Err(Error::InvalidValue),
+        }
+    }
+}
+
+// DigestAlgorithm で選ぶ場合
+#[derive(Clone)]
+pub enum Hash {
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha384(Sha384),
+    Sha512(Sha512),
+}
+
+impl Hash {
+    pub fn new(algorithm: DigestAlgorithm) -> Self {
+        match algorithm {
+            DigestAlgorithm::Sha1 => Hash::Sha1(Sha1::new()),
+            DigestAlgorithm::Sha256 => Hash::Sha256(Sha256::new()),
+            DigestAlgorithm::Sha384 => Hash::Sha384(Sha384::new()),
+            DigestAlgorithm::Sha512 => Hash::Sha512(Sha512::new()),
+        }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Hash::Sha1(h) => h.update(data),
+            Hash::Sha256(h) => h.update(data),
+            Hash::Sha384(h) => h.update(data),
+            Hash::Sha512(h) => h.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Hash::Sha1(h) => h.finalize(),
+            Hash::Sha256(h) => h.finalize(),
+            Hash::Sha384(h) => h.finalize(),
+            Hash::Sha512(h) => h.finalize(),
+        }
+    }
+}
+
+pub fn digest(algorithm: DigestAlgorithm, data: &[u8]) -> Vec<u8> {
+    let mut hash = Hash::new(algorithm);
+    hash.update(data);
+    hash.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn digest_algorithm() {
+        assert_eq!(
+            DigestAlgorithm::try_from(HashAlgorithm::SHA384).unwrap(),
+            DigestAlgorithm::Sha384
+        );
+        for algorithm in [
+            HashAlgorithm::None,
+            HashAlgorithm::MD5,
+            HashAlgorithm::SHA224,
+        ] {
+            assert!(matches!(
+                DigestAlgorithm::try_from(algorithm),
+                Err(Error::InvalidValue)
+            ));
+        }
+
+        assert_eq!(
+            digest(DigestAlgorithm::Sha256, b"abc"),
+            hex!("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+        assert_eq!(
+            digest(DigestAlgorithm::Sha384, b"").len(),
+            DigestAlgorithm::Sha384.output_length()
+        );
+    }
+}
@@ -0,0 +1,164 @@
+use super::HashFunction;
+
+// FIPS 180-4 5.3.1.
+const INITIAL_STATE: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+const BLOCK_SIZE: usize = 64;
+
+// 衝突耐性が無いので署名には使わない。TLS 1.2 の signature_algorithms が無い場合などの互換性のため
+#[derive(Clone)]
+pub struct Sha1 {
+    state: [u32; 5],
+    buffer: [u8; BLOCK_SIZE],
+    buffer_len: usize,
+    // 入力の長さ (bytes)
+    length: u64,
+}
+
+impl HashFunction for Sha1 {
+    const BLOCK_SIZE: usize = BLOCK_SIZE;
+    const OUTPUT_SIZE: usize = 20;
+
+    fn new() -> Self {
+        Sha1 {
+            state: INITIAL_STATE,
+            buffer: [0; BLOCK_SIZE],
+            buffer_len: 0,
+            length: 0,
+        }
+    }
+
+    fn update(&mut self, mut data: &[u8]) {
+        self.length += data.len() as u64;
+
+        if self.buffer_len > 0 {
+            let n = data.len().min(BLOCK_SIZE - self.buffer_len);
+            self.buffer[self.buffer_len..self.buffer_len + n].copy_from_slice(&data[..n]);
+            self.buffer_len += n;
+            data = &data[n..];
+            if self.buffer_len < BLOCK_SIZE {
+                return;
+            }
+            compress(&mut self.state, &self.buffer);
+            self.buffer_len = 0;
+        }
+
+        let mut blocks = data.chunks_exact(BLOCK_SIZE);
+        for block in &mut blocks {
+            compress(&mut self.state, block.try_into().unwrap());
+        }
+        let rest = blocks.remainder();
+        self.buffer[..rest.len()].copy_from_slice(rest);
+        self.buffer_len = rest.len();
+    }
+
+    // パディングは SHA-256 と同じ (FIPS 180-4 5.1.1)
+    fn finalize(mut self) -> Vec<u8> {
+        let bit_length = self.length * 8;
+        let mut padding = vec![0x80];
+        padding.resize(
+            (BLOCK_SIZE * 2 - 8 - 1 - self.buffer_len) % BLOCK_SIZE + 1,
+            0,
+        );
+        padding.extend_from_slice(&bit_length.to_be_bytes());
+        self.update(&padding);
+
+        self.state
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+}
+
+// FIPS 180-4 6.1.2.
+fn compress(state: &mut [u32; 5], block: &[u8; BLOCK_SIZE]) {
+    let mut w = [0u32; 80];
+    for (i, word) in block.chunks_exact(4).enumerate() {
+        w[i] = u32::from_be_bytes(word.try_into().unwrap());
+    }
+    for i in 16..80 {
+        w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+    }
+
+    let [mut a, mut b, mut c, mut d, mut e] = *state;
+    for (i, w) in w.iter().enumerate() {
+        let (f, k) = match i {
+            0..=19 => ((b & c) | (!b & d), 0x5a827999),
+            20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+            40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+            _ => (b ^ c ^ d, 0xca62c1d6),
+        };
+        let t = a
+            .rotate_left(5)
+            .wrapping_add(f)
+            .wrapping_add(e)
+            .wrapping_add(k)
+            .wrapping_add(*w);
+        e = d;
+        d = c;
+        c = b.rotate_left(30);
+        b = a;
+        a = t;
+    }
+
+    for (word, value) in state.iter_mut().zip([a, b, c, d, e]) {
+        *word = word.wrapping_add(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    // FIPS 180-4 の例と CAVP SHA1ShortMsg
+    #[test]
+    fn short_message() {
+        assert_eq!(
+            Sha1::digest(b""),
+            hex!("da39a3ee5e6b4b0d3255bfef95601890afd80709")
+        );
+        assert_eq!(
+            Sha1::digest(&hex!("36")),
+            hex!("c1dfd96eea8cc2b62785275bca38ac261256e278")
+        );
+        assert_eq!(
+            Sha1::digest(b"abc"),
+            hex!("a9993e364706816aba3e25717850c26c9cd0d89d")
+        );
+        assert_eq!(
+            Sha1::digest(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            hex!("84983e441c3bd26ebaae4aa1f95129e5e54670f1")
+        );
+    }
+
+    // 長さを入れる 8 bytes が最後のブロックに収まるかどうかの境目
+    #[test]
+    fn padding_boundary() {
+        assert_eq!(
+            Sha1::digest(&[b'a'; 55]),
+            hex!("c1c8bbdc22796e28c0e15163d20899b65621d65a")
+        );
+        assert_eq!(
+            Sha1::digest(&[b'a'; 56]),
+            hex!("c2db330f6083854c99d4b5bfb6e8f29f201be699")
+        );
+        assert_eq!(
+            Sha1::digest(&[b'a'; 64]),
+            hex!("0098ba824b5c16427bd7a1122a5a442a25ec644d")
+        );
+    }
+
+    // 100 万個の 'a' を、ブロックの境界に揃わない長さに分けて入力する
+    #[test]
+    fn long_message() {
+        let mut hash = Sha1::new();
+        for chunk in [b'a'; 1_000_000].chunks(999) {
+            hash.update(chunk);
+        }
+        assert_eq!(
+            hash.finalize(),
+            hex!("34aa973cd4c4daa4f61eeb2bdbad27316534016f")
+        );
+    }
+}
@@ -0,0 +1,149 @@
+use super::{hmac, DigestAlgorithm};
+use crate::tls::Opaque;
+
+use ser::NetworkEndian;
+use serde::Serialize;
+
+// RFC 5869 2.2. PRK = HMAC-Hash(salt, IKM)
+pub fn hkdf_extract(algorithm: DigestAlgorithm, salt: &[u8], ikm: &[u8]) -> Vec<u8> {
+    hmac(algorithm, salt, ikm)
+}
+
+// RFC 5869 2.3. T(i) = HMAC-Hash(PRK, T(i-1) | info | i) を並べて先頭 length bytes を使う
+pub fn hkdf_expand(algorithm: DigestAlgorithm, prk: &[u8], info: &[u8], length: usize) -> Vec<u8> {
+    assert!(length <= 255 * algorithm.output_length());
+
+    let mut okm = vec![];
+    let mut t = vec![];
+    let mut counter = 1u8;
+    while okm.len() < length {
+        let mut input = t;
+        input.extend_from_slice(info);
+        input.push(counter);
+        t = hmac(algorithm, prk, &input);
+        okm.extend_from_slice(&t);
+        counter = counter.wrapping_add(1);
+    }
+
+    okm.truncate(length);
+    okm
+}
+
+// RFC 8446 7.1. Key Schedule
+#[derive(Serialize, Debug)]
+struct HkdfLabel {
+    length: u16,
+    label: Opaque<u8>,
+    context: Opaque<u8>,
+}
+
+pub fn hkdf_expand_label(
+    algorithm: DigestAlgorithm,
+    secret: &[u8],
+    label: &str,
+    context: &[u8],
+    length: usize,
+) -> Vec<u8> {
+    let label = format!("tls13 {}", label).into_bytes();
+    let hkdf_label = HkdfLabel {
+        length: length as u16,
+        label: Opaque::<u8> {
+            length: label.len() as u8,
+            data: label,
+        },
+        context: Opaque::<u8> {
+            length: context.len() as u8,
+            data: context.to_vec(),
+        },
+    };
+    let info = ser::to_bytes::<_, NetworkEndian>(&hkdf_label).unwrap();
+    hkdf_expand(algorithm, secret, &info, length)
+}
+
+// Derive-Secret(Secret, Label, Messages) の Transcript-Hash(Messages) は呼び出し側で計算しておく
+pub fn derive_secret(
+    algorithm: DigestAlgorithm,
+    secret: &[u8],
+    label: &str,
+    transcript_hash: &[u8],
+) -> Vec<u8> {
+    hkdf_expand_label(
+        algorithm,
+        secret,
+        label,
+        transcript_hash,
+        algorithm.output_length(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    fn check(
+        algorithm: DigestAlgorithm,
+        ikm: &[u8],
+        salt: &[u8],
+        info: &[u8],
+        expected_prk: &[u8],
+        expected_okm: &[u8],
+    ) {
+        let prk = hkdf_extract(algorithm, salt, ikm);
+        assert_eq!(prk, expected_prk);
+        assert_eq!(
+            hkdf_expand(algorithm, &prk, info, expected_okm.len()),
+            expected_okm
+        );
+    }
+
+    // RFC 5869 A.1. - A.3.
+    #[test]
+    fn rfc5869_sha256() {
+        check(
+            DigestAlgorithm::Sha256,
+            &[0x0b; 22],
+            &hex!("000102030405060708090a0b0c"),
+            &hex!("f0f1f2f3f4f5f6f7f8f9"),
+            &hex!("077709362c2e32df0ddc3f0dc47bba6390b6c73bb50f9c3122ec844ad7c2b3e5"),
+            &hex!("3cb25f25faacd57a90434f64d0362f2a2d2d0a90cf1a5a4c5db02d56ecc4c5bf34007208d5b887185865"),
+        );
+        check(
+            DigestAlgorithm::Sha256,
+            &(0x00..0x50).collect::<Vec<u8>>(),
+            &(0x60..0xb0).collect::<Vec<u8>>(),
+            &(0xb0..=0xff).collect::<Vec<u8>>(),
+            &hex!("06a6b88c5853361a06104c9ceb35b45cef760014904671014a193f40c15fc244"),
+            &hex!("b11e398dc80327a1c8e7f78c596a49344f012eda2d4efad8a050cc4c19afa97c59045a99cac7827271cb41c65e590e09da3275600c2f09b8367793a9aca3db71cc30c58179ec3e87c14c01d5c1f3434f1d87"),
+        );
+        check(
+            DigestAlgorithm::Sha256,
+            &[0x0b; 22],
+            &[],
+            &[],
+            &hex!("19ef24a32c717b167f33a91d6f648bdf96596776afdb6377ac434c1c293ccb04"),
+            &hex!("8da4e775a563c18f715f802a063c5a31b8a11f5c5ee1879ec3454e5f3c738d2d9d201395faa4b61a96c8"),
+        );
+    }
+
+    // RFC 5869 A.4. と A.7. (salt を省略した場合はハッシュ長の 0 を使う)
+    #[test]
+    fn rfc5869_sha1() {
+        check(
+            DigestAlgorithm::Sha1,
+            &[0x0b; 11],
+            &hex!("000102030405060708090a0b0c"),
+            &hex!("f0f1f2f3f4f5f6f7f8f9"),
+            &hex!("9b6c18c432a7bf8f0e71c8eb88f4b30baa2ba243"),
+            &hex!("085a01ea1b10f36933068b56efa5ad81a4f14b822f5b091568a9cdd4f155fda2c22e422478d305f3f896"),
+        );
+        check(
+            DigestAlgorithm::Sha1,
+            &[0x0c; 22],
+            &[0; 20],
+            &[],
+            &hex!("2adccada18779e7c2077ad2eb19d3f3e731385dd"),
+            &hex!("2c91117204d745f3500d636a62f64f0ab3bae548aa53d423b0d1f27ebba6f5e5673a081d70cce7acfc48"),
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use super::{DigestAlgorithm, HashFunction, Sha1, Sha256, Sha384, Sha512};
+
+// RFC 2104 HMAC(K, m) = H((K ^ opad) || H((K ^ ipad) || m))
+// ブロック長より長い鍵は先にハッシュする
+#[derive(Clone)]
+pub struct Hmac<H: HashFunction> {
+    inner: H,
+    outer: H,
+}
+
+impl<H: HashFunction> Hmac<H> {
+    pub fn new(key: &[u8]) -> Self {
+        let mut block = if key.len() > H::BLOCK_SIZE {
+            H::digest(key)
+        } else {
+            key.to_vec()
+        };
+        block.resize(H::BLOCK_SIZE, 0);
+
+        let mut inner = H::new();
+        inner.update(&block.iter().map(|b| b ^ 0x36).collect::<Vec<u8>>());
+        let mut outer = H::new();
+        outer.update(&block.iter().map(|b| b ^ 0x5c).collect::<Vec<u8>>());
+        Hmac { inner, outer }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.inner.update(data);
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        let mut outer = self.outer;
+        outer.update(&self.inner.finalize());
+        outer.finalize()
+    }
+
+    pub fn mac(key: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut hmac = Hmac::<H>::new(key);
+        hmac.update(data);
+        hmac.finalize()
+    }
+}
+
+pub fn hmac(algorithm: DigestAlgorithm, key: &[u8], data: &[u8]) -> Vec<u8> {
+    match algorithm {
+        DigestAlgorithm::Sha1 => Hmac::<Sha1>::mac(key, data),
+        DigestAlgorithm::Sha256 => Hmac::<Sha256>::mac(key, data),
+        DigestAlgorithm::Sha384 => Hmac::<Sha384>::mac(key, data),
+        DigestAlgorithm::Sha512 => Hmac::<Sha512>::mac(key, data),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    // RFC 4231 4.2. - 4.8. の (key, data, HMAC-SHA-256, HMAC-SHA-384, HMAC-SHA-512)
+    // Test Case 5 は先頭 128 bits だけを比べる
+    #[allow(clippy::type_complexity)]
+    const RFC4231: &[(&[u8], &[u8], &[u8], &[u8], &[u8])] = &[
+        (
+            &[0x0b; 20],
+            b"Hi There",
+            &hex!("b0344c61d8db38535ca8afceaf0bf12b881dc200c9833da726e9376c2e32cff7"),
+            &hex!("afd03944d84895626b0825f4ab46907f15f9dadbe4101ec682aa034c7cebc59cfaea9ea9076ede7f4af152e8b2fa9cb6"),
+            &hex!("87aa7cdea5ef619d4ff0b4241a1d6cb02379f4e2ce4ec2787ad0b30545e17cdedaa833b7d6b8a702038b274eaea3f4e4be9d914eeb61f1702e696c203a126854"),
+        ),
+        (
+            b"Jefe",
+            b"what do ya want for nothing?",
+            &hex!("5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"),
+            &hex!("af45d2e376484031617f78d2b58a6b1b9c7ef464f5a01b47e42ec3736322445e8e2240ca5e69e2c78b3239ecfab21649"),
+            &hex!("164b7a7bfcf819e2e395fbe73b56e0a387bd64222e831fd610270cd7ea2505549758bf75c05a994a6d034f65f8f0e6fdcaeab1a34d4a6b4b636e070a38bce737"),
+        ),
+        (
+            &[0xaa; 20],
+            &[0xdd; 50],
+            &hex!("773ea91e36800e46854db8ebd09181a72959098b3ef8c122d9635514ced565fe"),
+            &hex!("88062608d3e6ad8a0aa2ace014c8a86f0aa635d947ac9febe83ef4e55966144b2a5ab39dc13814b94e3ab6e101a34f27"),
+            &hex!("fa73b0089d56a284efb0f0756c890be9b1b5dbdd8ee81a3655f83e33b2279d39bf3e848279a722c806b485a47e67c807b946a337bee8942674278859e13292fb"),
+        ),
+        (
+            &hex!("0102030405060708090a0b0c0d0e0f10111213141516171819"),
+            &[0xcd; 50],
+            &hex!("82558a389a443c0ea4cc819899f2083a85f0faa3e578f8077a2e3ff46729665b"),
+            &hex!("3e8a69b7783c25851933ab6290af6ca77a9981480850009cc5577c6e1f573b4e6801dd23c4a7d679ccf8a386c674cffb"),
+            &hex!("b0ba465637458c6990e5a8c5f61d4af7e576d97ff94b872de76f8050361ee3dba91ca5c11aa25eb4d679275cc5788063a5f19741120c4f2de2adebeb10a298dd"),
+        ),
+        (
+            &[0x0c; 20],
+            b"Test With Truncation",
+            &hex!("a3b6167473100ee06e0c796c2955552b"),
+            &hex!("3abf34c3503b2a23a46efc619baef897"),
+            &hex!("415fad6271580a531d4179bc891d87a6"),
+        ),
+        (
+            &[0xaa; 131],
+            b"Test Using Larger Than Block-Size Key - Hash Key First",
+            &hex!("60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"),
+            &hex!("4ece084485813e9088d2c63a041bc5b44f9ef1012a2b588f3cd11f05033ac4c60c2ef6ab4030fe8296248df163f44952"),
+            &hex!("80b24263c7c1a3ebb71493c1dd7be8b49b46d1f41b4aeec1121b013783f8f3526b56d037e05f2598bd0fd2215d6a1e5295e64f73f63f0aec8b915a985d786598"),
+        ),
+        (
+            &[0xaa; 131],
+            b"This is a test using a larger than block-size key and a larger than block-size data. The key needs to be hashed before being used by the HMAC algorithm.",
+            &hex!("9b09ffa71b942fcb27635fbcd5b0e944bfdc63644f0713938a7f51535c3a35e2"),
+            &hex!("6617178e941f020d351e2f254e8fd32c602420feb0b8fb9adccebb82461e99c5a678cc31e799176d3860e6110c46523e"),
+            &hex!("e37b6a775dc87dbaa4dfa9f96e5e3ffddebd71f8867289865df5a32d20cdc944b6022cac3c4982b10d5eeb55c3e4de15134676fb6de0446065c97440fa8c6a58"),
+        ),
+    ];
+
+    #[test]
+    fn rfc4231() {
+        for &(key, data, sha256, sha384, sha512) in RFC4231 {
+            for (algorithm, expected) in [
+                (DigestAlgorithm::Sha256, sha256),
+                (DigestAlgorithm::Sha384, sha384),
+                (DigestAlgorithm::Sha512, sha512),
+            ] {
+                let mac = hmac(algorithm, key, data);
+                assert_eq!(mac.len(), algorithm.output_length());
+                assert_eq!(&mac[..expected.len()], expected);
+            }
+        }
+    }
+
+    // RFC 2202 3. Test Case 1
+    #[test]
+    fn sha1() {
+        assert_eq!(
+            hmac(DigestAlgorithm::Sha1, &[0x0b; 20], b"Hi There"),
+            hex!("b617318655057264e28bc0b6fb378c8ef146be00")
+        );
+    }
+
+    #[test]
+    fn incremental_update() {
+        let (key, data, expected, _, _) = RFC4231[6];
+        let mut mac = Hmac::<Sha256>::new(key);
+        for chunk in data.chunks(7) {
+            mac.update(chunk);
+        }
+        assert_eq!(mac.finalize(), expected);
+    }
+}
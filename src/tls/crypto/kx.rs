@@ -0,0 +1,253 @@
+use super::rsa::RsaPrivateKey;
+use crate::tls::{AlertDescription, Error, NamedGroup, ProtocolVersion};
+
+use anyhow::{bail, Result};
+use p256::elliptic_curve::sec1::ToEncodedPoint;
+use rand::rngs::OsRng;
+use rand::RngCore;
+use subtle::{ConditionallySelectable, ConstantTimeEq};
+
+mod x25519;
+
+pub use x25519::*;
+
+// (EC)DHE の鍵共有。TLS 1.2 の ECDHE と TLS 1.3 の key_share で共通に使う
+// 相手の公開鍵が不正な場合、agree は illegal_parameter を返す
+pub trait KeyExchange: Sized {
+    const GROUP: NamedGroup;
+
+    fn generate() -> Self;
+    fn public_key(&self) -> Vec<u8>;
+    fn agree(self, peer_public_key: &[u8]) -> Result<Vec<u8>>;
+}
+
+pub struct Secp256r1(p256::ecdh::EphemeralSecret);
+
+impl KeyExchange for Secp256r1 {
+    const GROUP: NamedGroup = NamedGroup::secp256r1;
+
+    fn generate() -> Self {
+        Secp256r1(p256::ecdh::EphemeralSecret::random(&mut OsRng))
+    }
+
+    // 非圧縮形式
+    fn public_key(&self) -> Vec<u8> {
+        self.0
+            .public_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn agree(self, peer_public_key: &[u8]) -> Result<Vec<u8>> {
+        let Ok(peer_public_key) = p256::PublicKey::from_sec1_bytes(peer_public_key) else {
+            bail!(Error::Alert(AlertDescription::IllegalParameter))
+        };
+        let shared_secret = self.0.diffie_hellman(&peer_public_key);
+        Ok(shared_secret.raw_secret_bytes().to_vec())
+    }
+}
+
+pub struct Secp384r1(p384::ecdh::EphemeralSecret);
+
+impl KeyExchange for Secp384r1 {
+    const GROUP: NamedGroup = NamedGroup::secp384r1;
+
+    fn generate() -> Self {
+        Secp384r1(p384::ecdh::EphemeralSecret::random(&mut OsRng))
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.0
+            .public_key()
+            .to_encoded_point(false)
+            .as_bytes()
+            .to_vec()
+    }
+
+    fn agree(self, peer_public_key: &[u8]) -> Result<Vec<u8>> {
+        let Ok(peer_public_key) = p384::PublicKey::from_sec1_bytes(peer_public_key) else {
+            bail!(Error::Alert(AlertDescription::IllegalParameter))
+        };
+        let shared_secret = self.0.diffie_hellman(&peer_public_key);
+        Ok(shared_secret.raw_secret_bytes().to_vec())
+    }
+}
+
+// (EC)DHE の一時鍵。ネゴシエーションで決まったグループの KeyExchange に振り分ける
+pub enum EphemeralSecret {
+    X25519(X25519),
+    Secp256r1(Secp256r1),
+    Secp384r1(Secp384r1),
+}
+
+impl EphemeralSecret {
+    pub fn generate(group: NamedGroup) -> Result<Self> {
+        let secret = match group {
+            NamedGroup::x25519 => EphemeralSecret::X25519(X25519::generate()),
+            NamedGroup::secp256r1 => EphemeralSecret::Secp256r1(Secp256r1::generate()),
+            NamedGroup::secp384r1 => EphemeralSecret::Secp384r1(Secp384r1::generate()),
+            _ => bail!("{:?} is not supported", group),
+        };
+        Ok(secret)
+    }
+
+    pub fn is_supported(group: NamedGroup) -> bool {
+        [X25519::GROUP, Secp256r1::GROUP, Secp384r1::GROUP].contains(&group)
+    }
+
+    pub fn group(&self) -> NamedGroup {
+        match self {
+            EphemeralSecret::X25519(_) => X25519::GROUP,
+            EphemeralSecret::Secp256r1(_) => Secp256r1::GROUP,
+            EphemeralSecret::Secp384r1(_) => Secp384r1::GROUP,
+        }
+    }
+
+    pub fn public_key(&self) -> Vec<u8> {
+        match self {
+            EphemeralSecret::X25519(secret) => secret.public_key(),
+            EphemeralSecret::Secp256r1(secret) => secret.public_key(),
+            EphemeralSecret::Secp384r1(secret) => secret.public_key(),
+        }
+    }
+
+    pub fn agree(self, peer_public_key: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            EphemeralSecret::X25519(secret) => secret.agree(peer_public_key),
+            EphemeralSecret::Secp256r1(secret) => secret.agree(peer_public_key),
+            EphemeralSecret::Secp384r1(secret) => secret.agree(peer_public_key),
+        }
+    }
+}
+
+// RFC 5246 7.4.7.1. 静的 RSA で暗号化された PreMasterSecret を復号する
+// パディングや長さが不正でもエラーにせず、ランダムな PreMasterSecret で処理を続ける
+// どちらの場合も同じ処理をして、Finished の検証で失敗させる (Bleichenbacher 攻撃対策)
+// バージョンは中身にかかわらず ClientHello.client_version を使う (ロールバック攻撃対策)
+pub fn decrypt_pre_master_secret(
+    key: &RsaPrivateKey,
+    encrypted_pre_master_secret: &[u8],
+    client_version: ProtocolVersion,
+) -> Vec<u8> {
+    const PRE_MASTER_SECRET_LENGTH: usize = 48;
+
+    let mut pre_master_secret = vec![0; PRE_MASTER_SECRET_LENGTH];
+    pre_master_secret[..2].copy_from_slice(&(client_version as u16).to_be_bytes());
+    OsRng.fill_bytes(&mut pre_master_secret[2..]);
+
+    // 暗号文の長さは秘密ではないので分岐してよい
+    let k = key.size();
+    if encrypted_pre_master_secret.len() != k || k < PRE_MASTER_SECRET_LENGTH + 11 {
+        return pre_master_secret;
+    }
+    let em = key
+        .decrypt_raw(encrypted_pre_master_secret)
+        .unwrap_or_else(|_| vec![0; k]);
+
+    // EM = 0x00 || 0x02 || PS (0 以外) || 0x00 || PreMasterSecret (48 bytes)
+    let separator = k - PRE_MASTER_SECRET_LENGTH - 1;
+    let mut valid = em[0].ct_eq(&0) & em[1].ct_eq(&2) & em[separator].ct_eq(&0);
+    for byte in &em[2..separator] {
+        valid &= !byte.ct_eq(&0);
+    }
+    for (out, byte) in pre_master_secret[2..].iter_mut().zip(&em[separator + 3..]) {
+        out.conditional_assign(byte, valid);
+    }
+    pre_master_secret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::pem::parse_x509_pem;
+
+    #[test]
+    fn agree() {
+        for group in [
+            NamedGroup::x25519,
+            NamedGroup::secp256r1,
+            NamedGroup::secp384r1,
+        ] {
+            let client = EphemeralSecret::generate(group).unwrap();
+            let server = EphemeralSecret::generate(group).unwrap();
+            assert_eq!(client.group(), group);
+            let client_public_key = client.public_key();
+            let server_public_key = server.public_key();
+            assert_eq!(
+                client.agree(&server_public_key).unwrap(),
+                server.agree(&client_public_key).unwrap()
+            );
+        }
+        assert!(!EphemeralSecret::is_supported(NamedGroup::x448));
+        assert!(EphemeralSecret::generate(NamedGroup::secp521r1).is_err());
+    }
+
+    // 曲線上に無い点や長さの違う公開鍵は illegal_parameter
+    #[test]
+    fn agree_rejects_invalid_public_key() {
+        let mut not_on_curve = vec![4];
+        not_on_curve.extend([0; 31]);
+        not_on_curve.push(1);
+        not_on_curve.extend([0; 31]);
+        not_on_curve.push(1);
+        let p384_point = Secp384r1::generate().public_key();
+
+        for (group, public_key) in [
+            (NamedGroup::secp256r1, not_on_curve.clone()),
+            (NamedGroup::secp256r1, p384_point.clone()),
+            (NamedGroup::secp256r1, vec![]),
+            (NamedGroup::secp384r1, not_on_curve),
+            (NamedGroup::secp384r1, p384_point[..64].to_vec()),
+        ] {
+            let error = EphemeralSecret::generate(group)
+                .unwrap()
+                .agree(&public_key)
+                .unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<Error>(),
+                Some(Error::Alert(AlertDescription::IllegalParameter))
+            ));
+        }
+    }
+
+    // パディングが不正でも、エラーにせず client_version で始まる 48 bytes を返す
+    #[test]
+    fn pre_master_secret() {
+        let path = format!("{}/testdata/rsa/key2048.pem", env!("CARGO_MANIFEST_DIR"));
+        let (_, pem) = parse_x509_pem(&std::fs::read(path).unwrap()).unwrap();
+        let key = RsaPrivateKey::from_pkcs8_der(&pem.contents).unwrap();
+        let version = ProtocolVersion::TLSv1_2;
+
+        let mut pre_master_secret = vec![3, 3];
+        pre_master_secret.extend([0x5a; 46]);
+        let encrypted = key
+            .public_key()
+            .encrypt_pkcs1v15(&pre_master_secret)
+            .unwrap();
+        assert_eq!(
+            decrypt_pre_master_secret(&key, &encrypted, version),
+            pre_master_secret
+        );
+
+        // 中のバージョンが違っても client_version を使う
+        let mut rollback = pre_master_secret.clone();
+        rollback[1] = 1;
+        let encrypted = key.public_key().encrypt_pkcs1v15(&rollback).unwrap();
+        assert_eq!(
+            decrypt_pre_master_secret(&key, &encrypted, version),
+            pre_master_secret
+        );
+
+        let short = key
+            .public_key()
+            .encrypt_pkcs1v15(&pre_master_secret[..47])
+            .unwrap();
+        for encrypted in [short, vec![0; key.size()], vec![1; key.size() - 1]] {
+            let result = decrypt_pre_master_secret(&key, &encrypted, version);
+            assert_eq!(result.len(), 48);
+            assert_eq!(result[..2], [3, 3]);
+            assert_ne!(result, pre_master_secret);
+        }
+    }
+}
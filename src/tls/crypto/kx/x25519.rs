@@ -0,0 +1,330 @@
+use super::KeyExchange;
+use crate::tls::{AlertDescription, Error, NamedGroup};
+
+use anyhow::{bail, Result};
+use rand::rngs::OsRng;
+use rand::RngCore;
+
+// RFC 7748 X25519
+pub const X25519_KEY_LENGTH: usize = 32;
+
+// 4.1. ベースポイントの u 座標
+const BASE_POINT: [u8; X25519_KEY_LENGTH] = {
+    let mut u = [0u8; X25519_KEY_LENGTH];
+    u[0] = 9;
+    u
+};
+
+pub struct X25519 {
+    secret: [u8; X25519_KEY_LENGTH],
+    public_key: [u8; X25519_KEY_LENGTH],
+}
+
+impl KeyExchange for X25519 {
+    const GROUP: NamedGroup = NamedGroup::x25519;
+
+    fn generate() -> Self {
+        let mut secret = [0u8; X25519_KEY_LENGTH];
+        OsRng.fill_bytes(&mut secret);
+        X25519 {
+            public_key: x25519(&secret, &BASE_POINT),
+            secret,
+        }
+    }
+
+    fn public_key(&self) -> Vec<u8> {
+        self.public_key.to_vec()
+    }
+
+    // 6.1. 共有鍵がすべて 0 になる (相手が位数の小さい点を送ってきた) 場合は中止する
+    fn agree(self, peer_public_key: &[u8]) -> Result<Vec<u8>> {
+        let Ok(peer_public_key) = <[u8; X25519_KEY_LENGTH]>::try_from(peer_public_key) else {
+            bail!(Error::Alert(AlertDescription::IllegalParameter))
+        };
+        let shared_secret = x25519(&self.secret, &peer_public_key);
+        if shared_secret.iter().fold(0, |acc, byte| acc | byte) == 0 {
+            bail!(Error::Alert(AlertDescription::IllegalParameter))
+        }
+        Ok(shared_secret.to_vec())
+    }
+}
+
+// 5. X25519(k, u)
+pub fn x25519(
+    scalar: &[u8; X25519_KEY_LENGTH],
+    u: &[u8; X25519_KEY_LENGTH],
+) -> [u8; X25519_KEY_LENGTH] {
+    // decodeScalar25519
+    let mut k = *scalar;
+    k[0] &= 248;
+    k[31] &= 127;
+    k[31] |= 64;
+
+    // decodeUCoordinate。最上位ビットは無視し、p 以上の値も受け付ける
+    let x1 = FieldElement::from_bytes(u);
+    let mut x2 = FieldElement::ONE;
+    let mut z2 = FieldElement::ZERO;
+    let mut x3 = x1;
+    let mut z3 = FieldElement::ONE;
+
+    // モンゴメリ・ラダー。スカラーのビットによらず同じ計算をして、入れ替えはマスクで行う
+    let mut swap = 0u64;
+    for t in (0..255).rev() {
+        let k_t = ((k[t / 8] >> (t % 8)) & 1) as u64;
+        swap ^= k_t;
+        FieldElement::conditional_swap(&mut x2, &mut x3, swap);
+        FieldElement::conditional_swap(&mut z2, &mut z3, swap);
+        swap = k_t;
+
+        let a = x2.add(&z2);
+        let aa = a.square();
+        let b = x2.sub(&z2);
+        let bb = b.square();
+        let e = aa.sub(&bb);
+        let c = x3.add(&z3);
+        let d = x3.sub(&z3);
+        let da = d.mul(&a);
+        let cb = c.mul(&b);
+        x3 = da.add(&cb).square();
+        z3 = x1.mul(&da.sub(&cb).square());
+        x2 = aa.mul(&bb);
+        // a24 = (486662 - 2) / 4
+        z2 = e.mul(&aa.add(&e.mul_small(121665)));
+    }
+    FieldElement::conditional_swap(&mut x2, &mut x3, swap);
+    FieldElement::conditional_swap(&mut z2, &mut z3, swap);
+
+    x2.mul(&z2.invert()).to_bytes()
+}
+
+//==================================================================================================
+// GF(2^255 - 19)
+//==================================================================================================
+
+const MASK: u64 = (1 << 51) - 1;
+
+// 51 bits ずつ 5 つの limb に分けて持つ。limb は 51 bits を少し超えてもよい
+#[derive(Clone, Copy)]
+struct FieldElement([u64; 5]);
+
+impl FieldElement {
+    const ZERO: FieldElement = FieldElement([0; 5]);
+    const ONE: FieldElement = FieldElement([1, 0, 0, 0, 0]);
+
+    fn from_bytes(bytes: &[u8; 32]) -> Self {
+        let word = |i: usize| u64::from_le_bytes(bytes[8 * i..8 * i + 8].try_into().unwrap());
+        let (w0, w1, w2, w3) = (word(0), word(1), word(2), word(3));
+        FieldElement([
+            w0 & MASK,
+            ((w0 >> 51) | (w1 << 13)) & MASK,
+            ((w1 >> 38) | (w2 << 26)) & MASK,
+            ((w2 >> 25) | (w3 << 39)) & MASK,
+            (w3 >> 12) & MASK,
+        ])
+    }
+
+    // p 未満にしてから出力する
+    fn to_bytes(self) -> [u8; 32] {
+        let mut h = self.carry().carry().0;
+
+        // h >= p なら q = 1
+        let mut q = (h[0] + 19) >> 51;
+        for limb in &h[1..] {
+            q = (limb + q) >> 51;
+        }
+        h[0] += 19 * q;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK;
+        }
+        h[4] &= MASK;
+
+        let words = [
+            h[0] | (h[1] << 51),
+            (h[1] >> 13) | (h[2] << 38),
+            (h[2] >> 26) | (h[3] << 25),
+            (h[3] >> 39) | (h[4] << 12),
+        ];
+        let mut bytes = [0u8; 32];
+        for (chunk, word) in bytes.chunks_exact_mut(8).zip(words) {
+            chunk.copy_from_slice(&word.to_le_bytes());
+        }
+        bytes
+    }
+
+    // 2^255 = 19 (mod p) で上の limb にあふれた分を戻す
+    fn carry(self) -> Self {
+        let mut h = self.0;
+        for i in 0..4 {
+            h[i + 1] += h[i] >> 51;
+            h[i] &= MASK;
+        }
+        h[0] += 19 * (h[4] >> 51);
+        h[4] &= MASK;
+        FieldElement(h)
+    }
+
+    fn add(&self, other: &Self) -> Self {
+        FieldElement(std::array::from_fn(|i| self.0[i] + other.0[i]))
+    }
+
+    // 負にならないように 2p を足してから引く
+    fn sub(&self, other: &Self) -> Self {
+        const TWO_P: [u64; 5] = [
+            0xfffffffffffda,
+            0xffffffffffffe,
+            0xffffffffffffe,
+            0xffffffffffffe,
+            0xffffffffffffe,
+        ];
+        FieldElement(std::array::from_fn(|i| self.0[i] + TWO_P[i] - other.0[i])).carry()
+    }
+
+    fn mul(&self, other: &Self) -> Self {
+        let a = self.0.map(|limb| limb as u128);
+        let b = other.0.map(|limb| limb as u128);
+        // x^5 の項は 2^255 = 19 で下の limb に回す
+        let b19 = b.map(|limb| 19 * limb);
+        let r = [
+            a[0] * b[0] + a[1] * b19[4] + a[2] * b19[3] + a[3] * b19[2] + a[4] * b19[1],
+            a[0] * b[1] + a[1] * b[0] + a[2] * b19[4] + a[3] * b19[3] + a[4] * b19[2],
+            a[0] * b[2] + a[1] * b[1] + a[2] * b[0] + a[3] * b19[4] + a[4] * b19[3],
+            a[0] * b[3] + a[1] * b[2] + a[2] * b[1] + a[3] * b[0] + a[4] * b19[4],
+            a[0] * b[4] + a[1] * b[3] + a[2] * b[2] + a[3] * b[1] + a[4] * b[0],
+        ];
+        Self::reduce(r)
+    }
+
+    fn square(&self) -> Self {
+        self.mul(self)
+    }
+
+    fn mul_small(&self, n: u64) -> Self {
+        Self::reduce(self.0.map(|limb| limb as u128 * n as u128))
+    }
+
+    fn reduce(mut r: [u128; 5]) -> Self {
+        for i in 0..4 {
+            r[i + 1] += r[i] >> 51;
+            r[i] &= MASK as u128;
+        }
+        r[0] += 19 * (r[4] >> 51);
+        r[4] &= MASK as u128;
+        FieldElement(r.map(|limb| limb as u64)).carry()
+    }
+
+    // z^(p-2) = z^-1。指数は公開された値なので分岐してよい
+    fn invert(&self) -> Self {
+        // p - 2 = 2^255 - 21 は 0..255 ビットのうち 2 と 4 だけが 0
+        let mut result = FieldElement::ONE;
+        for bit in (0..255).rev() {
+            result = result.square();
+            if bit != 2 && bit != 4 {
+                result = result.mul(self);
+            }
+        }
+        result
+    }
+
+    // swap が 1 なら a と b を入れ替える
+    fn conditional_swap(a: &mut Self, b: &mut Self, swap: u64) {
+        let mask = 0u64.wrapping_sub(swap);
+        for (a, b) in a.0.iter_mut().zip(b.0.iter_mut()) {
+            let t = mask & (*a ^ *b);
+            *a ^= t;
+            *b ^= t;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    // RFC 7748 5.2.
+    #[test]
+    fn rfc7748_vectors() {
+        assert_eq!(
+            x25519(
+                &hex!("a546e36bf0527c9d3b16154b82465edd62144c0ac1fc5a18506a2244ba449ac4"),
+                &hex!("e6db6867583030db3594c1a424b15f7c726624ec26b3353b10a903a6d0ab1c4c"),
+            ),
+            hex!("c3da55379de9c6908e94ea4df28d084f32eccf03491c71f754b4075577a28552")
+        );
+        // u の最上位ビットが立っている
+        assert_eq!(
+            x25519(
+                &hex!("4b66e9d4d1b4673c5ad22691957d6af5c11b6421e0ea01d42ca4169e7918ba0d"),
+                &hex!("e5210f12786811d3f4b7959d0538ae2c31dbe7106fc03c3efc4cd549c715a493"),
+            ),
+            hex!("95cbde9476e8907d7aade45cb4b873f88b595a68799fa152e6f8f7647aac7957")
+        );
+    }
+
+    // RFC 7748 5.2. k = u = 9 から始めて k = X25519(k, u), u = 元の k を繰り返す
+    #[test]
+    fn rfc7748_iterations() {
+        let mut k = BASE_POINT;
+        let mut u = BASE_POINT;
+        for i in 1..=1000 {
+            let result = x25519(&k, &u);
+            u = k;
+            k = result;
+            if i == 1 {
+                assert_eq!(
+                    k,
+                    hex!("422c8e7a6227d7bca1350b3e2bb7279f7897b87bb6854b783c60e80311ae3079")
+                );
+            }
+        }
+        assert_eq!(
+            k,
+            hex!("684cf59ba83309552800ef566f2f4d3c1c3887c49360e3875f2eb94d99532c51")
+        );
+    }
+
+    // RFC 7748 6.1.
+    #[test]
+    fn rfc7748_key_agreement() {
+        let alice = hex!("77076d0a7318a57d3c16c17251b26645df4c2f87ebc0992ab177fba51db92c2a");
+        let bob = hex!("5dab087e624a8a4b79e17f8b83800ee66f3bb1292618b6fd1c2f8b27ff88e0eb");
+        let alice_public = x25519(&alice, &BASE_POINT);
+        let bob_public = x25519(&bob, &BASE_POINT);
+        assert_eq!(
+            alice_public,
+            hex!("8520f0098930a754748b7ddcb43ef75a0dbf3a0d26381af4eba4a98eaa9b4e6a")
+        );
+        assert_eq!(
+            bob_public,
+            hex!("de9edb7d7b7dc1b4d35b61c2ece435373f8343c85b78674dadfc7e146f882b4f")
+        );
+        let shared_secret =
+            hex!("4a5d9d5ba4ce2de1728e3bf480350f25e07e21c947d19e3376f09b3c1e161742");
+        assert_eq!(x25519(&alice, &bob_public), shared_secret);
+        assert_eq!(x25519(&bob, &alice_public), shared_secret);
+    }
+
+    #[test]
+    fn agree() {
+        let alice = X25519::generate();
+        let bob = X25519::generate();
+        let alice_public = alice.public_key();
+        let bob_public = bob.public_key();
+        assert_eq!(
+            alice.agree(&bob_public).unwrap(),
+            bob.agree(&alice_public).unwrap()
+        );
+    }
+
+    #[test]
+    fn agree_rejects_invalid_public_key() {
+        for public_key in [&[9u8; 31][..], &[0; 32]] {
+            let error = X25519::generate().agree(public_key).unwrap_err();
+            assert!(matches!(
+                error.downcast_ref::<Error>(),
+                Some(Error::Alert(AlertDescription::IllegalParameter))
+            ));
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::{hmac, DigestAlgorithm};
+
+// RFC 5246 5. HMAC and the Pseudorandom Function
+// PRF(secret, label, seed) = P_<hash>(secret, label + seed)
+pub fn prf(
+    algorithm: DigestAlgorithm,
+    secret: &[u8],
+    label: &[u8],
+    seed: &[u8],
+    length: usize,
+) -> Vec<u8> {
+    let mut label_seed = label.to_vec();
+    label_seed.extend_from_slice(seed);
+    p_hash(algorithm, secret, &label_seed, length)
+}
+
+fn p_hash(algorithm: DigestAlgorithm, secret: &[u8], seed: &[u8], length: usize) -> Vec<u8> {
+    let mut result = vec![];
+
+    // A(0) = seed, A(i) = HMAC_hash(secret, A(i-1))
+    let mut a = seed.to_vec();
+    while result.len() < length {
+        a = hmac(algorithm, secret, &a);
+
+        let mut input = a.clone();
+        input.extend_from_slice(seed);
+        result.extend(hmac(algorithm, secret, &input));
+    }
+
+    result.truncate(length);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    // TLS 1.2 の P_SHA256 の既知の出力 (IETF TLS WG で共有されたテストベクタ)
+    #[test]
+    fn prf_sha256() {
+        assert_eq!(
+            prf(
+                DigestAlgorithm::Sha256,
+                &hex!("9bbe436ba940f017b17652849a71db35"),
+                b"test label",
+                &hex!("a0ba9f936cda311827a6f796ffd5198c"),
+                100
+            ),
+            hex!(
+                "e3f229ba727be17b8d122620557cd453c2aab21d07c3d495329b52d4e61edb5a"
+                "6b301791e90d35c9c9a46b4e14baf9af0fa022f7077def17abfd3797c0564bab"
+                "4fbc91666e9def9b97fce34f796789baa48082d122ee42c5a72e5a5110fff701"
+                "87347b66"
+            )
+        );
+    }
+}
@@ -0,0 +1,859 @@
+use super::bigint::{BigUint, Modulus};
+use super::hash::{digest, DigestAlgorithm};
+
+use anyhow::{anyhow, bail, Result};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use x509_parser::der_parser::ber::BerObject;
+use x509_parser::der_parser::der::parse_der;
+use x509_parser::oid_registry::OID_PKCS1_RSAENCRYPTION;
+use x509_parser::prelude::FromDer;
+use x509_parser::public_key::PublicKey;
+use x509_parser::x509::SubjectPublicKeyInfo;
+
+// RFC 8017 PKCS #1 v2.2
+#[derive(Clone)]
+pub struct RsaPublicKey {
+    n: BigUint,
+    e: BigUint,
+    modulus: Modulus,
+    // 法の長さ (bytes)
+    size: usize,
+}
+
+impl RsaPublicKey {
+    pub fn new(n: BigUint, e: BigUint) -> Result<Self> {
+        // e は 3 以上の奇数 (RFC 8017 3.1)
+        if !e.is_odd() || e.bits() < 2 || n.bits() < 512 {
+            bail!("invalid RSA public key");
+        }
+        let size = n.bits().div_ceil(8);
+        Ok(RsaPublicKey {
+            modulus: Modulus::new(&n)?,
+            n,
+            e,
+            size,
+        })
+    }
+
+    // 証明書の SubjectPublicKeyInfo (DER)
+    pub fn from_public_key_der(spki: &[u8]) -> Result<Self> {
+        let (_, spki) = SubjectPublicKeyInfo::from_der(spki)?;
+        match spki.parsed()? {
+            PublicKey::RSA(key) => RsaPublicKey::new(
+                BigUint::from_bytes_be(key.modulus),
+                BigUint::from_bytes_be(key.exponent),
+            ),
+            _ => bail!("not an RSA public key"),
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    pub fn modulus_bits(&self) -> usize {
+        self.n.bits()
+    }
+
+    // 5.2.2. RSAVP1 (暗号化の RSAEP も同じ計算)
+    fn public_operation(&self, input: &[u8]) -> Result<BigUint> {
+        let m = BigUint::from_bytes_be(input);
+        if !m.lt(&self.n) {
+            bail!("message representative out of range");
+        }
+        Ok(self.modulus.pow(&m, &self.e))
+    }
+
+    // 7.2.1. RSAES-PKCS1-V1_5-ENCRYPT
+    pub fn encrypt_pkcs1v15(&self, message: &[u8]) -> Result<Vec<u8>> {
+        let k = self.size;
+        if message.len() + 11 > k {
+            bail!("message too long");
+        }
+
+        // EM = 0x00 || 0x02 || PS || 0x00 || M。PS は 0 を含まない乱数
+        let mut em = vec![0u8; k];
+        em[1] = 2;
+        let ps = &mut em[2..k - message.len() - 1];
+        OsRng.fill_bytes(ps);
+        for byte in ps.iter_mut() {
+            while *byte == 0 {
+                *byte = OsRng.next_u32() as u8;
+            }
+        }
+        em[k - message.len()..].copy_from_slice(message);
+
+        Ok(self.public_operation(&em)?.to_bytes_be(k))
+    }
+
+    // 8.2.2. RSASSA-PKCS1-V1_5-VERIFY
+    pub fn verify_pkcs1v15(
+        &self,
+        hash: DigestAlgorithm,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        if signature.len() != self.size {
+            bail!("invalid signature length");
+        }
+        let em = self.public_operation(signature)?.to_bytes_be(self.size);
+        if em != emsa_pkcs1v15_encode(hash, message, self.size)? {
+            bail!("invalid signature");
+        }
+        Ok(())
+    }
+
+    // 8.1.2. RSASSA-PSS-VERIFY
+    // TLS ではソルトの長さはハッシュの長さと同じ (RFC 8446 4.2.3)
+    pub fn verify_pss(
+        &self,
+        hash: DigestAlgorithm,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        if signature.len() != self.size {
+            bail!("invalid signature length");
+        }
+        let em_bits = self.modulus_bits() - 1;
+        let em_length = em_bits.div_ceil(8);
+        let m = self.public_operation(signature)?;
+        if m.bits() > 8 * em_length {
+            bail!("invalid signature");
+        }
+        emsa_pss_verify(hash, message, &m.to_bytes_be(em_length), em_bits)
+    }
+}
+
+impl PartialEq for RsaPublicKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.n.ct_eq(&other.n) && self.e.ct_eq(&other.e)
+    }
+}
+
+// CRT の値 (RFC 8017 3.2 の 2 つ目の表現) を持つ秘密鍵
+#[derive(Clone)]
+pub struct RsaPrivateKey {
+    public_key: RsaPublicKey,
+    p: Modulus,
+    q: Modulus,
+    q_value: BigUint,
+    // d mod (p - 1), d mod (q - 1)
+    dp: BigUint,
+    dq: BigUint,
+    // q^-1 mod p
+    q_inv: BigUint,
+    // 逆元 (フェルマーの小定理) に使う p - 2, q - 2
+    p_minus_2: BigUint,
+    q_minus_2: BigUint,
+}
+
+impl RsaPrivateKey {
+    // A.1.2. RSAPrivateKey
+    pub fn from_pkcs1_der(der: &[u8]) -> Result<Self> {
+        let (_, key) = parse_der(der)?;
+        let fields = key.as_sequence()?;
+        if fields.len() < 9 || fields[0].as_u32()? != 0 {
+            bail!("unsupported RSA private key");
+        }
+        let integer = |object: &BerObject| -> Result<BigUint> {
+            Ok(BigUint::from_bytes_be(object.as_slice()?))
+        };
+        let n = integer(&fields[1])?;
+        let e = integer(&fields[2])?;
+        let p = integer(&fields[4])?;
+        let q = integer(&fields[5])?;
+        let dp = integer(&fields[6])?;
+        let dq = integer(&fields[7])?;
+        let q_inv = integer(&fields[8])?;
+
+        if !p.mul(&q).ct_eq(&n) {
+            bail!("invalid RSA private key");
+        }
+        let two = BigUint::from_u64(2);
+        let p_minus_2 = p.sub(&two);
+        let q_minus_2 = q.sub(&two);
+        let key = RsaPrivateKey {
+            public_key: RsaPublicKey::new(n, e)?,
+            p: Modulus::new(&p)?,
+            q: Modulus::new(&q)?,
+            q_value: q,
+            dp,
+            dq,
+            q_inv,
+            p_minus_2,
+            q_minus_2,
+        };
+
+        // CRT の値が正しいか、署名して検証してみる
+        let message = BigUint::from_u64(2);
+        let signature = key.private_operation(&message.to_bytes_be(key.size()));
+        if signature.is_err() {
+            bail!("invalid RSA private key");
+        }
+        Ok(key)
+    }
+
+    // RFC 5208 PrivateKeyInfo のうち rsaEncryption のもの
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        let (_, info) = parse_der(der)?;
+        let fields = info.as_sequence()?;
+        if fields.len() < 3 {
+            bail!("invalid PrivateKeyInfo");
+        }
+        let algorithm = fields[1].as_sequence()?;
+        let oid = algorithm
+            .first()
+            .ok_or_else(|| anyhow!("invalid PrivateKeyInfo"))?
+            .as_oid()?;
+        if *oid != OID_PKCS1_RSAENCRYPTION {
+            bail!("not an RSA private key");
+        }
+        Self::from_pkcs1_der(fields[2].as_slice()?)
+    }
+
+    pub fn public_key(&self) -> &RsaPublicKey {
+        &self.public_key
+    }
+
+    pub fn size(&self) -> usize {
+        self.public_key.size
+    }
+
+    // 5.1.2. RSADP (署名の RSASP1 も同じ計算)
+    // 中国の剰余定理で計算する。乱数 r で c * r^e を復号してから r を外す (ブラインディング)
+    // 故障攻撃対策に、結果を公開鍵で戻して確かめる
+    fn private_operation(&self, input: &[u8]) -> Result<BigUint> {
+        let public_key = &self.public_key;
+        let c = BigUint::from_bytes_be(input);
+        if !c.lt(&public_key.n) {
+            bail!("ciphertext representative out of range");
+        }
+
+        let mut random = vec![0u8; public_key.size];
+        OsRng.fill_bytes(&mut random);
+        let r = public_key.modulus.reduce(&BigUint::from_bytes_be(&random));
+        let blinded = public_key
+            .modulus
+            .mul(&c, &public_key.modulus.pow(&r, &public_key.e));
+
+        // m_1 = (c r^e)^dp = c^dp * r mod p なので r^-1 = r^(p-2) を掛ける
+        let m1 = self.p.pow(&blinded, &self.dp);
+        let m1 = self
+            .p
+            .mul(&m1, &self.p.pow(&self.p.reduce(&r), &self.p_minus_2));
+        let m2 = self.q.pow(&blinded, &self.dq);
+        let m2 = self
+            .q
+            .mul(&m2, &self.q.pow(&self.q.reduce(&r), &self.q_minus_2));
+
+        // m = m_2 + q * (q^-1 (m_1 - m_2) mod p)
+        let h = self
+            .p
+            .mul(&self.q_inv, &self.p.sub(&m1, &self.p.reduce(&m2)));
+        let m = m2.add(&self.q_value.mul(&h));
+
+        if !public_key.modulus.pow(&m, &public_key.e).ct_eq(&c) {
+            bail!("RSA private key operation failed");
+        }
+        Ok(m)
+    }
+
+    // 暗号文をそのまま復号して、長さ k の EM を返す
+    // TLS の RSA 鍵交換はパディングの検査を呼び出し側で定数時間で行う
+    pub fn decrypt_raw(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        if ciphertext.len() != self.size() {
+            bail!("decryption error");
+        }
+        Ok(self.private_operation(ciphertext)?.to_bytes_be(self.size()))
+    }
+
+    // 7.2.2. RSAES-PKCS1-V1_5-DECRYPT
+    // パディングの誤りを区別できると Bleichenbacher 攻撃ができるので、TLS の鍵交換には使わない
+    pub fn decrypt_pkcs1v15(&self, ciphertext: &[u8]) -> Result<Vec<u8>> {
+        let em = self.decrypt_raw(ciphertext)?;
+        let separator = em[2..].iter().position(|byte| *byte == 0);
+        match separator {
+            Some(separator) if em[0] == 0 && em[1] == 2 && separator >= 8 => {
+                Ok(em[separator + 3..].to_vec())
+            }
+            _ => bail!("decryption error"),
+        }
+    }
+
+    // 8.2.1. RSASSA-PKCS1-V1_5-SIGN
+    pub fn sign_pkcs1v15(&self, hash: DigestAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        let em = emsa_pkcs1v15_encode(hash, message, self.size())?;
+        Ok(self.private_operation(&em)?.to_bytes_be(self.size()))
+    }
+
+    // 8.1.1. RSASSA-PSS-SIGN
+    pub fn sign_pss(&self, hash: DigestAlgorithm, message: &[u8]) -> Result<Vec<u8>> {
+        let em_bits = self.public_key.modulus_bits() - 1;
+        let em = emsa_pss_encode(hash, message, em_bits)?;
+        Ok(self.private_operation(&em)?.to_bytes_be(self.size()))
+    }
+}
+
+//==================================================================================================
+// エンコード
+//==================================================================================================
+
+// 9.2. の注 1. DigestInfo の DER のうちハッシュ値の前の部分
+fn digest_info_prefix(hash: DigestAlgorithm) -> &'static [u8] {
+    match hash {
+        DigestAlgorithm::Sha1 => &[
+            0x30, 0x21, 0x30, 0x09, 0x06, 0x05, 0x2b, 0x0e, 0x03, 0x02, 0x1a, 0x05, 0x00, 0x04,
+            0x14,
+        ],
+        DigestAlgorithm::Sha256 => &[
+            0x30, 0x31, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x01, 0x05, 0x00, 0x04, 0x20,
+        ],
+        DigestAlgorithm::Sha384 => &[
+            0x30, 0x41, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x02, 0x05, 0x00, 0x04, 0x30,
+        ],
+        DigestAlgorithm::Sha512 => &[
+            0x30, 0x51, 0x30, 0x0d, 0x06, 0x09, 0x60, 0x86, 0x48, 0x01, 0x65, 0x03, 0x04, 0x02,
+            0x03, 0x05, 0x00, 0x04, 0x40,
+        ],
+    }
+}
+
+// 9.2. EMSA-PKCS1-v1_5-ENCODE
+// EM = 0x00 || 0x01 || PS (0xff) || 0x00 || DigestInfo
+fn emsa_pkcs1v15_encode(hash: DigestAlgorithm, message: &[u8], length: usize) -> Result<Vec<u8>> {
+    let mut t = digest_info_prefix(hash).to_vec();
+    t.extend(digest(hash, message));
+    if length < t.len() + 11 {
+        bail!("intended encoded message length too short");
+    }
+    let mut em = vec![0xff; length];
+    em[0] = 0;
+    em[1] = 1;
+    em[length - t.len() - 1] = 0;
+    em[length - t.len()..].copy_from_slice(&t);
+    Ok(em)
+}
+
+// B.2.1. MGF1
+fn mgf1(hash: DigestAlgorithm, seed: &[u8], length: usize) -> Vec<u8> {
+    let mut mask = vec![];
+    let mut counter = 0u32;
+    while mask.len() < length {
+        mask.extend(digest(hash, &[seed, &counter.to_be_bytes()].concat()));
+        counter += 1;
+    }
+    mask.truncate(length);
+    mask
+}
+
+// 9.1.1. EMSA-PSS-ENCODE
+fn emsa_pss_encode(hash: DigestAlgorithm, message: &[u8], em_bits: usize) -> Result<Vec<u8>> {
+    let m_hash = digest(hash, message);
+    let h_length = m_hash.len();
+    let salt_length = h_length;
+    let em_length = em_bits.div_ceil(8);
+    if em_length < h_length + salt_length + 2 {
+        bail!("encoding error");
+    }
+
+    let mut salt = vec![0u8; salt_length];
+    OsRng.fill_bytes(&mut salt);
+    // M' = 0x00 * 8 || mHash || salt
+    let h = digest(hash, &[&[0u8; 8], m_hash.as_slice(), &salt].concat());
+
+    // DB = PS || 0x01 || salt
+    let mut db = vec![0u8; em_length - h_length - 1];
+    let db_length = db.len();
+    db[db_length - salt_length - 1] = 1;
+    db[db_length - salt_length..].copy_from_slice(&salt);
+    for (d, m) in db.iter_mut().zip(mgf1(hash, &h, db_length)) {
+        *d ^= m;
+    }
+    db[0] &= 0xff >> (8 * em_length - em_bits);
+
+    // EM = maskedDB || H || 0xbc
+    let mut em = db;
+    em.extend(h);
+    em.push(0xbc);
+    Ok(em)
+}
+
+// 9.1.2. EMSA-PSS-VERIFY
+fn emsa_pss_verify(hash: DigestAlgorithm, message: &[u8], em: &[u8], em_bits: usize) -> Result<()> {
+    let m_hash = digest(hash, message);
+    let h_length = m_hash.len();
+    let salt_length = h_length;
+    let em_length = em.len();
+    if em_length < h_length + salt_length + 2 || em[em_length - 1] != 0xbc {
+        bail!("inconsistent");
+    }
+
+    let (masked_db, h) = em[..em_length - 1].split_at(em_length - h_length - 1);
+    let unused_bits = 8 * em_length - em_bits;
+    if masked_db[0] & !(0xff >> unused_bits) != 0 {
+        bail!("inconsistent");
+    }
+    let mut db: Vec<u8> = masked_db
+        .iter()
+        .zip(mgf1(hash, h, masked_db.len()))
+        .map(|(d, m)| d ^ m)
+        .collect();
+    db[0] &= 0xff >> unused_bits;
+
+    let ps_length = db.len() - salt_length - 1;
+    if db[..ps_length].iter().any(|byte| *byte != 0) || db[ps_length] != 1 {
+        bail!("inconsistent");
+    }
+    let salt = &db[ps_length + 1..];
+    let h_prime = digest(hash, &[&[0u8; 8], m_hash.as_slice(), salt].concat());
+    if h_prime != h {
+        bail!("inconsistent");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+    use x509_parser::pem::parse_x509_pem;
+
+    const MESSAGE: &[u8] = b"tls-from-scratch";
+
+    fn load(name: &str) -> Vec<u8> {
+        let path = format!("{}/testdata/rsa/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let (_, pem) = parse_x509_pem(&std::fs::read(path).unwrap()).unwrap();
+        pem.contents
+    }
+
+    // PKCS#8 の 2048 bits の鍵
+    fn key2048() -> RsaPrivateKey {
+        RsaPrivateKey::from_pkcs8_der(&load("key2048.pem")).unwrap()
+    }
+
+    // PKCS#1 の 1025 bits の鍵。PSS の emLen が k - 1 になる
+    fn key1025() -> RsaPrivateKey {
+        RsaPrivateKey::from_pkcs1_der(&load("key1025.pem")).unwrap()
+    }
+
+    // 署名は Python の cryptography で作ったもの
+    #[test]
+    fn pkcs1v15_known_answer() {
+        let expected = hex!(
+            "6cacc6c9a8327e4af6f1e8adee1f3f546c6ac581829fb69dfbfe463d7c5e2601"
+            "cc0a0c1dabb3c1f3f5ec7de2f1b53075cfba6068ab4b70c557cf43e1f39e023f"
+            "e15b912e313abac75adb5753ad576f2c56e1a242681c5d01227949b80f907178"
+            "d367e280420ec491346fd601db6ddd2e7de898e1a2bde03116d0d54a534368db"
+            "18586cafe673cc806880c724d3840a5ef0ebab6970eec805e083f51a89749152"
+            "5a1c679342d2dad5a87bfc6de8cba5d09808b95e8978838d7659eed8a013e104"
+            "160325a235f870d1b890c971ab48895df6b4899a3a52dd877cae5aef790aae59"
+            "82e75f161f79d86130181dcad978a24297b3d24b1594f2e2f42118dd708711a3"
+        );
+        let key = key2048();
+        assert_eq!(
+            key.sign_pkcs1v15(DigestAlgorithm::Sha256, MESSAGE).unwrap(),
+            expected
+        );
+        key.public_key()
+            .verify_pkcs1v15(DigestAlgorithm::Sha256, MESSAGE, &expected)
+            .unwrap();
+
+        let expected = hex!(
+            "0125f47224b127108d4521fdcea7872f9d70210568830550c027f6376135ca9a"
+            "6bc170ffcdf0fbdadc320550a16b13d25fb446b9a49fa89ff261a8b4f1ceb2fb"
+            "7a8a715a8c4ca7408f798c27b8f42cd5c42f4320e6538d2a8574609e4828e40c"
+            "a05b09ee451aed7512bd7c1bbc94a3a71b55c371b67fab92c574b2216043bcea"
+            "57"
+        );
+        let key = key1025();
+        assert_eq!(
+            key.sign_pkcs1v15(DigestAlgorithm::Sha512, MESSAGE).unwrap(),
+            expected
+        );
+        key.public_key()
+            .verify_pkcs1v15(DigestAlgorithm::Sha512, MESSAGE, &expected)
+            .unwrap();
+    }
+
+    #[test]
+    fn pss_known_answer() {
+        let signature = hex!(
+            "7384dc8258627c4ca4ff01c2391e5d42be8369fc8beef785e3de5ac49d410ea5"
+            "eef74d73f6474b37564ffbfc2d5e7d9d975fe78de9f1024f6dee1c5cc1acd544"
+            "df62fd76496abe99649bce7b9490c3c1798201d3d93176d5640d762ff9df6c20"
+            "e88a7f278684d0007c76b4891c28cae8f295bd90d119e1df9d46a8f6ad12fef4"
+            "f31de645bba51fbed91debcf7022768f4a08d136967d39b6bc37edf76b82fb7e"
+            "2e18ea7d19a88865067730a2e61b05c6c6922824873bcf29d87a5f641a0b63ac"
+            "368e6712772c63a166f565e0d5ecfa26af9fe22d2860aa8ac172ecf3f08c8d84"
+            "9f5a92ab1038243563b738f2b17a6e8d899610c2da228fc02aba1c9ec240aa8f"
+        );
+        key2048()
+            .public_key()
+            .verify_pss(DigestAlgorithm::Sha256, MESSAGE, &signature)
+            .unwrap();
+
+        let signature = hex!(
+            "00570c68e0248c2347913e05f52a0d8ce69c019fbb8c4054ff57e2cdc8454c5b"
+            "79cb591a71df38f640fd9c64b0b5cfa8e82405f942d41675305a6136e5b4fbd3"
+            "6fc3c7661cbe931b7dac68e20f12689aea03027df202a4c8fc6cd55e732edbc6"
+            "a46c8e66c20bc2662fe7b91ae3ba51857c2a8f6e68a5be77abd532916eb962be"
+            "08"
+        );
+        key1025()
+            .public_key()
+            .verify_pss(DigestAlgorithm::Sha384, MESSAGE, &signature)
+            .unwrap();
+    }
+
+    // ソルトが乱数なので、署名は毎回変わるが検証はできる
+    #[test]
+    fn pss_sign() {
+        for (key, hash) in [
+            (key2048(), DigestAlgorithm::Sha256),
+            (key1025(), DigestAlgorithm::Sha384),
+        ] {
+            let first = key.sign_pss(hash, MESSAGE).unwrap();
+            let second = key.sign_pss(hash, MESSAGE).unwrap();
+            assert_ne!(first, second);
+            for signature in [first, second] {
+                assert_eq!(signature.len(), key.size());
+                key.public_key()
+                    .verify_pss(hash, MESSAGE, &signature)
+                    .unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn invalid_pkcs1v15_signature() {
+        let key = key2048();
+        let public_key = key.public_key();
+        let hash = DigestAlgorithm::Sha256;
+        let signature = key.sign_pkcs1v15(hash, MESSAGE).unwrap();
+
+        assert!(public_key
+            .verify_pkcs1v15(hash, b"other", &signature)
+            .is_err());
+        assert!(public_key
+            .verify_pkcs1v15(DigestAlgorithm::Sha384, MESSAGE, &signature)
+            .is_err());
+        let mut modified = signature.clone();
+        modified[100] ^= 1;
+        assert!(public_key
+            .verify_pkcs1v15(hash, MESSAGE, &modified)
+            .is_err());
+        assert!(public_key
+            .verify_pkcs1v15(hash, MESSAGE, &signature[1..])
+            .is_err());
+        // n 以上の署名
+        let n = public_key.n.to_bytes_be(key.size());
+        assert!(public_key.verify_pkcs1v15(hash, MESSAGE, &n).is_err());
+
+        // パディングを壊した EM に秘密鍵で署名する
+        let em = emsa_pkcs1v15_encode(hash, MESSAGE, key.size()).unwrap();
+        let separator = key.size() - 19 - 32 - 1;
+        let mut block_type_2 = em.clone();
+        block_type_2[1] = 2;
+        let mut no_separator = em.clone();
+        no_separator[separator] = 0xff;
+        let mut short_padding = em.clone();
+        short_padding[2..10].fill(0);
+        for em in [block_type_2, no_separator, short_padding] {
+            let forged = key.private_operation(&em).unwrap().to_bytes_be(key.size());
+            assert!(public_key.verify_pkcs1v15(hash, MESSAGE, &forged).is_err());
+        }
+    }
+
+    #[test]
+    fn invalid_pss_signature() {
+        let key = key2048();
+        let public_key = key.public_key();
+        let hash = DigestAlgorithm::Sha256;
+        let signature = key.sign_pss(hash, MESSAGE).unwrap();
+
+        assert!(public_key.verify_pss(hash, b"other", &signature).is_err());
+        assert!(public_key
+            .verify_pss(DigestAlgorithm::Sha384, MESSAGE, &signature)
+            .is_err());
+        let mut modified = signature.clone();
+        modified[0] ^= 1;
+        assert!(public_key.verify_pss(hash, MESSAGE, &modified).is_err());
+
+        // 末尾の 0xbc を変えた EM
+        let mut em = public_key
+            .public_operation(&signature)
+            .unwrap()
+            .to_bytes_be(key.size());
+        em[key.size() - 1] = 0xbd;
+        let forged = key.private_operation(&em).unwrap().to_bytes_be(key.size());
+        assert!(public_key.verify_pss(hash, MESSAGE, &forged).is_err());
+
+        // 1025 bits の鍵で emBits (1024 bits) を超える値 (n - 1) を署名として渡す
+        let key = key1025();
+        let public_key = key.public_key();
+        let n_minus_1 = public_key.n.sub(&BigUint::from_u64(1));
+        let forged = key
+            .private_operation(&n_minus_1.to_bytes_be(key.size()))
+            .unwrap()
+            .to_bytes_be(key.size());
+        assert!(public_key
+            .verify_pss(DigestAlgorithm::Sha384, MESSAGE, &forged)
+            .is_err());
+    }
+
+    // EMSA-PKCS1-v1_5 の T (DigestInfo) を差し替えた EM に秘密鍵で署名する
+    fn forge_pkcs1v15(key: &RsaPrivateKey, t: &[u8], separator: u8) -> Vec<u8> {
+        let k = key.size();
+        let mut em = vec![0xff; k];
+        em[0] = 0;
+        em[1] = 1;
+        em[k - t.len() - 1] = separator;
+        em[k - t.len()..].copy_from_slice(t);
+        key.private_operation(&em).unwrap().to_bytes_be(k)
+    }
+
+    // DigestInfo は DER の符号化まで一致しなければならない
+    #[test]
+    fn invalid_pkcs1v15_digest_info() {
+        let key = key2048();
+        let public_key = key.public_key();
+        let hash = DigestAlgorithm::Sha256;
+        let h = digest(hash, MESSAGE);
+        let prefix = digest_info_prefix(hash);
+        let t = [prefix, h.as_slice()].concat();
+        public_key
+            .verify_pkcs1v15(hash, MESSAGE, &forge_pkcs1v15(&key, &t, 0))
+            .unwrap();
+
+        let sha256_oid = &prefix[4..15];
+        let sha384_oid = &digest_info_prefix(DigestAlgorithm::Sha384)[4..15];
+        let invalid = [
+            // パラメータの NULL が無い
+            [&[0x30, 0x2f, 0x30, 0x0b], sha256_oid, &[0x04, 0x20], &h].concat(),
+            // 長さを long form で書いた
+            [&[0x30, 0x81, 0x31], &prefix[2..], &h].concat(),
+            [&[0x30, 0x32, 0x30, 0x81, 0x0d], &prefix[4..], &h].concat(),
+            // ハッシュの OID が違う
+            [&prefix[..4], sha384_oid, &prefix[15..], &h].concat(),
+            // DigestInfo の後ろにゴミがある
+            [t.as_slice(), &[0]].concat(),
+            [&[0x30, 0x32], &prefix[2..], &h, &[0]].concat(),
+            // ハッシュが短い
+            [&[0x30, 0x30], &prefix[2..18], &[0x1f], &h[..31]].concat(),
+        ];
+        for t in invalid {
+            let forged = forge_pkcs1v15(&key, &t, 0);
+            assert!(
+                public_key.verify_pkcs1v15(hash, MESSAGE, &forged).is_err(),
+                "{:02x?}",
+                t
+            );
+        }
+        // 区切りの 0x00 が無い
+        let forged = forge_pkcs1v15(&key, &t, 0xff);
+        assert!(public_key.verify_pkcs1v15(hash, MESSAGE, &forged).is_err());
+    }
+
+    // 範囲外や長さの違う署名の値
+    #[test]
+    fn invalid_signature_values() {
+        let key = key2048();
+        let public_key = key.public_key();
+        let k = key.size();
+        let hash = DigestAlgorithm::Sha256;
+        let signature = key.sign_pkcs1v15(hash, MESSAGE).unwrap();
+        let n = &public_key.n;
+
+        let values = [
+            vec![0; k],
+            BigUint::from_u64(1).to_bytes_be(k),
+            n.sub(&BigUint::from_u64(1)).to_bytes_be(k),
+            n.to_bytes_be(k),
+            // s + n は mod n で正しい署名と同じ値になる
+            BigUint::from_bytes_be(&signature).add(n).to_bytes_be(k + 1),
+            [&[0], signature.as_slice()].concat(),
+            [signature.as_slice(), &[0]].concat(),
+            vec![],
+        ];
+        for value in &values {
+            assert!(public_key.verify_pkcs1v15(hash, MESSAGE, value).is_err());
+            assert!(public_key.verify_pss(hash, MESSAGE, value).is_err());
+        }
+    }
+
+    // EMSA-PSS-ENCODE をソルトと DB の先頭のバイトを指定して行い、秘密鍵で署名する
+    fn forge_pss(key: &RsaPrivateKey, hash: DigestAlgorithm, salt: &[u8], pad: u8) -> Vec<u8> {
+        let em_bits = key.public_key().modulus_bits() - 1;
+        let em_length = em_bits.div_ceil(8);
+        let m_hash = digest(hash, MESSAGE);
+        let h = digest(hash, &[&[0u8; 8], m_hash.as_slice(), salt].concat());
+        let mut db = vec![0u8; em_length - h.len() - 1];
+        let ps_length = db.len() - salt.len() - 1;
+        db[0] = pad;
+        db[ps_length] = 1;
+        db[ps_length + 1..].copy_from_slice(salt);
+        let mask = mgf1(hash, &h, db.len());
+        let mut em: Vec<u8> = db.iter().zip(mask).map(|(d, m)| d ^ m).collect();
+        em[0] &= 0xff >> (8 * em_length - em_bits);
+        em.extend(h);
+        em.push(0xbc);
+        key.private_operation(&em).unwrap().to_bytes_be(key.size())
+    }
+
+    #[test]
+    fn invalid_pss_encoding() {
+        let key = key2048();
+        let public_key = key.public_key();
+        let hash = DigestAlgorithm::Sha256;
+        public_key
+            .verify_pss(hash, MESSAGE, &forge_pss(&key, hash, &[7; 32], 0))
+            .unwrap();
+
+        // ソルトの長さはハッシュの長さでなければならない
+        for salt_length in [0, 20, 31, 33, 64] {
+            let forged = forge_pss(&key, hash, &vec![7; salt_length], 0);
+            assert!(
+                public_key.verify_pss(hash, MESSAGE, &forged).is_err(),
+                "{}",
+                salt_length
+            );
+        }
+        // PS が 0 でない
+        let forged = forge_pss(&key, hash, &[7; 32], 0x01);
+        assert!(public_key.verify_pss(hash, MESSAGE, &forged).is_err());
+
+        // maskedDB の emBits を超える最上位ビットが立っている
+        let em_length = key.size();
+        let signature = forge_pss(&key, hash, &[7; 32], 0);
+        let mut em = public_key
+            .public_operation(&signature)
+            .unwrap()
+            .to_bytes_be(em_length);
+        em[0] |= 0x80;
+        let forged = key.private_operation(&em).unwrap().to_bytes_be(key.size());
+        assert!(public_key.verify_pss(hash, MESSAGE, &forged).is_err());
+
+        // H を書き換えた
+        em[0] &= 0x7f;
+        em[em_length - 2] ^= 1;
+        let forged = key.private_operation(&em).unwrap().to_bytes_be(key.size());
+        assert!(public_key.verify_pss(hash, MESSAGE, &forged).is_err());
+    }
+
+    #[test]
+    fn decrypt_pkcs1v15() {
+        let key = key1025();
+        let ciphertext = hex!(
+            "000bc412c3ac509c12d52b31abb4bcb9e8e701b85d2d58a1ebd9852fa3f01da1"
+            "0999dcf65ee17e2c03cef51ce436efa465f1a508aeae68dda8a846e07882f09f"
+            "856b939943fb1e69d739e555c6d6ed7b94d98677db320f569123e906eee9e7d0"
+            "287672268226e602b03cb19bd2b6a83e23e20fa146e3ede55b372dd1c23be4e9"
+            "53"
+        );
+        assert_eq!(key.decrypt_pkcs1v15(&ciphertext).unwrap(), MESSAGE);
+        assert!(key.decrypt_pkcs1v15(&ciphertext[1..]).is_err());
+
+        let ciphertext = key.public_key().encrypt_pkcs1v15(MESSAGE).unwrap();
+        assert_eq!(key.decrypt_pkcs1v15(&ciphertext).unwrap(), MESSAGE);
+        assert!(key
+            .public_key()
+            .encrypt_pkcs1v15(&vec![0; key.size() - 10])
+            .is_err());
+    }
+
+    // 復号すると不正なパディングになる暗号文は、どれも同じエラーになる
+    #[test]
+    fn invalid_pkcs1v15_padding() {
+        let key = key2048();
+        let k = key.size();
+        let valid = |ps_length: usize| {
+            let mut em = vec![0u8; k];
+            em[1] = 2;
+            em[2..2 + ps_length].fill(0xaa);
+            em
+        };
+
+        let mut block_type_1 = valid(k - 3);
+        block_type_1[1] = 1;
+        let mut leading_byte = valid(k - 3);
+        leading_byte[0] = 1;
+        let no_separator = valid(k - 2);
+        for em in [block_type_1, leading_byte, no_separator, valid(7)] {
+            let ciphertext = key.public_key().public_operation(&em).unwrap();
+            let error = key
+                .decrypt_pkcs1v15(&ciphertext.to_bytes_be(k))
+                .unwrap_err();
+            assert_eq!(error.to_string(), "decryption error");
+        }
+
+        // PS が 8 bytes あれば空のメッセージでもよい
+        let ciphertext = key.public_key().public_operation(&valid(k - 3)).unwrap();
+        assert!(key
+            .decrypt_pkcs1v15(&ciphertext.to_bytes_be(k))
+            .unwrap()
+            .is_empty());
+        let ciphertext = key.public_key().public_operation(&valid(8)).unwrap();
+        assert_eq!(
+            key.decrypt_pkcs1v15(&ciphertext.to_bytes_be(k))
+                .unwrap()
+                .len(),
+            k - 11
+        );
+    }
+
+    #[test]
+    fn public_key_bounds() {
+        let n = |bits: usize| {
+            let mut bytes = vec![0u8; bits.div_ceil(8)];
+            bytes[0] = 1 << ((bits - 1) % 8);
+            *bytes.last_mut().unwrap() |= 1;
+            BigUint::from_bytes_be(&bytes)
+        };
+        let e = BigUint::from_u64(65537);
+
+        let key = RsaPublicKey::new(n(512), BigUint::from_u64(3)).unwrap();
+        assert_eq!(key.size(), 64);
+        assert_eq!(key.modulus_bits(), 512);
+        assert_eq!(RsaPublicKey::new(n(513), e.clone()).unwrap().size(), 65);
+
+        assert!(RsaPublicKey::new(n(511), e.clone()).is_err());
+        assert!(RsaPublicKey::new(n(512), BigUint::from_u64(1)).is_err());
+        assert!(RsaPublicKey::new(n(512), BigUint::from_u64(65536)).is_err());
+        assert!(RsaPublicKey::new(n(512).sub(&BigUint::from_u64(1)), e).is_err());
+    }
+
+    // CRT の値が壊れていたら、公開鍵で戻したときに一致しないので結果を返さない
+    #[test]
+    fn private_operation_fault() {
+        let mut key = key2048();
+        key.dp = key.dp.add(&BigUint::from_u64(1));
+        assert!(key.sign_pkcs1v15(DigestAlgorithm::Sha256, MESSAGE).is_err());
+
+        let mut key = key1025();
+        key.q_inv = key.q_inv.add(&BigUint::from_u64(1));
+        assert!(key.decrypt_raw(&[1; 129]).is_err());
+    }
+
+    #[test]
+    fn encoded_message_length() {
+        // DigestInfo (SHA-512) は 83 bytes
+        assert!(emsa_pkcs1v15_encode(DigestAlgorithm::Sha512, MESSAGE, 93).is_err());
+        assert_eq!(
+            emsa_pkcs1v15_encode(DigestAlgorithm::Sha512, MESSAGE, 94)
+                .unwrap()
+                .len(),
+            94
+        );
+        // PSS はハッシュとソルトと 2 bytes
+        assert!(emsa_pss_encode(DigestAlgorithm::Sha512, MESSAGE, 8 * 129).is_err());
+        assert_eq!(
+            emsa_pss_encode(DigestAlgorithm::Sha512, MESSAGE, 8 * 129 + 1)
+                .unwrap()
+                .len(),
+            130
+        );
+    }
+}
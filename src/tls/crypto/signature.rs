@@ -0,0 +1,326 @@
+use super::hash::DigestAlgorithm;
+use super::rsa::{RsaPrivateKey, RsaPublicKey};
+use crate::tls::{is_pkcs1_scheme, AlertDescription, Error, NamedGroup, SignatureScheme};
+
+use anyhow::{bail, Result};
+use p256::ecdsa::signature::Verifier;
+use p256::pkcs8::{DecodePrivateKey, DecodePublicKey};
+use x509_parser::pem::Pem;
+
+// spki は証明書の SubjectPublicKeyInfo (DER)
+pub fn verify_signature(
+    scheme: SignatureScheme,
+    spki: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let result = match scheme {
+        SignatureScheme::rsa_pkcs1_sha256
+        | SignatureScheme::rsa_pkcs1_sha384
+        | SignatureScheme::rsa_pkcs1_sha512
+        | SignatureScheme::rsa_pss_rsae_sha256
+        | SignatureScheme::rsa_pss_rsae_sha384
+        | SignatureScheme::rsa_pss_rsae_sha512 => verify_rsa(scheme, spki, message, signature),
+        SignatureScheme::ecdsa_secp256r1_sha256 => verify_p256(spki, message, signature),
+        SignatureScheme::ecdsa_secp384r1_sha384 => verify_p384(spki, message, signature),
+        _ => bail!("{:?} is not supported", scheme),
+    };
+
+    if result.is_err() {
+        bail!(Error::Alert(AlertDescription::DecryptError))
+    }
+    Ok(())
+}
+
+fn verify_rsa(
+    scheme: SignatureScheme,
+    spki: &[u8],
+    message: &[u8],
+    signature: &[u8],
+) -> Result<()> {
+    let key = RsaPublicKey::from_public_key_der(spki)?;
+    let hash = rsa_hash_algorithm(scheme);
+    if is_pkcs1_scheme(scheme) {
+        key.verify_pkcs1v15(hash, message, signature)
+    } else {
+        key.verify_pss(hash, message, signature)
+    }
+}
+
+// 公開鍵の曲線が違う場合や署名の DER が不正な場合も、検証の失敗として扱う
+fn verify_p256(spki: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    let key = p256::ecdsa::VerifyingKey::from_public_key_der(spki)?;
+    let signature = p256::ecdsa::Signature::from_der(signature)?;
+    Ok(key.verify(message, &signature)?)
+}
+
+fn verify_p384(spki: &[u8], message: &[u8], signature: &[u8]) -> Result<()> {
+    let key = p384::ecdsa::VerifyingKey::from_public_key_der(spki)?;
+    let signature = p384::ecdsa::Signature::from_der(signature)?;
+    Ok(key.verify(message, &signature)?)
+}
+
+fn rsa_hash_algorithm(scheme: SignatureScheme) -> DigestAlgorithm {
+    match scheme {
+        SignatureScheme::rsa_pkcs1_sha256 | SignatureScheme::rsa_pss_rsae_sha256 => {
+            DigestAlgorithm::Sha256
+        }
+        SignatureScheme::rsa_pkcs1_sha384 | SignatureScheme::rsa_pss_rsae_sha384 => {
+            DigestAlgorithm::Sha384
+        }
+        _ => DigestAlgorithm::Sha512,
+    }
+}
+
+// 自分の証明書に対応する秘密鍵
+pub enum SigningKey {
+    Rsa(Box<RsaPrivateKey>),
+    Secp256r1(p256::ecdsa::SigningKey),
+    Secp384r1(p384::ecdsa::SigningKey),
+}
+
+impl std::fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // 秘密鍵の中身は出さない
+        match self {
+            SigningKey::Rsa(_) => write!(f, "SigningKey::Rsa"),
+            SigningKey::Secp256r1(_) => write!(f, "SigningKey::Secp256r1"),
+            SigningKey::Secp384r1(_) => write!(f, "SigningKey::Secp384r1"),
+        }
+    }
+}
+
+impl SigningKey {
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self> {
+        if let Ok(key) = RsaPrivateKey::from_pkcs8_der(der) {
+            return Ok(SigningKey::Rsa(Box::new(key)));
+        }
+        if let Ok(key) = p256::ecdsa::SigningKey::from_pkcs8_der(der) {
+            return Ok(SigningKey::Secp256r1(key));
+        }
+        if let Ok(key) = p384::ecdsa::SigningKey::from_pkcs8_der(der) {
+            return Ok(SigningKey::Secp384r1(key));
+        }
+        bail!("unsupported private key")
+    }
+
+    pub fn from_pkcs1_der(der: &[u8]) -> Result<Self> {
+        let key = RsaPrivateKey::from_pkcs1_der(der)?;
+        Ok(SigningKey::Rsa(Box::new(key)))
+    }
+
+    pub fn from_sec1_der(der: &[u8]) -> Result<Self> {
+        if let Ok(key) = p256::SecretKey::from_sec1_der(der) {
+            return Ok(SigningKey::Secp256r1(key.into()));
+        }
+        if let Ok(key) = p384::SecretKey::from_sec1_der(der) {
+            return Ok(SigningKey::Secp384r1(key.into()));
+        }
+        bail!("unsupported EC private key")
+    }
+
+    // DER は形式が書かれていないので PKCS#8, PKCS#1, SEC1 の順に試す
+    pub fn from_der(der: &[u8]) -> Result<Self> {
+        Self::from_pkcs8_der(der)
+            .or_else(|_| Self::from_pkcs1_der(der))
+            .or_else(|_| Self::from_sec1_der(der))
+            .map_err(|_| anyhow::anyhow!("unsupported private key"))
+    }
+
+    // PKCS#8 (PRIVATE KEY), PKCS#1 (RSA PRIVATE KEY), SEC1 (EC PRIVATE KEY) の最初の鍵を読む
+    pub fn from_pem(pem: &[u8]) -> Result<Self> {
+        for pem in Pem::iter_from_buffer(pem) {
+            let pem = pem?;
+            match pem.label.as_str() {
+                "PRIVATE KEY" => return Self::from_pkcs8_der(&pem.contents),
+                "RSA PRIVATE KEY" => return Self::from_pkcs1_der(&pem.contents),
+                "EC PRIVATE KEY" => return Self::from_sec1_der(&pem.contents),
+                _ => continue,
+            }
+        }
+        bail!("no private key found")
+    }
+
+    // 証明書の SubjectPublicKeyInfo (DER) がこの鍵の公開鍵か
+    pub fn matches_public_key(&self, spki: &[u8]) -> bool {
+        match self {
+            SigningKey::Rsa(key) => RsaPublicKey::from_public_key_der(spki)
+                .is_ok_and(|public_key| &public_key == key.public_key()),
+            SigningKey::Secp256r1(key) => p256::ecdsa::VerifyingKey::from_public_key_der(spki)
+                .is_ok_and(|public_key| &public_key == key.verifying_key()),
+            SigningKey::Secp384r1(key) => p384::ecdsa::VerifyingKey::from_public_key_der(spki)
+                .is_ok_and(|public_key| &public_key == key.verifying_key()),
+        }
+    }
+
+    // この鍵で使える署名方式 (優先度の高い順)
+    pub fn schemes(&self) -> Vec<SignatureScheme> {
+        match self {
+            SigningKey::Rsa(_) => vec![
+                SignatureScheme::rsa_pss_rsae_sha256,
+                SignatureScheme::rsa_pss_rsae_sha384,
+                SignatureScheme::rsa_pss_rsae_sha512,
+                SignatureScheme::rsa_pkcs1_sha256,
+                SignatureScheme::rsa_pkcs1_sha384,
+                SignatureScheme::rsa_pkcs1_sha512,
+            ],
+            SigningKey::Secp256r1(_) => vec![SignatureScheme::ecdsa_secp256r1_sha256],
+            SigningKey::Secp384r1(_) => vec![SignatureScheme::ecdsa_secp384r1_sha384],
+        }
+    }
+
+    // ECDSA の鍵の曲線
+    pub fn named_group(&self) -> Option<NamedGroup> {
+        match self {
+            SigningKey::Rsa(_) => None,
+            SigningKey::Secp256r1(_) => Some(NamedGroup::secp256r1),
+            SigningKey::Secp384r1(_) => Some(NamedGroup::secp384r1),
+        }
+    }
+
+    pub fn sign(&self, scheme: SignatureScheme, message: &[u8]) -> Result<Vec<u8>> {
+        use p256::ecdsa::signature::Signer;
+
+        if !self.schemes().contains(&scheme) {
+            bail!("{:?} is not supported by the key", scheme);
+        }
+
+        let signature = match self {
+            SigningKey::Rsa(key) if is_pkcs1_scheme(scheme) => {
+                key.sign_pkcs1v15(rsa_hash_algorithm(scheme), message)?
+            }
+            SigningKey::Rsa(key) => key.sign_pss(rsa_hash_algorithm(scheme), message)?,
+            // TLS の ECDSA 署名は DER
+            SigningKey::Secp256r1(key) => {
+                let signature: p256::ecdsa::Signature = key.sign(message);
+                signature.to_der().as_bytes().to_vec()
+            }
+            SigningKey::Secp384r1(key) => {
+                let signature: p384::ecdsa::Signature = key.sign(message);
+                signature.to_der().as_bytes().to_vec()
+            }
+        };
+        Ok(signature)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use x509_parser::pem::parse_x509_pem;
+    use x509_parser::prelude::{FromDer, X509Certificate};
+
+    const MESSAGE: &[u8] = b"tls-from-scratch";
+
+    fn read(path: &str) -> Vec<u8> {
+        std::fs::read(format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), path)).unwrap()
+    }
+
+    fn der(path: &str) -> Vec<u8> {
+        parse_x509_pem(&read(path)).unwrap().1.contents
+    }
+
+    fn assert_decrypt_error(result: Result<()>) {
+        assert!(matches!(
+            result.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::Alert(AlertDescription::DecryptError))
+        ));
+    }
+
+    #[test]
+    fn rsa() {
+        let key = SigningKey::from_pem(&read("rsa/key2048.pem")).unwrap();
+        let spki = der("rsa/pub2048.pem");
+        assert!(key.matches_public_key(&spki));
+        assert_eq!(key.named_group(), None);
+
+        for scheme in key.schemes() {
+            let signature = key.sign(scheme, MESSAGE).unwrap();
+            verify_signature(scheme, &spki, MESSAGE, &signature).unwrap();
+            assert_decrypt_error(verify_signature(scheme, &spki, b"other", &signature));
+        }
+
+        // PKCS#1 v1.5 の署名を PSS として検証しない
+        let signature = key
+            .sign(SignatureScheme::rsa_pkcs1_sha256, MESSAGE)
+            .unwrap();
+        assert_decrypt_error(verify_signature(
+            SignatureScheme::rsa_pss_rsae_sha256,
+            &spki,
+            MESSAGE,
+            &signature,
+        ));
+        assert!(key
+            .sign(SignatureScheme::ecdsa_secp256r1_sha256, MESSAGE)
+            .is_err());
+    }
+
+    #[test]
+    fn ecdsa() {
+        let key = SigningKey::from_pem(&read("verifier/leaf.key")).unwrap();
+        let certificate = der("verifier/server.pem");
+        let (_, certificate) = X509Certificate::from_der(&certificate).unwrap();
+        let spki = certificate.public_key().raw;
+        assert!(key.matches_public_key(spki));
+        assert!(!key.matches_public_key(&der("rsa/pub2048.pem")));
+        assert_eq!(key.named_group(), Some(NamedGroup::secp256r1));
+
+        let scheme = SignatureScheme::ecdsa_secp256r1_sha256;
+        let signature = key.sign(scheme, MESSAGE).unwrap();
+        verify_signature(scheme, spki, MESSAGE, &signature).unwrap();
+        assert_decrypt_error(verify_signature(scheme, spki, b"other", &signature));
+        assert_decrypt_error(verify_signature(
+            SignatureScheme::ecdsa_secp384r1_sha384,
+            spki,
+            MESSAGE,
+            &signature,
+        ));
+        assert!(key
+            .sign(SignatureScheme::rsa_pss_rsae_sha256, MESSAGE)
+            .is_err());
+    }
+
+    // leaf.key の公開鍵
+    fn ecdsa_spki() -> Vec<u8> {
+        let certificate = der("verifier/server.pem");
+        let (_, certificate) = X509Certificate::from_der(&certificate).unwrap();
+        certificate.public_key().raw.to_vec()
+    }
+
+    #[test]
+    fn from_pem() {
+        let rsa_spki = der("rsa/pub2048.pem");
+        for path in ["rsa/key2048.pem", "rsa/key2048_pkcs1.pem"] {
+            let key = SigningKey::from_pem(&read(path)).unwrap();
+            assert!(key.matches_public_key(&rsa_spki), "{}", path);
+        }
+        for path in ["verifier/leaf.key", "verifier/leaf_pkcs8.key"] {
+            let key = SigningKey::from_pem(&read(path)).unwrap();
+            assert!(key.matches_public_key(&ecdsa_spki()), "{}", path);
+        }
+        assert!(SigningKey::from_pem(&read("rsa/key1025.pem")).is_ok());
+
+        // 鍵より前にある証明書は読み飛ばす
+        let pem = [read("verifier/server.pem"), read("verifier/leaf.key")].concat();
+        assert!(SigningKey::from_pem(&pem)
+            .unwrap()
+            .matches_public_key(&ecdsa_spki()));
+        assert!(SigningKey::from_pem(&read("verifier/root.pem")).is_err());
+        assert!(SigningKey::from_pem(&read("rsa/pub2048.pem")).is_err());
+    }
+
+    #[test]
+    fn from_der() {
+        let rsa_spki = der("rsa/pub2048.pem");
+        for path in ["rsa/key2048.der", "rsa/key2048_pkcs1.der"] {
+            let key = SigningKey::from_der(&read(path)).unwrap();
+            assert!(key.matches_public_key(&rsa_spki), "{}", path);
+        }
+        for path in ["verifier/leaf.der", "verifier/leaf_pkcs8.der"] {
+            let key = SigningKey::from_der(&read(path)).unwrap();
+            assert!(key.matches_public_key(&ecdsa_spki()), "{}", path);
+        }
+        assert!(SigningKey::from_der(&der("verifier/root.pem")).is_err());
+        assert!(SigningKey::from_der(&read("verifier/leaf.key")).is_err());
+    }
+}
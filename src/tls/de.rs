@@ -48,7 +48,7 @@ pub fn be_u16(input: Buffer) -> IResult<u16> {
 }
 
 pub fn be_u32(input: Buffer) -> IResult<u32> {
-    if input.length < 2 {
+    if input.length < 4 {
         return Err(nom::Err::Error(nomError::new(
             input,
             nom::error::ErrorKind::Eof,
@@ -59,8 +59,41 @@ pub fn be_u32(input: Buffer) -> IResult<u32> {
     Ok((Buffer::new(i, input.length - 4), d))
 }
 
-pub fn take<C: nom::ToUsize>(input: Buffer, n: C) -> IResult<&[u8]> {
+pub fn take<'a, C: nom::ToUsize>(input: Buffer<'a>, n: C) -> IResult<'a, &'a [u8]> {
     let n = n.to_usize();
+    if input.length < n {
+        return Err(nom::Err::Error(nomError::new(
+            input,
+            nom::error::ErrorKind::Eof,
+        )));
+    }
+
     let (i, d) = nom_take::<usize, &[u8], nomError<&[u8]>>(n)(input.data).unwrap();
     Ok((Buffer::new(i, input.length - n), d))
 }
+
+// 未知の値など、構文としては読めるが解釈できない入力
+pub fn invalid_value<O>(input: Buffer) -> IResult<O> {
+    Err(nom::Err::Error(nomError::new(
+        input,
+        nom::error::ErrorKind::Verify,
+    )))
+}
+
+// 読めるが値として許されない入力。decode_error ではなく illegal_parameter で中止する
+pub fn illegal_parameter<O>(input: Buffer) -> IResult<O> {
+    Err(nom::Err::Error(nomError::new(
+        input,
+        nom::error::ErrorKind::Not,
+    )))
+}
+
+// パースのエラーを送るアラートにする
+pub fn decode_alert(error: &nom::Err<nomError<Buffer>>) -> super::AlertDescription {
+    match error {
+        nom::Err::Error(e) | nom::Err::Failure(e) if e.code == nom::error::ErrorKind::Not => {
+            super::AlertDescription::IllegalParameter
+        }
+        _ => super::AlertDescription::DecodeError,
+    }
+}
@@ -1,8 +1,15 @@
-use super::hello_messaage::{ClientHello, ServerHello};
-use super::{be_u8, take, u24, Buffer, Error, IResult, Opaque, ProtocolVersion};
+use super::hello_messaage::{
+    ClientHello, DistinguishedName, DistinguishedNames, Extensions, NamedGroup, ServerHello,
+    SignatureScheme, SignatureSchemeList,
+};
+use super::{
+    be_u32, be_u8, decode_alert, illegal_parameter, invalid_value, take, u24, Buffer, Error,
+    IResult, Opaque, ProtocolVersion, Vector,
+};
 
+use anyhow::{bail, Result};
 use enum_try_from::impl_enum_try_from;
-use ser::ByteOrder;
+use ser::{ByteOrder, NetworkEndian};
 use serde::Serialize;
 use serde_repr::Serialize_repr;
 
@@ -14,7 +21,17 @@ pub struct Handshake {
 }
 
 impl Handshake {
-    pub fn deserialize(input: Buffer) -> IResult<Self> {
+    pub fn new(body: HandshakeBody) -> Self {
+        let length = ser::bytes_size(&body).unwrap();
+        Handshake {
+            msg_type: body.msg_type(),
+            length: u24::from(length),
+            body,
+        }
+    }
+
+    // Certificate など TLS 1.2 と TLS 1.3 で形式が異なるメッセージがあるので、バージョンを受け取る
+    pub fn deserialize(input: Buffer, version: ProtocolVersion) -> IResult<Self> {
         let (input, msg_type) = HandshakeType::deserialize(input)?;
         let (input, length) = take(input, 3u8)?;
         let length = u24::from(length);
@@ -22,18 +39,59 @@ impl Handshake {
         let (input, fragment) = take(input, length)?;
         let fragment = Buffer::new(fragment, length);
 
-        let body = match msg_type {
+        // どのメッセージも余りなく読み切れなければならない
+        let (rest, body) = match msg_type {
+            HandshakeType::ClientHello => {
+                let (rest, body) = ClientHello::deserialize(fragment)?;
+                (rest, HandshakeBody::ClientHello(body))
+            }
             HandshakeType::ServerHello => {
-                let (_, body) = ServerHello::deserialize(fragment)?;
-                HandshakeBody::ServerHello(body)
+                let (rest, body) = ServerHello::deserialize(fragment)?;
+                (rest, HandshakeBody::ServerHello(body))
+            }
+            HandshakeType::NewSessionTicket => {
+                let (rest, body) = NewSessionTicket::deserialize(fragment, version)?;
+                (rest, HandshakeBody::NewSessionTicket(body))
+            }
+            HandshakeType::EncryptedExtensions => {
+                let (rest, body) = Extensions::deserialize(fragment, msg_type)?;
+                (rest, HandshakeBody::EncryptedExtensions(body))
             }
             HandshakeType::Certificate => {
-                let (_, body) = Certificate::deserialize(fragment)?;
-                HandshakeBody::Certificate(body)
+                let (rest, body) = Certificate::deserialize(fragment, version)?;
+                (rest, HandshakeBody::Certificate(body))
+            }
+            HandshakeType::ServerKeyExchange => {
+                let (rest, body) = ServerKeyExchange::deserialize(fragment)?;
+                (rest, HandshakeBody::ServerKeyExchange(body))
             }
-            HandshakeType::ServerHelloDone => HandshakeBody::ServerHelloDone(()),
-            _ => unimplemented!(),
+            HandshakeType::CertificateRequest => {
+                let (rest, body) = CertificateRequest::deserialize(fragment, version)?;
+                (rest, HandshakeBody::CertificateRequest(body))
+            }
+            HandshakeType::ServerHelloDone => (fragment, HandshakeBody::ServerHelloDone(())),
+            HandshakeType::EndOfEarlyData => (fragment, HandshakeBody::EndOfEarlyData(())),
+            HandshakeType::CertificateVerify => {
+                let (rest, body) = CertificateVerify::deserialize(fragment)?;
+                (rest, HandshakeBody::CertificateVerify(body))
+            }
+            HandshakeType::ClientKeyExchange => {
+                let (rest, body) = ClientKeyExchange::deserialize(fragment)?;
+                (rest, HandshakeBody::ClientKeyExchange(body))
+            }
+            HandshakeType::Finished => {
+                let (rest, body) = Finished::deserialize(fragment)?;
+                (rest, HandshakeBody::Finished(body))
+            }
+            HandshakeType::KeyUpdate => {
+                let (rest, body) = KeyUpdate::deserialize(fragment)?;
+                (rest, HandshakeBody::KeyUpdate(body))
+            }
+            _ => return invalid_value(input),
         };
+        if rest.length() > 0 {
+            return invalid_value(rest);
+        }
 
         Ok((
             input,
@@ -44,6 +102,52 @@ impl Handshake {
             },
         ))
     }
+
+    pub fn from_bytes(data: &[u8], version: ProtocolVersion) -> Result<Self> {
+        match Handshake::deserialize(Buffer::new(data, data.len()), version) {
+            Ok((_, handshake)) => Ok(handshake),
+            Err(e) => bail!(Error::Alert(decode_alert(&e))),
+        }
+    }
+
+    pub fn to_bytes<O: ByteOrder>(&self) -> Vec<u8> {
+        ser::to_bytes::<_, O>(self).unwrap()
+    }
+}
+
+// 1レコードに複数のメッセージが入っていたり、1メッセージが複数レコードに分割されていることがあるので
+// ハンドシェイクメッセージ単位に組み立て直す
+#[derive(Debug, Default)]
+pub struct HandshakeJoiner {
+    buffer: Vec<u8>,
+}
+
+impl HandshakeJoiner {
+    pub fn new() -> Self {
+        HandshakeJoiner::default()
+    }
+
+    pub fn push(&mut self, data: &[u8]) {
+        self.buffer.extend_from_slice(data);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    // msg_type + length + body の生バイト列を返す (Transcript-Hash用)
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        if self.buffer.len() < 4 {
+            return None;
+        }
+
+        let length = u24::from(&self.buffer[1..4]).to_usize();
+        if self.buffer.len() < 4 + length {
+            return None;
+        }
+
+        Some(self.buffer.drain(..4 + length).collect())
+    }
 }
 
 #[repr(C)]
@@ -51,19 +155,50 @@ impl Handshake {
 pub enum HandshakeBody {
     ClientHello(ClientHello),
     ServerHello(ServerHello),
+    NewSessionTicket(NewSessionTicket),
+    EndOfEarlyData(()),
+    EncryptedExtensions(Extensions),
     Certificate(Certificate),
+    ServerKeyExchange(ServerKeyExchange),
+    CertificateRequest(CertificateRequest),
     ServerHelloDone(()),
+    CertificateVerify(CertificateVerify),
     ClientKeyExchange(ClientKeyExchange),
+    Finished(Finished),
+    KeyUpdate(KeyUpdate),
+}
+
+impl HandshakeBody {
+    pub fn msg_type(&self) -> HandshakeType {
+        match self {
+            HandshakeBody::ClientHello(_) => HandshakeType::ClientHello,
+            HandshakeBody::ServerHello(_) => HandshakeType::ServerHello,
+            HandshakeBody::NewSessionTicket(_) => HandshakeType::NewSessionTicket,
+            HandshakeBody::EndOfEarlyData(_) => HandshakeType::EndOfEarlyData,
+            HandshakeBody::EncryptedExtensions(_) => HandshakeType::EncryptedExtensions,
+            HandshakeBody::Certificate(_) => HandshakeType::Certificate,
+            HandshakeBody::ServerKeyExchange(_) => HandshakeType::ServerKeyExchange,
+            HandshakeBody::CertificateRequest(_) => HandshakeType::CertificateRequest,
+            HandshakeBody::ServerHelloDone(_) => HandshakeType::ServerHelloDone,
+            HandshakeBody::CertificateVerify(_) => HandshakeType::CertificateVerify,
+            HandshakeBody::ClientKeyExchange(_) => HandshakeType::ClientKeyExchange,
+            HandshakeBody::Finished(_) => HandshakeType::Finished,
+            HandshakeBody::KeyUpdate(_) => HandshakeType::KeyUpdate,
+        }
+    }
 }
 
 impl_enum_try_from! {
     #[allow(dead_code)]
     #[repr(u8)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum HandshakeType {
         HelloRequest = 0,
         ClientHello = 1,
         ServerHello = 2,
+        NewSessionTicket = 4,
+        EndOfEarlyData = 5,
+        EncryptedExtensions = 8,
         Certificate = 11,
         ServerKeyExchange = 12,
         CertificateRequest = 13,
@@ -71,6 +206,8 @@ impl_enum_try_from! {
         CertificateVerify = 15,
         ClientKeyExchange = 16,
         Finished = 20,
+        KeyUpdate = 24,
+        MessageHash = 254,
     },
     u8,
     Error,
@@ -80,35 +217,254 @@ impl_enum_try_from! {
 impl HandshakeType {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, msg_type) = be_u8(input)?;
-        let msg_type = HandshakeType::try_from(msg_type).unwrap();
-        Ok((input, msg_type))
+        match HandshakeType::try_from(msg_type) {
+            Ok(msg_type) => Ok((input, msg_type)),
+            Err(_) => invalid_value(input),
+        }
+    }
+}
+
+// TLS 1.2 (RFC 5077 3.3) では ticket_lifetime_hint と ticket のみ
+// TLS 1.3 (RFC 8446 4.6.1) では ticket_age_add, ticket_nonce, extensions が付く
+#[derive(Serialize, Debug)]
+pub struct NewSessionTicket {
+    pub ticket_lifetime: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ticket_age_add: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ticket_nonce: Option<Opaque<u8>>,
+    pub ticket: Opaque<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Extensions>,
+}
+
+impl NewSessionTicket {
+    pub fn deserialize(input: Buffer, version: ProtocolVersion) -> IResult<Self> {
+        let (input, ticket_lifetime) = be_u32(input)?;
+        if version != ProtocolVersion::TLSv1_3 {
+            let (input, ticket) = Opaque::<u16>::deserialize(input)?;
+            return Ok((
+                input,
+                NewSessionTicket {
+                    ticket_lifetime,
+                    ticket_age_add: None,
+                    ticket_nonce: None,
+                    ticket,
+                    extensions: None,
+                },
+            ));
+        }
+
+        let (input, ticket_age_add) = be_u32(input)?;
+        let (input, ticket_nonce) = Opaque::<u8>::deserialize(input)?;
+        let (input, ticket) = Opaque::<u16>::deserialize(input)?;
+        let (input, extensions) = Extensions::deserialize(input, HandshakeType::NewSessionTicket)?;
+        Ok((
+            input,
+            NewSessionTicket {
+                ticket_lifetime,
+                ticket_age_add: Some(ticket_age_add),
+                ticket_nonce: Some(ticket_nonce),
+                ticket,
+                extensions: Some(extensions),
+            },
+        ))
     }
 }
 
+// TLS 1.3 では certificate_request_context と証明書ごとの extensions が付く
 #[derive(Serialize, Debug)]
 pub struct Certificate {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_request_context: Option<Opaque<u8>>,
     pub length: u24,
-    pub certificate_list: Opaque<u24>,
+    pub certificate_list: Vec<CertificateEntry>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct CertificateEntry {
+    pub cert_data: Opaque<u24>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Extensions>,
 }
 
 impl Certificate {
-    pub fn deserialize(input: Buffer) -> IResult<Self> {
+    // 送信用。TLS 1.3 では certificate_request_context と空の extensions を付ける
+    pub fn new(version: ProtocolVersion, context: &[u8], certificates: &[Vec<u8>]) -> Self {
+        let tls13 = version == ProtocolVersion::TLSv1_3;
+        let certificate_list: Vec<CertificateEntry> = certificates
+            .iter()
+            .map(|certificate| CertificateEntry {
+                cert_data: Opaque::<u24>::new(certificate.clone()),
+                extensions: tls13.then(|| Extensions::new(vec![])),
+            })
+            .collect();
+        let length = u24::from(ser::bytes_size(&certificate_list).unwrap());
+        Certificate {
+            certificate_request_context: tls13.then(|| Opaque::<u8>::new(context.to_vec())),
+            length,
+            certificate_list,
+        }
+    }
+
+    pub fn deserialize(input: Buffer, version: ProtocolVersion) -> IResult<Self> {
+        let (input, certificate_request_context) = if version == ProtocolVersion::TLSv1_3 {
+            let (input, context) = Opaque::<u8>::deserialize(input)?;
+            (input, Some(context))
+        } else {
+            (input, None)
+        };
+
         let (input, length) = u24::deserialize(input)?;
-        let (input, certificate_list) = Opaque::<u24>::deserialize(input)?;
+        let (input, list) = take(input, length)?;
+        let mut list = Buffer::new(list, length);
+
+        let mut certificate_list = vec![];
+        while list.length() > 0 {
+            let (rest, cert_data) = Opaque::<u24>::deserialize(list)?;
+            let (rest, extensions) = if version == ProtocolVersion::TLSv1_3 {
+                let (rest, extensions) = Extensions::deserialize(rest, HandshakeType::Certificate)?;
+                (rest, Some(extensions))
+            } else {
+                (rest, None)
+            };
+            list = rest;
+
+            certificate_list.push(CertificateEntry {
+                cert_data,
+                extensions,
+            });
+        }
+
         Ok((
             input,
             Certificate {
+                certificate_request_context,
                 length,
                 certificate_list,
             },
         ))
     }
+
+    pub fn certificates(&self) -> Vec<&[u8]> {
+        self.certificate_list
+            .iter()
+            .map(|entry| entry.cert_data.data.as_slice())
+            .collect()
+    }
+}
+
+// TLS 1.2 では証明書の種類、署名アルゴリズム、CA の DN
+// TLS 1.3 では certificate_request_context と extensions (signature_algorithms など)
+#[derive(Serialize, Debug)]
+pub struct CertificateRequest {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_request_context: Option<Opaque<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_types: Option<ClientCertificateTypes>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub supported_signature_algorithms: Option<SignatureSchemeList>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub certificate_authorities: Option<DistinguishedNames>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub extensions: Option<Extensions>,
+}
+
+impl CertificateRequest {
+    pub fn deserialize(input: Buffer, version: ProtocolVersion) -> IResult<Self> {
+        if version == ProtocolVersion::TLSv1_3 {
+            let (input, context) = Opaque::<u8>::deserialize(input)?;
+            let (input, extensions) =
+                Extensions::deserialize(input, HandshakeType::CertificateRequest)?;
+            return Ok((
+                input,
+                CertificateRequest {
+                    certificate_request_context: Some(context),
+                    certificate_types: None,
+                    supported_signature_algorithms: None,
+                    certificate_authorities: None,
+                    extensions: Some(extensions),
+                },
+            ));
+        }
+
+        let (input, length) = be_u8(input)?;
+        let (input, data) = take(input, length)?;
+        let certificate_types = data
+            .iter()
+            .filter_map(|certificate_type| ClientCertificateType::try_from(*certificate_type).ok())
+            .collect();
+        let (input, signature_algorithms) = SignatureSchemeList::deserialize(input)?;
+        let (input, authorities) = DistinguishedNames::deserialize_with(input, |input| {
+            let (input, name) = DistinguishedName::deserialize(input)?;
+            Ok((input, Some(name)))
+        })?;
+        Ok((
+            input,
+            CertificateRequest {
+                certificate_request_context: None,
+                certificate_types: Some(ClientCertificateTypes::new(certificate_types)),
+                supported_signature_algorithms: Some(signature_algorithms),
+                certificate_authorities: Some(authorities),
+                extensions: None,
+            },
+        ))
+    }
+}
+
+pub type ClientCertificateTypes = Vector<u8, ClientCertificateType>;
+
+impl_enum_try_from! {
+    #[allow(non_camel_case_types)]
+    #[repr(u8)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum ClientCertificateType {
+        rsa_sign = 1,
+        dss_sign = 2,
+        rsa_fixed_dh = 3,
+        dss_fixed_dh = 4,
+        ecdsa_sign = 64,
+        rsa_fixed_ecdh = 65,
+        ecdsa_fixed_ecdh = 66,
+    },
+    u8,
+    Error,
+    Error::InvalidValue
+}
+
+#[derive(Serialize, Debug)]
+pub struct CertificateVerify {
+    pub algorithm: SignatureScheme,
+    pub signature: Opaque<u16>,
+}
+
+impl CertificateVerify {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, algorithm) = SignatureScheme::deserialize(input)?;
+        let (input, signature) = Opaque::<u16>::deserialize(input)?;
+        Ok((
+            input,
+            CertificateVerify {
+                algorithm,
+                signature,
+            },
+        ))
+    }
+}
+
+// RFC 8446 4.4.3. 64 個のスペース + コンテキスト文字列 + 0x00 + Transcript-Hash
+pub fn certificate_verify_message(context: &[u8], transcript_hash: &[u8]) -> Vec<u8> {
+    let mut message = vec![0x20; 64];
+    message.extend_from_slice(context);
+    message.push(0);
+    message.extend_from_slice(transcript_hash);
+    message
 }
 
 #[derive(Serialize, Debug)]
 pub struct PreMasterSecret {
     pub protocol_version: ProtocolVersion,
-    pub random: Opaque<u16>,
+    pub random: Vec<u8>, // 46 bytes
 }
 
 impl PreMasterSecret {
@@ -117,4 +473,347 @@ impl PreMasterSecret {
     }
 }
 
-pub type ClientKeyExchange = Opaque<u16>;
+// RFC 5246 7.4.7. 中身の形式は鍵交換の方式で決まるので、受信時はそのまま持っておく
+#[derive(Serialize, Debug)]
+pub struct ClientKeyExchange {
+    pub exchange_keys: Vec<u8>,
+}
+
+impl ClientKeyExchange {
+    // 静的 RSA: 暗号化した PreMasterSecret
+    pub fn new_rsa(encrypted_pre_master_secret: Vec<u8>) -> Self {
+        let exchange_keys = Opaque::<u16>::new(encrypted_pre_master_secret);
+        ClientKeyExchange {
+            exchange_keys: ser::to_bytes::<_, NetworkEndian>(&exchange_keys).unwrap(),
+        }
+    }
+
+    // ECDHE: クライアントの一時公開鍵 (RFC 8422 5.7)
+    pub fn new_ecdh(public_key: Vec<u8>) -> Self {
+        let exchange_keys = Opaque::<u8>::new(public_key);
+        ClientKeyExchange {
+            exchange_keys: ser::to_bytes::<_, NetworkEndian>(&exchange_keys).unwrap(),
+        }
+    }
+
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let length = input.length();
+        let (input, exchange_keys) = take(input, length)?;
+        Ok((
+            input,
+            ClientKeyExchange {
+                exchange_keys: exchange_keys.to_vec(),
+            },
+        ))
+    }
+
+    // 余分なデータがあれば None
+    pub fn encrypted_pre_master_secret(&self) -> Option<&[u8]> {
+        let (length, encrypted) = self.exchange_keys.split_at_checked(2)?;
+        (u16::from_be_bytes([length[0], length[1]]) as usize == encrypted.len())
+            .then_some(encrypted)
+    }
+
+    // 余分なデータがあれば None
+    pub fn ecdh_public_key(&self) -> Option<&[u8]> {
+        let (length, public_key) = self.exchange_keys.split_first()?;
+        (*length as usize == public_key.len()).then_some(public_key)
+    }
+}
+
+// RFC 8422 5.4. ECDHE のパラメーターとサーバー証明書の鍵による署名
+#[derive(Serialize, Debug)]
+pub struct ServerKeyExchange {
+    pub params: ServerEcdhParams,
+    pub algorithm: SignatureScheme,
+    pub signature: Opaque<u16>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ServerEcdhParams {
+    pub curve_type: EcCurveType,
+    pub named_curve: NamedGroup,
+    pub public: Opaque<u8>,
+}
+
+impl ServerKeyExchange {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, params) = ServerEcdhParams::deserialize(input)?;
+        let (input, algorithm) = SignatureScheme::deserialize(input)?;
+        let (input, signature) = Opaque::<u16>::deserialize(input)?;
+        Ok((
+            input,
+            ServerKeyExchange {
+                params,
+                algorithm,
+                signature,
+            },
+        ))
+    }
+}
+
+impl ServerEcdhParams {
+    pub fn new(named_curve: NamedGroup, public: Vec<u8>) -> Self {
+        ServerEcdhParams {
+            curve_type: EcCurveType::named_curve,
+            named_curve,
+            public: Opaque::<u8>::new(public),
+        }
+    }
+
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, curve_type) = be_u8(input)?;
+        let Ok(curve_type) = EcCurveType::try_from(curve_type) else {
+            return invalid_value(input);
+        };
+        let (input, named_curve) = NamedGroup::deserialize(input)?;
+        let (input, public) = Opaque::<u8>::deserialize(input)?;
+        Ok((
+            input,
+            ServerEcdhParams {
+                curve_type,
+                named_curve,
+                public,
+            },
+        ))
+    }
+
+    // 署名対象は client_random + server_random + params
+    pub fn to_bytes(&self) -> Vec<u8> {
+        ser::to_bytes::<_, NetworkEndian>(self).unwrap()
+    }
+}
+
+impl_enum_try_from! {
+    #[allow(non_camel_case_types)]
+    #[repr(u8)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum EcCurveType {
+        named_curve = 3,
+    },
+    u8,
+    Error,
+    Error::InvalidValue
+}
+
+#[derive(Serialize, Debug)]
+pub struct Finished {
+    pub verify_data: Vec<u8>,
+}
+
+impl Finished {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let length = input.length();
+        let (input, verify_data) = take(input, length)?;
+        Ok((
+            input,
+            Finished {
+                verify_data: verify_data.to_vec(),
+            },
+        ))
+    }
+}
+
+// RFC 8446 4.6.3.
+#[derive(Serialize, Debug)]
+pub struct KeyUpdate {
+    pub request_update: KeyUpdateRequest,
+}
+
+impl KeyUpdate {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, request_update) = be_u8(input)?;
+        let Ok(request_update) = KeyUpdateRequest::try_from(request_update) else {
+            return illegal_parameter(input);
+        };
+        Ok((input, KeyUpdate { request_update }))
+    }
+}
+
+impl_enum_try_from! {
+    #[allow(non_camel_case_types)]
+    #[repr(u8)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum KeyUpdateRequest {
+        update_not_requested = 0,
+        update_requested = 1,
+    },
+    u8,
+    Error,
+    Error::InvalidValue
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::AlertDescription;
+
+    // TLS_AES_128_GCM_SHA256, supported_versions (TLS 1.3) の ServerHello
+    fn server_hello(compression_method: u8, trailing: &[u8]) -> Vec<u8> {
+        let mut body = vec![3, 3];
+        body.extend([0; 32]);
+        body.push(0);
+        body.extend([0x13, 0x01, compression_method]);
+        body.extend([0, 6, 0, 43, 0, 2, 3, 4]);
+        body.extend(trailing);
+
+        let mut data = vec![HandshakeType::ServerHello as u8, 0, 0, body.len() as u8];
+        data.extend(body);
+        data
+    }
+
+    fn decode_error(data: &[u8]) -> Option<AlertDescription> {
+        match Handshake::from_bytes(data, ProtocolVersion::TLSv1_2) {
+            Ok(_) => None,
+            Err(e) => match e.downcast_ref::<Error>() {
+                Some(Error::Alert(description)) => Some(*description),
+                _ => None,
+            },
+        }
+    }
+
+    #[test]
+    fn server_hello_decodes() {
+        assert_eq!(decode_error(&server_hello(0, &[])), None);
+    }
+
+    #[test]
+    fn server_hello_unknown_compression_method() {
+        assert_eq!(
+            decode_error(&server_hello(1, &[])),
+            Some(AlertDescription::IllegalParameter)
+        );
+    }
+
+    #[test]
+    fn server_hello_trailing_bytes() {
+        assert_eq!(
+            decode_error(&server_hello(0, &[0])),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    #[test]
+    fn key_update_unknown_request() {
+        let data = [HandshakeType::KeyUpdate as u8, 0, 0, 1, 2];
+        assert_eq!(
+            decode_error(&data),
+            Some(AlertDescription::IllegalParameter)
+        );
+    }
+
+    fn handshake(msg_type: HandshakeType, body: &[u8]) -> Vec<u8> {
+        let mut data = vec![msg_type as u8, 0, 0, body.len() as u8];
+        data.extend(body);
+        data
+    }
+
+    // 中身は読めるが、1 byte 余分に付いていたら decode_error
+    fn assert_rejects_trailing_bytes(
+        msg_type: HandshakeType,
+        version: ProtocolVersion,
+        body: &[u8],
+    ) {
+        let data = handshake(msg_type, body);
+        let decoded = Handshake::from_bytes(&data, version).unwrap();
+        assert_eq!(decoded.msg_type, msg_type);
+
+        let data = handshake(msg_type, &[body, &[0]].concat());
+        let error = Handshake::from_bytes(&data, version).unwrap_err();
+        assert!(
+            matches!(
+                error.downcast_ref::<Error>(),
+                Some(Error::Alert(AlertDescription::DecodeError))
+            ),
+            "{msg_type:?} ({version:?})"
+        );
+    }
+
+    #[test]
+    fn new_session_ticket_trailing_bytes() {
+        assert_rejects_trailing_bytes(
+            HandshakeType::NewSessionTicket,
+            ProtocolVersion::TLSv1_2,
+            &[0, 0, 0, 1, 0, 1, 0xaa],
+        );
+        assert_rejects_trailing_bytes(
+            HandshakeType::NewSessionTicket,
+            ProtocolVersion::TLSv1_3,
+            &[0, 0, 0, 1, 0, 0, 0, 2, 1, 0, 0, 1, 0xaa, 0, 0],
+        );
+    }
+
+    #[test]
+    fn end_of_early_data_trailing_bytes() {
+        assert_rejects_trailing_bytes(HandshakeType::EndOfEarlyData, ProtocolVersion::TLSv1_3, &[]);
+    }
+
+    #[test]
+    fn encrypted_extensions_trailing_bytes() {
+        assert_rejects_trailing_bytes(
+            HandshakeType::EncryptedExtensions,
+            ProtocolVersion::TLSv1_3,
+            &[0, 0],
+        );
+    }
+
+    #[test]
+    fn certificate_trailing_bytes() {
+        assert_rejects_trailing_bytes(
+            HandshakeType::Certificate,
+            ProtocolVersion::TLSv1_2,
+            &[0, 0, 4, 0, 0, 1, 0xaa],
+        );
+        assert_rejects_trailing_bytes(
+            HandshakeType::Certificate,
+            ProtocolVersion::TLSv1_3,
+            &[0, 0, 0, 6, 0, 0, 1, 0xaa, 0, 0],
+        );
+    }
+
+    #[test]
+    fn server_key_exchange_trailing_bytes() {
+        assert_rejects_trailing_bytes(
+            HandshakeType::ServerKeyExchange,
+            ProtocolVersion::TLSv1_2,
+            &[3, 0, 0x17, 1, 4, 4, 3, 0, 1, 0],
+        );
+    }
+
+    #[test]
+    fn certificate_request_trailing_bytes() {
+        assert_rejects_trailing_bytes(
+            HandshakeType::CertificateRequest,
+            ProtocolVersion::TLSv1_2,
+            &[1, 64, 0, 2, 4, 3, 0, 0],
+        );
+        assert_rejects_trailing_bytes(
+            HandshakeType::CertificateRequest,
+            ProtocolVersion::TLSv1_3,
+            &[0, 0, 0],
+        );
+    }
+
+    #[test]
+    fn server_hello_done_trailing_bytes() {
+        assert_rejects_trailing_bytes(
+            HandshakeType::ServerHelloDone,
+            ProtocolVersion::TLSv1_2,
+            &[],
+        );
+    }
+
+    #[test]
+    fn certificate_verify_trailing_bytes() {
+        assert_rejects_trailing_bytes(
+            HandshakeType::CertificateVerify,
+            ProtocolVersion::TLSv1_3,
+            &[4, 3, 0, 1, 0],
+        );
+    }
+
+    #[test]
+    fn key_update_trailing_bytes() {
+        assert_rejects_trailing_bytes(HandshakeType::KeyUpdate, ProtocolVersion::TLSv1_3, &[1]);
+    }
+}
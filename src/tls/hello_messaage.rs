@@ -1,7 +1,8 @@
-use super::{be_u32, be_u8, take, Buffer, IResult};
-use super::{CipherSuite, Error, Opaque, ProtocolVersion, Vector};
+use super::{be_u16, be_u32, be_u8, illegal_parameter, invalid_value, take, Buffer, IResult};
+use super::{CipherSuite, Error, HandshakeType, Opaque, ProtocolVersion, Vector};
 
 use enum_try_from::impl_enum_try_from;
+use ser::NetworkEndian;
 use serde::Serialize;
 use serde_repr::Serialize_repr;
 
@@ -15,13 +16,115 @@ pub struct ClientHello {
     pub extensions: Extensions,
 }
 
+impl ClientHello {
+    // GREASE (RFC 8701) など未知のスイートや圧縮方式は読み飛ばす
+    // 未知の値を読み飛ばす前の長さで、空のリストや 32 bytes を超える session_id を拒否する
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, protocol_version) = ProtocolVersion::deserialize(input)?;
+        let (input, random) = Random::deserialize(input)?;
+        let (input, session_id) = Opaque::<u8>::deserialize(input)?;
+        if session_id.data.len() > 32 {
+            return invalid_value(input);
+        }
+        let (_, length) = be_u16(input.clone())?;
+        if length == 0 || length % 2 != 0 {
+            return invalid_value(input);
+        }
+        let (input, chipher_suites) = CipherSuites::deserialize_with(input, |input| {
+            let (input, suite) = be_u16(input)?;
+            Ok((input, CipherSuite::try_from(suite).ok()))
+        })?;
+        let (input, length) = be_u8(input)?;
+        if length == 0 {
+            return invalid_value(input);
+        }
+        let (input, data) = take(input, length)?;
+        let compression_methods = data
+            .iter()
+            .filter_map(|method| CompressionMethod::try_from(*method).ok())
+            .collect();
+        let compression_methods = CompressionMethods::new(compression_methods);
+
+        let (input, extensions) = if input.length() > 0 {
+            Extensions::deserialize(input, HandshakeType::ClientHello)?
+        } else {
+            (input, Extensions::new(vec![]))
+        };
+        if input.length() > 0 {
+            return invalid_value(input);
+        }
+
+        Ok((
+            input,
+            ClientHello {
+                protocol_version,
+                random,
+                session_id,
+                chipher_suites,
+                compression_methods,
+                extensions,
+            },
+        ))
+    }
+}
+
 #[derive(Serialize, Debug, Clone, Copy)]
 pub struct Random {
     pub gmt_unix_time: u32,
     pub random_bytes: [u8; 28],
 }
 
+// RFC 8446 4.1.3. HelloRetryRequest の random は SHA-256("HelloRetryRequest") 固定
+const HELLO_RETRY_REQUEST_RANDOM: [u8; 32] = [
+    0xCF, 0x21, 0xAD, 0x74, 0xE5, 0x9A, 0x61, 0x11, 0xBE, 0x1D, 0x8C, 0x02, 0x1E, 0x65, 0xB8, 0x91,
+    0xC2, 0xA2, 0x11, 0x16, 0x7A, 0xBB, 0x8C, 0x5E, 0x07, 0x9E, 0x09, 0xE2, 0xC8, 0xA8, 0x33, 0x9C,
+];
+
+// RFC 8446 4.1.3. TLS 1.3 に対応したサーバーが TLS 1.2 以下を選んだときに random の末尾8バイトに入れる値
+const DOWNGRADE_TLS12: [u8; 8] = *b"DOWNGRD\x01";
+const DOWNGRADE_TLS11: [u8; 8] = *b"DOWNGRD\x00";
+
 impl Random {
+    pub fn is_hello_retry_request(&self) -> bool {
+        self.to_bytes() == HELLO_RETRY_REQUEST_RANDOM
+    }
+
+    pub fn is_downgrade_to_tls12(&self) -> bool {
+        self.random_bytes[20..] == DOWNGRADE_TLS12
+    }
+
+    pub fn is_downgrade_to_tls11_or_below(&self) -> bool {
+        self.random_bytes[20..] == DOWNGRADE_TLS11
+    }
+
+    pub fn is_downgrade(&self) -> bool {
+        self.is_downgrade_to_tls12() || self.is_downgrade_to_tls11_or_below()
+    }
+
+    pub fn generate() -> Self {
+        Random {
+            gmt_unix_time: rand::random(),
+            random_bytes: rand::random(),
+        }
+    }
+
+    pub fn hello_retry_request() -> Self {
+        let (gmt_unix_time, random_bytes) = HELLO_RETRY_REQUEST_RANDOM.split_at(4);
+        Random {
+            gmt_unix_time: u32::from_be_bytes(gmt_unix_time.try_into().unwrap()),
+            random_bytes: random_bytes.try_into().unwrap(),
+        }
+    }
+
+    // TLS 1.3 に対応したサーバーが TLS 1.2 を選んだことを示す
+    pub fn set_downgrade_to_tls12(&mut self) {
+        self.random_bytes[20..].copy_from_slice(&DOWNGRADE_TLS12);
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        ser::to_bytes::<_, NetworkEndian>(self).unwrap()
+    }
+
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, gmt_unix_time) = be_u32(input)?;
         let (input, random_bytes) = take(input, 28u8)?;
@@ -46,6 +149,76 @@ pub struct Extension {
     pub data: Vector<u16, ExtensionData>,
 }
 
+impl Extension {
+    pub fn new(data: ExtensionData) -> Self {
+        Extension {
+            extension_type: data.extension_type(),
+            data: Vector::<u16, _>::new(vec![data]),
+        }
+    }
+
+    // 未知の拡張は None を返す
+    pub fn deserialize(input: Buffer, msg_type: HandshakeType) -> IResult<Option<Self>> {
+        let (input, extension_type) = be_u16(input)?;
+        let (input, length) = be_u16(input)?;
+        let (input, data) = take(input, length)?;
+        let data = Buffer::new(data, length);
+
+        let Ok(extension_type) = ExtensionType::try_from(extension_type) else {
+            return Ok((input, None));
+        };
+        // 拡張の中身は余りなく読み切れなければならない
+        let (rest, data) = ExtensionData::deserialize(data, extension_type, msg_type)?;
+        if rest.length() > 0 {
+            return invalid_value(rest);
+        }
+
+        Ok((input, Some(Extension::new(data))))
+    }
+}
+
+impl Extensions {
+    // RFC 8446 4.2. 同じ種類の拡張は1つまで (読み飛ばす未知の拡張も含めて確かめる)
+    // ClientHello の pre_shared_key は最後でなければならない (RFC 8446 4.2.11)
+    pub fn deserialize(input: Buffer, msg_type: HandshakeType) -> IResult<Self> {
+        let (input, length) = be_u16(input)?;
+        let (input, data) = take(input, length)?;
+        let mut data = Buffer::new(data, length);
+
+        let mut extension_types = vec![];
+        let mut extensions = vec![];
+        while data.length() > 0 {
+            let (_, extension_type) = be_u16(data.clone())?;
+            if extension_types.contains(&extension_type) {
+                return invalid_value(data);
+            }
+            if msg_type == HandshakeType::ClientHello
+                && extension_types.last() == Some(&(ExtensionType::PreSharedKey as u16))
+            {
+                return illegal_parameter(data);
+            }
+            extension_types.push(extension_type);
+
+            let (rest, extension) = Extension::deserialize(data, msg_type)?;
+            data = rest;
+            extensions.extend(extension);
+        }
+
+        Ok((input, Extensions::new(extensions)))
+    }
+
+    pub fn get(&self, extension_type: ExtensionType) -> Option<&ExtensionData> {
+        self.data
+            .iter()
+            .find(|extension| extension.extension_type == extension_type)
+            .and_then(|extension| extension.data.data.first())
+    }
+
+    pub fn contains(&self, extension_type: ExtensionType) -> bool {
+        self.get(extension_type).is_some()
+    }
+}
+
 #[derive(Serialize, Debug)]
 pub struct SignatureAndHashAlgorithm {
     pub hash: HashAlgorithm,
@@ -74,15 +247,15 @@ impl ServerHello {
         let (input, cipher_suite) = CipherSuite::deserialize(input)?;
         let (input, compression_method) = CompressionMethod::deserialize(input)?;
 
+        let (input, extensions) = if input.length() > 0 {
+            Extensions::deserialize(input, HandshakeType::ServerHello)?
+        } else {
+            (input, Extensions::new(vec![]))
+        };
         if input.length() > 0 {
-            eprintln!("Deserialize extension is not implemented");
+            return invalid_value(input);
         }
 
-        let extensions = Extensions {
-            length: 0,
-            data: vec![],
-        };
-
         Ok((
             input,
             ServerHello {
@@ -102,7 +275,7 @@ impl ServerHello {
 //=============================================================================
 impl_enum_try_from! {
     #[repr(u8)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum CompressionMethod {
         Null = 0,
     },
@@ -114,16 +287,31 @@ impl_enum_try_from! {
 impl CompressionMethod {
     pub fn deserialize(input: Buffer) -> IResult<Self> {
         let (input, compression_method) = be_u8(input)?;
-        let compression_metthod = CompressionMethod::try_from(compression_method).unwrap();
-        Ok((input, compression_metthod))
+        match CompressionMethod::try_from(compression_method) {
+            Ok(compression_method) => Ok((input, compression_method)),
+            Err(_) => illegal_parameter(input),
+        }
     }
 }
 
 impl_enum_try_from! {
     #[repr(u16)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum ExtensionType {
+        ServerName = 0,
+        SupportedGroups = 10,
         SignatureAlgorithms = 13,
+        ApplicationLayerProtocolNegotiation = 16,
+        SessionTicket = 35,
+        PreSharedKey = 41,
+        EarlyData = 42,
+        SupportedVersions = 43,
+        Cookie = 44,
+        PskKeyExchangeModes = 45,
+        CertificateAuthorities = 47,
+        PostHandshakeAuth = 49,
+        KeyShare = 51,
+        RenegotiationInfo = 0xff01,
     },
     u16,
     Error,
@@ -132,13 +320,438 @@ impl_enum_try_from! {
 
 #[derive(Serialize, Debug)]
 pub enum ExtensionData {
-    SignatureAlgorithms(Vector<u16, SignatureAndHashAlgorithm>),
+    ServerName(ServerNameList),
+    ServerNameAck(()),
+    SupportedGroups(NamedGroupList),
+    SignatureAlgorithms(SignatureSchemeList),
+    ApplicationLayerProtocolNegotiation(ProtocolNameList),
+    PreSharedKeyClientHello(OfferedPsks),
+    PreSharedKeyServerHello(u16),
+    EarlyData(()),
+    MaxEarlyDataSize(u32),
+    SupportedVersions(ProtocolVersionList),
+    SelectedVersion(ProtocolVersion),
+    Cookie(Opaque<u16>),
+    PskKeyExchangeModes(PskKeyExchangeModeList),
+    CertificateAuthorities(DistinguishedNames),
+    PostHandshakeAuth(()),
+    // RFC 5077 3.2. 長さを持たないチケットそのもの (空なら新しいチケットの要求)
+    SessionTicket(Vec<u8>),
+    // RFC 5746 3.2. 最初のハンドシェイクでは空
+    RenegotiationInfo(Opaque<u8>),
+    KeyShareClientHello(KeyShareEntries),
+    KeyShareServerHello(KeyShareEntry),
+    KeyShareHelloRetryRequest(NamedGroup),
+}
+
+impl ExtensionData {
+    pub fn extension_type(&self) -> ExtensionType {
+        match self {
+            ExtensionData::ServerName(_) | ExtensionData::ServerNameAck(_) => {
+                ExtensionType::ServerName
+            }
+            ExtensionData::SupportedGroups(_) => ExtensionType::SupportedGroups,
+            ExtensionData::SignatureAlgorithms(_) => ExtensionType::SignatureAlgorithms,
+            ExtensionData::ApplicationLayerProtocolNegotiation(_) => {
+                ExtensionType::ApplicationLayerProtocolNegotiation
+            }
+            ExtensionData::PreSharedKeyClientHello(_)
+            | ExtensionData::PreSharedKeyServerHello(_) => ExtensionType::PreSharedKey,
+            ExtensionData::EarlyData(_) | ExtensionData::MaxEarlyDataSize(_) => {
+                ExtensionType::EarlyData
+            }
+            ExtensionData::SupportedVersions(_) | ExtensionData::SelectedVersion(_) => {
+                ExtensionType::SupportedVersions
+            }
+            ExtensionData::Cookie(_) => ExtensionType::Cookie,
+            ExtensionData::PskKeyExchangeModes(_) => ExtensionType::PskKeyExchangeModes,
+            ExtensionData::CertificateAuthorities(_) => ExtensionType::CertificateAuthorities,
+            ExtensionData::PostHandshakeAuth(_) => ExtensionType::PostHandshakeAuth,
+            ExtensionData::SessionTicket(_) => ExtensionType::SessionTicket,
+            ExtensionData::RenegotiationInfo(_) => ExtensionType::RenegotiationInfo,
+            ExtensionData::KeyShareClientHello(_)
+            | ExtensionData::KeyShareServerHello(_)
+            | ExtensionData::KeyShareHelloRetryRequest(_) => ExtensionType::KeyShare,
+        }
+    }
+
+    // 同じ拡張でも ClientHello と ServerHello などで中身の形式が異なる
+    pub fn deserialize(
+        input: Buffer,
+        extension_type: ExtensionType,
+        msg_type: HandshakeType,
+    ) -> IResult<Self> {
+        match (extension_type, msg_type) {
+            // サーバーからの応答は空。ホスト名のリストは ClientHello にしか入らない
+            (
+                ExtensionType::ServerName,
+                HandshakeType::ServerHello | HandshakeType::EncryptedExtensions,
+            ) => {
+                if input.length() > 0 {
+                    return invalid_value(input);
+                }
+                Ok((input, ExtensionData::ServerNameAck(())))
+            }
+            // server_name_list<1..2^16-1>
+            (ExtensionType::ServerName, HandshakeType::ClientHello) => {
+                let (_, length) = be_u16(input.clone())?;
+                if length == 0 {
+                    return invalid_value(input);
+                }
+                let (input, list) =
+                    ServerNameList::deserialize_with(input, ServerName::deserialize)?;
+                Ok((input, ExtensionData::ServerName(list)))
+            }
+            (ExtensionType::ServerName, _) => invalid_value(input),
+            (ExtensionType::SupportedGroups, _) => {
+                let (input, groups) = NamedGroupList::deserialize_with(input, |input| {
+                    let (input, group) = be_u16(input)?;
+                    Ok((input, NamedGroup::try_from(group).ok()))
+                })?;
+                Ok((input, ExtensionData::SupportedGroups(groups)))
+            }
+            (ExtensionType::SignatureAlgorithms, _) => {
+                let (input, schemes) = SignatureSchemeList::deserialize(input)?;
+                Ok((input, ExtensionData::SignatureAlgorithms(schemes)))
+            }
+            (ExtensionType::ApplicationLayerProtocolNegotiation, _) => {
+                let (input, protocols) = ProtocolNameList::deserialize_with(input, |input| {
+                    let (input, protocol) = ProtocolName::deserialize(input)?;
+                    Ok((input, Some(protocol)))
+                })?;
+                Ok((
+                    input,
+                    ExtensionData::ApplicationLayerProtocolNegotiation(protocols),
+                ))
+            }
+            (ExtensionType::PreSharedKey, HandshakeType::ClientHello) => {
+                let (input, offered_psks) = OfferedPsks::deserialize(input)?;
+                Ok((input, ExtensionData::PreSharedKeyClientHello(offered_psks)))
+            }
+            (ExtensionType::PreSharedKey, _) => {
+                let (input, selected_identity) = be_u16(input)?;
+                Ok((
+                    input,
+                    ExtensionData::PreSharedKeyServerHello(selected_identity),
+                ))
+            }
+            // NewSessionTicket では max_early_data_size、それ以外は空
+            (ExtensionType::EarlyData, HandshakeType::NewSessionTicket) => {
+                let (input, max_early_data_size) = be_u32(input)?;
+                Ok((input, ExtensionData::MaxEarlyDataSize(max_early_data_size)))
+            }
+            (ExtensionType::EarlyData, _) => Ok((input, ExtensionData::EarlyData(()))),
+            // versions<2..254>
+            (ExtensionType::SupportedVersions, HandshakeType::ClientHello) => {
+                let (input, length) = be_u8(input)?;
+                if length < 2 || length % 2 != 0 {
+                    return invalid_value(input);
+                }
+                let (input, data) = take(input, length)?;
+                let mut data = Buffer::new(data, length);
+
+                let mut versions = vec![];
+                while data.length() > 0 {
+                    let (rest, version) = be_u16(data)?;
+                    data = rest;
+                    versions.extend(ProtocolVersion::try_from(version).ok());
+                }
+                let versions = ProtocolVersionList::new(versions);
+                Ok((input, ExtensionData::SupportedVersions(versions)))
+            }
+            (ExtensionType::SupportedVersions, _) => {
+                let (input, version) = ProtocolVersion::deserialize(input)?;
+                Ok((input, ExtensionData::SelectedVersion(version)))
+            }
+            (ExtensionType::Cookie, _) => {
+                let (input, cookie) = Opaque::<u16>::deserialize(input)?;
+                Ok((input, ExtensionData::Cookie(cookie)))
+            }
+            (ExtensionType::PskKeyExchangeModes, _) => {
+                let (input, length) = be_u8(input)?;
+                let (input, data) = take(input, length)?;
+                let modes = data
+                    .iter()
+                    .filter_map(|mode| PskKeyExchangeMode::try_from(*mode).ok())
+                    .collect();
+                let modes = PskKeyExchangeModeList::new(modes);
+                Ok((input, ExtensionData::PskKeyExchangeModes(modes)))
+            }
+            (ExtensionType::CertificateAuthorities, _) => {
+                let (input, authorities) = DistinguishedNames::deserialize_with(input, |input| {
+                    let (input, name) = DistinguishedName::deserialize(input)?;
+                    Ok((input, Some(name)))
+                })?;
+                Ok((input, ExtensionData::CertificateAuthorities(authorities)))
+            }
+            (ExtensionType::PostHandshakeAuth, _) => {
+                Ok((input, ExtensionData::PostHandshakeAuth(())))
+            }
+            (ExtensionType::RenegotiationInfo, _) => {
+                let (input, renegotiated_connection) = Opaque::<u8>::deserialize(input)?;
+                Ok((
+                    input,
+                    ExtensionData::RenegotiationInfo(renegotiated_connection),
+                ))
+            }
+            (ExtensionType::SessionTicket, _) => {
+                let length = input.length();
+                let (input, ticket) = take(input, length)?;
+                Ok((input, ExtensionData::SessionTicket(ticket.to_vec())))
+            }
+            (ExtensionType::KeyShare, HandshakeType::ClientHello) => {
+                let (input, entries) = KeyShareEntries::deserialize_with(input, |input| {
+                    let (input, entry) = KeyShareEntry::deserialize(input)?;
+                    Ok((input, entry))
+                })?;
+                Ok((input, ExtensionData::KeyShareClientHello(entries)))
+            }
+            // HelloRetryRequest では selected_group のみ
+            (ExtensionType::KeyShare, HandshakeType::ServerHello) if input.length() == 2 => {
+                let (input, group) = NamedGroup::deserialize(input)?;
+                Ok((input, ExtensionData::KeyShareHelloRetryRequest(group)))
+            }
+            (ExtensionType::KeyShare, _) => {
+                let (input, entry) = KeyShareEntry::deserialize(input)?;
+                let Some(entry) = entry else {
+                    return invalid_value(input);
+                };
+                Ok((input, ExtensionData::KeyShareServerHello(entry)))
+            }
+        }
+    }
+}
+
+pub type ServerNameList = Vector<u16, ServerName>;
+pub type NamedGroupList = Vector<u16, NamedGroup>;
+pub type ProtocolVersionList = Vector<u8, ProtocolVersion>;
+pub type KeyShareEntries = Vector<u16, KeyShareEntry>;
+// RFC 7301 3.1. "h2" や "http/1.1" など
+pub type ProtocolName = Opaque<u8>;
+pub type ProtocolNameList = Vector<u16, ProtocolName>;
+// DER の X.501 Name
+pub type DistinguishedName = Opaque<u16>;
+pub type DistinguishedNames = Vector<u16, DistinguishedName>;
+
+#[derive(Serialize, Debug)]
+pub struct ServerName {
+    pub name_type: NameType,
+    pub host_name: Opaque<u16>,
+}
+
+impl ServerName {
+    pub fn new(host_name: &str) -> Self {
+        ServerName {
+            name_type: NameType::HostName,
+            host_name: Opaque::<u16>::new(host_name.as_bytes().to_vec()),
+        }
+    }
+
+    pub fn deserialize(input: Buffer) -> IResult<Option<Self>> {
+        let (input, name_type) = be_u8(input)?;
+        let (input, host_name) = Opaque::<u16>::deserialize(input)?;
+        let Ok(name_type) = NameType::try_from(name_type) else {
+            return Ok((input, None));
+        };
+        Ok((
+            input,
+            Some(ServerName {
+                name_type,
+                host_name,
+            }),
+        ))
+    }
+}
+
+impl_enum_try_from! {
+    #[repr(u8)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum NameType {
+        HostName = 0,
+    },
+    u8,
+    Error,
+    Error::InvalidValue
+}
+
+#[derive(Serialize, Debug)]
+pub struct KeyShareEntry {
+    pub group: NamedGroup,
+    pub key_exchange: Opaque<u16>,
+}
+
+impl KeyShareEntry {
+    // 未知のグループは None を返す
+    pub fn deserialize(input: Buffer) -> IResult<Option<Self>> {
+        let (input, group) = be_u16(input)?;
+        let (input, key_exchange) = Opaque::<u16>::deserialize(input)?;
+        let Ok(group) = NamedGroup::try_from(group) else {
+            return Ok((input, None));
+        };
+        Ok((
+            input,
+            Some(KeyShareEntry {
+                group,
+                key_exchange,
+            }),
+        ))
+    }
+}
+
+// RFC 8446 4.2.11. pre_shared_key は ClientHello の最後の拡張でなければならない
+#[derive(Serialize, Debug)]
+pub struct OfferedPsks {
+    pub identities: PskIdentities,
+    pub binders: PskBinderEntries,
+}
+
+pub type PskIdentities = Vector<u16, PskIdentity>;
+pub type PskBinderEntry = Opaque<u8>;
+pub type PskBinderEntries = Vector<u16, PskBinderEntry>;
+
+impl OfferedPsks {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, identities) = PskIdentities::deserialize_with(input, |input| {
+            let (input, identity) = PskIdentity::deserialize(input)?;
+            Ok((input, Some(identity)))
+        })?;
+        let (input, binders) = PskBinderEntries::deserialize_with(input, |input| {
+            let (input, binder) = PskBinderEntry::deserialize(input)?;
+            Ok((input, Some(binder)))
+        })?;
+        Ok((
+            input,
+            OfferedPsks {
+                identities,
+                binders,
+            },
+        ))
+    }
+
+    // バインダーは binders を除いた ClientHello に対して計算するので、末尾から切り落とす長さ
+    pub fn binders_size(&self) -> usize {
+        2 + self.binders.length as usize
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct PskIdentity {
+    pub identity: Opaque<u16>,
+    pub obfuscated_ticket_age: u32,
+}
+
+impl PskIdentity {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, identity) = Opaque::<u16>::deserialize(input)?;
+        let (input, obfuscated_ticket_age) = be_u32(input)?;
+        Ok((
+            input,
+            PskIdentity {
+                identity,
+                obfuscated_ticket_age,
+            },
+        ))
+    }
+}
+
+pub type PskKeyExchangeModeList = Vector<u8, PskKeyExchangeMode>;
+
+impl_enum_try_from! {
+    #[allow(non_camel_case_types)]
+    #[repr(u8)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum PskKeyExchangeMode {
+        psk_ke = 0,
+        psk_dhe_ke = 1,
+    },
+    u8,
+    Error,
+    Error::InvalidValue
+}
+
+impl_enum_try_from! {
+    #[allow(non_camel_case_types)]
+    #[repr(u16)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum NamedGroup {
+        secp256r1 = 0x0017,
+        secp384r1 = 0x0018,
+        secp521r1 = 0x0019,
+        x25519 = 0x001D,
+        x448 = 0x001E,
+        ffdhe2048 = 0x0100,
+        ffdhe3072 = 0x0101,
+        ffdhe4096 = 0x0102,
+        ffdhe6144 = 0x0103,
+        ffdhe8192 = 0x0104,
+    },
+    u16,
+    Error,
+    Error::InvalidValue
+}
+
+impl NamedGroup {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, group) = be_u16(input)?;
+        match NamedGroup::try_from(group) {
+            Ok(group) => Ok((input, group)),
+            Err(_) => invalid_value(input),
+        }
+    }
+}
+
+pub type SignatureSchemeList = Vector<u16, SignatureScheme>;
+
+impl SignatureSchemeList {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        SignatureSchemeList::deserialize_with(input, |input| {
+            let (input, scheme) = be_u16(input)?;
+            Ok((input, SignatureScheme::try_from(scheme).ok()))
+        })
+    }
+}
+
+impl_enum_try_from! {
+    #[allow(non_camel_case_types)]
+    #[repr(u16)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SignatureScheme {
+        rsa_pkcs1_sha1 = 0x0201,
+        ecdsa_sha1 = 0x0203,
+        rsa_pkcs1_sha256 = 0x0401,
+        rsa_pkcs1_sha384 = 0x0501,
+        rsa_pkcs1_sha512 = 0x0601,
+        ecdsa_secp256r1_sha256 = 0x0403,
+        ecdsa_secp384r1_sha384 = 0x0503,
+        ecdsa_secp521r1_sha512 = 0x0603,
+        rsa_pss_rsae_sha256 = 0x0804,
+        rsa_pss_rsae_sha384 = 0x0805,
+        rsa_pss_rsae_sha512 = 0x0806,
+        ed25519 = 0x0807,
+        ed448 = 0x0808,
+        rsa_pss_pss_sha256 = 0x0809,
+        rsa_pss_pss_sha384 = 0x080a,
+        rsa_pss_pss_sha512 = 0x080b,
+    },
+    u16,
+    Error,
+    Error::InvalidValue
+}
+
+impl SignatureScheme {
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, scheme) = be_u16(input)?;
+        match SignatureScheme::try_from(scheme) {
+            Ok(scheme) => Ok((input, scheme)),
+            Err(_) => invalid_value(input),
+        }
+    }
 }
 
 impl_enum_try_from! {
     #[allow(dead_code, clippy::upper_case_acronyms)]
     #[repr(u8)]
-    #[derive(Serialize_repr, Debug)]
+    #[derive(Serialize_repr, Debug, Clone, Copy, PartialEq, Eq)]
     pub enum HashAlgorithm {
         None = 0,
         MD5 = 1,
@@ -168,3 +781,206 @@ impl_enum_try_from! {
     Error::InvalidValue
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::{decode_alert, AlertDescription};
+
+    fn decode(
+        data: &[u8],
+        extension_type: ExtensionType,
+        msg_type: HandshakeType,
+    ) -> Option<ExtensionData> {
+        ExtensionData::deserialize(Buffer::new(data, data.len()), extension_type, msg_type)
+            .ok()
+            .map(|(_, extension)| extension)
+    }
+
+    #[test]
+    fn server_name_ack() {
+        for msg_type in [
+            HandshakeType::ServerHello,
+            HandshakeType::EncryptedExtensions,
+        ] {
+            assert!(matches!(
+                decode(&[], ExtensionType::ServerName, msg_type),
+                Some(ExtensionData::ServerNameAck(()))
+            ));
+        }
+        assert!(decode(&[], ExtensionType::ServerName, HandshakeType::ClientHello).is_none());
+        assert!(decode(
+            &[],
+            ExtensionType::ServerName,
+            HandshakeType::CertificateRequest
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn server_name_list() {
+        let data = [0, 6, 0, 0, 3, b'a', b'.', b'b'];
+        assert!(matches!(
+            decode(&data, ExtensionType::ServerName, HandshakeType::ClientHello),
+            Some(ExtensionData::ServerName(list)) if list.data[0].host_name.data == b"a.b"
+        ));
+        assert!(decode(
+            &[0, 0],
+            ExtensionType::ServerName,
+            HandshakeType::ClientHello
+        )
+        .is_none());
+        assert!(decode(
+            &data,
+            ExtensionType::ServerName,
+            HandshakeType::EncryptedExtensions
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn supported_versions() {
+        assert!(matches!(
+            decode(&[2, 3, 4], ExtensionType::SupportedVersions, HandshakeType::ClientHello),
+            Some(ExtensionData::SupportedVersions(versions))
+                if versions.data == [ProtocolVersion::TLSv1_3]
+        ));
+        assert!(decode(
+            &[0],
+            ExtensionType::SupportedVersions,
+            HandshakeType::ClientHello
+        )
+        .is_none());
+        assert!(decode(
+            &[1, 3],
+            ExtensionType::SupportedVersions,
+            HandshakeType::ClientHello
+        )
+        .is_none());
+    }
+
+    const SUPPORTED_VERSIONS: [u8; 7] = [0, 43, 0, 3, 2, 3, 4];
+    const GREASE: [u8; 4] = [0xfa, 0xfa, 0, 0];
+
+    // identity "x" と 32 bytes の binder が1つずつ
+    fn pre_shared_key() -> Vec<u8> {
+        let mut extension = vec![0, 41, 0, 44, 0, 7, 0, 1, b'x', 0, 0, 0, 0, 0, 33, 32];
+        extension.extend([0; 32]);
+        extension
+    }
+
+    fn client_hello(
+        session_id: &[u8],
+        cipher_suites: &[u8],
+        extensions: &[&[u8]],
+        trailing: &[u8],
+    ) -> Vec<u8> {
+        let mut data = vec![3, 3];
+        data.extend([0; 32]);
+        data.push(session_id.len() as u8);
+        data.extend(session_id);
+        data.extend((cipher_suites.len() as u16).to_be_bytes());
+        data.extend(cipher_suites);
+        data.extend([1, 0]);
+        let extensions = extensions.concat();
+        data.extend((extensions.len() as u16).to_be_bytes());
+        data.extend(extensions);
+        data.extend(trailing);
+        data
+    }
+
+    fn client_hello_alert(data: &[u8]) -> Option<AlertDescription> {
+        match ClientHello::deserialize(Buffer::new(data, data.len())) {
+            Ok(_) => None,
+            Err(e) => Some(decode_alert(&e)),
+        }
+    }
+
+    #[test]
+    fn client_hello_decodes() {
+        let data = client_hello(
+            &[0; 32],
+            &[0x13, 0x01],
+            &[&GREASE, &SUPPORTED_VERSIONS, &pre_shared_key()],
+            &[],
+        );
+        let (_, client_hello) = ClientHello::deserialize(Buffer::new(&data, data.len())).unwrap();
+        assert_eq!(client_hello.extensions.data.len(), 2);
+        assert!(client_hello
+            .extensions
+            .contains(ExtensionType::PreSharedKey));
+    }
+
+    #[test]
+    fn client_hello_duplicate_extension() {
+        let data = client_hello(
+            &[],
+            &[0x13, 0x01],
+            &[&SUPPORTED_VERSIONS, &SUPPORTED_VERSIONS],
+            &[],
+        );
+        assert_eq!(
+            client_hello_alert(&data),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    #[test]
+    fn client_hello_duplicate_unknown_extension() {
+        let data = client_hello(&[], &[0x13, 0x01], &[&GREASE, &GREASE], &[]);
+        assert_eq!(
+            client_hello_alert(&data),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    #[test]
+    fn client_hello_pre_shared_key_not_last() {
+        let data = client_hello(
+            &[],
+            &[0x13, 0x01],
+            &[&pre_shared_key(), &SUPPORTED_VERSIONS],
+            &[],
+        );
+        assert_eq!(
+            client_hello_alert(&data),
+            Some(AlertDescription::IllegalParameter)
+        );
+    }
+
+    #[test]
+    fn client_hello_empty_cipher_suites() {
+        let data = client_hello(&[], &[], &[&SUPPORTED_VERSIONS], &[]);
+        assert_eq!(
+            client_hello_alert(&data),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    #[test]
+    fn client_hello_odd_cipher_suites() {
+        let data = client_hello(&[], &[0x13, 0x01, 0x13], &[&SUPPORTED_VERSIONS], &[]);
+        assert_eq!(
+            client_hello_alert(&data),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    #[test]
+    fn client_hello_long_session_id() {
+        let data = client_hello(&[0; 33], &[0x13, 0x01], &[&SUPPORTED_VERSIONS], &[]);
+        assert_eq!(
+            client_hello_alert(&data),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    #[test]
+    fn client_hello_trailing_bytes() {
+        let data = client_hello(&[], &[0x13, 0x01], &[&SUPPORTED_VERSIONS], &[0]);
+        assert_eq!(
+            client_hello_alert(&data),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use super::crypto::{
+    derive_secret, digest, hkdf_expand_label, hkdf_extract, hmac, prf, AeadAlgorithm,
+    DigestAlgorithm,
+};
+use super::{HandshakeType, Random, RecordProtection};
+
+#[derive(Debug, Default, Clone)]
+pub struct Transcript {
+    messages: Vec<u8>,
+}
+
+impl Transcript {
+    pub fn new() -> Self {
+        Transcript::default()
+    }
+
+    pub fn update(&mut self, message: &[u8]) {
+        self.messages.extend_from_slice(message);
+    }
+
+    // TLS 1.2 の CertificateVerify はハッシュではなくメッセージ全体に署名する
+    pub fn messages(&self) -> &[u8] {
+        &self.messages
+    }
+
+    pub fn hash(&self, algorithm: DigestAlgorithm) -> Vec<u8> {
+        digest(algorithm, &self.messages)
+    }
+
+    // RFC 8446 4.4.1. HelloRetryRequest を受け取ったら ClientHello1 を message_hash に置き換える
+    pub fn replace_with_message_hash(&mut self, algorithm: DigestAlgorithm) {
+        let hash = self.hash(algorithm);
+        let mut messages = vec![HandshakeType::MessageHash as u8, 0, 0, hash.len() as u8];
+        messages.extend_from_slice(&hash);
+        self.messages = messages;
+    }
+}
+
+//=============================================================================
+// TLS 1.2 (RFC 5246 6.3, 8.1)
+//=============================================================================
+
+pub fn master_secret(
+    algorithm: DigestAlgorithm,
+    pre_master_secret: &[u8],
+    client_random: &Random,
+    server_random: &Random,
+) -> Vec<u8> {
+    let mut seed = client_random.to_bytes();
+    seed.extend(server_random.to_bytes());
+    prf(algorithm, pre_master_secret, b"master secret", &seed, 48)
+}
+
+#[derive(Debug)]
+pub struct KeyBlock {
+    pub client_write_key: Vec<u8>,
+    pub server_write_key: Vec<u8>,
+    pub client_write_iv: Vec<u8>,
+    pub server_write_iv: Vec<u8>,
+}
+
+impl KeyBlock {
+    // AEAD なので MAC key は無く、IV は 4 bytes の salt (fixed_iv) のみ
+    pub fn new(
+        algorithm: DigestAlgorithm,
+        aead: AeadAlgorithm,
+        master_secret: &[u8],
+        client_random: &Random,
+        server_random: &Random,
+    ) -> Self {
+        let key_length = aead.key_length();
+        let iv_length = 4;
+
+        let mut seed = server_random.to_bytes();
+        seed.extend(client_random.to_bytes());
+        let key_block = prf(
+            algorithm,
+            master_secret,
+            b"key expansion",
+            &seed,
+            (key_length + iv_length) * 2,
+        );
+
+        let (client_write_key, rest) = key_block.split_at(key_length);
+        let (server_write_key, rest) = rest.split_at(key_length);
+        let (client_write_iv, server_write_iv) = rest.split_at(iv_length);
+
+        KeyBlock {
+            client_write_key: client_write_key.to_vec(),
+            server_write_key: server_write_key.to_vec(),
+            client_write_iv: client_write_iv.to_vec(),
+            server_write_iv: server_write_iv.to_vec(),
+        }
+    }
+}
+
+// label は "client finished" または "server finished"
+pub fn verify_data(
+    algorithm: DigestAlgorithm,
+    master_secret: &[u8],
+    label: &[u8],
+    handshake_hash: &[u8],
+) -> Vec<u8> {
+    prf(algorithm, master_secret, label, handshake_hash, 12)
+}
+
+//=============================================================================
+// TLS 1.3 (RFC 8446 7.1)
+//=============================================================================
+
+// Early Secret -> Handshake Secret -> Master Secret と段階的に進める
+pub struct KeySchedule {
+    algorithm: DigestAlgorithm,
+    secret: Vec<u8>,
+}
+
+impl KeySchedule {
+    // Early Secret = HKDF-Extract(0, PSK)
+    pub fn new(algorithm: DigestAlgorithm, psk: Option<&[u8]>) -> Self {
+        let zeros = vec![0; algorithm.output_length()];
+        let secret = hkdf_extract(algorithm, &zeros, psk.unwrap_or(&zeros));
+        KeySchedule { algorithm, secret }
+    }
+
+    pub fn algorithm(&self) -> DigestAlgorithm {
+        self.algorithm
+    }
+
+    pub fn derive_secret(&self, label: &str, transcript_hash: &[u8]) -> Vec<u8> {
+        derive_secret(self.algorithm, &self.secret, label, transcript_hash)
+    }
+
+    // 次の段階の Secret = HKDF-Extract(Derive-Secret(., "derived", ""), (EC)DHE or 0)
+    pub fn advance(&mut self, ikm: Option<&[u8]>) {
+        let empty_hash = digest(self.algorithm, &[]);
+        let derived = self.derive_secret("derived", &empty_hash);
+        let zeros = vec![0; self.algorithm.output_length()];
+        self.secret = hkdf_extract(self.algorithm, &derived, ikm.unwrap_or(&zeros));
+    }
+}
+
+// RFC 8446 7.2. KeyUpdate 後の traffic secret
+pub fn next_traffic_secret(algorithm: DigestAlgorithm, traffic_secret: &[u8]) -> Vec<u8> {
+    hkdf_expand_label(
+        algorithm,
+        traffic_secret,
+        "traffic upd",
+        &[],
+        algorithm.output_length(),
+    )
+}
+
+// RFC 8446 4.2.11.2. binder は Early Secret から導出した鍵で計算する Finished と同じ形式
+pub fn psk_binder(algorithm: DigestAlgorithm, psk: &[u8], transcript_hash: &[u8]) -> Vec<u8> {
+    let early_secret = KeySchedule::new(algorithm, Some(psk));
+    let binder_key = early_secret.derive_secret("res binder", &digest(algorithm, &[]));
+    finished_verify_data(algorithm, &binder_key, transcript_hash)
+}
+
+// RFC 8446 4.6.1. チケットごとの PSK
+pub fn resumption_psk(
+    algorithm: DigestAlgorithm,
+    resumption_master_secret: &[u8],
+    ticket_nonce: &[u8],
+) -> Vec<u8> {
+    hkdf_expand_label(
+        algorithm,
+        resumption_master_secret,
+        "resumption",
+        ticket_nonce,
+        algorithm.output_length(),
+    )
+}
+
+// RFC 8446 7.3. Traffic Key Calculation
+pub fn traffic_protection(
+    algorithm: DigestAlgorithm,
+    aead: AeadAlgorithm,
+    traffic_secret: &[u8],
+) -> RecordProtection {
+    let key = hkdf_expand_label(algorithm, traffic_secret, "key", &[], aead.key_length());
+    let iv = hkdf_expand_label(algorithm, traffic_secret, "iv", &[], aead.nonce_length());
+    RecordProtection::new_tls13(aead, &key, &iv)
+}
+
+// RFC 8446 4.4.4. Finished
+pub fn finished_verify_data(
+    algorithm: DigestAlgorithm,
+    base_key: &[u8],
+    transcript_hash: &[u8],
+) -> Vec<u8> {
+    let finished_key = hkdf_expand_label(
+        algorithm,
+        base_key,
+        "finished",
+        &[],
+        algorithm.output_length(),
+    );
+    hmac(algorithm, &finished_key, transcript_hash)
+}
+
+// Finished の検証で一致するまでの時間から内容を推測されないようにする
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (a, b)| acc | (a ^ b)) == 0
+}
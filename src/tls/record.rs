@@ -0,0 +1,387 @@
+use super::crypto::{AeadAlgorithm, AeadCipher};
+use super::{be_u16, take, Buffer, ContentType, IResult, ProtocolVersion};
+use super::{Alert, AlertDescription, AlertLevel, ChangeCipherSpec, Error, HandshakeJoiner};
+
+use anyhow::{bail, Result};
+use ser::NetworkEndian;
+use serde::Serialize;
+use std::io::{Read, Write};
+
+// 初期状態は TLS_NULL_WITH_NULL_NULL なので平文のレコードもこれで扱う
+#[derive(Serialize, Debug)]
+pub struct TLSCiphertext {
+    pub content_type: ContentType,
+    pub protocol_version: ProtocolVersion,
+    pub length: u16,
+    pub fragment: Vec<u8>,
+}
+
+// RFC 8446 5.1. ミドルボックスが TLS 1.2 と区別できないよう、TLS 1.3 でもレコードのバージョンは 0x0303
+const LEGACY_RECORD_VERSION: ProtocolVersion = ProtocolVersion::TLSv1_2;
+
+impl TLSCiphertext {
+    pub fn new(content_type: ContentType, fragment: Vec<u8>) -> Self {
+        TLSCiphertext {
+            content_type,
+            protocol_version: LEGACY_RECORD_VERSION,
+            length: fragment.len() as u16,
+            fragment,
+        }
+    }
+
+    pub fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, content_type) = ContentType::deserialize(input)?;
+        let (input, protocol_version) = ProtocolVersion::deserialize(input)?;
+        let (input, length) = be_u16(input)?;
+        let (input, fragment) = take(input, length)?;
+
+        Ok((
+            input,
+            TLSCiphertext {
+                content_type,
+                protocol_version,
+                length,
+                fragment: fragment.to_vec(),
+            },
+        ))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        ser::to_bytes::<_, NetworkEndian>(self).unwrap()
+    }
+}
+
+pub fn read_record<R: Read>(reader: &mut R) -> Result<TLSCiphertext> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+
+    // RFC 8446 5. 未知の ContentType のレコードは unexpected_message で中止する
+    let Ok(content_type) = ContentType::try_from(header[0]) else {
+        bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+    };
+    let length = u16::from_be_bytes([header[3], header[4]]);
+    let mut fragment = vec![0; length as usize];
+    reader.read_exact(&mut fragment)?;
+
+    // RFC 8446 5.1. legacy_record_version は無視する
+    Ok(TLSCiphertext {
+        content_type,
+        protocol_version: LEGACY_RECORD_VERSION,
+        length,
+        fragment,
+    })
+}
+
+pub fn write_record<W: Write>(writer: &mut W, record: &TLSCiphertext) -> Result<()> {
+    writer.write_all(&record.to_bytes())?;
+    Ok(())
+}
+
+// RFC 5246 6.2.3.3. AEAD Ciphers, RFC 5288 3. AES-GCM Cipher Suites, RFC 6655 3. RSA-Based AES-CCM Cipher Suites
+// RFC 8446 5.2. Record Payload Protection
+pub struct RecordProtection {
+    version: ProtocolVersion,
+    algorithm: AeadAlgorithm,
+    cipher: AeadCipher,
+    iv: Vec<u8>,
+    sequence_number: u64,
+}
+
+impl RecordProtection {
+    // TLS 1.2 の iv は 4 bytes の salt
+    pub fn new(algorithm: AeadAlgorithm, key: &[u8], fixed_iv: &[u8]) -> Self {
+        RecordProtection {
+            version: ProtocolVersion::TLSv1_2,
+            algorithm,
+            cipher: AeadCipher::new(algorithm, key),
+            iv: fixed_iv.to_vec(),
+            sequence_number: 0,
+        }
+    }
+
+    pub fn new_tls13(algorithm: AeadAlgorithm, key: &[u8], iv: &[u8]) -> Self {
+        RecordProtection {
+            version: ProtocolVersion::TLSv1_3,
+            algorithm,
+            cipher: AeadCipher::new(algorithm, key),
+            iv: iv.to_vec(),
+            sequence_number: 0,
+        }
+    }
+
+    fn additional_data(&self, content_type: ContentType, length: usize) -> Vec<u8> {
+        let mut aad = vec![];
+        if self.version == ProtocolVersion::TLSv1_2 {
+            aad.extend_from_slice(&self.sequence_number.to_be_bytes());
+        }
+        aad.push(content_type as u8);
+        aad.extend_from_slice(&(LEGACY_RECORD_VERSION as u16).to_be_bytes());
+        aad.extend_from_slice(&(length as u16).to_be_bytes());
+        aad
+    }
+
+    // TLS 1.3 は iv とパディングしたシーケンス番号の XOR
+    fn tls13_nonce(&self) -> Vec<u8> {
+        let mut nonce = self.iv.clone();
+        let sequence_number = self.sequence_number.to_be_bytes();
+        let offset = nonce.len() - sequence_number.len();
+        for (n, s) in nonce[offset..].iter_mut().zip(sequence_number) {
+            *n ^= s;
+        }
+        nonce
+    }
+
+    pub fn is_exhausted(&self) -> bool {
+        self.sequence_number >= self.algorithm.record_limit()
+    }
+
+    // 上限の近くの動作を確かめるため、送受信したことにしてシーケンス番号を進める
+    #[cfg(test)]
+    pub fn skip_records(&mut self, count: u64) {
+        self.sequence_number += count;
+    }
+
+    pub fn encrypt(&mut self, content_type: ContentType, plaintext: &[u8]) -> TLSCiphertext {
+        let record = if self.version == ProtocolVersion::TLSv1_3 {
+            // TLSInnerPlaintext = content || ContentType || zeros (パディングは付けない)
+            let mut inner_plaintext = plaintext.to_vec();
+            inner_plaintext.push(content_type as u8);
+
+            let length = inner_plaintext.len() + self.algorithm.tag_length();
+            let aad = self.additional_data(ContentType::ApplicationData, length);
+            let encrypted_record = self
+                .cipher
+                .seal(&self.tls13_nonce(), &aad, &inner_plaintext);
+
+            TLSCiphertext::new(ContentType::ApplicationData, encrypted_record)
+        } else {
+            // nonce_explicit にはシーケンス番号をそのまま使う
+            let explicit_nonce = self.sequence_number.to_be_bytes();
+            let mut nonce = self.iv.clone();
+            nonce.extend_from_slice(&explicit_nonce);
+
+            let aad = self.additional_data(content_type, plaintext.len());
+            let mut fragment = explicit_nonce.to_vec();
+            fragment.extend(self.cipher.seal(&nonce, &aad, plaintext));
+
+            TLSCiphertext::new(content_type, fragment)
+        };
+        self.sequence_number += 1;
+
+        record
+    }
+
+    pub fn decrypt(&mut self, record: &TLSCiphertext) -> Result<(ContentType, Vec<u8>)> {
+        let bad_record_mac = Error::Alert(AlertDescription::BadRecordMac);
+        let tag_length = self.algorithm.tag_length();
+
+        let result = if self.version == ProtocolVersion::TLSv1_3 {
+            if record.content_type != ContentType::ApplicationData {
+                bail!(Error::Alert(AlertDescription::UnexpectedMessage));
+            }
+
+            let aad = self.additional_data(record.content_type, record.fragment.len());
+            let Ok(mut inner_plaintext) =
+                self.cipher
+                    .open(&self.tls13_nonce(), &aad, &record.fragment)
+            else {
+                bail!(bad_record_mac)
+            };
+
+            // 末尾の 0 パディングを取り除いた最後の 1 byte が本来の ContentType
+            while inner_plaintext.last() == Some(&0) {
+                inner_plaintext.pop();
+            }
+            let Some(content_type) = inner_plaintext.pop() else {
+                bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+            };
+            let Ok(content_type) = ContentType::try_from(content_type) else {
+                bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+            };
+            (content_type, inner_plaintext)
+        } else {
+            if record.fragment.len() < 8 + tag_length {
+                bail!(bad_record_mac);
+            }
+
+            let (explicit_nonce, ciphertext) = record.fragment.split_at(8);
+            let mut nonce = self.iv.clone();
+            nonce.extend_from_slice(explicit_nonce);
+
+            let aad = self.additional_data(record.content_type, ciphertext.len() - tag_length);
+            let Ok(plaintext) = self.cipher.open(&nonce, &aad, ciphertext) else {
+                bail!(bad_record_mac)
+            };
+            (record.content_type, plaintext)
+        };
+        self.sequence_number += 1;
+
+        Ok(result)
+    }
+}
+
+#[derive(Debug)]
+pub enum Message {
+    Handshake(Vec<u8>),
+    ChangeCipherSpec,
+    Alert(Alert),
+    ApplicationData(Vec<u8>),
+}
+
+pub struct RecordLayer<S> {
+    stream: S,
+    read_protection: Option<RecordProtection>,
+    write_protection: Option<RecordProtection>,
+    joiner: HandshakeJoiner,
+    // 拒否した 0-RTT のデータとして読み飛ばしてよい残りのバイト数
+    early_data_to_skip: usize,
+}
+
+impl<S: Read + Write> RecordLayer<S> {
+    pub fn new(stream: S) -> Self {
+        RecordLayer {
+            stream,
+            read_protection: None,
+            write_protection: None,
+            joiner: HandshakeJoiner::new(),
+            early_data_to_skip: 0,
+        }
+    }
+
+    // 鍵の切り替えをまたいでハンドシェイクメッセージが分割されていてはいけない
+    pub fn set_read_protection(&mut self, protection: RecordProtection) -> Result<()> {
+        if !self.joiner.is_empty() {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage));
+        }
+        self.read_protection = Some(protection);
+        Ok(())
+    }
+
+    pub fn set_write_protection(&mut self, protection: RecordProtection) {
+        self.write_protection = Some(protection);
+    }
+
+    // 送信鍵で暗号化したレコード数が上限に達したら KeyUpdate が必要
+    pub fn is_write_key_exhausted(&self) -> bool {
+        self.write_protection
+            .as_ref()
+            .is_some_and(|protection| protection.is_exhausted())
+    }
+
+    // 0-RTT を送った後に HelloRetryRequest を受け取ったら ClientHello2 は平文で送る
+    pub fn clear_write_protection(&mut self) {
+        self.write_protection = None;
+    }
+
+    // RFC 8446 4.2.10. 0-RTT を拒否したら、復号できないレコード (HelloRetryRequest 後は平文の
+    // application_data) を limit bytes まで読み飛ばす。正しく読めたレコードが来たら終わり
+    pub fn skip_early_data(&mut self, limit: usize) {
+        self.early_data_to_skip = limit;
+    }
+
+    fn skip_rejected_early_data(&mut self, record: &TLSCiphertext) -> Result<bool> {
+        if self.early_data_to_skip == 0 || record.content_type != ContentType::ApplicationData {
+            return Ok(false);
+        }
+        let Some(rest) = self.early_data_to_skip.checked_sub(record.fragment.len()) else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        self.early_data_to_skip = rest;
+        Ok(true)
+    }
+
+    pub fn read_message(&mut self) -> Result<Message> {
+        loop {
+            if let Some(message) = self.joiner.pop() {
+                return Ok(Message::Handshake(message));
+            }
+
+            let record = read_record(&mut self.stream)?;
+            if record.fragment.len() > MAX_CIPHERTEXT_LENGTH {
+                bail!(Error::Alert(AlertDescription::RecordOverflow));
+            }
+
+            // ChangeCipherSpec は常に平文で送られてくる
+            let decrypted = match &mut self.read_protection {
+                Some(protection) if record.content_type != ContentType::ChangeCipherSpec => {
+                    Some(protection.decrypt(&record))
+                }
+                _ => None,
+            };
+            let (content_type, fragment) = match decrypted {
+                Some(Ok(result)) => result,
+                Some(Err(_)) if self.skip_rejected_early_data(&record)? => continue,
+                Some(Err(e)) => return Err(e),
+                None if self.read_protection.is_none()
+                    && self.skip_rejected_early_data(&record)? =>
+                {
+                    continue
+                }
+                None => (record.content_type, record.fragment),
+            };
+            if content_type != ContentType::ChangeCipherSpec {
+                self.early_data_to_skip = 0;
+            }
+            if fragment.len() > MAX_PLAINTEXT_LENGTH {
+                bail!(Error::Alert(AlertDescription::RecordOverflow));
+            }
+
+            if content_type != ContentType::Handshake && !self.joiner.is_empty() {
+                bail!(Error::Alert(AlertDescription::UnexpectedMessage));
+            }
+
+            match content_type {
+                ContentType::Handshake if fragment.is_empty() => {
+                    bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+                }
+                ContentType::Handshake => self.joiner.push(&fragment),
+                ContentType::ChangeCipherSpec => {
+                    if fragment != [ChangeCipherSpec::ChangeCipherSpec as u8] {
+                        bail!(Error::Alert(AlertDescription::UnexpectedMessage));
+                    }
+                    return Ok(Message::ChangeCipherSpec);
+                }
+                ContentType::Alert => {
+                    return Ok(Message::Alert(Alert::from_bytes(&fragment)?));
+                }
+                ContentType::ApplicationData => return Ok(Message::ApplicationData(fragment)),
+            }
+        }
+    }
+
+    fn write(&mut self, content_type: ContentType, data: &[u8]) -> Result<()> {
+        for fragment in data.chunks(MAX_PLAINTEXT_LENGTH) {
+            let record = match &mut self.write_protection {
+                Some(protection) => protection.encrypt(content_type, fragment),
+                None => TLSCiphertext::new(content_type, fragment.to_vec()),
+            };
+            write_record(&mut self.stream, &record)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_handshake(&mut self, data: &[u8]) -> Result<()> {
+        self.write(ContentType::Handshake, data)
+    }
+
+    pub fn write_application_data(&mut self, data: &[u8]) -> Result<()> {
+        self.write(ContentType::ApplicationData, data)
+    }
+
+    pub fn write_alert(&mut self, level: AlertLevel, description: AlertDescription) -> Result<()> {
+        let alert = ser::to_bytes::<_, NetworkEndian>(&Alert { level, description })?;
+        self.write(ContentType::Alert, &alert)
+    }
+
+    // ChangeCipherSpec は暗号化しない
+    pub fn write_change_cipher_spec(&mut self) -> Result<()> {
+        let data = ser::to_bytes::<_, NetworkEndian>(&ChangeCipherSpec::ChangeCipherSpec)?;
+        write_record(
+            &mut self.stream,
+            &TLSCiphertext::new(ContentType::ChangeCipherSpec, data),
+        )
+    }
+}
+
+pub const MAX_PLAINTEXT_LENGTH: usize = 1 << 14;
+const MAX_CIPHERTEXT_LENGTH: usize = MAX_PLAINTEXT_LENGTH + 256;
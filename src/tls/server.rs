@@ -0,0 +1,1745 @@
+use super::crypto::{decrypt_pre_master_secret, verify_signature, EphemeralSecret, SigningKey};
+use super::{
+    certificate_verify_message, constant_time_eq, finished_verify_data, is_pkcs1_scheme,
+    master_secret, psk_binder, resumption_psk, traffic_protection, unix_time_millis, verify_data,
+    AlertDescription, AntiReplay, ApplicationMessage, Certificate, CertificateRequest,
+    CertificateVerify, CertifiedKey, CipherSuite, ClientCertificateType, ClientCertificateTypes,
+    ClientHello, ClientHelloInfo, CompressionMethod, ConnectionCore, DistinguishedNames,
+    EarlyDataStatus, Error, Extension, ExtensionData, ExtensionType, Extensions, Finished,
+    Handshake, HandshakeBody, KeyBlock, KeySchedule, KeyShareEntry, Message, NamedGroup,
+    NewSessionTicket, Opaque, ProtocolVersion, PskKeyExchangeMode, Random, RecordProtection,
+    ResolvesServerCert, ResumptionEvent, ResumptionMetrics, RootCertStore, ServerEcdhParams,
+    ServerHello, ServerKeyExchange, ServerSessionCache, ServerSessionValue, SignatureScheme,
+    SignatureSchemeList, StaticServerCert, StoresServerSessions, TicketKeys, Transcript,
+    DEFAULT_SESSION_LIFETIME,
+};
+
+use anyhow::{bail, Result};
+use ser::NetworkEndian;
+use std::io::{Read, Write};
+use std::sync::Arc;
+use x509_parser::parse_x509_certificate;
+
+// RFC 8446 4.6.1. チケットの有効期間は最大7日
+const MAX_TICKET_LIFETIME: u32 = 604800;
+// 拒否した 0-RTT は max_early_data_size にレコードのオーバーヘッド分を足した長さまで読み飛ばす
+// (0-RTT を受け付けない設定でも、以前のチケットで送られてきたものは読み飛ばす)
+const REJECTED_EARLY_DATA_ALLOWANCE: usize = 1 << 16;
+// CertificateRequest で示す、クライアントの署名として検証できる方式
+// TLS 1.3 でも証明書の署名のために PKCS#1 v1.5 を含める (RFC 8446 4.2.3)
+const CLIENT_SIGNATURE_SCHEMES: [SignatureScheme; 8] = [
+    SignatureScheme::ecdsa_secp256r1_sha256,
+    SignatureScheme::ecdsa_secp384r1_sha384,
+    SignatureScheme::rsa_pss_rsae_sha256,
+    SignatureScheme::rsa_pss_rsae_sha384,
+    SignatureScheme::rsa_pss_rsae_sha512,
+    SignatureScheme::rsa_pkcs1_sha256,
+    SignatureScheme::rsa_pkcs1_sha384,
+    SignatureScheme::rsa_pkcs1_sha512,
+];
+
+// クライアント証明書を要求するか
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ClientAuthMode {
+    None,
+    // 要求するが、送られてこなくてもハンドシェイクを続ける (送られてきた場合は検証する)
+    Optional,
+    Required,
+}
+
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    // 優先度の高い順。クライアントの順序ではなくサーバーの順序で選ぶ
+    pub versions: Vec<ProtocolVersion>,
+    pub cipher_suites: Vec<CipherSuite>,
+    pub groups: Vec<NamedGroup>,
+    // ClientHello の server_name などから証明書を選ぶ
+    pub cert_resolver: Arc<dyn ResolvesServerCert>,
+    // TLS 1.2 のセッション ID と、ticket_keys が無い場合の TLS 1.3 のチケットで再開するセッション
+    pub session_store: Option<Arc<dyn StoresServerSessions>>,
+    // TLS 1.2 のセッションチケットと TLS 1.3 のチケットにセッションを暗号化して入れる
+    pub ticket_keys: Option<Arc<TicketKeys>>,
+    // 再開できる期間 (秒)。ticket_keys の鍵の更新間隔より長くはしない
+    pub session_lifetime: u32,
+    // TLS 1.3 の 0-RTT で受け取るデータの上限。0 なら 0-RTT は受け付けない
+    pub max_early_data_size: u32,
+    pub anti_replay: Arc<AntiReplay>,
+    pub resumption_metrics: Arc<ResumptionMetrics>,
+    pub client_auth: ClientAuthMode,
+    // クライアント証明書の検証に使う CA。subject は CertificateRequest の certificate_authorities にも入れる
+    pub client_roots: RootCertStore,
+}
+
+impl ServerConfig {
+    // 常に同じ証明書を使う
+    pub fn new(certified_key: CertifiedKey) -> Self {
+        ServerConfig::with_cert_resolver(Arc::new(StaticServerCert::new(certified_key)))
+    }
+
+    pub fn with_cert_resolver(cert_resolver: Arc<dyn ResolvesServerCert>) -> Self {
+        ServerConfig {
+            versions: vec![ProtocolVersion::TLSv1_3, ProtocolVersion::TLSv1_2],
+            cipher_suites: vec![
+                CipherSuite::TLS_AES_128_GCM_SHA256,
+                CipherSuite::TLS_AES_256_GCM_SHA384,
+                CipherSuite::TLS_AES_128_CCM_SHA256,
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+                CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_256_GCM_SHA384,
+                CipherSuite::TLS_ECDHE_RSA_WITH_AES_128_GCM_SHA256,
+                CipherSuite::TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384,
+                // 前方秘匿性が無いので最後に選ぶ
+                CipherSuite::TLS_RSA_WITH_AES_128_GCM_SHA256,
+                CipherSuite::TLS_RSA_WITH_AES_256_GCM_SHA384,
+            ],
+            groups: vec![
+                NamedGroup::x25519,
+                NamedGroup::secp256r1,
+                NamedGroup::secp384r1,
+            ],
+            cert_resolver,
+            session_store: Some(Arc::new(ServerSessionCache::default())),
+            ticket_keys: Some(Arc::new(TicketKeys::default())),
+            session_lifetime: DEFAULT_SESSION_LIFETIME,
+            max_early_data_size: 0,
+            anti_replay: Arc::new(AntiReplay::default()),
+            resumption_metrics: Arc::new(ResumptionMetrics::new()),
+            client_auth: ClientAuthMode::None,
+            client_roots: RootCertStore::new(),
+        }
+    }
+
+    // certificates は CERTIFICATE を並べた PEM (先頭がエンドエンティティ)
+    // 鍵は PKCS#8 (PRIVATE KEY), PKCS#1 (RSA PRIVATE KEY), SEC1 (EC PRIVATE KEY) のいずれか
+    pub fn from_pem(certificates: &[u8], key: &[u8]) -> Result<Self> {
+        Ok(ServerConfig::new(CertifiedKey::from_pem(
+            certificates,
+            key,
+        )?))
+    }
+
+    pub fn from_der(certificates: Vec<Vec<u8>>, key: &[u8]) -> Result<Self> {
+        Ok(ServerConfig::new(CertifiedKey::from_der(
+            certificates,
+            key,
+        )?))
+    }
+
+    fn offers(&self, version: ProtocolVersion) -> bool {
+        self.versions.contains(&version)
+    }
+
+    // 設定されていても実装の無いスイートやグループは選ばない
+    fn select_cipher_suite(
+        &self,
+        client_hello: &ClientHello,
+        predicate: impl Fn(&CipherSuite) -> bool,
+    ) -> Option<CipherSuite> {
+        self.cipher_suites
+            .iter()
+            .filter(|suite| suite.aead_algorithm().is_some())
+            .filter(|suite| predicate(suite))
+            .find(|suite| client_hello.chipher_suites.data.contains(suite))
+            .copied()
+    }
+
+    fn supported_groups(&self) -> impl Iterator<Item = NamedGroup> + '_ {
+        self.groups
+            .iter()
+            .copied()
+            .filter(|group| EphemeralSecret::is_supported(*group))
+    }
+
+    fn session_lifetime(&self) -> u32 {
+        let lifetime = self.session_lifetime.min(MAX_TICKET_LIFETIME);
+        match &self.ticket_keys {
+            Some(ticket_keys) => lifetime.min(ticket_keys.rotation_interval().as_secs() as u32),
+            None => lifetime,
+        }
+    }
+
+    // 1つ前の鍵で暗号化されていた場合は true も返す
+    fn decrypt_ticket(&self, ticket: &[u8]) -> Option<(ServerSessionValue, bool)> {
+        let (plaintext, renew) = self.ticket_keys.as_ref()?.decrypt(ticket)?;
+        let session = ServerSessionValue::from_bytes(&plaintext).ok()?;
+        Some((session, renew))
+    }
+
+    // 認証済みのクライアントに限る設定なら、クライアント証明書の無いセッションは再開しない
+    fn accepts_resumption(&self, session: &ServerSessionValue) -> bool {
+        self.client_auth != ClientAuthMode::Required || !session.peer_certificates.is_empty()
+    }
+
+    fn certificate_authorities(&self) -> DistinguishedNames {
+        DistinguishedNames::new(
+            self.client_roots
+                .subjects()
+                .into_iter()
+                .map(Opaque::<u16>::new)
+                .collect(),
+        )
+    }
+
+    // ticket_keys が無ければ session_store に保存し、そのキーをチケットにする
+    fn issue_ticket(&self, session: &ServerSessionValue) -> Option<Vec<u8>> {
+        if let Some(ticket_keys) = &self.ticket_keys {
+            return Some(ticket_keys.encrypt(&session.to_bytes()));
+        }
+        let key = rand::random::<[u8; 32]>().to_vec();
+        self.session_store
+            .as_ref()?
+            .put(key.clone(), session.clone());
+        Some(key)
+    }
+}
+
+// 0-RTT の判断に使うので、選んだ PSK の identity の情報も持っておく
+struct AcceptedPsk {
+    index: u16,
+    session: ServerSessionValue,
+    obfuscated_ticket_age: u32,
+    binder: Vec<u8>,
+}
+
+pub struct ServerConnection<S> {
+    config: Arc<ServerConfig>,
+    core: ConnectionCore<S>,
+    server_name: Option<String>,
+    // 検証したクライアント証明書のチェーン (先頭がエンドエンティティ)
+    peer_certificates: Vec<Vec<u8>>,
+    resumed: bool,
+    early_data_status: EarlyDataStatus,
+    early_data: Vec<u8>,
+    change_cipher_spec_sent: bool,
+}
+
+impl<S: Read + Write> ServerConnection<S> {
+    // ハンドシェイクが完了した状態で返す
+    pub fn new(config: Arc<ServerConfig>, stream: S) -> Result<Self> {
+        let mut connection = ServerConnection {
+            config,
+            core: ConnectionCore::new(stream),
+            server_name: None,
+            peer_certificates: vec![],
+            resumed: false,
+            early_data_status: EarlyDataStatus::NotSent,
+            early_data: vec![],
+            change_cipher_spec_sent: false,
+        };
+
+        if let Err(e) = connection.handshake() {
+            connection.core.abort(&e);
+            return Err(e);
+        }
+        Ok(connection)
+    }
+
+    pub fn protocol_version(&self) -> ProtocolVersion {
+        self.core.version
+    }
+
+    pub fn cipher_suite(&self) -> CipherSuite {
+        self.core.cipher_suite
+    }
+
+    // クライアントが server_name で指定したホスト名
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    // 再開した場合は最初のハンドシェイクで検証したもの。送られていなければ空
+    pub fn peer_certificates(&self) -> &[Vec<u8>] {
+        &self.peer_certificates
+    }
+
+    pub fn is_resumed(&self) -> bool {
+        self.resumed
+    }
+
+    pub fn early_data_status(&self) -> EarlyDataStatus {
+        self.early_data_status
+    }
+
+    // 0-RTT で受け取ったデータ。再送されたものかもしれないので read とは分けて取り出す
+    pub fn take_early_data(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.early_data)
+    }
+
+    // TLS 1.3 では鍵ごとのレコード数の上限に達する前に自動で KeyUpdate する
+    pub fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.core.write(data)
+    }
+
+    // RFC 8446 4.6.3. 送信鍵を更新する。request_peer なら相手にも受信鍵の更新を要求する
+    pub fn update_keys(&mut self, request_peer: bool) -> Result<()> {
+        self.core.update_keys(request_peer)
+    }
+
+    // close_notify を受け取ったら None
+    pub fn read(&mut self) -> Result<Option<Vec<u8>>> {
+        let result = self.read_application_data();
+        if let Err(e) = &result {
+            self.core.abort(e);
+        }
+        result
+    }
+
+    pub fn close(&mut self) -> Result<()> {
+        self.core.close()
+    }
+
+    // KeyUpdate 以外の post-handshake メッセージは受け付けない
+    fn read_application_data(&mut self) -> Result<Option<Vec<u8>>> {
+        match self.core.read_application_message()? {
+            ApplicationMessage::Data(data) => Ok(Some(data)),
+            ApplicationMessage::CloseNotify => Ok(None),
+            ApplicationMessage::Handshake(_) => {
+                bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+            }
+        }
+    }
+
+    fn read_client_hello(&mut self) -> Result<(Vec<u8>, ClientHello)> {
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::ClientHello(client_hello) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        Ok((data, client_hello))
+    }
+
+    // クライアントが 32 bytes の session_id を送ってきたらミドルボックス互換モードとみなし、
+    // 最初のハンドシェイクメッセージの直後にダミーの ChangeCipherSpec を1回だけ送る (RFC 8446 D.4)
+    fn send_compatibility_change_cipher_spec(&mut self, client_hello: &ClientHello) -> Result<()> {
+        if client_hello.session_id.data.is_empty() || self.change_cipher_spec_sent {
+            return Ok(());
+        }
+        self.change_cipher_spec_sent = true;
+        self.core.record_layer.write_change_cipher_spec()
+    }
+
+    fn handshake(&mut self) -> Result<()> {
+        let config = self.config.clone();
+        let mut transcript = Transcript::new();
+
+        let (data, client_hello) = self.read_client_hello()?;
+        transcript.update(&data);
+
+        if !client_hello
+            .compression_methods
+            .data
+            .contains(&CompressionMethod::Null)
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        self.server_name = requested_server_name(&client_hello);
+
+        // TLS 1.3 は supported_versions でのみネゴシエーションされる
+        let version = match client_hello
+            .extensions
+            .get(ExtensionType::SupportedVersions)
+        {
+            Some(ExtensionData::SupportedVersions(versions)) => config
+                .versions
+                .iter()
+                .find(|version| versions.data.contains(version))
+                .copied(),
+            _ if client_hello.protocol_version == ProtocolVersion::TLSv1_2
+                && config.offers(ProtocolVersion::TLSv1_2) =>
+            {
+                Some(ProtocolVersion::TLSv1_2)
+            }
+            _ => None,
+        };
+        let Some(version) = version else {
+            bail!(Error::Alert(AlertDescription::ProtocolVersion))
+        };
+
+        // RFC 7507 3. より高いバージョンに対応しているのにフォールバックしてきた接続は拒否する
+        if client_hello
+            .chipher_suites
+            .data
+            .contains(&CipherSuite::TLS_FALLBACK_SCSV)
+            && config.versions.first() != Some(&version)
+        {
+            bail!(Error::Alert(AlertDescription::InappropriateFallback));
+        }
+
+        // TLS 1.3 では signature_algorithms は必須 (RFC 8446 9.2)
+        let signature_schemes = match client_hello
+            .extensions
+            .get(ExtensionType::SignatureAlgorithms)
+        {
+            Some(ExtensionData::SignatureAlgorithms(schemes)) => schemes.data.clone(),
+            _ if version == ProtocolVersion::TLSv1_3 => {
+                bail!(Error::Alert(AlertDescription::MissingExtension))
+            }
+            _ => vec![],
+        };
+        let alpn_protocols = match client_hello
+            .extensions
+            .get(ExtensionType::ApplicationLayerProtocolNegotiation)
+        {
+            Some(ExtensionData::ApplicationLayerProtocolNegotiation(protocols)) => protocols
+                .data
+                .iter()
+                .map(|protocol| protocol.data.clone())
+                .collect(),
+            _ => vec![],
+        };
+        let client_hello_info = ClientHelloInfo {
+            version,
+            server_name: self.server_name.clone(),
+            alpn_protocols,
+            signature_schemes,
+        };
+        let Some(certified_key) = config.cert_resolver.resolve(&client_hello_info) else {
+            if self.server_name.is_some() {
+                bail!(Error::Alert(AlertDescription::UnrecognizedName))
+            }
+            bail!(Error::Alert(AlertDescription::HandshakeFailure))
+        };
+        // signature_algorithms が無い TLS 1.2 では SHA-1 とみなされるが、SHA-1 では署名しない (RFC 5246 7.4.1.4.1)
+        // TLS 1.2 の静的 RSA では署名しないので、署名方式が無くても続ける
+        let scheme = certified_key.choose_scheme(version, &client_hello_info.signature_schemes);
+
+        self.core.version = version;
+        match (version, scheme) {
+            (ProtocolVersion::TLSv1_3, Some(scheme)) => {
+                self.handshake_tls13(transcript, data, client_hello, &certified_key, scheme)
+            }
+            (ProtocolVersion::TLSv1_3, None) => {
+                bail!(Error::Alert(AlertDescription::HandshakeFailure))
+            }
+            _ => self.handshake_tls12(transcript, client_hello, &certified_key, scheme),
+        }
+    }
+
+    // TLS 1.3 では certificate_request_context は空にする (ハンドシェイク後の認証には対応しない)
+    fn send_certificate_request(&mut self, transcript: &mut Transcript) -> Result<()> {
+        let signature_algorithms = SignatureSchemeList::new(CLIENT_SIGNATURE_SCHEMES.to_vec());
+        let certificate_authorities = self.config.certificate_authorities();
+        let certificate_request = if self.core.version == ProtocolVersion::TLSv1_3 {
+            let mut extensions = vec![Extension::new(ExtensionData::SignatureAlgorithms(
+                signature_algorithms,
+            ))];
+            if !certificate_authorities.data.is_empty() {
+                extensions.push(Extension::new(ExtensionData::CertificateAuthorities(
+                    certificate_authorities,
+                )));
+            }
+            CertificateRequest {
+                certificate_request_context: Some(Opaque::<u8>::new(vec![])),
+                certificate_types: None,
+                supported_signature_algorithms: None,
+                certificate_authorities: None,
+                extensions: Some(Extensions::new(extensions)),
+            }
+        } else {
+            CertificateRequest {
+                certificate_request_context: None,
+                certificate_types: Some(ClientCertificateTypes::new(vec![
+                    ClientCertificateType::rsa_sign,
+                    ClientCertificateType::ecdsa_sign,
+                ])),
+                supported_signature_algorithms: Some(signature_algorithms),
+                certificate_authorities: Some(certificate_authorities),
+                extensions: None,
+            }
+        };
+        self.core.send_handshake(
+            transcript,
+            HandshakeBody::CertificateRequest(certificate_request),
+        )
+    }
+
+    // 空の Certificate は、必須なら TLS 1.3 では certificate_required、TLS 1.2 では handshake_failure
+    // 証明書があれば CertificateVerify が続くので true を返す
+    fn read_client_certificate(&mut self, transcript: &mut Transcript) -> Result<bool> {
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::Certificate(certificate) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        if certificate
+            .certificate_request_context
+            .as_ref()
+            .is_some_and(|context| !context.data.is_empty())
+        {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        transcript.update(&data);
+
+        let certificates: Vec<Vec<u8>> = certificate
+            .certificates()
+            .into_iter()
+            .map(|certificate| certificate.to_vec())
+            .collect();
+        if certificates.is_empty() {
+            return match self.config.client_auth {
+                ClientAuthMode::Required if self.core.version == ProtocolVersion::TLSv1_3 => {
+                    bail!(Error::Alert(AlertDescription::CertificateRequired))
+                }
+                ClientAuthMode::Required => bail!(Error::Alert(AlertDescription::HandshakeFailure)),
+                _ => Ok(false),
+            };
+        }
+        self.config
+            .client_roots
+            .verify_client_chain(&certificates)?;
+        self.peer_certificates = certificates;
+        Ok(true)
+    }
+
+    // TLS 1.3 では Transcript-Hash から作った内容に、TLS 1.2 ではここまでのハンドシェイクメッセージそのものに署名する
+    fn read_client_certificate_verify(&mut self, transcript: &mut Transcript) -> Result<()> {
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::CertificateVerify(certificate_verify) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let scheme = certificate_verify.algorithm;
+        let tls13 = self.core.version == ProtocolVersion::TLSv1_3;
+        if !CLIENT_SIGNATURE_SCHEMES.contains(&scheme) || tls13 && is_pkcs1_scheme(scheme) {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+        let message = if tls13 {
+            certificate_verify_message(
+                b"TLS 1.3, client CertificateVerify",
+                &transcript.hash(self.core.cipher_suite.hash_algorithm()),
+            )
+        } else {
+            transcript.messages().to_vec()
+        };
+        self.verify_client_signature(scheme, &message, &certificate_verify.signature.data)?;
+        transcript.update(&data);
+        Ok(())
+    }
+
+    fn verify_client_signature(
+        &self,
+        scheme: SignatureScheme,
+        message: &[u8],
+        signature: &[u8],
+    ) -> Result<()> {
+        let Some(certificate) = self.peer_certificates.first() else {
+            bail!(Error::Alert(AlertDescription::CertificateRequired))
+        };
+        let Ok((_, certificate)) = parse_x509_certificate(certificate) else {
+            bail!(Error::Alert(AlertDescription::BadCertificate))
+        };
+        verify_signature(scheme, certificate.public_key().raw, message, signature)
+    }
+
+    //=========================================================================
+    // TLS 1.3
+    //=========================================================================
+
+    fn handshake_tls13(
+        &mut self,
+        mut transcript: Transcript,
+        mut client_hello_data: Vec<u8>,
+        mut client_hello: ClientHello,
+        certified_key: &CertifiedKey,
+        scheme: SignatureScheme,
+    ) -> Result<()> {
+        let config = self.config.clone();
+        let metrics = &config.resumption_metrics;
+
+        let Some(cipher_suite) =
+            config.select_cipher_suite(&client_hello, |suite| suite.is_tls13())
+        else {
+            bail!(Error::Alert(AlertDescription::HandshakeFailure))
+        };
+        let hash = cipher_suite.hash_algorithm();
+        let aead = cipher_suite.aead_algorithm().unwrap();
+        self.core.cipher_suite = cipher_suite;
+
+        // binder は ClientHello の直前までの Transcript に続けて計算する
+        let mut transcript_before_client_hello = Transcript::new();
+
+        // 送られてきた key_share の中からサーバーの優先順で選び、無ければ HelloRetryRequest で要求する
+        let (group, peer_public_key) = match select_key_share(&config, &client_hello)? {
+            Some(key_share) => key_share,
+            None => {
+                let Some(ExtensionData::SupportedGroups(groups)) =
+                    client_hello.extensions.get(ExtensionType::SupportedGroups)
+                else {
+                    bail!(Error::Alert(AlertDescription::MissingExtension))
+                };
+                let Some(group) = config
+                    .supported_groups()
+                    .find(|group| groups.data.contains(group))
+                else {
+                    bail!(Error::Alert(AlertDescription::HandshakeFailure))
+                };
+                self.send_hello_retry_request(&mut transcript, &client_hello, group)?;
+                if client_hello.extensions.contains(ExtensionType::EarlyData) {
+                    self.reject_early_data();
+                }
+                transcript_before_client_hello = transcript.clone();
+
+                let (data, retried_client_hello) = self.read_client_hello()?;
+                // ClientHello2 は key_share 以外を変えてはならず、early_data も付けられない
+                if retried_client_hello.session_id.data != client_hello.session_id.data
+                    || !retried_client_hello
+                        .chipher_suites
+                        .data
+                        .contains(&cipher_suite)
+                    || retried_client_hello
+                        .extensions
+                        .contains(ExtensionType::EarlyData)
+                {
+                    bail!(Error::Alert(AlertDescription::IllegalParameter));
+                }
+                transcript.update(&data);
+                client_hello_data = data;
+                client_hello = retried_client_hello;
+
+                match select_key_share(&config, &client_hello)? {
+                    Some(key_share) if key_share.0 == group => key_share,
+                    _ => bail!(Error::Alert(AlertDescription::IllegalParameter)),
+                }
+            }
+        };
+
+        let psk = self.accept_psk(
+            &transcript_before_client_hello,
+            &client_hello_data,
+            &client_hello,
+            cipher_suite,
+        )?;
+
+        // 0-RTT は最初の PSK をチケットと同じスイートで選び、再送でないと確認できた場合のみ受け付ける
+        let early_data_offered = client_hello.extensions.contains(ExtensionType::EarlyData);
+        let early_data_accepted = match &psk {
+            Some(psk)
+                if early_data_offered
+                    && psk.index == 0
+                    && config.max_early_data_size > 0
+                    && psk.session.max_early_data_size > 0
+                    && psk.session.cipher_suite == cipher_suite =>
+            {
+                let ticket_age = psk
+                    .obfuscated_ticket_age
+                    .wrapping_sub(psk.session.ticket_age_add);
+                let fresh =
+                    config
+                        .anti_replay
+                        .accept(ticket_age, psk.session.age_millis(), &psk.binder);
+                if !fresh {
+                    metrics.record(ResumptionEvent::ReplayDetected);
+                }
+                fresh
+            }
+            _ => false,
+        };
+        if early_data_accepted {
+            self.early_data_status = EarlyDataStatus::Accepted;
+            metrics.record(ResumptionEvent::EarlyDataAccepted);
+        } else if early_data_offered {
+            self.reject_early_data();
+        }
+
+        let mut key_schedule =
+            KeySchedule::new(hash, psk.as_ref().map(|psk| psk.session.secret.as_slice()));
+        // client_early_traffic_secret は ClientHello までの Transcript-Hash から作る
+        let client_early_traffic_secret = early_data_accepted
+            .then(|| key_schedule.derive_secret("c e traffic", &transcript.hash(hash)));
+
+        // ServerHello
+        let key_share = EphemeralSecret::generate(group)?;
+        let public_key = key_share.public_key();
+        let Ok(shared_secret) = key_share.agree(&peer_public_key) else {
+            bail!(Error::Alert(AlertDescription::IllegalParameter))
+        };
+        let mut extensions = vec![
+            Extension::new(ExtensionData::SelectedVersion(ProtocolVersion::TLSv1_3)),
+            Extension::new(ExtensionData::KeyShareServerHello(KeyShareEntry {
+                group,
+                key_exchange: Opaque::<u16>::new(public_key),
+            })),
+        ];
+        if let Some(psk) = &psk {
+            extensions.push(Extension::new(ExtensionData::PreSharedKeyServerHello(
+                psk.index,
+            )));
+        }
+        let server_hello = ServerHello {
+            protocol_version: ProtocolVersion::TLSv1_2,
+            random: Random::generate(),
+            session_id: Opaque::<u8>::new(client_hello.session_id.data.clone()),
+            cipher_suite,
+            compression_method: CompressionMethod::Null,
+            extensions: Extensions::new(extensions),
+        };
+        self.core
+            .send_handshake(&mut transcript, HandshakeBody::ServerHello(server_hello))?;
+        self.send_compatibility_change_cipher_spec(&client_hello)?;
+
+        key_schedule.advance(Some(&shared_secret));
+        let client_handshake_traffic_secret =
+            key_schedule.derive_secret("c hs traffic", &transcript.hash(hash));
+        let server_handshake_traffic_secret =
+            key_schedule.derive_secret("s hs traffic", &transcript.hash(hash));
+        self.core
+            .record_layer
+            .set_write_protection(traffic_protection(
+                hash,
+                aead,
+                &server_handshake_traffic_secret,
+            ));
+        // 0-RTT を受け付けたら EndOfEarlyData までは client_early_traffic_secret で読む
+        self.core
+            .record_layer
+            .set_read_protection(traffic_protection(
+                hash,
+                aead,
+                client_early_traffic_secret
+                    .as_ref()
+                    .unwrap_or(&client_handshake_traffic_secret),
+            ))?;
+
+        // EncryptedExtensions
+        let mut extensions = vec![];
+        if self.server_name.is_some() {
+            extensions.push(Extension::new(ExtensionData::ServerNameAck(())));
+        }
+        if early_data_accepted {
+            extensions.push(Extension::new(ExtensionData::EarlyData(())));
+        }
+        self.core.send_handshake(
+            &mut transcript,
+            HandshakeBody::EncryptedExtensions(Extensions::new(extensions)),
+        )?;
+
+        // PSK で再開する場合は Certificate と CertificateVerify を送らない
+        let client_certificate_requested =
+            psk.is_none() && config.client_auth != ClientAuthMode::None;
+        if let Some(psk) = &psk {
+            self.resumed = true;
+            self.server_name = psk.session.server_name.clone();
+            self.peer_certificates = psk.session.peer_certificates.clone();
+        } else {
+            // CertificateRequest
+            if client_certificate_requested {
+                self.send_certificate_request(&mut transcript)?;
+            }
+
+            // Certificate
+            self.core.send_handshake(
+                &mut transcript,
+                HandshakeBody::Certificate(Certificate::new(
+                    ProtocolVersion::TLSv1_3,
+                    &[],
+                    &certified_key.certificates,
+                )),
+            )?;
+
+            // CertificateVerify
+            let message = certificate_verify_message(
+                b"TLS 1.3, server CertificateVerify",
+                &transcript.hash(hash),
+            );
+            let signature = certified_key.key.sign(scheme, &message)?;
+            self.core.send_handshake(
+                &mut transcript,
+                HandshakeBody::CertificateVerify(CertificateVerify {
+                    algorithm: scheme,
+                    signature: Opaque::<u16>::new(signature),
+                }),
+            )?;
+        }
+
+        // Finished
+        let verify_data = finished_verify_data(
+            hash,
+            &server_handshake_traffic_secret,
+            &transcript.hash(hash),
+        );
+        self.core.send_handshake(
+            &mut transcript,
+            HandshakeBody::Finished(Finished { verify_data }),
+        )?;
+
+        // application traffic secret はサーバーの Finished までの Transcript-Hash から作る
+        key_schedule.advance(None);
+        let client_application_traffic_secret =
+            key_schedule.derive_secret("c ap traffic", &transcript.hash(hash));
+        let server_application_traffic_secret =
+            key_schedule.derive_secret("s ap traffic", &transcript.hash(hash));
+        self.core
+            .record_layer
+            .set_write_protection(traffic_protection(
+                hash,
+                aead,
+                &server_application_traffic_secret,
+            ));
+
+        // 0-RTT のデータと EndOfEarlyData
+        if early_data_accepted {
+            self.read_early_data(&mut transcript)?;
+            self.core
+                .record_layer
+                .set_read_protection(traffic_protection(
+                    hash,
+                    aead,
+                    &client_handshake_traffic_secret,
+                ))?;
+        }
+
+        // クライアントの Certificate と CertificateVerify
+        if client_certificate_requested && self.read_client_certificate(&mut transcript)? {
+            self.read_client_certificate_verify(&mut transcript)?;
+        }
+
+        // クライアントの Finished
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::Finished(finished) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let expected = finished_verify_data(
+            hash,
+            &client_handshake_traffic_secret,
+            &transcript.hash(hash),
+        );
+        if !constant_time_eq(&finished.verify_data, &expected) {
+            bail!(Error::Alert(AlertDescription::DecryptError));
+        }
+        transcript.update(&data);
+        let resumption_master_secret =
+            key_schedule.derive_secret("res master", &transcript.hash(hash));
+
+        self.core
+            .record_layer
+            .set_read_protection(traffic_protection(
+                hash,
+                aead,
+                &client_application_traffic_secret,
+            ))?;
+        self.core.read_traffic_secret = client_application_traffic_secret;
+        self.core.write_traffic_secret = server_application_traffic_secret;
+
+        if !self.resumed {
+            metrics.record(ResumptionEvent::FullHandshake);
+        }
+        self.send_new_session_ticket(&client_hello, &resumption_master_secret)
+    }
+
+    // RFC 8446 4.1.4. ClientHello1 は message_hash に置き換える
+    fn send_hello_retry_request(
+        &mut self,
+        transcript: &mut Transcript,
+        client_hello: &ClientHello,
+        group: NamedGroup,
+    ) -> Result<()> {
+        transcript.replace_with_message_hash(self.core.cipher_suite.hash_algorithm());
+
+        let retry_request = ServerHello {
+            protocol_version: ProtocolVersion::TLSv1_2,
+            random: Random::hello_retry_request(),
+            session_id: Opaque::<u8>::new(client_hello.session_id.data.clone()),
+            cipher_suite: self.core.cipher_suite,
+            compression_method: CompressionMethod::Null,
+            extensions: Extensions::new(vec![
+                Extension::new(ExtensionData::SelectedVersion(ProtocolVersion::TLSv1_3)),
+                Extension::new(ExtensionData::KeyShareHelloRetryRequest(group)),
+            ]),
+        };
+        self.core
+            .send_handshake(transcript, HandshakeBody::ServerHello(retry_request))?;
+        self.send_compatibility_change_cipher_spec(client_hello)
+    }
+
+    // RFC 8446 4.2.11. 使える PSK のうち最初のものを選ぶ。選んだ PSK の binder が合わなければ中断する
+    // psk_dhe_ke のみ対応し、チケットのスイートはハッシュ関数が同じであればよい
+    fn accept_psk(
+        &self,
+        transcript: &Transcript,
+        client_hello_data: &[u8],
+        client_hello: &ClientHello,
+        cipher_suite: CipherSuite,
+    ) -> Result<Option<AcceptedPsk>> {
+        let Some(ExtensionData::PreSharedKeyClientHello(offered_psks)) =
+            client_hello.extensions.get(ExtensionType::PreSharedKey)
+        else {
+            return Ok(None);
+        };
+        match client_hello
+            .extensions
+            .get(ExtensionType::PskKeyExchangeModes)
+        {
+            Some(ExtensionData::PskKeyExchangeModes(modes))
+                if modes.data.contains(&PskKeyExchangeMode::psk_dhe_ke) => {}
+            Some(_) => return Ok(None),
+            None => bail!(Error::Alert(AlertDescription::MissingExtension)),
+        }
+        if offered_psks.identities.data.len() != offered_psks.binders.data.len() {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+
+        let config = &self.config;
+        let hash = cipher_suite.hash_algorithm();
+        for (index, (identity, binder)) in offered_psks
+            .identities
+            .data
+            .iter()
+            .zip(&offered_psks.binders.data)
+            .enumerate()
+        {
+            let ticket = &identity.identity.data;
+            // チケットを暗号化していなければ session_store から取り出す (同じチケットは1回しか使えない)
+            let session = match config.decrypt_ticket(ticket) {
+                Some((session, _)) => Some(session),
+                None => config
+                    .session_store
+                    .as_ref()
+                    .and_then(|store| store.take(ticket)),
+            };
+            let Some(session) = session.filter(|session| {
+                session.version == ProtocolVersion::TLSv1_3
+                    && !session.is_expired()
+                    && session.server_name == self.server_name
+                    && session.cipher_suite.hash_algorithm() == hash
+                    && config.accepts_resumption(session)
+            }) else {
+                continue;
+            };
+
+            let mut binder_transcript = transcript.clone();
+            binder_transcript.update(
+                &client_hello_data[..client_hello_data.len() - offered_psks.binders_size()],
+            );
+            let expected = psk_binder(hash, &session.secret, &binder_transcript.hash(hash));
+            if !constant_time_eq(&binder.data, &expected) {
+                bail!(Error::Alert(AlertDescription::DecryptError));
+            }
+
+            config.resumption_metrics.record(ResumptionEvent::PskHit);
+            return Ok(Some(AcceptedPsk {
+                index: index as u16,
+                session,
+                obfuscated_ticket_age: identity.obfuscated_ticket_age,
+                binder: binder.data.clone(),
+            }));
+        }
+        config.resumption_metrics.record(ResumptionEvent::PskMiss);
+        Ok(None)
+    }
+
+    // RFC 8446 4.2.10. 拒否した 0-RTT のデータは読み飛ばす
+    fn reject_early_data(&mut self) {
+        self.early_data_status = EarlyDataStatus::Rejected;
+        self.config
+            .resumption_metrics
+            .record(ResumptionEvent::EarlyDataRejected);
+        self.core.record_layer.skip_early_data(
+            self.config.max_early_data_size as usize + REJECTED_EARLY_DATA_ALLOWANCE,
+        );
+    }
+
+    // EndOfEarlyData までの application_data が 0-RTT のデータ
+    fn read_early_data(&mut self, transcript: &mut Transcript) -> Result<()> {
+        loop {
+            match self.core.record_layer.read_message()? {
+                Message::ApplicationData(data) => {
+                    if self.early_data.len() + data.len() > self.config.max_early_data_size as usize
+                    {
+                        bail!(Error::Alert(AlertDescription::UnexpectedMessage));
+                    }
+                    self.early_data.extend(data);
+                }
+                Message::Handshake(data) => {
+                    let Ok(Handshake {
+                        body: HandshakeBody::EndOfEarlyData(_),
+                        ..
+                    }) = Handshake::from_bytes(&data, self.core.version)
+                    else {
+                        bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+                    };
+                    transcript.update(&data);
+                    return Ok(());
+                }
+                Message::ChangeCipherSpec => continue,
+                Message::Alert(alert) => bail!(Error::ReceivedAlert(alert.description)),
+            }
+        }
+    }
+
+    // RFC 8446 4.6.1. psk_dhe_ke に対応しているクライアントにだけチケットを1枚送る
+    fn send_new_session_ticket(
+        &mut self,
+        client_hello: &ClientHello,
+        resumption_master_secret: &[u8],
+    ) -> Result<()> {
+        match client_hello
+            .extensions
+            .get(ExtensionType::PskKeyExchangeModes)
+        {
+            Some(ExtensionData::PskKeyExchangeModes(modes))
+                if modes.data.contains(&PskKeyExchangeMode::psk_dhe_ke) => {}
+            _ => return Ok(()),
+        }
+        let config = self.config.clone();
+        let hash = self.core.cipher_suite.hash_algorithm();
+
+        // 1枚しか送らないので ticket_nonce は固定でよい
+        let ticket_nonce = vec![0];
+        let session = ServerSessionValue {
+            version: ProtocolVersion::TLSv1_3,
+            cipher_suite: self.core.cipher_suite,
+            secret: resumption_psk(hash, resumption_master_secret, &ticket_nonce),
+            server_name: self.server_name.clone(),
+            peer_certificates: self.peer_certificates.clone(),
+            created_at: unix_time_millis(),
+            lifetime: config.session_lifetime(),
+            ticket_age_add: rand::random(),
+            max_early_data_size: config.max_early_data_size,
+        };
+        let Some(ticket) = config.issue_ticket(&session) else {
+            return Ok(());
+        };
+
+        let mut extensions = vec![];
+        if session.max_early_data_size > 0 {
+            extensions.push(Extension::new(ExtensionData::MaxEarlyDataSize(
+                session.max_early_data_size,
+            )));
+        }
+        let data = Handshake::new(HandshakeBody::NewSessionTicket(NewSessionTicket {
+            ticket_lifetime: session.lifetime,
+            ticket_age_add: Some(session.ticket_age_add),
+            ticket_nonce: Some(Opaque::<u8>::new(ticket_nonce)),
+            ticket: Opaque::<u16>::new(ticket),
+            extensions: Some(Extensions::new(extensions)),
+        }))
+        .to_bytes::<NetworkEndian>();
+        self.core.record_layer.write_handshake(&data)
+    }
+
+    //=========================================================================
+    // TLS 1.2
+    //=========================================================================
+
+    // 鍵交換は ECDHE か静的 RSA
+    fn handshake_tls12(
+        &mut self,
+        mut transcript: Transcript,
+        client_hello: ClientHello,
+        certified_key: &CertifiedKey,
+        scheme: Option<SignatureScheme>,
+    ) -> Result<()> {
+        let config = self.config.clone();
+
+        let secure_renegotiation = secure_renegotiation(&client_hello)?;
+        if let Some((session, renew_ticket)) = self.find_tls12_session(&client_hello) {
+            return self.resume_tls12(
+                transcript,
+                client_hello,
+                session,
+                renew_ticket,
+                secure_renegotiation,
+            );
+        }
+
+        // ECDHE では ServerKeyExchange に署名する
+        let Some(cipher_suite) = config.select_cipher_suite(&client_hello, |suite| {
+            certified_key.supports_cipher_suite(*suite)
+                && (suite.is_ecdhe_key_exchange() && scheme.is_some()
+                    || suite.is_rsa_key_exchange())
+        }) else {
+            bail!(Error::Alert(AlertDescription::HandshakeFailure))
+        };
+        let hash = cipher_suite.hash_algorithm();
+        let aead = cipher_suite.aead_algorithm().unwrap();
+        self.core.cipher_suite = cipher_suite;
+
+        let ecdhe = match scheme {
+            Some(scheme) if cipher_suite.is_ecdhe_key_exchange() => Some((
+                select_ecdhe_group(&config, &client_hello, certified_key)?,
+                scheme,
+            )),
+            _ => None,
+        };
+
+        // クライアントが session_ticket を送ってきたらチケットを、そうでなければセッション ID を発行する
+        let issue_ticket = client_hello
+            .extensions
+            .contains(ExtensionType::SessionTicket)
+            && config.ticket_keys.is_some();
+        let session_id = if !issue_ticket && config.session_store.is_some() {
+            rand::random::<[u8; 32]>().to_vec()
+        } else {
+            vec![]
+        };
+
+        // ServerHello
+        let client_random = client_hello.random;
+        let server_random = tls12_server_random(&config);
+        let server_hello = ServerHello {
+            protocol_version: ProtocolVersion::TLSv1_2,
+            random: server_random,
+            session_id: Opaque::<u8>::new(session_id.clone()),
+            cipher_suite,
+            compression_method: CompressionMethod::Null,
+            extensions: Extensions::new(server_hello_extensions_tls12(
+                secure_renegotiation,
+                issue_ticket,
+            )),
+        };
+        self.core
+            .send_handshake(&mut transcript, HandshakeBody::ServerHello(server_hello))?;
+
+        // Certificate
+        self.core.send_handshake(
+            &mut transcript,
+            HandshakeBody::Certificate(Certificate::new(
+                ProtocolVersion::TLSv1_2,
+                &[],
+                &certified_key.certificates,
+            )),
+        )?;
+
+        // ServerKeyExchange (静的 RSA では送らない)
+        // 署名対象は client_random + server_random + ServerECDHParams
+        let key_share = match ecdhe {
+            Some((group, scheme)) => {
+                let key_share = EphemeralSecret::generate(group)?;
+                let params = ServerEcdhParams::new(group, key_share.public_key());
+                let mut message = client_random.to_bytes();
+                message.extend_from_slice(&server_random.to_bytes());
+                message.extend_from_slice(&params.to_bytes());
+                let signature = certified_key.key.sign(scheme, &message)?;
+                self.core.send_handshake(
+                    &mut transcript,
+                    HandshakeBody::ServerKeyExchange(ServerKeyExchange {
+                        params,
+                        algorithm: scheme,
+                        signature: Opaque::<u16>::new(signature),
+                    }),
+                )?;
+                Some(key_share)
+            }
+            None => None,
+        };
+
+        // CertificateRequest
+        let client_certificate_requested = config.client_auth != ClientAuthMode::None;
+        if client_certificate_requested {
+            self.send_certificate_request(&mut transcript)?;
+        }
+
+        // ServerHelloDone
+        self.core
+            .send_handshake(&mut transcript, HandshakeBody::ServerHelloDone(()))?;
+
+        // クライアントの Certificate
+        let client_certificate_verify =
+            client_certificate_requested && self.read_client_certificate(&mut transcript)?;
+
+        // ClientKeyExchange
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::ClientKeyExchange(client_key_exchange) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let pre_master_secret = match (key_share, &certified_key.key) {
+            (Some(key_share), _) => {
+                let Some(peer_public_key) = client_key_exchange.ecdh_public_key() else {
+                    bail!(Error::Alert(AlertDescription::DecodeError))
+                };
+                let Ok(pre_master_secret) = key_share.agree(peer_public_key) else {
+                    bail!(Error::Alert(AlertDescription::IllegalParameter))
+                };
+                pre_master_secret
+            }
+            // 復号に失敗してもここではエラーにせず、Finished の検証で失敗させる
+            (None, SigningKey::Rsa(key)) => {
+                let Some(encrypted) = client_key_exchange.encrypted_pre_master_secret() else {
+                    bail!(Error::Alert(AlertDescription::DecodeError))
+                };
+                decrypt_pre_master_secret(key, encrypted, client_hello.protocol_version)
+            }
+            (None, _) => bail!(Error::Alert(AlertDescription::InternalError)),
+        };
+        transcript.update(&data);
+
+        // CertificateVerify
+        if client_certificate_verify {
+            self.read_client_certificate_verify(&mut transcript)?;
+        }
+
+        let master_secret = master_secret(hash, &pre_master_secret, &client_random, &server_random);
+        let key_block = KeyBlock::new(hash, aead, &master_secret, &client_random, &server_random);
+
+        self.read_finished_tls12(&mut transcript, &master_secret, &key_block)?;
+
+        // NewSessionTicket は ChangeCipherSpec の前に送る (RFC 5077 3.3)
+        let session = ServerSessionValue {
+            version: ProtocolVersion::TLSv1_2,
+            cipher_suite,
+            secret: master_secret.clone(),
+            server_name: self.server_name.clone(),
+            peer_certificates: self.peer_certificates.clone(),
+            created_at: unix_time_millis(),
+            lifetime: config.session_lifetime(),
+            ticket_age_add: 0,
+            max_early_data_size: 0,
+        };
+        if issue_ticket {
+            self.send_new_session_ticket_tls12(&mut transcript, &session)?;
+        } else if let (false, Some(session_store)) = (session_id.is_empty(), &config.session_store)
+        {
+            session_store.put(session_id, session);
+        }
+
+        self.send_finished_tls12(&mut transcript, &master_secret, &key_block)?;
+        config
+            .resumption_metrics
+            .record(ResumptionEvent::FullHandshake);
+        Ok(())
+    }
+
+    // RFC 5077 3.4. チケットが送られてきたらセッション ID では探さない
+    // 1つ前の鍵で暗号化されたチケットなら true も返し、チケットを発行し直す
+    fn find_tls12_session(&self, client_hello: &ClientHello) -> Option<(ServerSessionValue, bool)> {
+        let config = &self.config;
+        let metrics = &config.resumption_metrics;
+        let resumable = |session: &ServerSessionValue| {
+            session.version == ProtocolVersion::TLSv1_2
+                && !session.is_expired()
+                && session.server_name == self.server_name
+                && config.cipher_suites.contains(&session.cipher_suite)
+                && config.accepts_resumption(session)
+                && client_hello
+                    .chipher_suites
+                    .data
+                    .contains(&session.cipher_suite)
+        };
+
+        if let Some(ExtensionData::SessionTicket(ticket)) =
+            client_hello.extensions.get(ExtensionType::SessionTicket)
+        {
+            if !ticket.is_empty() {
+                let session = config
+                    .decrypt_ticket(ticket)
+                    .filter(|(session, _)| resumable(session));
+                metrics.record(match session {
+                    Some(_) => ResumptionEvent::TicketHit,
+                    None => ResumptionEvent::TicketMiss,
+                });
+                return session;
+            }
+        }
+
+        if client_hello.session_id.data.is_empty() {
+            return None;
+        }
+        let session = config
+            .session_store
+            .as_ref()
+            .and_then(|store| store.get(&client_hello.session_id.data))
+            .filter(resumable);
+        metrics.record(match session {
+            Some(_) => ResumptionEvent::SessionIdHit,
+            None => ResumptionEvent::SessionIdMiss,
+        });
+        session.map(|session| (session, false))
+    }
+
+    // 短縮ハンドシェイク (RFC 5246 7.3)
+    // ServerHello ではクライアントのセッション ID をそのまま返し、ChangeCipherSpec と Finished を先に送る
+    fn resume_tls12(
+        &mut self,
+        mut transcript: Transcript,
+        client_hello: ClientHello,
+        session: ServerSessionValue,
+        renew_ticket: bool,
+        secure_renegotiation: bool,
+    ) -> Result<()> {
+        let config = self.config.clone();
+        let cipher_suite = session.cipher_suite;
+        let hash = cipher_suite.hash_algorithm();
+        let aead = cipher_suite.aead_algorithm().unwrap();
+        self.core.cipher_suite = cipher_suite;
+
+        // ServerHello
+        let client_random = client_hello.random;
+        let server_random = tls12_server_random(&config);
+        let server_hello = ServerHello {
+            protocol_version: ProtocolVersion::TLSv1_2,
+            random: server_random,
+            session_id: Opaque::<u8>::new(client_hello.session_id.data.clone()),
+            cipher_suite,
+            compression_method: CompressionMethod::Null,
+            extensions: Extensions::new(server_hello_extensions_tls12(
+                secure_renegotiation,
+                renew_ticket,
+            )),
+        };
+        self.core
+            .send_handshake(&mut transcript, HandshakeBody::ServerHello(server_hello))?;
+
+        if renew_ticket {
+            self.send_new_session_ticket_tls12(&mut transcript, &session)?;
+        }
+
+        let key_block = KeyBlock::new(hash, aead, &session.secret, &client_random, &server_random);
+        self.send_finished_tls12(&mut transcript, &session.secret, &key_block)?;
+        self.read_finished_tls12(&mut transcript, &session.secret, &key_block)?;
+        self.resumed = true;
+        self.peer_certificates = session.peer_certificates;
+        Ok(())
+    }
+
+    // 再開時に発行し直す場合も、有効期間は最初のハンドシェイクから数える
+    fn send_new_session_ticket_tls12(
+        &mut self,
+        transcript: &mut Transcript,
+        session: &ServerSessionValue,
+    ) -> Result<()> {
+        let Some(ticket_keys) = self.config.ticket_keys.clone() else {
+            return Ok(());
+        };
+        let elapsed = (session.age_millis() / 1000) as u32;
+        self.core.send_handshake(
+            transcript,
+            HandshakeBody::NewSessionTicket(NewSessionTicket {
+                ticket_lifetime: session.lifetime.saturating_sub(elapsed),
+                ticket_age_add: None,
+                ticket_nonce: None,
+                ticket: Opaque::<u16>::new(ticket_keys.encrypt(&session.to_bytes())),
+                extensions: None,
+            }),
+        )
+    }
+
+    // クライアントの ChangeCipherSpec と Finished
+    fn read_finished_tls12(
+        &mut self,
+        transcript: &mut Transcript,
+        master_secret: &[u8],
+        key_block: &KeyBlock,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let aead = self.core.cipher_suite.aead_algorithm().unwrap();
+
+        match self.core.record_layer.read_message()? {
+            Message::ChangeCipherSpec => {}
+            Message::Alert(alert) => bail!(Error::ReceivedAlert(alert.description)),
+            _ => bail!(Error::Alert(AlertDescription::UnexpectedMessage)),
+        }
+        self.core
+            .record_layer
+            .set_read_protection(RecordProtection::new(
+                aead,
+                &key_block.client_write_key,
+                &key_block.client_write_iv,
+            ))?;
+
+        let (data, handshake) = self.core.read_handshake()?;
+        let HandshakeBody::Finished(finished) = handshake.body else {
+            bail!(Error::Alert(AlertDescription::UnexpectedMessage))
+        };
+        let expected = verify_data(
+            hash,
+            master_secret,
+            b"client finished",
+            &transcript.hash(hash),
+        );
+        if !constant_time_eq(&finished.verify_data, &expected) {
+            bail!(Error::Alert(AlertDescription::DecryptError));
+        }
+        transcript.update(&data);
+        Ok(())
+    }
+
+    // ChangeCipherSpec と Finished
+    fn send_finished_tls12(
+        &mut self,
+        transcript: &mut Transcript,
+        master_secret: &[u8],
+        key_block: &KeyBlock,
+    ) -> Result<()> {
+        let hash = self.core.cipher_suite.hash_algorithm();
+        let aead = self.core.cipher_suite.aead_algorithm().unwrap();
+
+        self.core.record_layer.write_change_cipher_spec()?;
+        self.core
+            .record_layer
+            .set_write_protection(RecordProtection::new(
+                aead,
+                &key_block.server_write_key,
+                &key_block.server_write_iv,
+            ));
+        let verify_data = verify_data(
+            hash,
+            master_secret,
+            b"server finished",
+            &transcript.hash(hash),
+        );
+        self.core.send_handshake(
+            transcript,
+            HandshakeBody::Finished(Finished { verify_data }),
+        )
+    }
+}
+
+// 再ネゴシエーションには対応しないが、安全な再ネゴシエーションに対応していることは示す (RFC 5746 3.6)
+fn secure_renegotiation(client_hello: &ClientHello) -> Result<bool> {
+    match client_hello
+        .extensions
+        .get(ExtensionType::RenegotiationInfo)
+    {
+        Some(ExtensionData::RenegotiationInfo(renegotiated_connection))
+            if !renegotiated_connection.data.is_empty() =>
+        {
+            bail!(Error::Alert(AlertDescription::HandshakeFailure))
+        }
+        Some(_) => Ok(true),
+        None => Ok(client_hello
+            .chipher_suites
+            .data
+            .contains(&CipherSuite::TLS_EMPTY_RENEGOTIATION_INFO_SCSV)),
+    }
+}
+
+// session_ticket は NewSessionTicket を送る場合のみ付ける (RFC 5077 3.2)
+fn server_hello_extensions_tls12(
+    secure_renegotiation: bool,
+    session_ticket: bool,
+) -> Vec<Extension> {
+    let mut extensions = vec![];
+    if secure_renegotiation {
+        extensions.push(Extension::new(ExtensionData::RenegotiationInfo(
+            Opaque::<u8>::new(vec![]),
+        )));
+    }
+    if session_ticket {
+        extensions.push(Extension::new(ExtensionData::SessionTicket(vec![])));
+    }
+    extensions
+}
+
+// TLS 1.3 にも対応している場合は random の末尾で downgrade を示す (RFC 8446 4.1.3)
+fn tls12_server_random(config: &ServerConfig) -> Random {
+    let mut random = Random::generate();
+    if config.offers(ProtocolVersion::TLSv1_3) {
+        random.set_downgrade_to_tls12();
+    }
+    random
+}
+
+// supported_groups が無ければどの曲線でも良い (RFC 8422 4)
+// ECDSA の証明書の曲線もクライアントが対応しているものでなければならない (RFC 8422 5.1)
+fn select_ecdhe_group(
+    config: &ServerConfig,
+    client_hello: &ClientHello,
+    certified_key: &CertifiedKey,
+) -> Result<NamedGroup> {
+    let group = match client_hello.extensions.get(ExtensionType::SupportedGroups) {
+        Some(ExtensionData::SupportedGroups(groups)) => {
+            if certified_key
+                .key
+                .named_group()
+                .is_some_and(|curve| !groups.data.contains(&curve))
+            {
+                bail!(Error::Alert(AlertDescription::HandshakeFailure));
+            }
+            config
+                .supported_groups()
+                .find(|group| groups.data.contains(group))
+        }
+        _ => config.supported_groups().next(),
+    };
+    let Some(group) = group else {
+        bail!(Error::Alert(AlertDescription::HandshakeFailure))
+    };
+    Ok(group)
+}
+
+fn requested_server_name(client_hello: &ClientHello) -> Option<String> {
+    let Some(ExtensionData::ServerName(names)) =
+        client_hello.extensions.get(ExtensionType::ServerName)
+    else {
+        return None;
+    };
+    names
+        .data
+        .first()
+        .and_then(|name| String::from_utf8(name.host_name.data.clone()).ok())
+}
+
+// 同じグループの key_share が複数あるのは不正 (RFC 8446 4.2.8)
+fn select_key_share(
+    config: &ServerConfig,
+    client_hello: &ClientHello,
+) -> Result<Option<(NamedGroup, Vec<u8>)>> {
+    let Some(ExtensionData::KeyShareClientHello(entries)) =
+        client_hello.extensions.get(ExtensionType::KeyShare)
+    else {
+        bail!(Error::Alert(AlertDescription::MissingExtension))
+    };
+    for (i, entry) in entries.data.iter().enumerate() {
+        if entries.data[..i].iter().any(|e| e.group == entry.group) {
+            bail!(Error::Alert(AlertDescription::IllegalParameter));
+        }
+    }
+    Ok(config.supported_groups().find_map(|group| {
+        entries
+            .data
+            .iter()
+            .find(|entry| entry.group == group)
+            .map(|entry| (group, entry.key_exchange.data.clone()))
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tls::test_util::{certified_key, roots, run};
+    use crate::tls::{ClientConfig, ClientConnection, StaticClientCert};
+
+    // 受け取るバイト列を決めておき、送ったものを記録するストリーム
+    struct MockStream {
+        input: std::io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug)]
+    struct NoCert;
+
+    impl ResolvesServerCert for NoCert {
+        fn resolve(&self, _: &ClientHelloInfo) -> Option<Arc<CertifiedKey>> {
+            None
+        }
+    }
+
+    // 最初のレコードで中止したときに送ったアラート
+    fn handshake_alert(input: &[u8]) -> Option<AlertDescription> {
+        let config = Arc::new(ServerConfig::with_cert_resolver(Arc::new(NoCert)));
+        let mut stream = MockStream {
+            input: std::io::Cursor::new(input.to_vec()),
+            output: vec![],
+        };
+        assert!(ServerConnection::new(config, &mut stream).is_err());
+
+        match stream.output.as_slice() {
+            [21, 3, 3, 0, 2, 2, description] => AlertDescription::try_from(*description).ok(),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn unknown_content_type() {
+        assert_eq!(
+            handshake_alert(&[0x18, 3, 3, 0, 1, 0]),
+            Some(AlertDescription::UnexpectedMessage)
+        );
+    }
+
+    // レコードのバージョンは見ないので、中身のアラートの誤りとして扱われる
+    #[test]
+    fn unknown_record_version() {
+        assert_eq!(
+            handshake_alert(&[21, 0x12, 0x34, 0, 2, 3, 0]),
+            Some(AlertDescription::IllegalParameter)
+        );
+    }
+
+    #[test]
+    fn unknown_alert_level() {
+        assert_eq!(
+            handshake_alert(&[21, 3, 3, 0, 2, 3, 0]),
+            Some(AlertDescription::IllegalParameter)
+        );
+    }
+
+    #[test]
+    fn unknown_alert_description() {
+        assert_eq!(
+            handshake_alert(&[21, 3, 3, 0, 2, 2, 0xff]),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    #[test]
+    fn truncated_alert() {
+        assert_eq!(
+            handshake_alert(&[21, 3, 3, 0, 1, 2]),
+            Some(AlertDescription::DecodeError)
+        );
+    }
+
+    fn client_config(version: ProtocolVersion) -> ClientConfig {
+        ClientConfig {
+            versions: vec![version],
+            roots: roots(),
+            ..ClientConfig::default()
+        }
+    }
+
+    #[test]
+    fn compatibility_change_cipher_spec() {
+        for middlebox_compatibility in [true, false] {
+            let client_config = ClientConfig {
+                middlebox_compatibility,
+                ..client_config(ProtocolVersion::TLSv1_3)
+            };
+            let server_config = Arc::new(ServerConfig::new(certified_key("server.pem")));
+            let (client, server) = run(
+                move |stream| {
+                    ClientConnection::new(Arc::new(client_config), "example.test", stream)
+                },
+                move |stream| {
+                    let connection = ServerConnection::new(server_config, stream)?;
+                    Ok(connection.change_cipher_spec_sent)
+                },
+            );
+            client.unwrap();
+            assert_eq!(server.unwrap(), middlebox_compatibility);
+        }
+    }
+
+    // ハンドシェイク後に CertificateRequest を送り、クライアントの応答を読む
+    fn post_handshake_auth(
+        client_config: ClientConfig,
+        context: &'static [u8],
+    ) -> (Result<Option<Vec<u8>>>, Result<Vec<Handshake>>) {
+        let server_config = Arc::new(ServerConfig::new(certified_key("server.pem")));
+        run(
+            move |stream| {
+                let mut client =
+                    ClientConnection::new(Arc::new(client_config), "example.test", stream)?;
+                client.read()
+            },
+            move |stream| {
+                let mut connection = ServerConnection::new(server_config, stream)?;
+                let signature_algorithms =
+                    SignatureSchemeList::new(CLIENT_SIGNATURE_SCHEMES.to_vec());
+                let certificate_request = CertificateRequest {
+                    certificate_request_context: Some(Opaque::<u8>::new(context.to_vec())),
+                    certificate_types: None,
+                    supported_signature_algorithms: None,
+                    certificate_authorities: None,
+                    extensions: Some(Extensions::new(vec![Extension::new(
+                        ExtensionData::SignatureAlgorithms(signature_algorithms),
+                    )])),
+                };
+                let data = Handshake::new(HandshakeBody::CertificateRequest(certificate_request))
+                    .to_bytes::<NetworkEndian>();
+                connection.core.record_layer.write_handshake(&data)?;
+
+                let mut response = vec![];
+                for _ in 0..3 {
+                    response.push(connection.core.read_handshake()?.1);
+                }
+                connection.write(b"authenticated")?;
+                Ok(response)
+            },
+        )
+    }
+
+    #[test]
+    fn post_handshake_certificate_request() {
+        let mut client_config = client_config(ProtocolVersion::TLSv1_3);
+        client_config.client_cert_resolver =
+            Some(Arc::new(StaticClientCert::new(certified_key("client.pem"))));
+        let (client, server) = post_handshake_auth(client_config, b"context");
+        assert_eq!(client.unwrap().as_deref(), Some(&b"authenticated"[..]));
+
+        let response = server.unwrap();
+        let [Handshake {
+            body: HandshakeBody::Certificate(certificate),
+            ..
+        }, Handshake {
+            body: HandshakeBody::CertificateVerify(certificate_verify),
+            ..
+        }, Handshake {
+            body: HandshakeBody::Finished(finished),
+            ..
+        }] = response.as_slice()
+        else {
+            panic!("unexpected response: {:?}", response)
+        };
+        // 応答には要求と同じ context が付く
+        assert_eq!(
+            certificate
+                .certificate_request_context
+                .as_ref()
+                .unwrap()
+                .data,
+            b"context"
+        );
+        assert_eq!(certificate.certificate_list.len(), 2);
+        assert_eq!(
+            certificate_verify.algorithm,
+            SignatureScheme::ecdsa_secp256r1_sha256
+        );
+        assert_eq!(finished.verify_data.len(), 32);
+    }
+
+    #[test]
+    fn post_handshake_certificate_request_rejected() {
+        // post_handshake_auth を送っていないクライアント
+        let (client, server) =
+            post_handshake_auth(client_config(ProtocolVersion::TLSv1_3), b"context");
+        assert!(matches!(
+            client.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::Alert(AlertDescription::UnexpectedMessage))
+        ));
+        assert!(matches!(
+            server.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::ReceivedAlert(AlertDescription::UnexpectedMessage))
+        ));
+
+        // ハンドシェイク後の要求の context は空であってはならない
+        let mut client_config = client_config(ProtocolVersion::TLSv1_3);
+        client_config.client_cert_resolver =
+            Some(Arc::new(StaticClientCert::new(certified_key("client.pem"))));
+        let (client, server) = post_handshake_auth(client_config, b"");
+        assert!(matches!(
+            client.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::Alert(AlertDescription::IllegalParameter))
+        ));
+        assert!(matches!(
+            server.unwrap_err().downcast_ref::<Error>(),
+            Some(Error::ReceivedAlert(AlertDescription::IllegalParameter))
+        ));
+    }
+
+    // 証明書が選べなければ、server_name があれば unrecognized_name、無ければ handshake_failure
+    #[test]
+    fn no_certificate_for_server_name() {
+        for (server_name, alert) in [
+            ("example.test", AlertDescription::UnrecognizedName),
+            ("127.0.0.1", AlertDescription::HandshakeFailure),
+        ] {
+            let client_config = Arc::new(client_config(ProtocolVersion::TLSv1_3));
+            let server_config = Arc::new(ServerConfig::with_cert_resolver(Arc::new(NoCert)));
+            let (client, server) = run(
+                move |stream| ClientConnection::new(client_config, server_name, stream).map(|_| ()),
+                move |stream| ServerConnection::new(server_config, stream).map(|_| ()),
+            );
+            assert!(matches!(
+                client.unwrap_err().downcast_ref::<Error>(),
+                Some(Error::ReceivedAlert(description)) if *description == alert
+            ));
+            assert!(matches!(
+                server.unwrap_err().downcast_ref::<Error>(),
+                Some(Error::Alert(description)) if *description == alert
+            ));
+        }
+    }
+}
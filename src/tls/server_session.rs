@@ -0,0 +1,743 @@
+use super::crypto::{AeadAlgorithm, AeadCipher};
+use super::{be_u32, take, u24, Buffer, CipherSuite, IResult, Opaque, ProtocolVersion, Vector};
+
+use anyhow::{anyhow, Result};
+use ser::NetworkEndian;
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// 再開用セッションの有効期間の既定値 (秒)
+pub const DEFAULT_SESSION_LIFETIME: u32 = 7200;
+const DEFAULT_SESSION_CACHE_SIZE: usize = 1024;
+const DEFAULT_TICKET_KEY_ROTATION: Duration = Duration::from_secs(12 * 60 * 60);
+// RFC 8446 8.3. クライアントとサーバーのチケットの年齢の差として許す範囲
+const DEFAULT_ANTI_REPLAY_WINDOW: Duration = Duration::from_secs(10);
+
+const TICKET_KEY_NAME_LENGTH: usize = 16;
+const TICKET_NONCE_LENGTH: usize = 12;
+
+pub fn unix_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|time| time.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+// サーバーが再開のために覚えておく情報。チケットにはこれを暗号化して入れる
+#[derive(Debug, Clone)]
+pub struct ServerSessionValue {
+    pub version: ProtocolVersion,
+    pub cipher_suite: CipherSuite,
+    // TLS 1.2 は master secret、TLS 1.3 はチケットごとの PSK
+    pub secret: Vec<u8>,
+    pub server_name: Option<String>,
+    // 再開時は Certificate が送られないので前回のものを引き継ぐ
+    pub peer_certificates: Vec<Vec<u8>>,
+    // UNIX 時間 (ミリ秒)。TLS 1.3 ではチケットの年齢の確認にも使う
+    pub created_at: u64,
+    // 秒
+    pub lifetime: u32,
+    pub ticket_age_add: u32,
+    // 0 なら 0-RTT は受け付けない
+    pub max_early_data_size: u32,
+}
+
+// チケットに入れる形式
+#[derive(Serialize)]
+struct EncodedSession {
+    version: ProtocolVersion,
+    cipher_suite: CipherSuite,
+    secret: Opaque<u8>,
+    server_name: Opaque<u16>,
+    peer_certificates: Vector<u24, Opaque<u24>>,
+    created_at: u64,
+    lifetime: u32,
+    ticket_age_add: u32,
+    max_early_data_size: u32,
+}
+
+impl ServerSessionValue {
+    pub fn age_millis(&self) -> u64 {
+        unix_time_millis().saturating_sub(self.created_at)
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.age_millis() >= self.lifetime as u64 * 1000
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let server_name = self.server_name.clone().unwrap_or_default();
+        let peer_certificates = self
+            .peer_certificates
+            .iter()
+            .map(|certificate| Opaque::<u24>::new(certificate.clone()))
+            .collect();
+        let encoded = EncodedSession {
+            version: self.version,
+            cipher_suite: self.cipher_suite,
+            secret: Opaque::<u8>::new(self.secret.clone()),
+            server_name: Opaque::<u16>::new(server_name.into_bytes()),
+            peer_certificates: Vector::<u24, Opaque<u24>>::new(peer_certificates),
+            created_at: self.created_at,
+            lifetime: self.lifetime,
+            ticket_age_add: self.ticket_age_add,
+            max_early_data_size: self.max_early_data_size,
+        };
+        ser::to_bytes::<_, NetworkEndian>(&encoded).unwrap()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self> {
+        let (input, value) = ServerSessionValue::deserialize(Buffer::new(data, data.len()))
+            .map_err(|_| anyhow!("failed to decode session"))?;
+        if input.length() != 0 {
+            return Err(anyhow!("failed to decode session"));
+        }
+        Ok(value)
+    }
+
+    fn deserialize(input: Buffer) -> IResult<Self> {
+        let (input, version) = ProtocolVersion::deserialize(input)?;
+        let (input, cipher_suite) = CipherSuite::deserialize(input)?;
+        let (input, secret) = Opaque::<u8>::deserialize(input)?;
+        let (input, server_name) = Opaque::<u16>::deserialize(input)?;
+        let server_name = match server_name.data.is_empty() {
+            true => None,
+            false => String::from_utf8(server_name.data).ok(),
+        };
+
+        let (input, length) = u24::deserialize(input)?;
+        let (input, data) = take(input, length.to_usize())?;
+        let mut data = Buffer::new(data, length.to_usize());
+        let mut peer_certificates = vec![];
+        while data.length() > 0 {
+            let (rest, certificate) = Opaque::<u24>::deserialize(data)?;
+            data = rest;
+            peer_certificates.push(certificate.data);
+        }
+
+        let (input, created_at_high) = be_u32(input)?;
+        let (input, created_at_low) = be_u32(input)?;
+        let (input, lifetime) = be_u32(input)?;
+        let (input, ticket_age_add) = be_u32(input)?;
+        let (input, max_early_data_size) = be_u32(input)?;
+        Ok((
+            input,
+            ServerSessionValue {
+                version,
+                cipher_suite,
+                secret: secret.data,
+                server_name,
+                peer_certificates,
+                created_at: (created_at_high as u64) << 32 | created_at_low as u64,
+                lifetime,
+                ticket_age_add,
+                max_early_data_size,
+            },
+        ))
+    }
+}
+
+//=============================================================================
+// セッションの保存
+//=============================================================================
+
+// TLS 1.2 のセッション ID や TLS 1.3 のチケット (チケットを暗号化しない場合) をキーにして保存する
+pub trait StoresServerSessions: fmt::Debug + Send + Sync {
+    fn put(&self, key: Vec<u8>, value: ServerSessionValue);
+    fn get(&self, key: &[u8]) -> Option<ServerSessionValue>;
+    // 1回しか使えないもの (TLS 1.3 のチケット) は取り出したら消す
+    fn take(&self, key: &[u8]) -> Option<ServerSessionValue>;
+}
+
+// 最後に使ってから時間が経ったものから消していくメモリ上のキャッシュ
+#[derive(Debug)]
+pub struct ServerSessionCache {
+    capacity: usize,
+    ttl: Duration,
+    state: Mutex<CacheState>,
+}
+
+#[derive(Debug, Default)]
+struct CacheState {
+    entries: HashMap<Vec<u8>, CacheEntry>,
+    // 最後に使った順 (値が小さいほど古い)
+    recently_used: BTreeMap<u64, Vec<u8>>,
+    counter: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    value: ServerSessionValue,
+    inserted_at: Instant,
+    last_used: u64,
+}
+
+impl CacheState {
+    fn remove(&mut self, key: &[u8]) -> Option<CacheEntry> {
+        let entry = self.entries.remove(key)?;
+        self.recently_used.remove(&entry.last_used);
+        Some(entry)
+    }
+
+    fn touch(&mut self, key: &[u8]) {
+        self.counter += 1;
+        let counter = self.counter;
+        if let Some(entry) = self.entries.get_mut(key) {
+            self.recently_used.remove(&entry.last_used);
+            self.recently_used.insert(counter, key.to_vec());
+            entry.last_used = counter;
+        }
+    }
+}
+
+impl ServerSessionCache {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        ServerSessionCache {
+            capacity,
+            ttl,
+            state: Mutex::new(CacheState::default()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().unwrap().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for ServerSessionCache {
+    fn default() -> Self {
+        ServerSessionCache::new(
+            DEFAULT_SESSION_CACHE_SIZE,
+            Duration::from_secs(DEFAULT_SESSION_LIFETIME as u64),
+        )
+    }
+}
+
+impl StoresServerSessions for ServerSessionCache {
+    fn put(&self, key: Vec<u8>, value: ServerSessionValue) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut state = self.state.lock().unwrap();
+        state.remove(&key);
+        while state.entries.len() >= self.capacity {
+            let Some((_, oldest)) = state.recently_used.pop_first() else {
+                break;
+            };
+            state.entries.remove(&oldest);
+        }
+        state.entries.insert(
+            key.clone(),
+            CacheEntry {
+                value,
+                inserted_at: Instant::now(),
+                last_used: 0,
+            },
+        );
+        state.touch(&key);
+    }
+
+    fn get(&self, key: &[u8]) -> Option<ServerSessionValue> {
+        let mut state = self.state.lock().unwrap();
+        if state.entries.get(key)?.inserted_at.elapsed() >= self.ttl {
+            state.remove(key);
+            return None;
+        }
+        state.touch(key);
+        state.entries.get(key).map(|entry| entry.value.clone())
+    }
+
+    fn take(&self, key: &[u8]) -> Option<ServerSessionValue> {
+        let mut state = self.state.lock().unwrap();
+        let entry = state.remove(key)?;
+        (entry.inserted_at.elapsed() < self.ttl).then_some(entry.value)
+    }
+}
+
+//=============================================================================
+// チケットの暗号化
+//=============================================================================
+
+struct TicketKey {
+    name: [u8; TICKET_KEY_NAME_LENGTH],
+    cipher: AeadCipher,
+    created_at: Instant,
+}
+
+impl TicketKey {
+    fn generate() -> Self {
+        let key = rand::random::<[u8; 32]>();
+        TicketKey {
+            name: rand::random(),
+            cipher: AeadCipher::new(AeadAlgorithm::Aes256Gcm, &key),
+            created_at: Instant::now(),
+        }
+    }
+}
+
+struct TicketKeyPair {
+    current: TicketKey,
+    previous: Option<TicketKey>,
+}
+
+// RFC 5077 4. の形式 (key_name, 暗号化したセッション) を AES-256-GCM で作る
+// key_name || nonce || ciphertext || tag で、key_name は追加データとして認証する
+// 鍵は rotation_interval ごとに作り直し、1つ前の鍵で暗号化されたチケットも受け付ける
+pub struct TicketKeys {
+    rotation_interval: Duration,
+    keys: Mutex<TicketKeyPair>,
+}
+
+impl TicketKeys {
+    pub fn new(rotation_interval: Duration) -> Self {
+        TicketKeys {
+            rotation_interval,
+            keys: Mutex::new(TicketKeyPair {
+                current: TicketKey::generate(),
+                previous: None,
+            }),
+        }
+    }
+
+    // この間はどちらかの鍵で必ず復号できるので、チケットの有効期間はこれ以下にする
+    pub fn rotation_interval(&self) -> Duration {
+        self.rotation_interval
+    }
+
+    pub fn rotate(&self) {
+        let mut keys = self.keys.lock().unwrap();
+        let current = std::mem::replace(&mut keys.current, TicketKey::generate());
+        keys.previous = Some(current);
+    }
+
+    fn rotate_if_needed(&self, keys: &mut TicketKeyPair) {
+        let age = keys.current.created_at.elapsed();
+        if age < self.rotation_interval {
+            return;
+        }
+        let current = std::mem::replace(&mut keys.current, TicketKey::generate());
+        // 長く使われていなかった鍵は1つ前の鍵としても残さない
+        keys.previous = (age < self.rotation_interval * 2).then_some(current);
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let mut keys = self.keys.lock().unwrap();
+        self.rotate_if_needed(&mut keys);
+
+        let nonce = rand::random::<[u8; TICKET_NONCE_LENGTH]>();
+        let mut ticket = keys.current.name.to_vec();
+        ticket.extend_from_slice(&nonce);
+        ticket.extend(
+            keys.current
+                .cipher
+                .seal(&nonce, &keys.current.name, plaintext),
+        );
+        ticket
+    }
+
+    // 1つ前の鍵で復号できた場合は true も返すので、新しいチケットを発行し直す
+    pub fn decrypt(&self, ticket: &[u8]) -> Option<(Vec<u8>, bool)> {
+        if ticket.len() < TICKET_KEY_NAME_LENGTH + TICKET_NONCE_LENGTH {
+            return None;
+        }
+        let (name, rest) = ticket.split_at(TICKET_KEY_NAME_LENGTH);
+        let (nonce, ciphertext) = rest.split_at(TICKET_NONCE_LENGTH);
+
+        let mut keys = self.keys.lock().unwrap();
+        self.rotate_if_needed(&mut keys);
+        if keys.current.name == name {
+            let plaintext = keys.current.cipher.open(nonce, name, ciphertext).ok()?;
+            return Some((plaintext, false));
+        }
+        match &keys.previous {
+            Some(previous) if previous.name == name => {
+                let plaintext = previous.cipher.open(nonce, name, ciphertext).ok()?;
+                Some((plaintext, true))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Default for TicketKeys {
+    fn default() -> Self {
+        TicketKeys::new(DEFAULT_TICKET_KEY_ROTATION)
+    }
+}
+
+// 鍵そのものは出力しない
+impl fmt::Debug for TicketKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TicketKeys")
+            .field("rotation_interval", &self.rotation_interval)
+            .finish_non_exhaustive()
+    }
+}
+
+//=============================================================================
+// 0-RTT の再送対策 (RFC 8446 8)
+//=============================================================================
+
+// チケットの年齢がクライアントの申告と window 以上ずれていたら拒否し (8.3)、
+// window の間に受け取った ClientHello の binder を覚えておいて同じものを拒否する (8.2)
+#[derive(Debug)]
+pub struct AntiReplay {
+    window: Duration,
+    seen: Mutex<HashMap<Vec<u8>, Instant>>,
+}
+
+impl AntiReplay {
+    pub fn new(window: Duration) -> Self {
+        AntiReplay {
+            window,
+            seen: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // どちらもミリ秒
+    pub fn accept(&self, client_ticket_age: u32, server_ticket_age: u64, binder: &[u8]) -> bool {
+        let window = self.window.as_millis() as u64;
+        if (client_ticket_age as u64).abs_diff(server_ticket_age) > window {
+            return false;
+        }
+
+        // window を過ぎたものは年齢の確認で拒否されるので忘れてよい
+        let mut seen = self.seen.lock().unwrap();
+        seen.retain(|_, received_at| received_at.elapsed() < self.window);
+        if seen.contains_key(binder) {
+            return false;
+        }
+        seen.insert(binder.to_vec(), Instant::now());
+        true
+    }
+}
+
+impl Default for AntiReplay {
+    fn default() -> Self {
+        AntiReplay::new(DEFAULT_ANTI_REPLAY_WINDOW)
+    }
+}
+
+//=============================================================================
+// 再開の統計
+//=============================================================================
+
+#[derive(Debug, Default)]
+pub struct ResumptionMetrics {
+    full_handshakes: AtomicU64,
+    session_id_hits: AtomicU64,
+    session_id_misses: AtomicU64,
+    ticket_hits: AtomicU64,
+    ticket_misses: AtomicU64,
+    psk_hits: AtomicU64,
+    psk_misses: AtomicU64,
+    early_data_accepted: AtomicU64,
+    early_data_rejected: AtomicU64,
+    replays_detected: AtomicU64,
+}
+
+// misses はクライアントが再開を求めたのに受け入れられなかった数
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ResumptionStats {
+    pub full_handshakes: u64,
+    // TLS 1.2 のセッション ID
+    pub session_id_hits: u64,
+    pub session_id_misses: u64,
+    // TLS 1.2 のセッションチケット
+    pub ticket_hits: u64,
+    pub ticket_misses: u64,
+    // TLS 1.3 の PSK
+    pub psk_hits: u64,
+    pub psk_misses: u64,
+    pub early_data_accepted: u64,
+    pub early_data_rejected: u64,
+    // early_data_rejected のうち再送対策で拒否したもの
+    pub replays_detected: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResumptionEvent {
+    FullHandshake,
+    SessionIdHit,
+    SessionIdMiss,
+    TicketHit,
+    TicketMiss,
+    PskHit,
+    PskMiss,
+    EarlyDataAccepted,
+    EarlyDataRejected,
+    ReplayDetected,
+}
+
+impl ResumptionMetrics {
+    pub fn new() -> Self {
+        ResumptionMetrics::default()
+    }
+
+    pub fn record(&self, event: ResumptionEvent) {
+        let counter = match event {
+            ResumptionEvent::FullHandshake => &self.full_handshakes,
+            ResumptionEvent::SessionIdHit => &self.session_id_hits,
+            ResumptionEvent::SessionIdMiss => &self.session_id_misses,
+            ResumptionEvent::TicketHit => &self.ticket_hits,
+            ResumptionEvent::TicketMiss => &self.ticket_misses,
+            ResumptionEvent::PskHit => &self.psk_hits,
+            ResumptionEvent::PskMiss => &self.psk_misses,
+            ResumptionEvent::EarlyDataAccepted => &self.early_data_accepted,
+            ResumptionEvent::EarlyDataRejected => &self.early_data_rejected,
+            ResumptionEvent::ReplayDetected => &self.replays_detected,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> ResumptionStats {
+        ResumptionStats {
+            full_handshakes: self.full_handshakes.load(Ordering::Relaxed),
+            session_id_hits: self.session_id_hits.load(Ordering::Relaxed),
+            session_id_misses: self.session_id_misses.load(Ordering::Relaxed),
+            ticket_hits: self.ticket_hits.load(Ordering::Relaxed),
+            ticket_misses: self.ticket_misses.load(Ordering::Relaxed),
+            psk_hits: self.psk_hits.load(Ordering::Relaxed),
+            psk_misses: self.psk_misses.load(Ordering::Relaxed),
+            early_data_accepted: self.early_data_accepted.load(Ordering::Relaxed),
+            early_data_rejected: self.early_data_rejected.load(Ordering::Relaxed),
+            replays_detected: self.replays_detected.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(secret: &[u8]) -> ServerSessionValue {
+        ServerSessionValue {
+            version: ProtocolVersion::TLSv1_3,
+            cipher_suite: CipherSuite::TLS_AES_128_GCM_SHA256,
+            secret: secret.to_vec(),
+            server_name: Some("example.test".to_string()),
+            peer_certificates: vec![vec![1, 2, 3]],
+            created_at: unix_time_millis(),
+            lifetime: DEFAULT_SESSION_LIFETIME,
+            ticket_age_add: 0x01020304,
+            max_early_data_size: 16384,
+        }
+    }
+
+    // ttl だけ前に保存したことにする
+    fn expire(cache: &ServerSessionCache, key: &[u8]) {
+        let mut state = cache.state.lock().unwrap();
+        let entry = state.entries.get_mut(key).unwrap();
+        entry.inserted_at = Instant::now().checked_sub(cache.ttl).unwrap();
+    }
+
+    // 鍵を作ってから age だけ経ったことにする
+    fn age_current_key(keys: &TicketKeys, age: Duration) {
+        keys.keys.lock().unwrap().current.created_at = Instant::now().checked_sub(age).unwrap();
+    }
+
+    #[test]
+    fn session_value_round_trip() {
+        let value = session(b"secret");
+        let decoded = ServerSessionValue::from_bytes(&value.to_bytes()).unwrap();
+        assert_eq!(decoded.secret, value.secret);
+        assert_eq!(decoded.server_name, value.server_name);
+        assert_eq!(decoded.peer_certificates, value.peer_certificates);
+        assert_eq!(decoded.created_at, value.created_at);
+        assert_eq!(decoded.ticket_age_add, value.ticket_age_add);
+        assert_eq!(decoded.max_early_data_size, value.max_early_data_size);
+
+        let mut data = value.to_bytes();
+        data.push(0);
+        assert!(ServerSessionValue::from_bytes(&data).is_err());
+    }
+
+    #[test]
+    fn cache_evicts_least_recently_used() {
+        let cache = ServerSessionCache::new(2, Duration::from_secs(60));
+        cache.put(b"a".to_vec(), session(b"a"));
+        cache.put(b"b".to_vec(), session(b"b"));
+        // a を使ったので、次に消えるのは b
+        assert!(cache.get(b"a").is_some());
+        cache.put(b"c".to_vec(), session(b"c"));
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(b"b").is_none());
+        assert!(cache.get(b"a").is_some());
+        assert!(cache.get(b"c").is_some());
+
+        // 同じキーで保存し直しても数は増えない
+        cache.put(b"c".to_vec(), session(b"d"));
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(b"c").unwrap().secret, b"d");
+        cache.put(b"e".to_vec(), session(b"e"));
+        assert!(cache.get(b"a").is_none());
+    }
+
+    #[test]
+    fn cache_without_capacity() {
+        let cache = ServerSessionCache::new(0, Duration::from_secs(60));
+        cache.put(b"a".to_vec(), session(b"a"));
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_expires_entries() {
+        let cache = ServerSessionCache::new(4, Duration::from_secs(60));
+        cache.put(b"a".to_vec(), session(b"a"));
+        cache.put(b"b".to_vec(), session(b"b"));
+        expire(&cache, b"a");
+        expire(&cache, b"b");
+        assert!(cache.get(b"a").is_none());
+        assert!(cache.take(b"b").is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn cache_take_is_single_use() {
+        let cache = ServerSessionCache::new(4, Duration::from_secs(60));
+        cache.put(b"a".to_vec(), session(b"a"));
+        assert_eq!(cache.take(b"a").unwrap().secret, b"a");
+        assert!(cache.take(b"a").is_none());
+        assert!(cache.get(b"a").is_none());
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn ticket_round_trip() {
+        let keys = TicketKeys::new(Duration::from_secs(60));
+        let ticket = keys.encrypt(b"session");
+        assert_eq!(
+            ticket.len(),
+            TICKET_KEY_NAME_LENGTH + TICKET_NONCE_LENGTH + b"session".len() + 16
+        );
+        assert_eq!(keys.decrypt(&ticket), Some((b"session".to_vec(), false)));
+        // 同じ平文でも nonce が違う
+        assert_ne!(keys.encrypt(b"session"), ticket);
+    }
+
+    #[test]
+    fn ticket_with_previous_key() {
+        let keys = TicketKeys::new(Duration::from_secs(60));
+        let ticket = keys.encrypt(b"session");
+        keys.rotate();
+        assert_eq!(keys.decrypt(&ticket), Some((b"session".to_vec(), true)));
+        let reissued = keys.encrypt(b"session");
+        assert_eq!(keys.decrypt(&reissued), Some((b"session".to_vec(), false)));
+
+        // 2回更新すると最初の鍵は使えない
+        keys.rotate();
+        assert_eq!(keys.decrypt(&ticket), None);
+        assert_eq!(keys.decrypt(&reissued), Some((b"session".to_vec(), true)));
+    }
+
+    #[test]
+    fn ticket_key_rotation() {
+        let interval = Duration::from_secs(60);
+
+        // 更新の間隔を過ぎると新しい鍵で暗号化し、前の鍵のチケットも受け付ける
+        let keys = TicketKeys::new(interval);
+        let ticket = keys.encrypt(b"session");
+        age_current_key(&keys, interval);
+        assert_ne!(keys.encrypt(b"session")[..16], ticket[..16]);
+        assert_eq!(keys.decrypt(&ticket), Some((b"session".to_vec(), true)));
+
+        // 間隔の2倍を過ぎていたら前の鍵は残さない
+        let keys = TicketKeys::new(interval);
+        let ticket = keys.encrypt(b"session");
+        age_current_key(&keys, interval * 2);
+        assert_eq!(keys.decrypt(&ticket), None);
+        assert!(keys.keys.lock().unwrap().previous.is_none());
+    }
+
+    #[test]
+    fn ticket_rejects_unknown_key_or_modified_ticket() {
+        let keys = TicketKeys::new(Duration::from_secs(60));
+        let ticket = keys.encrypt(b"session");
+
+        let mut unknown_key = ticket.clone();
+        unknown_key[0] ^= 1;
+        assert_eq!(keys.decrypt(&unknown_key), None);
+        assert_eq!(
+            TicketKeys::new(Duration::from_secs(60)).decrypt(&ticket),
+            None
+        );
+
+        for i in [TICKET_KEY_NAME_LENGTH, ticket.len() - 1] {
+            let mut modified = ticket.clone();
+            modified[i] ^= 1;
+            assert_eq!(keys.decrypt(&modified), None);
+        }
+        assert_eq!(keys.decrypt(&ticket[..ticket.len() - 1]), None);
+        assert_eq!(keys.decrypt(&ticket[..TICKET_KEY_NAME_LENGTH]), None);
+    }
+
+    #[test]
+    fn anti_replay() {
+        let anti_replay = AntiReplay::new(Duration::from_secs(10));
+        assert!(anti_replay.accept(5000, 5000, b"binder"));
+        // 同じ binder は2回目を拒否する
+        assert!(!anti_replay.accept(5000, 5000, b"binder"));
+        assert!(anti_replay.accept(5000, 14000, b"other"));
+
+        // 年齢の差が window を超えたら binder にかかわらず拒否する
+        assert!(!anti_replay.accept(5000, 15001, b"late"));
+        assert!(!anti_replay.accept(15001, 5000, b"early"));
+        assert!(anti_replay.accept(5000, 15000, b"late"));
+    }
+
+    #[test]
+    fn anti_replay_forgets_old_binders() {
+        let anti_replay = AntiReplay::new(Duration::ZERO);
+        assert!(anti_replay.accept(0, 0, b"binder"));
+        assert!(anti_replay.accept(0, 0, b"binder"));
+        assert_eq!(anti_replay.seen.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn metrics() {
+        let metrics = ResumptionMetrics::new();
+        assert_eq!(metrics.snapshot(), ResumptionStats::default());
+
+        for event in [
+            ResumptionEvent::FullHandshake,
+            ResumptionEvent::FullHandshake,
+            ResumptionEvent::SessionIdHit,
+            ResumptionEvent::SessionIdMiss,
+            ResumptionEvent::TicketHit,
+            ResumptionEvent::TicketMiss,
+            ResumptionEvent::PskHit,
+            ResumptionEvent::PskHit,
+            ResumptionEvent::PskHit,
+            ResumptionEvent::PskMiss,
+            ResumptionEvent::EarlyDataAccepted,
+            ResumptionEvent::EarlyDataRejected,
+            ResumptionEvent::EarlyDataRejected,
+            ResumptionEvent::ReplayDetected,
+        ] {
+            metrics.record(event);
+        }
+        assert_eq!(
+            metrics.snapshot(),
+            ResumptionStats {
+                full_handshakes: 2,
+                session_id_hits: 1,
+                session_id_misses: 1,
+                ticket_hits: 1,
+                ticket_misses: 1,
+                psk_hits: 3,
+                psk_misses: 1,
+                early_data_accepted: 1,
+                early_data_rejected: 2,
+                replays_detected: 1,
+            }
+        );
+    }
+}
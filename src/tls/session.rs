@@ -0,0 +1,164 @@
+use super::CipherSuite;
+
+use std::collections::{HashMap, VecDeque};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+// RFC 8446 4.6.1. チケットの有効期間は最大7日
+const MAX_TICKET_LIFETIME: u32 = 604800;
+// サーバーごとに保持するチケットの数
+const MAX_TICKETS_PER_SERVER: usize = 8;
+// RFC 5246 F.1.4. セッション ID の有効期間は最大24時間 (チケットも同じ上限にする)
+const MAX_TLS12_SESSION_LIFETIME: u32 = 86400;
+
+// NewSessionTicket から作った再開用の情報
+#[derive(Debug, Clone)]
+pub struct Tls13Session {
+    pub cipher_suite: CipherSuite,
+    pub ticket: Vec<u8>,
+    pub psk: Vec<u8>,
+    pub ticket_age_add: u32,
+    pub lifetime: Duration,
+    pub received_at: Instant,
+    // 0 なら 0-RTT は使えない
+    pub max_early_data_size: u32,
+    // 再開時は Certificate が送られないので前回のものを引き継ぐ
+    pub peer_certificates: Vec<Vec<u8>>,
+}
+
+impl Tls13Session {
+    // lifetime が 0 や上限超えのチケットは None
+    pub fn new(
+        cipher_suite: CipherSuite,
+        ticket: Vec<u8>,
+        psk: Vec<u8>,
+        ticket_age_add: u32,
+        ticket_lifetime: u32,
+        max_early_data_size: u32,
+        peer_certificates: Vec<Vec<u8>>,
+    ) -> Option<Self> {
+        if ticket_lifetime == 0 || ticket_lifetime > MAX_TICKET_LIFETIME {
+            return None;
+        }
+        Some(Tls13Session {
+            cipher_suite,
+            ticket,
+            psk,
+            ticket_age_add,
+            lifetime: Duration::from_secs(ticket_lifetime as u64),
+            received_at: Instant::now(),
+            max_early_data_size,
+            peer_certificates,
+        })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.received_at.elapsed() >= self.lifetime
+    }
+
+    // RFC 8446 4.2.11. ミリ秒単位の経過時間に ticket_age_add を足す (mod 2^32)
+    pub fn obfuscated_ticket_age(&self) -> u32 {
+        let age = self.received_at.elapsed().as_millis() as u32;
+        age.wrapping_add(self.ticket_age_add)
+    }
+}
+
+// TLS 1.2 のフルハンドシェイクでサーバーが割り当てたセッション ID やチケットと master secret
+#[derive(Debug, Clone)]
+pub struct Tls12Session {
+    pub session_id: Vec<u8>,
+    // 空ならチケットは無く、セッション ID で再開する
+    pub ticket: Vec<u8>,
+    pub cipher_suite: CipherSuite,
+    pub master_secret: Vec<u8>,
+    pub peer_certificates: Vec<Vec<u8>>,
+    pub lifetime: Duration,
+    pub established_at: Instant,
+}
+
+impl Tls12Session {
+    // ticket_lifetime_hint が 0 なら有効期間の指定は無い (RFC 5077 3.3)
+    pub fn new(
+        session_id: Vec<u8>,
+        ticket: Vec<u8>,
+        ticket_lifetime_hint: u32,
+        cipher_suite: CipherSuite,
+        master_secret: Vec<u8>,
+        peer_certificates: Vec<Vec<u8>>,
+    ) -> Self {
+        let lifetime = match ticket_lifetime_hint {
+            0 => MAX_TLS12_SESSION_LIFETIME,
+            hint => hint.min(MAX_TLS12_SESSION_LIFETIME),
+        };
+        Tls12Session {
+            session_id,
+            ticket,
+            cipher_suite,
+            master_secret,
+            peer_certificates,
+            lifetime: Duration::from_secs(lifetime as u64),
+            established_at: Instant::now(),
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.established_at.elapsed() >= self.lifetime
+    }
+}
+
+// サーバー名ごとにチケットを保持する
+// 同じチケットを使い回すと接続を追跡できてしまうので、取り出したチケットは消す (RFC 8446 C.4)
+// TLS 1.2 のセッション ID やチケットは再開しても使えるので、サーバーごとに1つを使い回す
+#[derive(Debug, Default)]
+pub struct ClientSessionStore {
+    sessions: Mutex<HashMap<String, VecDeque<Tls13Session>>>,
+    tls12_sessions: Mutex<HashMap<String, Tls12Session>>,
+}
+
+impl ClientSessionStore {
+    pub fn new() -> Self {
+        ClientSessionStore::default()
+    }
+
+    pub fn insert(&self, server_name: &str, session: Tls13Session) {
+        let mut sessions = self.sessions.lock().unwrap();
+        let sessions = sessions.entry(server_name.to_string()).or_default();
+        if sessions.len() >= MAX_TICKETS_PER_SERVER {
+            sessions.pop_front();
+        }
+        sessions.push_back(session);
+    }
+
+    // 新しいものから、期限切れでないチケットを取り出す
+    pub fn take(&self, server_name: &str) -> Option<Tls13Session> {
+        let mut sessions = self.sessions.lock().unwrap();
+        let sessions = sessions.get_mut(server_name)?;
+        while let Some(session) = sessions.pop_back() {
+            if !session.is_expired() {
+                return Some(session);
+            }
+        }
+        None
+    }
+
+    pub fn insert_tls12(&self, server_name: &str, session: Tls12Session) {
+        let mut sessions = self.tls12_sessions.lock().unwrap();
+        sessions.insert(server_name.to_string(), session);
+    }
+
+    pub fn tls12_session(&self, server_name: &str) -> Option<Tls12Session> {
+        let mut sessions = self.tls12_sessions.lock().unwrap();
+        match sessions.get(server_name) {
+            Some(session) if session.is_expired() => {
+                sessions.remove(server_name);
+                None
+            }
+            session => session.cloned(),
+        }
+    }
+
+    // サーバーが再開を受け入れなかったセッションを消す
+    pub fn remove_tls12(&self, server_name: &str) {
+        self.tls12_sessions.lock().unwrap().remove(server_name);
+    }
+}
@@ -0,0 +1,74 @@
+use super::{CertifiedKey, RootCertStore};
+
+use anyhow::Result;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+
+// testdata/verifier の証明書は同じ鍵 (leaf.key) で、server.pem はサーバー用、client.pem はクライアント用
+pub fn load(name: &str) -> Vec<u8> {
+    let path = format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), name);
+    std::fs::read(path).unwrap()
+}
+
+pub fn certified_key(certificate: &str) -> CertifiedKey {
+    let chain = [
+        load(&format!("verifier/{}", certificate)),
+        load("verifier/inter.pem"),
+    ]
+    .concat();
+    CertifiedKey::from_pem(&chain, &load("verifier/leaf.key")).unwrap()
+}
+
+pub fn roots() -> RootCertStore {
+    let mut roots = RootCertStore::new();
+    roots.add_pem(&load("verifier/root.pem")).unwrap();
+    roots
+}
+
+// 未読のデータを残したまま閉じると RST になり、相手が直前のアラートを読めないことがある
+// 閉じる前に送信側を閉じて、相手が閉じるまで読み捨てる
+pub struct GracefulStream(pub TcpStream);
+
+impl Read for GracefulStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for GracefulStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.flush()
+    }
+}
+
+impl Drop for GracefulStream {
+    fn drop(&mut self) {
+        let _ = self.0.shutdown(Shutdown::Write);
+        let _ = std::io::copy(&mut self.0, &mut std::io::sink());
+    }
+}
+
+// server を別のスレッドで動かし、client から接続する
+// client の接続は server が終わるまで閉じないので、server の側は中止した場合だけ読み捨ててから閉じる
+pub fn run<C, T: Send + 'static>(
+    client: impl FnOnce(GracefulStream) -> Result<C>,
+    server: impl FnOnce(TcpStream) -> Result<T> + Send + 'static,
+) -> (Result<C>, Result<T>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let server = std::thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let result = server(stream.try_clone().unwrap());
+        if result.is_err() {
+            drop(GracefulStream(stream));
+        }
+        result
+    });
+
+    let client = client(GracefulStream(TcpStream::connect(addr).unwrap()));
+    (client, server.join().unwrap())
+}
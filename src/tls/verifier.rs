@@ -0,0 +1,312 @@
+use super::crypto::verify_signature;
+use super::{normalize_host_name, AlertDescription, Error, SignatureScheme};
+
+use anyhow::{bail, Result};
+use std::net::IpAddr;
+use x509_parser::certificate::X509Certificate;
+use x509_parser::extensions::{ExtendedKeyUsage, GeneralName};
+use x509_parser::oid_registry::{
+    OID_EC_P256, OID_NIST_EC_P384, OID_PKCS1_SHA256WITHRSA, OID_PKCS1_SHA384WITHRSA,
+    OID_PKCS1_SHA512WITHRSA, OID_SIG_ECDSA_WITH_SHA256, OID_SIG_ECDSA_WITH_SHA384,
+};
+use x509_parser::parse_x509_certificate;
+use x509_parser::pem::Pem;
+
+// 中間 CA をたどる回数の上限
+const MAX_CHAIN_DEPTH: usize = 8;
+
+// 信頼する CA 証明書 (DER)
+#[derive(Debug, Clone, Default)]
+pub struct RootCertStore {
+    roots: Vec<Vec<u8>>,
+}
+
+impl RootCertStore {
+    pub fn new() -> Self {
+        RootCertStore::default()
+    }
+
+    pub fn add(&mut self, certificate: Vec<u8>) -> Result<()> {
+        parse_x509_certificate(&certificate)?;
+        self.roots.push(certificate);
+        Ok(())
+    }
+
+    // CERTIFICATE を並べた PEM。追加した数を返す
+    pub fn add_pem(&mut self, certificates: &[u8]) -> Result<usize> {
+        let mut count = 0;
+        for pem in Pem::iter_from_buffer(certificates) {
+            let pem = pem?;
+            if pem.label == "CERTIFICATE" {
+                self.add(pem.contents)?;
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    pub fn len(&self) -> usize {
+        self.roots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.roots.is_empty()
+    }
+
+    // CertificateRequest の certificate_authorities に入れる subject の DN (DER)
+    pub fn subjects(&self) -> Vec<Vec<u8>> {
+        self.roots
+            .iter()
+            .filter_map(|root| parse_x509_certificate(root).ok())
+            .map(|(_, root)| root.subject().as_raw().to_vec())
+            .collect()
+    }
+
+    pub fn verify_client_chain(&self, chain: &[Vec<u8>]) -> Result<()> {
+        self.verify_chain(chain, |usage| usage.client_auth)
+    }
+
+    // エンドエンティティの subjectAltName に server_name が含まれていることも確かめる (RFC 6125)
+    // CA が1つも無ければすべて unknown_ca になる
+    pub fn verify_server_chain(&self, chain: &[Vec<u8>], server_name: &str) -> Result<()> {
+        self.verify_chain(chain, |usage| usage.server_auth)?;
+        let (_, end_entity) = parse_x509_certificate(&chain[0])?;
+        if !matches_server_name(&end_entity, server_name) {
+            bail!(Error::Alert(AlertDescription::BadCertificate));
+        }
+        Ok(())
+    }
+
+    // RFC 5280 6. のパス検証のうち、署名、有効期間、CA であることだけを確かめる
+    // chain は先頭がエンドエンティティで、残りは順不同の中間 CA (ルートを含んでいてもよい)
+    // 失効の確認や名前制約、ポリシーには対応しない
+    fn verify_chain(
+        &self,
+        chain: &[Vec<u8>],
+        purpose: fn(&ExtendedKeyUsage) -> bool,
+    ) -> Result<()> {
+        let mut certificates = vec![];
+        for certificate in chain {
+            let Ok((_, certificate)) = parse_x509_certificate(certificate) else {
+                bail!(Error::Alert(AlertDescription::BadCertificate))
+            };
+            certificates.push(certificate);
+        }
+        let mut roots = vec![];
+        for root in &self.roots {
+            let (_, root) = parse_x509_certificate(root)?;
+            roots.push(root);
+        }
+        let Some((end_entity, intermediates)) = certificates.split_first() else {
+            bail!(Error::Alert(AlertDescription::BadCertificate))
+        };
+
+        // extendedKeyUsage があれば clientAuth (サーバーなら serverAuth) が含まれていなければならない
+        match end_entity.extended_key_usage() {
+            Ok(Some(usage)) if !usage.value.any && !purpose(usage.value) => {
+                bail!(Error::Alert(AlertDescription::UnsupportedCertificate))
+            }
+            Ok(_) => {}
+            Err(_) => bail!(Error::Alert(AlertDescription::BadCertificate)),
+        }
+
+        let mut current = end_entity;
+        for _ in 0..MAX_CHAIN_DEPTH {
+            if !current.validity().is_valid() {
+                bail!(Error::Alert(AlertDescription::CertificateExpired));
+            }
+            if roots
+                .iter()
+                .any(|root| root.validity().is_valid() && is_issued_by(current, root))
+            {
+                return Ok(());
+            }
+            let Some(issuer) = intermediates
+                .iter()
+                .find(|issuer| issuer.is_ca() && is_issued_by(current, issuer))
+            else {
+                bail!(Error::Alert(AlertDescription::UnknownCa))
+            };
+            current = issuer;
+        }
+        bail!(Error::Alert(AlertDescription::UnknownCa))
+    }
+}
+
+// IP アドレスは iPAddress と、ホスト名は dNSName と比べる。commonName は見ない
+fn matches_server_name(certificate: &X509Certificate, server_name: &str) -> bool {
+    let Ok(Some(names)) = certificate.subject_alternative_name() else {
+        return false;
+    };
+    let names = &names.value.general_names;
+    if let Ok(address) = server_name.parse::<IpAddr>() {
+        let octets = match address {
+            IpAddr::V4(address) => address.octets().to_vec(),
+            IpAddr::V6(address) => address.octets().to_vec(),
+        };
+        return names
+            .iter()
+            .any(|name| matches!(name, GeneralName::IPAddress(address) if *address == octets));
+    }
+    let server_name = normalize_host_name(server_name);
+    names.iter().any(|name| match name {
+        GeneralName::DNSName(pattern) => {
+            matches_dns_name(&normalize_host_name(pattern), &server_name)
+        }
+        _ => false,
+    })
+}
+
+// ワイルドカードは左端のラベル全体の "*" だけを扱い、1つのラベルにだけ一致させる
+// "*.com" のようにトップレベルドメインの直下に付いたものは認めない
+fn matches_dns_name(pattern: &str, name: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(parent) if !parent.contains('.') => false,
+        Some(parent) => name
+            .split_once('.')
+            .is_some_and(|(label, rest)| !label.is_empty() && rest == parent),
+        None => pattern == name,
+    }
+}
+
+fn is_issued_by(certificate: &X509Certificate, issuer: &X509Certificate) -> bool {
+    if certificate.issuer().as_raw() != issuer.subject().as_raw() {
+        return false;
+    }
+    let Some(scheme) = certificate_signature_scheme(certificate, issuer) else {
+        return false;
+    };
+    verify_signature(
+        scheme,
+        issuer.public_key().raw,
+        certificate.tbs_certificate.as_ref(),
+        &certificate.signature_value.data,
+    )
+    .is_ok()
+}
+
+// 証明書の署名アルゴリズムを、ハンドシェイクの署名と同じ検証に使える署名方式に対応させる
+// ECDSA は発行者の鍵の曲線とハッシュ関数の組み合わせが TLS 1.3 と同じものだけ
+fn certificate_signature_scheme(
+    certificate: &X509Certificate,
+    issuer: &X509Certificate,
+) -> Option<SignatureScheme> {
+    let algorithm = &certificate.signature_algorithm.algorithm;
+    let curve = issuer
+        .public_key()
+        .algorithm
+        .parameters
+        .as_ref()
+        .and_then(|parameters| parameters.as_oid().ok());
+    if *algorithm == OID_PKCS1_SHA256WITHRSA {
+        Some(SignatureScheme::rsa_pkcs1_sha256)
+    } else if *algorithm == OID_PKCS1_SHA384WITHRSA {
+        Some(SignatureScheme::rsa_pkcs1_sha384)
+    } else if *algorithm == OID_PKCS1_SHA512WITHRSA {
+        Some(SignatureScheme::rsa_pkcs1_sha512)
+    } else if *algorithm == OID_SIG_ECDSA_WITH_SHA256 && curve == Some(OID_EC_P256) {
+        Some(SignatureScheme::ecdsa_secp256r1_sha256)
+    } else if *algorithm == OID_SIG_ECDSA_WITH_SHA384 && curve == Some(OID_NIST_EC_P384) {
+        Some(SignatureScheme::ecdsa_secp384r1_sha384)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // testdata/verifier: ルート -> 中間 CA -> エンドエンティティ (P-256)
+    // server.pem は serverAuth で example.test, *.wild.test, 127.0.0.1、client.pem は clientAuth
+    fn load(name: &str) -> Vec<u8> {
+        let path = format!("{}/testdata/verifier/{}", env!("CARGO_MANIFEST_DIR"), name);
+        let pem = std::fs::read(path).unwrap();
+        Pem::iter_from_buffer(&pem)
+            .next()
+            .unwrap()
+            .unwrap()
+            .contents
+    }
+
+    fn roots() -> RootCertStore {
+        let mut roots = RootCertStore::new();
+        roots.add(load("root.pem")).unwrap();
+        roots
+    }
+
+    fn alert(result: Result<()>) -> Option<AlertDescription> {
+        match result.err()?.downcast_ref::<Error>() {
+            Some(Error::Alert(description)) => Some(*description),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn server_chain() {
+        let chain = [load("server.pem"), load("inter.pem")];
+        for name in ["example.test", "EXAMPLE.test.", "a.wild.test", "127.0.0.1"] {
+            assert!(
+                roots().verify_server_chain(&chain, name).is_ok(),
+                "{}",
+                name
+            );
+        }
+        for name in [
+            "other.test",
+            "wild.test",
+            "a.b.wild.test",
+            "127.0.0.2",
+            "::1",
+        ] {
+            assert_eq!(
+                alert(roots().verify_server_chain(&chain, name)),
+                Some(AlertDescription::BadCertificate),
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn server_chain_without_roots() {
+        let chain = [load("server.pem"), load("inter.pem")];
+        assert_eq!(
+            alert(RootCertStore::new().verify_server_chain(&chain, "example.test")),
+            Some(AlertDescription::UnknownCa)
+        );
+    }
+
+    #[test]
+    fn missing_intermediate() {
+        assert_eq!(
+            alert(roots().verify_server_chain(&[load("server.pem")], "example.test")),
+            Some(AlertDescription::UnknownCa)
+        );
+    }
+
+    #[test]
+    fn key_purpose() {
+        let server = [load("server.pem"), load("inter.pem")];
+        let client = [load("client.pem"), load("inter.pem")];
+        assert!(roots().verify_client_chain(&client).is_ok());
+        assert_eq!(
+            alert(roots().verify_client_chain(&server)),
+            Some(AlertDescription::UnsupportedCertificate)
+        );
+        assert_eq!(
+            alert(roots().verify_server_chain(&client, "example.test")),
+            Some(AlertDescription::UnsupportedCertificate)
+        );
+    }
+
+    #[test]
+    fn dns_name() {
+        assert!(matches_dns_name("example.test", "example.test"));
+        assert!(matches_dns_name("*.example.test", "a.example.test"));
+        assert!(!matches_dns_name("*.example.test", "example.test"));
+        assert!(!matches_dns_name("*.example.test", ".example.test"));
+        assert!(!matches_dns_name("*.test", "example.test"));
+        assert!(!matches_dns_name("a*.example.test", "ab.example.test"));
+    }
+}